@@ -94,6 +94,60 @@ def get_macro_nodes(root: Node, placeholder: str) -> list[Node]:
     return list(ancestors)
 
 
+def get_item_key(node: Node) -> tuple[str, str]:
+    # Items are identified by their kind and name, unnamed nodes (e.g., comments) by their text
+    name = node.child_by_field_name("name")
+    text = name.text if name else node.text
+    return node.type, text.decode() if text else ""
+
+
+def validate_impl_changes(
+    node: Node,
+    template_node: Node,
+    allowed_change_nodes: list[Node],
+    scope_feedback: OrderedDict[str, str],
+) -> None:
+    body = node.child_by_field_name("body")
+    template_body = template_node.child_by_field_name("body")
+    if not body or not template_body or not node.text or not template_node.text:
+        return
+
+    # Check for changes to the impl header (trait, type, generics, where clause)
+    header_len = body.start_byte - node.start_byte
+    template_header_len = template_body.start_byte - template_node.start_byte
+    if node.text[:header_len] != template_node.text[:template_header_len]:
+        scope_feedback["top_level_changes"] = (
+            "The generated code modifies parts outside the function body.\n"
+            "You must **only** modify the `unimplemented!()` function body and leave everything else **unchanged**!"
+        )
+
+    items = {get_item_key(item): item for item in body.named_children}
+    template_items = {get_item_key(item): item for item in template_body.named_children}
+
+    # Check for added or removed impl members
+    if items.keys() != template_items.keys():
+        scope_feedback["member_changes"] = (
+            "The generated code adds or removes items inside an `impl` block.\n"
+            "You must **only** modify the `unimplemented!()` function body and leave everything else **unchanged**!"
+        )
+
+    # Check for allowed changes to impl members
+    for key, template_item in template_items.items():
+        item = items.get(key)
+        if not item or template_item.text == item.text:
+            continue
+
+        if template_item not in allowed_change_nodes or template_item.type != "function_item":
+            scope_feedback["top_level_changes"] = (
+                "The generated code modifies parts outside the function body.\n"
+                "You must **only** modify the `unimplemented!()` function body and leave everything else **unchanged**!"
+            )
+        elif RustFnSignature(template_item) != RustFnSignature(item):
+            scope_feedback["signature_changes"] = (
+                "You must preserve the function signature in the template intact and **not modify it**!"
+            )
+
+
 def validate_changes(code: str, template: str) -> OrderedDict[str, str]:
     code_root = get_root(code)
     template_root = get_root(template)
@@ -114,6 +168,15 @@ def validate_changes(code: str, template: str) -> OrderedDict[str, str]:
     # Check for allowed changes
     for template_node, node in zip(template_nodes, nodes):
         if not template_node.text == node.text:
+            # Methods of impl blocks are validated member by member
+            if (
+                template_node.type == "impl_item"
+                and node.type == "impl_item"
+                and template_node in allowed_change_nodes
+            ):
+                validate_impl_changes(node, template_node, allowed_change_nodes, scope_feedback)
+                continue
+
             if (
                 template_node not in allowed_change_nodes
                 or not template_node.type == "function_item"
@@ -0,0 +1,22 @@
+fn push_while_borrowed() {
+    let mut v = vec![1, 2, 3];
+    let first = &v[0];
+    v.push(4); //~ ERROR E0502
+    println!("{}", first);
+}
+
+fn clear_while_borrowed() {
+    let mut s = String::from("hello");
+    let slice = &s[..];
+    s.clear(); //~ ERROR E0502
+    println!("{}", slice);
+}
+
+fn mutate_while_iterating() {
+    let mut v = vec![1, 2, 3];
+    for x in v.iter() {
+        v.push(*x); //~ ERROR E0502
+    }
+}
+
+fn main() {}
@@ -193,3 +193,56 @@ fn perf_useless_vec() {
         println!("{}", item);
     }
 }
+
+// Pedantic violations
+fn pedantic_needless_pass_by_value(v: Vec<i32>) -> usize {
+    v.len() // takes ownership but only reads
+}
+
+fn pedantic_cast_possible_truncation(x: u64) -> u8 {
+    x as u8 // may truncate
+}
+
+fn pedantic_unreadable_literal() -> u64 {
+    1234567890 // should use separators
+}
+
+fn pedantic_explicit_iter_loop() {
+    let v = vec![1, 2, 3];
+    for x in v.iter() { // should iterate over &v
+        println!("{}", x);
+    }
+}
+
+fn pedantic_manual_string_new() -> String {
+    "".to_string() // should use String::new()
+}
+
+// Nursery violations
+fn nursery_missing_const_for_fn() -> i32 {
+    42 // could be a const fn
+}
+
+fn nursery_redundant_clone() -> String {
+    let s = String::from("hello");
+    let t = s.clone(); // s is never used again
+    t
+}
+
+struct Point {
+    x: i32,
+}
+
+impl Point {
+    fn nursery_use_self() -> Point {
+        Point { x: 0 } // should use Self
+    }
+}
+
+fn nursery_option_if_let_else(x: Option<i32>) -> i32 {
+    if let Some(v) = x { v * 2 } else { 0 } // should use map_or
+}
+
+fn nursery_suboptimal_flops(a: f64, b: f64, c: f64) -> f64 {
+    a * b + c // should use mul_add
+}
@@ -0,0 +1,19 @@
+fn dangling_in_block() {
+    let r;
+    {
+        let x = 5;
+        r = &x; //~ ERROR E0597
+    }
+    println!("{}", r);
+}
+
+fn dangling_string() {
+    let s: &str;
+    {
+        let owned = String::from("hello");
+        s = &owned; //~ ERROR E0597
+    }
+    println!("{}", s);
+}
+
+fn main() {}
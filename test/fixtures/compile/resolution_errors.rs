@@ -0,0 +1,14 @@
+fn unknown_variable() -> i32 {
+    undefined_value //~ ERROR E0425
+}
+
+fn unknown_function() {
+    missing_helper(); //~ ERROR E0425
+}
+
+fn misspelled_local() {
+    let count = 1;
+    println!("{}", cuont); //~ ERROR E0425
+}
+
+fn main() {}
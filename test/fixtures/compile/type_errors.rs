@@ -0,0 +1,17 @@
+fn return_mismatch() -> i32 {
+    "forty-two" //~ ERROR E0308
+}
+
+fn argument_mismatch() {
+    let _: u8 = takes_u8(1.5); //~ ERROR E0308
+}
+
+fn takes_u8(x: u8) -> u8 {
+    x
+}
+
+fn let_mismatch() {
+    let _x: bool = 1; //~ ERROR E0308
+}
+
+fn main() {}
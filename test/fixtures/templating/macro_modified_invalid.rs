@@ -0,0 +1,28 @@
+macro_rules! square {
+    ($x:expr) => {
+        $x * $x
+    };
+}
+
+fn summarize(values: &[i32]) -> String {
+    let squares: Vec<i32> = values.iter().map(|v| square!(*v)).collect();
+    let evens = vec![0, 2, 4, 6, 8];
+    if squares.iter().any(|s| matches!(s, n if *n < 0)) {
+        panic!("{}", format!("negative square in {{ {:?} }}", squares));
+    }
+    println!("opening brace: {{, raw: {}", "{");
+    format!(
+        "{} squares, {} even, total {}",
+        squares.len(),
+        squares.iter().filter(|s| evens.contains(&(*s % 10))).count(),
+        squares.iter().sum::<i32>()
+    )
+}
+
+fn report(message: &str) {
+    println!("[summary] {}", message);
+}
+
+fn main() {
+    report(&summarize(&[1, 2, 3]));
+}
@@ -0,0 +1,17 @@
+macro_rules! square {
+    ($x:expr) => {
+        $x * $x
+    };
+}
+
+fn summarize(values: &[i32]) -> String {
+    unimplemented!()
+}
+
+fn report(message: &str) {
+    println!("[report] {}", message);
+}
+
+fn main() {
+    report(&summarize(&[1, 2, 3]));
+}
@@ -0,0 +1,28 @@
+trait Shape {
+    const SIDES: u32;
+
+    fn area(&self) -> f64;
+    fn describe(&self) -> String;
+}
+
+struct Square {
+    side: f64,
+}
+
+impl Shape for Square {
+    const SIDES: u32 = 5;
+
+    fn area(&self) -> f64 {
+        self.side * self.side
+    }
+
+    fn perimeter(&self) -> f64 {
+        4.0 * self.side
+    }
+
+    fn describe(&self) -> String {
+        format!("square with {} sides", Self::SIDES)
+    }
+}
+
+fn main() {}
@@ -0,0 +1,24 @@
+trait Shape {
+    const SIDES: u32;
+
+    fn area(&self) -> f64;
+    fn describe(&self) -> String;
+}
+
+struct Square {
+    side: f64,
+}
+
+impl Shape for Square {
+    const SIDES: u32 = 4;
+
+    fn area(&self) -> f64 {
+        unimplemented!()
+    }
+
+    fn describe(&self) -> String {
+        format!("square with {} sides", Self::SIDES)
+    }
+}
+
+fn main() {}
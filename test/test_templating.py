@@ -8,7 +8,7 @@
 import pytest
 from pathlib import Path
 
-from ideas.ast_rust import validate_changes
+from ideas.ast_rust import get_root, validate_changes
 
 
 @pytest.fixture
@@ -40,3 +40,68 @@ def test_modified_invalid(template: str, modified_invalid: str):
     feedback = validate_changes(modified_invalid, template)
     assert feedback
     assert list(feedback.keys()) == ["top_level_changes", "signature_changes"]
+
+
+@pytest.fixture
+def trait_template(fixtures_dir: Path) -> str:
+    return (fixtures_dir / "trait_template.rs").read_text()
+
+
+@pytest.fixture
+def trait_modified_valid(fixtures_dir: Path) -> str:
+    return (fixtures_dir / "trait_modified_valid.rs").read_text()
+
+
+@pytest.fixture
+def trait_modified_invalid(fixtures_dir: Path) -> str:
+    return (fixtures_dir / "trait_modified_invalid.rs").read_text()
+
+
+def test_trait_modified_valid(trait_template: str, trait_modified_valid: str):
+    feedback = validate_changes(trait_modified_valid, trait_template)
+    assert not feedback
+
+
+def test_trait_modified_invalid(trait_template: str, trait_modified_invalid: str):
+    # The added trait method and the changed associated const are flagged separately
+    feedback = validate_changes(trait_modified_invalid, trait_template)
+    assert feedback
+    assert list(feedback.keys()) == ["member_changes", "top_level_changes"]
+
+
+@pytest.fixture
+def macro_template(fixtures_dir: Path) -> str:
+    return (fixtures_dir / "macro_template.rs").read_text()
+
+
+@pytest.fixture
+def macro_modified_valid(fixtures_dir: Path) -> str:
+    return (fixtures_dir / "macro_modified_valid.rs").read_text()
+
+
+@pytest.fixture
+def macro_modified_invalid(fixtures_dir: Path) -> str:
+    return (fixtures_dir / "macro_modified_invalid.rs").read_text()
+
+
+def test_macro_modified_valid(macro_template: str, macro_modified_valid: str):
+    feedback = validate_changes(macro_modified_valid, macro_template)
+    assert not feedback
+
+
+def test_macro_modified_invalid(macro_template: str, macro_modified_invalid: str):
+    # Only the println! inside the non-target function changed
+    feedback = validate_changes(macro_modified_invalid, macro_template)
+    assert feedback
+    assert list(feedback.keys()) == ["top_level_changes"]
+
+
+# every fixture, including the *_invalid ones, is syntactically valid Rust; a parse error here
+# means a grammar update changed what the validator sees, not that the fixture is wrong
+@pytest.mark.parametrize(
+    "fixture",
+    sorted((Path(__file__).parent / "fixtures" / "templating").glob("*.rs")),
+    ids=lambda path: path.name,
+)
+def test_fixture_parses_cleanly(fixture: Path):
+    assert not get_root(fixture.read_text()).has_error
@@ -4,6 +4,7 @@
 # SPDX-License-Identifier: Apache-2.0
 #
 
+import re
 from contextlib import nullcontext as does_not_raise
 from pathlib import Path
 
@@ -139,6 +140,36 @@ def test_check_rust(rust_paths: tuple[Path, Path]):
     assert out2 != ""
 
 
+def expected_diagnostics(code: str) -> set[tuple[int, str]]:
+    # Lines annotated with `//~ ERROR <code>` must trigger exactly that error code
+    expected = set()
+    for lineno, line in enumerate(code.splitlines(), start=1):
+        if match := re.search(r"//~ ERROR (E\d{4})", line):
+            expected.add((lineno, match.group(1)))
+    return expected
+
+
+@pytest.mark.parametrize(
+    "fixture_name",
+    ["type_errors.rs", "borrow_errors.rs", "lifetime_errors.rs", "resolution_errors.rs"],
+)
+def test_check_rust_error_categories(fixtures_dir: Path, fixture_name: str):
+    code = (fixtures_dir / fixture_name).read_text()
+    expected = expected_diagnostics(code)
+    assert expected
+
+    success, out = tools.check_rust(code, structured_output=True)
+    assert not success
+
+    actual = set()
+    for msg in tools.tool_output_to_js_dict(out):
+        if msg.get("level") != "error" or not msg.get("code"):
+            continue
+        primary = next(span for span in msg["spans"] if span["is_primary"])
+        actual.add((primary["line_start"], msg["code"]["code"]))
+    assert actual == expected
+
+
 @pytest.fixture
 def echo_123(fixtures_dir: Path) -> Path:
     return fixtures_dir / "echo_123"
@@ -0,0 +1,500 @@
+//! [`Crateify`], crateify as a library: a run over one translation directory, set up with builder
+//! methods where the command line has flags, that hands back a [`Report`] of what it did instead
+//! of printing one.
+//!
+//! The command line (see [`crate::cli`]) fills in the same options from its flags, so that both
+//! generate the same files from the same tree.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
+use std::sync::{Mutex, PoisonError};
+
+use crate::error::CrateifyError;
+use crate::events::{Event, Events};
+use crate::file_attrs::FileAttrs;
+use crate::graph::GraphFormat;
+use crate::ident::Shadowing;
+use crate::ignore::{Excludes, IgnoreFiles};
+use crate::inner_attrs::InnerAttrs;
+use crate::module_tree::Directory;
+use crate::overlay::Overlay;
+use crate::platform::CfgRules;
+use crate::prelude::Prelude;
+use crate::progress::{self, Progress};
+use crate::provenance::Provenance;
+use crate::prune::PruneEmpty;
+use crate::reexport::Reexport;
+use crate::test_modules::TestModules;
+use crate::visibility::{Visibilities, Visibility};
+use crate::write::Output;
+use crate::{Context, FailurePolicy, Root};
+
+/// The options of a run, with the command line's defaults
+pub struct Crateify {
+    pub(crate) dirs: Vec<PathBuf>,
+    pub(crate) events: Events,
+    pub(crate) progress: progress::Sink,
+    pub(crate) native_paths: bool,
+    pub(crate) quiet: bool,
+    pub(crate) policy: FailurePolicy,
+    pub(crate) skip_invalid_names: bool,
+    pub(crate) merge_collisions: bool,
+    pub(crate) allow_case_collisions: bool,
+    pub(crate) snake_case: bool,
+    pub(crate) shadowing: Shadowing,
+    pub(crate) rename_reserved: bool,
+    pub(crate) crate_name: Option<String>,
+    pub(crate) keep_empty: bool,
+    pub(crate) prune_empty: PruneEmpty,
+    pub(crate) clean: bool,
+    pub(crate) force: bool,
+    /// `None` picks lib.rs for a directory named `src` (and under `--workspace`), mod.rs otherwise
+    pub(crate) lib_root: Option<bool>,
+    pub(crate) file_modules: bool,
+    pub(crate) flatten: bool,
+    pub(crate) header: Option<String>,
+    pub(crate) inner_attrs: InnerAttrs,
+    pub(crate) file_attrs: FileAttrs,
+    pub(crate) no_header: bool,
+    pub(crate) fmt: bool,
+    pub(crate) strict_version: bool,
+    pub(crate) visibility: Visibility,
+    pub(crate) visibility_file: Option<PathBuf>,
+    pub(crate) reexport: Reexport,
+    pub(crate) prelude: Option<Vec<String>>,
+    pub(crate) cfgs: CfgRules,
+    pub(crate) provenance: Option<PathBuf>,
+    pub(crate) test_detection: bool,
+    pub(crate) test_patterns: Vec<String>,
+    pub(crate) workspace: bool,
+    pub(crate) binaries: bool,
+    pub(crate) hybrid: bool,
+    pub(crate) output: Output,
+    pub(crate) output_dir: Option<PathBuf>,
+    pub(crate) relative_paths: bool,
+    pub(crate) default_ignores: bool,
+    pub(crate) jobs: Option<usize>,
+    pub(crate) max_depth: Option<usize>,
+    pub(crate) follow_symlinks: bool,
+    pub(crate) manifest: Option<PathBuf>,
+    pub(crate) graph: Option<PathBuf>,
+    pub(crate) graph_format: GraphFormat,
+    pub(crate) excludes: Vec<String>,
+    /// Collect a [`Report`] rather than report on stderr
+    pub(crate) report: bool,
+}
+
+/// What a library run did
+#[derive(Debug)]
+pub struct Report {
+    /// Every directory that got a module file (or would have, in a dry run), sorted by path, the
+    /// translation directory first; as in the `--manifest` file
+    pub directories: Vec<Directory>,
+    /// The content of each module file by its path, whether or not the file had to change
+    pub generated: BTreeMap<PathBuf, String>,
+    /// What `--events` would have written, in order, ending with the summary
+    pub events: Vec<Event>,
+}
+
+impl Report {
+    /// The number of directories that failed, each reported by an error event; other errors,
+    /// such as symlink cycles, have their event but fail no directory
+    pub fn failed(&self) -> usize {
+        self.events
+            .iter()
+            .find_map(|e| match e {
+                Event::Summary { failed, .. } => Some(*failed),
+                _ => None,
+            })
+            .unwrap_or_default()
+    }
+}
+
+impl Crateify {
+    /// A run over the translation directory `root`, which writes its files like `crateify <root>`
+    /// but reports nothing on stderr
+    pub fn new(root: impl Into<PathBuf>) -> Crateify {
+        Crateify {
+            dirs: vec![root.into()],
+            quiet: true,
+            events: Events::collect(),
+            report: true,
+            ..Crateify::defaults()
+        }
+    }
+
+    /// The options of a command line without any flags or directories
+    pub(crate) fn defaults() -> Crateify {
+        Crateify {
+            dirs: Vec::new(),
+            events: Events::disabled(),
+            progress: progress::Sink::Off,
+            native_paths: false,
+            quiet: false,
+            policy: FailurePolicy::KeepGoing,
+            skip_invalid_names: false,
+            merge_collisions: false,
+            allow_case_collisions: false,
+            snake_case: false,
+            shadowing: Shadowing::Warn,
+            rename_reserved: false,
+            crate_name: None,
+            keep_empty: false,
+            prune_empty: PruneEmpty::Keep,
+            clean: false,
+            force: false,
+            lib_root: None,
+            file_modules: false,
+            flatten: false,
+            header: None,
+            inner_attrs: InnerAttrs::default(),
+            file_attrs: FileAttrs::default(),
+            no_header: false,
+            fmt: false,
+            strict_version: false,
+            visibility: Visibility::Pub,
+            visibility_file: None,
+            reexport: Reexport::None,
+            prelude: None,
+            cfgs: CfgRules::default(),
+            provenance: None,
+            test_detection: true,
+            test_patterns: Vec::new(),
+            workspace: false,
+            binaries: false,
+            hybrid: false,
+            output: Output::Disk,
+            output_dir: None,
+            relative_paths: false,
+            default_ignores: true,
+            jobs: None,
+            max_depth: None,
+            follow_symlinks: false,
+            manifest: None,
+            graph: None,
+            graph_format: GraphFormat::Dot,
+            excludes: Vec::new(),
+            report: false,
+        }
+    }
+
+    /// `--exclude`: leaves out the entries matching `glob`; can be given several times
+    pub fn exclude(mut self, glob: impl Into<String>) -> Crateify {
+        self.excludes.push(glob.into());
+        self
+    }
+
+    /// `--visibility`: what goes in front of each `mod` declaration
+    pub fn visibility(mut self, visibility: Visibility) -> Crateify {
+        self.visibility = visibility;
+        self
+    }
+
+    /// `--reexport`: the `use` lines after each declaration
+    pub fn reexport(mut self, reexport: Reexport) -> Crateify {
+        self.reexport = reexport;
+        self
+    }
+
+    /// `--prelude`: a `prelude.rs` next to the root module re-exporting what `selectors` name,
+    /// `types::*` or `errors::ErrorCode`
+    pub fn prelude<S: Into<String>>(mut self, selectors: impl IntoIterator<Item = S>) -> Crateify {
+        self.prelude = Some(selectors.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// `--provenance`: a doc comment on each declaration naming the C source of the module, from
+    /// the translator's map at `path`
+    pub fn provenance(mut self, path: impl Into<PathBuf>) -> Crateify {
+        self.provenance = Some(path.into());
+        self
+    }
+
+    /// Generates every module file without writing any; [`Report::generated`] still has them all
+    pub fn dry_run(mut self, dry_run: bool) -> Crateify {
+        self.output = if dry_run {
+            Output::Nowhere
+        } else {
+            Output::Disk
+        };
+        self
+    }
+
+    /// `--output`: writes the generated files to `dir`, which mirrors the one crate directory,
+    /// and leaves the crate directory untouched
+    pub fn output_dir(mut self, dir: impl Into<PathBuf>) -> Crateify {
+        self.output_dir = Some(dir.into());
+        self
+    }
+
+    /// `--relative-paths`: under [`Crateify::output_dir`], points the `#[path]` of each file module
+    /// at it with a relative path instead of an absolute one
+    pub fn relative_paths(mut self, relative_paths: bool) -> Crateify {
+        self.relative_paths = relative_paths;
+        self
+    }
+
+    /// `--root lib` or `--root mod`: whether the root module is lib.rs rather than mod.rs
+    pub fn lib_root(mut self, lib_root: bool) -> Crateify {
+        self.lib_root = Some(lib_root);
+        self
+    }
+
+    /// `--flatten`: declares every directory inline in the root module
+    pub fn flatten(mut self, flatten: bool) -> Crateify {
+        self.flatten = flatten;
+        self
+    }
+
+    /// `--fmt`: formats each module file with rustfmt, under the crate's `rustfmt.toml`
+    pub fn fmt(mut self, fmt: bool) -> Crateify {
+        self.fmt = fmt;
+        self
+    }
+
+    /// `--keep-empty`: writes a module file in directories without modules too
+    pub fn keep_empty(mut self, keep_empty: bool) -> Crateify {
+        self.keep_empty = keep_empty;
+        self
+    }
+
+    /// `--prune-empty` or `--prune-empty=dry`: what to do after the traversal with the
+    /// directories that contribute no modules
+    pub fn prune_empty(mut self, prune_empty: PruneEmpty) -> Crateify {
+        self.prune_empty = prune_empty;
+        self
+    }
+
+    /// `--clean`: deletes generated module files left without modules
+    pub fn clean(mut self, clean: bool) -> Crateify {
+        self.clean = clean;
+        self
+    }
+
+    /// `--force`: overwrites module files crateify didn't generate
+    pub fn force(mut self, force: bool) -> Crateify {
+        self.force = force;
+        self
+    }
+
+    /// `--merge-collisions`: renames file modules that share their name with a directory module
+    pub fn merge_collisions(mut self, merge_collisions: bool) -> Crateify {
+        self.merge_collisions = merge_collisions;
+        self
+    }
+
+    /// `--snake-case`: declares modules whose names aren't snake_case under a snake_case name
+    pub fn snake_case(mut self, snake_case: bool) -> Crateify {
+        self.snake_case = snake_case;
+        self
+    }
+
+    /// `--deny-shadowing` or `--rename-shadowing`: what to do with modules named like `core`,
+    /// `std`, `alloc`, `proc_macro` or the [`Crateify::crate_name`]
+    pub fn shadowing(mut self, shadowing: Shadowing) -> Crateify {
+        self.shadowing = shadowing;
+        self
+    }
+
+    /// `--rename-reserved`: declares modules whose entries Windows reserves for devices, `con.rs`
+    /// or `aux/`, with a trailing underscore instead of failing their directory
+    pub fn rename_reserved(mut self, rename_reserved: bool) -> Crateify {
+        self.rename_reserved = rename_reserved;
+        self
+    }
+
+    /// `--crate-name`: the name of the package, which modules shadow like a standard crate
+    pub fn crate_name(mut self, name: impl Into<String>) -> Crateify {
+        self.crate_name = Some(name.into());
+        self
+    }
+
+    /// `--skip-invalid-names`: leaves out entries whose names aren't UTF-8
+    pub fn skip_invalid_names(mut self, skip_invalid_names: bool) -> Crateify {
+        self.skip_invalid_names = skip_invalid_names;
+        self
+    }
+
+    /// `--follow-symlinks`: descends into symlinked directories
+    pub fn follow_symlinks(mut self, follow_symlinks: bool) -> Crateify {
+        self.follow_symlinks = follow_symlinks;
+        self
+    }
+
+    /// `--header`: crate documentation at the top of the root module
+    pub fn header(mut self, text: impl Into<String>) -> Crateify {
+        self.header = Some(text.into());
+        self
+    }
+
+    /// `--max-depth`: how many levels below the translation directory to go at most
+    pub fn max_depth(mut self, levels: usize) -> Crateify {
+        self.max_depth = Some(levels);
+        self
+    }
+
+    /// `--jobs`: how many threads to process directories on, at least one
+    pub fn jobs(mut self, threads: usize) -> Crateify {
+        self.jobs = Some(threads.max(1));
+        self
+    }
+
+    /// `--fail-fast`: stops visiting directories after the first failure
+    pub fn fail_fast(mut self, fail_fast: bool) -> Crateify {
+        self.policy = if fail_fast {
+            FailurePolicy::FailFast
+        } else {
+            FailurePolicy::KeepGoing
+        };
+        self
+    }
+
+    /// Calls `callback` with the [`Progress`] of the run each time it enters a directory, from
+    /// whichever thread that happens on; the command line draws a bar from the same updates
+    pub fn on_progress(mut self, callback: impl FnMut(&Progress) + Send + 'static) -> Crateify {
+        self.progress = progress::Sink::Callback(Box::new(callback));
+        self
+    }
+
+    /// Generates the module files of the whole tree. Directories that fail are reported in the
+    /// [`Report`] like the rest; only options that don't hold up fail the run itself.
+    pub fn run(self) -> Result<Report, CrateifyError> {
+        let mut ctx = self.context()?;
+        crate::run_pass(&mut ctx, |_| Ok(()))?;
+
+        let roots = mem::take(
+            ctx.manifest_dirs
+                .get_mut()
+                .unwrap_or_else(PoisonError::into_inner),
+        );
+        let mut directories: Vec<Directory> = roots.into_values().flatten().collect();
+        directories.sort_by(|a, b| a.path.cmp(&b.path));
+        let generated = mem::take(
+            ctx.generated
+                .get_mut()
+                .unwrap_or_else(PoisonError::into_inner),
+        );
+        let events = ctx
+            .events
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take_collected();
+        Ok(Report {
+            directories,
+            generated,
+            events,
+        })
+    }
+
+    /// The state of a run with these options, ready for its first pass
+    pub(crate) fn context(self) -> Result<Context, CrateifyError> {
+        let mut tests = TestModules::new(self.test_detection);
+        if self.test_detection {
+            for pattern in &self.test_patterns {
+                tests.add(pattern)?;
+            }
+        }
+        let mut visibilities = Visibilities::new(self.visibility);
+        if let Some(path) = &self.visibility_file {
+            visibilities.read_overrides(path)?;
+        }
+        let overlay = match (&self.output_dir, &self.dirs[..]) {
+            (None, _) => None,
+            (Some(dir), [source]) => Some(Overlay::new(source, dir, self.relative_paths)?),
+            (Some(_), _) => {
+                return Err(CrateifyError::Usage(
+                    "--output mirrors a single crate directory".to_string(),
+                ));
+            }
+        };
+        let roots = self
+            .dirs
+            .iter()
+            .map(|dir| Root {
+                dir: dir.clone(),
+                crates: vec![dir.clone()],
+                // a directory named src is where cargo looks for lib.rs, also when it's named `.`
+                lib_root: self.lib_root.unwrap_or_else(|| {
+                    self.workspace
+                        || fs::canonicalize(dir)
+                            .unwrap_or_else(|_| dir.clone())
+                            .file_name()
+                            .is_some_and(|n| n == "src")
+                }),
+                affected: None,
+            })
+            .collect();
+        Ok(Context {
+            roots,
+            workspace: self.workspace,
+            binaries: self.binaries,
+            hybrid: self.hybrid,
+            c_files: Mutex::default(),
+            bin_dirs: Mutex::default(),
+            events: Mutex::new(self.events),
+            progress: progress::Tracker::new(self.progress),
+            native_paths: self.native_paths,
+            quiet: self.quiet,
+            report: self.report,
+            generated: Mutex::default(),
+            default_ignores: self.default_ignores,
+            excludes: Excludes::new(self.excludes)?,
+            ignore_files: IgnoreFiles::default(),
+            skip_invalid_names: self.skip_invalid_names,
+            file_modules: self.file_modules,
+            flatten: self.flatten,
+            inline_modules: Mutex::default(),
+            merge_collisions: self.merge_collisions,
+            allow_case_collisions: self.allow_case_collisions,
+            snake_case: self.snake_case,
+            shadowing: self.shadowing,
+            rename_reserved: self.rename_reserved,
+            crate_name: self.crate_name,
+            keep_empty: self.keep_empty,
+            prune_empty: self.prune_empty,
+            clean: self.clean,
+            force: self.force,
+            header: self.header,
+            inner_attrs: self.inner_attrs,
+            file_attrs: self.file_attrs,
+            no_header: self.no_header,
+            fmt: self.fmt,
+            rustfmt_missing: AtomicBool::new(false),
+            strict_version: self.strict_version,
+            visibilities,
+            reexport: self.reexport,
+            prelude: self.prelude.map(Prelude::new).transpose()?,
+            cfgs: self.cfgs,
+            provenance: self
+                .provenance
+                .as_deref()
+                .map(Provenance::read)
+                .transpose()?,
+            tests,
+            output: self.output,
+            overlay,
+            policy: self.policy,
+            // one directory at a time keeps where --fail-fast stops deterministic
+            workers: rayon::ThreadPoolBuilder::new()
+                .num_threads(self.jobs.unwrap_or(match self.policy {
+                    FailurePolicy::KeepGoing => 0,
+                    FailurePolicy::FailFast => 1,
+                }))
+                .build()
+                .map_err(|e| CrateifyError::io(Path::new("<threads>"), io::Error::other(e)))?,
+            follow_symlinks: self.follow_symlinks,
+            max_depth: self.max_depth,
+            visited: Mutex::default(),
+            manifest: self.manifest,
+            graph: self.graph.map(|path| (path, self.graph_format)),
+            module_graph: Mutex::default(),
+            manifest_dirs: Mutex::default(),
+            stopped: AtomicBool::new(false),
+            counts: Mutex::default(),
+        })
+    }
+}
@@ -0,0 +1,1066 @@
+//! The `crateify` command: its flags become the options of a [`Crateify`] run, plus what only
+//! the command line does: `--since`, `--files-from`, `--watch`, `--cargo-init`, `--workspace`,
+//! `--hybrid`, `--verify` and `--validate-crate`.
+
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use crate::diagnostic::{self, Code, ErrorFormat};
+use crate::error::{CrateifyError, USAGE};
+use crate::events::Events;
+use crate::git::{self, DiffMode};
+use crate::graph::{self, GraphFormat};
+use crate::ident::Shadowing;
+use crate::log::{debug, info};
+use crate::prelude::Prelude;
+use crate::progress;
+use crate::prune::PruneEmpty;
+use crate::reexport::Reexport;
+use crate::visibility::Visibility;
+use crate::write::{Change, Output};
+use crate::{
+    Context, Crateify, FailurePolicy, Root, affected_dirs, config, events, ident, ignore, log,
+    manifest, module_tree, run_pass, validate, verify, watch,
+};
+
+/// Prints the JSON Schema of one of crateify's machine-readable outputs to stdout
+fn print_schema(name: &str) -> Result<(), CrateifyError> {
+    let schema = match name {
+        events::EVENTS_SCHEMA => events::json_schema(),
+        module_tree::MANIFEST_SCHEMA => module_tree::json_schema(),
+        diagnostic::DIAGNOSTICS_SCHEMA => diagnostic::json_schema(),
+        graph::GRAPH_SCHEMA => graph::json_schema(),
+        _ => {
+            return Err(CrateifyError::Usage(format!(
+                "unknown schema {name}, expected one of: {}, {}, {}, {}",
+                events::EVENTS_SCHEMA,
+                module_tree::MANIFEST_SCHEMA,
+                diagnostic::DIAGNOSTICS_SCHEMA,
+                graph::GRAPH_SCHEMA
+            )));
+        }
+    };
+    println!("{schema:#}");
+    Ok(())
+}
+
+/// The members of `--workspace`: every immediate subdirectory of `root` that the traversal
+/// wouldn't skip, by name, with its crate directory: its `src/` if it has one, or itself
+fn workspace_members(root: &Path, ctx: &Context) -> Result<Vec<(String, PathBuf)>, CrateifyError> {
+    let mut entries = fs::read_dir(root)
+        .and_then(|entries| {
+            entries
+                .map(|e| e.map(|e| e.path()))
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .map_err(|e| CrateifyError::read_dir(root, e))?;
+    entries.sort();
+    let mut members = Vec::new();
+    for path in entries {
+        if !path.is_dir() {
+            if path.extension().is_some_and(|e| e == "rs") {
+                info!("{}: not in any workspace member, skipped", path.display());
+            }
+            continue;
+        }
+        if let Some(pattern) = ctx.excludes.matching(&ctx.relative_path(&path), true) {
+            debug!("{}: excluded by {pattern}", path.display());
+            ctx.count(|c| *c.excluded.entry(pattern.to_string()).or_default() += 1);
+            continue;
+        }
+        if let Some(ignored) = ctx.ignored(&path, true)? {
+            debug!(
+                "{}: ignored by {} in {}, not a workspace member",
+                path.display(),
+                ignored.pattern,
+                ignored.file.display()
+            );
+            continue;
+        }
+        if (ctx.default_ignores && ignore::is_ignored_dir(&path))
+            || (path.is_symlink() && !ctx.follow_symlinks)
+        {
+            debug!("{}: skipped, not a workspace member", path.display());
+            continue;
+        }
+        let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+            let message = format!(
+                "{}: name is not valid UTF-8, not a workspace member",
+                ctx.display_path(&path)
+            );
+            ctx.warn(Code::InvalidModuleName, &path, message)?;
+            continue;
+        };
+        let src = path.join("src");
+        let crate_dir = if src.is_dir() { src } else { path.clone() };
+        members.push((name.to_string(), crate_dir));
+    }
+    Ok(members)
+}
+
+/// Writes the Cargo.toml at `manifest` with `write`, which gets the path to show, unless one is
+/// already there and `--force` wasn't given; `shown` is where events report it
+fn init_manifest(
+    ctx: &Context,
+    manifest: &Path,
+    shown: &Path,
+    write: impl FnOnce(&str) -> Result<Change, CrateifyError>,
+) -> Result<(), CrateifyError> {
+    if manifest.exists() && !ctx.force {
+        return ctx.warn(
+            Code::ExistingFile,
+            shown,
+            format!(
+                "{}: already exists, left untouched (--force overwrites it)",
+                manifest.display()
+            ),
+        );
+    }
+    let change = write(&ctx.display_path(shown))?;
+    if ctx.output.is_stale(change) {
+        ctx.count(|c| c.stale += 1);
+    }
+    Ok(())
+}
+
+/// `--hybrid`: writes the build.rs next to the Cargo.toml at `manifest` that compiles the C files
+/// found below `crate_dir`, like [`init_manifest`]
+fn init_build_script(
+    ctx: &Context,
+    manifest: &Path,
+    shown: &Path,
+    crate_dir: &Path,
+) -> Result<(), CrateifyError> {
+    let build_rs = manifest.with_file_name("build.rs");
+    let c_files = ctx.c_files(crate_dir);
+    init_manifest(ctx, &build_rs, &shown.with_file_name("build.rs"), |shown| {
+        manifest::write_build_script(ctx.output, &build_rs, shown, crate_dir, &c_files)
+    })
+}
+
+/// Prints the usage line and the layout of the machine-readable outputs to stdout
+fn print_help() {
+    println!(
+        "{USAGE}
+
+Generates the mod.rs files that turn a directory of Rust sources into a crate; see README.md
+for every option.
+
+--config <path> reads options from that TOML file rather than from crateify.toml in the current
+directory, if there is one. Its keys are the long options without their leading --, its values
+true for a switch, a string or integer for an option's value, and an array of strings for an
+option that can be repeated; an option given on the command line replaces the file's.
+
+--max-depth <n> stops descending n levels below the crate directory. Without it, a tree of any
+depth is walked, paths longer than the platform opens (PATH_MAX, 4096 bytes on Linux and 1024
+on macOS) included: their files are opened relative to a handle on a directory above them.
+
+--validate-crate <package_dir> --manifest <path.json> checks the package against the manifest
+of an earlier run: that its targets build the crate roots and binaries, that every declared
+module has its file and every .rs file of the tree is declared. --cargo-check also reports the
+declared modules cargo check never compiled and the undeclared files it did.
+
+--error-format json writes each warning and error on stderr as one JSON object per line with
+\"schema\": \"{diagnostics}\", \"version\": {diagnostics_version}, \"level\", \"code\", \"path\" and
+\"message\", and the summary on stdout as a summary event of --events.
+
+--events <path|-> writes newline-delimited JSON events, one object per line with \"schema\":
+\"{events}\" and \"version\": {events_version}.
+
+--manifest <path.json> writes the module tree of the last pass as one JSON object:
+  schema        \"{manifest}\"
+  version       {manifest_version}, bumped whenever a field changes
+  roots         each crate directory as given, mapped to every directory in it whose module
+                file was generated, sorted by path, but for those of binaries:
+    path          relative to the crate directory, \"\" for the crate directory itself
+    module_path   \"crate\", \"crate::net\", ...
+    module_file   \"mod.rs\", \"net/mod.rs\", ...
+    status        \"created\", \"updated\" or \"unchanged\" (what would happen under --dry-run
+                  and --check)
+    modules       the declared modules, each {{\"name\": <identifier>, \"source\": <file>}}
+                  and \"renamed_from\": <name> when --rename-shadowing or --rename-reserved
+                  renamed it, plus \"portable_name\": <entry name> to rename a reserved entry
+                  to on disk, and under --output \"physical_source\": <absolute path> where
+                  rustc reads it from
+  binaries      each crate directory as given, mapped to the directories in it that became
+                [[bin]] targets under --cargo-init and --workspace, sorted by path:
+    path          relative to the crate directory
+    name          the name of the target
+    main          its main.rs
+    module_file   the module file generated next to main.rs for it to declare, if any
+  c_sources     each crate directory as given, mapped to the .c files in it that the build.rs
+                of --hybrid compiles, sorted
+
+--graph <path> --graph-format json writes which modules use which as one JSON object with
+\"schema\": \"{graph}\", \"version\": {graph_version}, \"modules\", \"edges\" and \"warnings\".
+
+crateify --print-schema <{events}|{manifest}|{diagnostics}|{graph}> prints the JSON Schema of
+each.",
+        diagnostics = diagnostic::DIAGNOSTICS_SCHEMA,
+        diagnostics_version = diagnostic::DIAGNOSTICS_VERSION,
+        events = events::EVENTS_SCHEMA,
+        events_version = events::EVENTS_VERSION,
+        manifest = module_tree::MANIFEST_SCHEMA,
+        manifest_version = module_tree::MANIFEST_VERSION,
+        graph = graph::GRAPH_SCHEMA,
+        graph_version = graph::GRAPH_VERSION,
+    );
+}
+
+/// Prints the error that ended a run on stderr, in the format of `--error-format`
+pub fn print_error(err: &CrateifyError) {
+    diagnostic::print_error("", err);
+}
+
+/// Takes the value following `flag` off the argument list
+fn flag_value(
+    args: &mut impl Iterator<Item = String>,
+    flag: &str,
+    expected: &str,
+) -> Result<String, CrateifyError> {
+    args.next()
+        .ok_or_else(|| CrateifyError::Usage(format!("{flag} expects {expected}")))
+}
+
+/// Runs the `crateify` command with the arguments after the program name
+pub fn run(args: impl IntoIterator<Item = String>) -> Result<(), CrateifyError> {
+    log::init_from_env();
+
+    let mut args = config::with_config(args.into_iter().collect())?.into_iter();
+    let mut options = Crateify::defaults();
+    let mut translation_dirs = Vec::new();
+    let mut verbosity = 0;
+    let mut since = None;
+    let mut files_from = None;
+    let mut graph_format = false;
+    let mut diff_mode = DiffMode::Worktree;
+    let mut watch = false;
+    let mut poll = false;
+    let mut poll_interval = Duration::from_millis(500);
+    let mut cargo_init = false;
+    let mut crate_name = None;
+    let mut edition = None;
+    let mut verify = false;
+    let mut validate_crate = None;
+    let mut cargo_check = false;
+    let mut file_attr = false;
+    let mut remove_file_attrs = false;
+    let mut error_format = ErrorFormat::Human;
+    let mut events_to_stdout = false;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--events" => {
+                let target = flag_value(&mut args, "--events", "a file path or - for stdout")?;
+                events_to_stdout = target == "-";
+                options.events = Events::open(&target)?;
+            }
+            "--help" | "-h" => {
+                print_help();
+                return Ok(());
+            }
+            "--error-format" => {
+                let format = flag_value(&mut args, "--error-format", "human or json")?;
+                error_format = match format.as_str() {
+                    "human" => ErrorFormat::Human,
+                    "json" => ErrorFormat::Json,
+                    other => {
+                        return Err(CrateifyError::Usage(format!(
+                            "--error-format {other}: expected human or json"
+                        )));
+                    }
+                };
+                // from here on, so that the usage errors of the flags after it are JSON as well
+                diagnostic::set_format(error_format);
+            }
+            "--native-paths" => options.native_paths = true,
+            "-v" | "--verbose" => verbosity += 1,
+            "-vv" => verbosity += 2,
+            "-q" | "--quiet" => options.quiet = true,
+            "--manifest" => {
+                options.manifest = Some(PathBuf::from(flag_value(
+                    &mut args,
+                    "--manifest",
+                    "a JSON file path",
+                )?));
+            }
+            "--output" => {
+                options.output_dir = Some(PathBuf::from(flag_value(
+                    &mut args,
+                    "--output",
+                    "a directory",
+                )?));
+            }
+            "--relative-paths" => options.relative_paths = true,
+            "--print-schema" => {
+                let name = flag_value(&mut args, "--print-schema", "a schema name")?;
+                return print_schema(&name);
+            }
+            "--since" => since = Some(flag_value(&mut args, "--since", "a git revision")?),
+            "--files-from" => {
+                files_from = Some(flag_value(
+                    &mut args,
+                    "--files-from",
+                    "a file path or - for stdin",
+                )?);
+            }
+            "--staged" => diff_mode = DiffMode::Staged,
+            "--worktree" => diff_mode = DiffMode::Worktree,
+            "--watch" => watch = true,
+            "--poll" => poll = true,
+            "--keep-going" => options.policy = FailurePolicy::KeepGoing,
+            "--fail-fast" => options.policy = FailurePolicy::FailFast,
+            "--skip-invalid-names" => options.skip_invalid_names = true,
+            "--merge-collisions" => options.merge_collisions = true,
+            "--allow-case-collisions" => options.allow_case_collisions = true,
+            "--snake-case" => options.snake_case = true,
+            "--rename-reserved" => options.rename_reserved = true,
+            "--deny-shadowing" | "--rename-shadowing" => {
+                let shadowing = if arg == "--deny-shadowing" {
+                    Shadowing::Deny
+                } else {
+                    Shadowing::Rename
+                };
+                if ![Shadowing::Warn, shadowing].contains(&options.shadowing) {
+                    return Err(CrateifyError::Usage(
+                        "--deny-shadowing and --rename-shadowing contradict each other".to_string(),
+                    ));
+                }
+                options.shadowing = shadowing;
+            }
+            "--keep-empty" => options.keep_empty = true,
+            "--prune-empty" => options.prune_empty = PruneEmpty::Remove,
+            "--prune-empty=dry" => options.prune_empty = PruneEmpty::Report,
+            "--clean" => options.clean = true,
+            "--no-default-ignores" => options.default_ignores = false,
+            "--follow-symlinks" => options.follow_symlinks = true,
+            "--exclude" => {
+                options
+                    .excludes
+                    .push(flag_value(&mut args, "--exclude", "a glob pattern")?)
+            }
+            "--force" => options.force = true,
+            "--dry-run" => options.output = Output::DryRun,
+            "--check" => options.output = Output::Check,
+            "--root" => {
+                options.lib_root = match flag_value(&mut args, "--root", "lib or mod")?.as_str() {
+                    "lib" => Some(true),
+                    "mod" => Some(false),
+                    other => {
+                        return Err(CrateifyError::Usage(format!(
+                            "--root expects lib or mod, got {other}"
+                        )));
+                    }
+                };
+            }
+            "--module-style" => {
+                options.file_modules =
+                    match flag_value(&mut args, "--module-style", "mod-rs or file")?.as_str() {
+                        "mod-rs" => false,
+                        "file" => true,
+                        other => {
+                            return Err(CrateifyError::Usage(format!(
+                                "--module-style expects mod-rs or file, got {other}"
+                            )));
+                        }
+                    };
+            }
+            "--flatten" => options.flatten = true,
+            "--header" => {
+                options.header = Some(flag_value(&mut args, "--header", "a comment text")?)
+            }
+            "--inner-attr" => {
+                options
+                    .inner_attrs
+                    .add(&flag_value(&mut args, "--inner-attr", "an attribute")?)?;
+            }
+            "--inner-attr-file" => {
+                options.inner_attrs.read_file(Path::new(&flag_value(
+                    &mut args,
+                    "--inner-attr-file",
+                    "a file path",
+                )?))?;
+            }
+            "--file-attr" => {
+                options
+                    .file_attrs
+                    .add(&flag_value(&mut args, "--file-attr", "<glob>=<attr>")?)?;
+                file_attr = true;
+            }
+            "--remove-file-attrs" => {
+                options.file_attrs.remove();
+                remove_file_attrs = true;
+            }
+            "--no-header" => options.no_header = true,
+            "--strict-version" => options.strict_version = true,
+            "--visibility" => {
+                let name = flag_value(&mut args, "--visibility", "pub, pub-crate or private")?;
+                options.visibility = Visibility::parse(&name).ok_or_else(|| {
+                    CrateifyError::Usage(format!(
+                        "--visibility expects one of {}, got {name}",
+                        Visibility::NAMES.join(", ")
+                    ))
+                })?;
+            }
+            "--graph" => {
+                options.graph = Some(PathBuf::from(flag_value(
+                    &mut args,
+                    "--graph",
+                    "a file path",
+                )?));
+            }
+            "--graph-format" => {
+                let format = flag_value(&mut args, "--graph-format", "dot or json")?;
+                options.graph_format = GraphFormat::parse(&format).ok_or_else(|| {
+                    CrateifyError::Usage(format!(
+                        "--graph-format expects one of {}, got {format}",
+                        GraphFormat::NAMES.join(", ")
+                    ))
+                })?;
+                graph_format = true;
+            }
+            "--reexport" => {
+                let mode = flag_value(&mut args, "--reexport", "none, glob or items")?;
+                options.reexport = Reexport::parse(&mode).ok_or_else(|| {
+                    CrateifyError::Usage(format!(
+                        "--reexport expects one of {}, got {mode}",
+                        Reexport::NAMES.join(", ")
+                    ))
+                })?;
+            }
+            "--prelude" => {
+                options.prelude = Some(Prelude::read_file(Path::new(&flag_value(
+                    &mut args,
+                    "--prelude",
+                    "a file path",
+                )?))?);
+            }
+            "--no-test-detection" => options.test_detection = false,
+            "--test-pattern" => {
+                options.test_patterns.push(flag_value(
+                    &mut args,
+                    "--test-pattern",
+                    "a glob pattern",
+                )?);
+            }
+            "--cfg-map" => {
+                options
+                    .cfgs
+                    .map_dir(&flag_value(&mut args, "--cfg-map", "<name>=<cfg>")?)?
+            }
+            "--cfg-suffix" => {
+                options.cfgs.add_suffix(&flag_value(
+                    &mut args,
+                    "--cfg-suffix",
+                    "<suffix>=<cfg>",
+                )?)?;
+            }
+            "--visibility-file" => {
+                options.visibility_file = Some(PathBuf::from(flag_value(
+                    &mut args,
+                    "--visibility-file",
+                    "a file path",
+                )?));
+            }
+            "--provenance" => {
+                options.provenance = Some(PathBuf::from(flag_value(
+                    &mut args,
+                    "--provenance",
+                    "a file path",
+                )?));
+            }
+            "--verify" => verify = true,
+            "--validate-crate" => {
+                validate_crate = Some(PathBuf::from(flag_value(
+                    &mut args,
+                    "--validate-crate",
+                    "a package directory",
+                )?));
+            }
+            "--cargo-check" => cargo_check = true,
+            "--cargo-init" => cargo_init = true,
+            "--hybrid" => options.hybrid = true,
+            "--fmt" => options.fmt = true,
+            "--workspace" => options.workspace = true,
+            "--crate-name" => {
+                crate_name = Some(flag_value(&mut args, "--crate-name", "a package name")?);
+            }
+            "--edition" => {
+                let year = flag_value(&mut args, "--edition", "an edition year")?;
+                if !manifest::EDITIONS.contains(&year.as_str()) {
+                    return Err(CrateifyError::Usage(format!(
+                        "--edition expects one of {}, got {year}",
+                        manifest::EDITIONS.join(", ")
+                    )));
+                }
+                edition = Some(year);
+            }
+            "--max-depth" => {
+                let n = flag_value(&mut args, "--max-depth", "a number of levels")?;
+                options.max_depth = match n.parse() {
+                    Ok(n) => Some(n),
+                    Err(_) => {
+                        return Err(CrateifyError::Usage(format!(
+                            "--max-depth expects a number of levels, got {n}"
+                        )));
+                    }
+                };
+            }
+            "--jobs" => {
+                let n = flag_value(&mut args, "--jobs", "a number of threads")?;
+                options.jobs = match n.parse() {
+                    Ok(n) if n > 0 => Some(n),
+                    _ => {
+                        return Err(CrateifyError::Usage(format!(
+                            "--jobs expects a positive number of threads, got {n}"
+                        )));
+                    }
+                };
+            }
+            "--poll-interval" => {
+                let ms = flag_value(&mut args, "--poll-interval", "a number of milliseconds")?;
+                poll_interval = match ms.parse() {
+                    Ok(ms) if ms > 0 => Duration::from_millis(ms),
+                    _ => {
+                        return Err(CrateifyError::Usage(format!(
+                            "--poll-interval expects a positive number of milliseconds, got {ms}"
+                        )));
+                    }
+                };
+            }
+            // everything after is a directory, even if it starts with `-`
+            "--" => translation_dirs.extend(args.by_ref().map(PathBuf::from)),
+            flag if flag.starts_with("--") => {
+                return Err(CrateifyError::Usage(format!("unknown option {flag}")));
+            }
+            flag if flag.len() > 1 && flag.starts_with('-') => {
+                return Err(CrateifyError::Usage(format!(
+                    "unknown option {flag} (a crate directory whose name starts with - goes after --)"
+                )));
+            }
+            _ => translation_dirs.push(PathBuf::from(arg)),
+        }
+    }
+
+    if options.keep_empty && options.prune_empty != PruneEmpty::Keep {
+        return Err(CrateifyError::Usage(
+            "--keep-empty and --prune-empty contradict each other".to_string(),
+        ));
+    }
+    if options.quiet && verbosity > 0 {
+        return Err(CrateifyError::Usage(
+            "--quiet and --verbose contradict each other".to_string(),
+        ));
+    }
+    if error_format == ErrorFormat::Json && verbosity > 0 {
+        return Err(CrateifyError::Usage(
+            "--error-format json keeps stderr to diagnostics, which log lines of --verbose \
+             would get mixed into"
+                .to_string(),
+        ));
+    }
+    if error_format == ErrorFormat::Json && events_to_stdout {
+        return Err(CrateifyError::Usage(
+            "--error-format json prints the summary on stdout, where --events - writes its own"
+                .to_string(),
+        ));
+    }
+    // a bar is redrawn in place, which only a terminal shows as such
+    if !options.quiet && error_format == ErrorFormat::Human && io::stderr().is_terminal() {
+        options.progress = progress::Sink::Bar { drawn: None };
+    }
+    if error_format == ErrorFormat::Json {
+        log::turn_off();
+    } else if options.quiet {
+        log::cap_at(log::Level::Error);
+    } else if verbosity > 0 {
+        log::raise_to(if verbosity == 1 {
+            log::Level::Info
+        } else {
+            log::Level::Debug
+        });
+    }
+    if cargo_check && validate_crate.is_none() {
+        return Err(CrateifyError::Usage(
+            "--cargo-check only applies together with --validate-crate".to_string(),
+        ));
+    }
+    if let Some(package_dir) = validate_crate {
+        if !translation_dirs.is_empty() {
+            return Err(CrateifyError::Usage(
+                "--validate-crate checks the crate directories of its --manifest, it takes none \
+                 of its own"
+                    .to_string(),
+            ));
+        }
+        let Some(manifest) = &options.manifest else {
+            return Err(CrateifyError::Usage(
+                "--validate-crate expects the --manifest <path.json> crateify wrote for the package"
+                    .to_string(),
+            ));
+        };
+        let excludes = ignore::Excludes::new(options.excludes.clone())?;
+        let notes = !options.quiet && error_format == ErrorFormat::Human;
+        return validate_package(
+            &package_dir,
+            manifest,
+            &excludes,
+            options.default_ignores,
+            cargo_check,
+            notes,
+        );
+    }
+    if translation_dirs.is_empty() {
+        return Err(CrateifyError::Usage(
+            "crateify expects at least one crate directory".to_string(),
+        ));
+    }
+    for (i, dir) in translation_dirs.iter().enumerate() {
+        // its files would be declared twice, once from each
+        if let Some(outer) = translation_dirs[..i]
+            .iter()
+            .chain(&translation_dirs[i + 1..])
+            .find(|other| dir.starts_with(other))
+        {
+            return Err(CrateifyError::Usage(if outer == dir {
+                format!("{} is given twice", dir.display())
+            } else {
+                format!("{} is inside {}, given too", dir.display(), outer.display())
+            }));
+        }
+    }
+    let several = translation_dirs.len() > 1;
+    if several && watch {
+        return Err(CrateifyError::Usage(
+            "--watch keeps a single crate directory up to date".to_string(),
+        ));
+    }
+    if several && files_from.is_some() {
+        return Err(CrateifyError::Usage(
+            "--files-from lists the files of a single crate directory".to_string(),
+        ));
+    }
+    if (several || options.workspace) && options.graph.is_some() {
+        return Err(CrateifyError::Usage(
+            "--graph draws the modules of a single crate".to_string(),
+        ));
+    }
+    if (several || options.workspace) && options.output_dir.is_some() {
+        return Err(CrateifyError::Usage(
+            "--output mirrors a single crate directory, not several or a --workspace".to_string(),
+        ));
+    }
+    if options.output_dir.is_some() && (options.clean || options.prune_empty != PruneEmpty::Keep) {
+        return Err(CrateifyError::Usage(
+            "--output leaves the crate directory untouched, so there is nothing for --clean or \
+             --prune-empty to delete there"
+                .to_string(),
+        ));
+    }
+    if options.output_dir.is_some() && options.output == Output::Check {
+        return Err(CrateifyError::Usage(
+            "--check compares the files in the crate directory, which --output doesn't write"
+                .to_string(),
+        ));
+    }
+    if (file_attr || remove_file_attrs) && options.output_dir.is_some() {
+        return Err(CrateifyError::Usage(
+            "--file-attr edits the translated files in the crate directory, which --output leaves \
+             untouched"
+                .to_string(),
+        ));
+    }
+    if file_attr && remove_file_attrs {
+        return Err(CrateifyError::Usage(
+            "--remove-file-attrs deletes what --file-attr adds, give only one".to_string(),
+        ));
+    }
+    if options.relative_paths && options.output_dir.is_none() {
+        return Err(CrateifyError::Usage(
+            "--relative-paths only applies together with --output".to_string(),
+        ));
+    }
+    if options.relative_paths && options.flatten {
+        return Err(CrateifyError::Usage(
+            "--relative-paths can't lead out of the inline modules of --flatten, which have no \
+             directory in the output to start from"
+                .to_string(),
+        ));
+    }
+    if graph_format && options.graph.is_none() {
+        return Err(CrateifyError::Usage(
+            "--graph-format only applies together with --graph".to_string(),
+        ));
+    }
+    if since.is_some() && files_from.is_some() {
+        return Err(CrateifyError::Usage(
+            "--since and --files-from both say what changed, give only one".to_string(),
+        ));
+    }
+    if several && crate_name.is_some() {
+        return Err(CrateifyError::Usage(
+            "--crate-name can't name the crates of several directories".to_string(),
+        ));
+    }
+    if watch && options.output != Output::Disk {
+        return Err(CrateifyError::Usage(
+            "--dry-run and --check write nothing, so there is nothing to keep up to date with \
+             --watch"
+                .to_string(),
+        ));
+    }
+    if verify && (watch || options.output != Output::Disk) {
+        return Err(CrateifyError::Usage(
+            "--verify checks the files a single run wrote, it can't be combined with --watch, \
+             --dry-run or --check"
+                .to_string(),
+        ));
+    }
+    if !cargo_init && !options.workspace && (crate_name.is_some() || edition.is_some()) {
+        return Err(CrateifyError::Usage(
+            "--crate-name and --edition only apply together with --cargo-init".to_string(),
+        ));
+    }
+    if options.hybrid && !cargo_init && !options.workspace {
+        return Err(CrateifyError::Usage(
+            "--hybrid writes a build.rs next to the Cargo.toml of --cargo-init or --workspace"
+                .to_string(),
+        ));
+    }
+    if options.flatten && options.file_modules {
+        return Err(CrateifyError::Usage(
+            "--flatten writes no module files below the crate directory, so there is no \
+             --module-style to choose"
+                .to_string(),
+        ));
+    }
+    if options.workspace && crate_name.is_some() {
+        return Err(CrateifyError::Usage(
+            "--workspace names each crate after its directory, --crate-name can't apply to all"
+                .to_string(),
+        ));
+    }
+
+    options.dirs = translation_dirs.clone();
+    options.binaries = cargo_init || options.workspace;
+    options.crate_name = crate_name.clone();
+    let mut ctx = options.context()?;
+    let edition = edition.as_deref().unwrap_or(manifest::DEFAULT_EDITION);
+    // the members of each root under --workspace
+    let mut workspaces = Vec::new();
+    for (i, translation_dir) in translation_dirs.iter().enumerate() {
+        if let Some(since) = &since {
+            match git::changed_paths(translation_dir, since, diff_mode) {
+                Ok(changed) => {
+                    ctx.roots[i].affected = Some(affected_dirs(translation_dir, &changed));
+                }
+                Err(reason) => ctx.warn(
+                    Code::GitDiff,
+                    translation_dir,
+                    format!("--since {since} ignored, processing every directory: {reason}"),
+                )?,
+            }
+        }
+        if let Some(source) = &files_from {
+            let files = listed_files(source, translation_dir)?;
+            ctx.roots[i].affected = Some(affected_dirs(translation_dir, &files));
+        }
+        if ctx.workspace {
+            let members = workspace_members(translation_dir, &ctx)?;
+            ctx.roots[i].crates = members
+                .iter()
+                .map(|(_, crate_dir)| crate_dir.clone())
+                .collect();
+            workspaces.push(members);
+        } else if cargo_init {
+            manifest::package_name(
+                &manifest::manifest_path(&ctx.mirror(translation_dir))?,
+                crate_name.as_deref(),
+            )?;
+        }
+    }
+    let translation_dir = &translation_dirs[0];
+    // taken before the first pass so that nothing created while it runs goes unnoticed
+    let mut poller = if watch {
+        let (poller, fallback) = watch::Poller::new(
+            translation_dir,
+            &ctx.module_file(translation_dir),
+            ctx.file_modules,
+            ctx.default_ignores,
+            ctx.follow_symlinks,
+            poll,
+            poll_interval,
+        )?;
+        if let Some(e) = fallback
+            && ctx.notes()
+        {
+            eprintln!(
+                "crateify: no file notifications for {} ({e}), polling every {}ms instead",
+                translation_dir.display(),
+                poll_interval.as_millis()
+            );
+        }
+        Some(poller)
+    } else {
+        None
+    };
+    // written once the first pass has found the binaries, and before its summary
+    let init_manifests = |ctx: &Context| -> Result<(), CrateifyError> {
+        for (i, translation_dir) in translation_dirs.iter().enumerate() {
+            if ctx.workspace {
+                let members = &workspaces[i];
+                for (member, crate_dir) in members {
+                    let manifest = translation_dir.join(member).join("Cargo.toml");
+                    let package = ident::package_name(member);
+                    init_manifest(ctx, &manifest, &manifest, |shown| {
+                        manifest::write_manifest(
+                            ctx.output,
+                            &manifest,
+                            shown,
+                            &ctx.module_file(crate_dir),
+                            crate_dir,
+                            &ctx.bin_targets(crate_dir),
+                            Some(&package),
+                            edition,
+                            ctx.hybrid,
+                        )
+                    })?;
+                    if ctx.hybrid {
+                        init_build_script(ctx, &manifest, &manifest, crate_dir)?;
+                    }
+                }
+                let names: Vec<String> = members.iter().map(|(name, _)| name.clone()).collect();
+                let manifest = translation_dir.join("Cargo.toml");
+                init_manifest(ctx, &manifest, &manifest, |shown| {
+                    manifest::write_workspace_manifest(ctx.output, &manifest, shown, &names)
+                })?;
+            } else if cargo_init {
+                let written = ctx.mirror(translation_dir);
+                // shown resolved, as it is outside of the crate directory that paths are relative to
+                let manifest = manifest::manifest_path(&written)?;
+                init_manifest(ctx, &manifest, &manifest, |shown| {
+                    manifest::write_manifest(
+                        ctx.output,
+                        &manifest,
+                        shown,
+                        &ctx.module_file(translation_dir),
+                        translation_dir,
+                        &ctx.bin_targets(translation_dir),
+                        crate_name.as_deref(),
+                        edition,
+                        ctx.hybrid,
+                    )
+                })?;
+                if ctx.hybrid {
+                    init_build_script(ctx, &manifest, &manifest, translation_dir)?;
+                }
+            }
+        }
+        Ok(())
+    };
+    let counts = run_pass(&mut ctx, init_manifests)?;
+    let Some(poller) = &mut poller else {
+        counts.into_result()?;
+        return if verify {
+            verify_crate(&ctx, edition)
+        } else {
+            Ok(())
+        };
+    };
+    if ctx.policy == FailurePolicy::FailFast {
+        counts.into_result()?;
+    }
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let handler_stop = Arc::clone(&stop);
+    if let Err(e) = ctrlc::set_handler(move || handler_stop.store(true, Ordering::Relaxed)) {
+        ctx.warn(
+            Code::SignalHandler,
+            translation_dir,
+            format!("cannot handle Ctrl-C, it will stop crateify without cleaning up: {e}"),
+        )?;
+    }
+    if ctx.notes() {
+        eprintln!(
+            "crateify: watching {} for changes, press Ctrl-C to stop",
+            translation_dir.display()
+        );
+    }
+    while let Some(changed) = poller.wait(&stop)? {
+        ctx.roots[0].affected = Some(affected_dirs(translation_dir, &changed));
+        let counts = run_pass(&mut ctx, |_| Ok(()))?;
+        if ctx.notes() {
+            eprintln!(
+                "crateify: {} path(s) changed, rewrote {} mod.rs file(s)",
+                changed.len(),
+                counts.created + counts.updated
+            );
+        }
+        // with --keep-going, failures are reported and the next change may well fix them
+        if ctx.policy == FailurePolicy::FailFast {
+            counts.into_result()?;
+        }
+    }
+    Ok(())
+}
+
+/// `--verify`: runs `cargo check` on the crate generated in each root and reports their module
+/// resolution errors with the lines they are about
+fn verify_crate(ctx: &Context, edition: &str) -> Result<(), CrateifyError> {
+    let mut unresolved = 0;
+    for Root { dir, .. } in &ctx.roots {
+        let report = if ctx.workspace {
+            verify::check_manifest(&dir.join("Cargo.toml"), true, dir)?
+        } else {
+            let root_file = ctx.module_file(dir);
+            match crate_manifest(&ctx.mirror(dir), &root_file)? {
+                Some(manifest) => verify::check_manifest(&manifest, false, dir)?,
+                None => verify::check_tree(&root_file, edition, dir)?,
+            }
+        };
+        let canonical = fs::canonicalize(dir).map_err(|e| CrateifyError::io(dir, e))?;
+        for d in &report.diagnostics {
+            let file = dir.join(d.file.strip_prefix(&canonical).unwrap_or(&d.file));
+            let shown = ctx.display_path(&file);
+            let message = format!("{shown}:{}: {} [{}]", d.line, d.message, d.code);
+            diagnostic::print(diagnostic::Level::Error, d.kind(), &shown, &message, None);
+            if diagnostic::format() == ErrorFormat::Human {
+                eprintln!("crateify:     {}", d.text);
+            }
+        }
+        if report.other_errors > 0 {
+            info!(
+                "{}: cargo check found {} other error(s), not in the module tree",
+                dir.display(),
+                report.other_errors
+            );
+        }
+        unresolved += report.diagnostics.len();
+    }
+    match unresolved {
+        0 => Ok(()),
+        n => Err(CrateifyError::Unresolved(n)),
+    }
+}
+
+/// `--validate-crate`: reports where the package in `package_dir` and the module tree of
+/// `manifest` disagree, followed by a note of what was checked if `notes`
+fn validate_package(
+    package_dir: &Path,
+    manifest: &Path,
+    excludes: &ignore::Excludes,
+    default_ignores: bool,
+    cargo_check: bool,
+    notes: bool,
+) -> Result<(), CrateifyError> {
+    let report = validate::validate(
+        package_dir,
+        manifest,
+        excludes,
+        default_ignores,
+        cargo_check,
+    )?;
+    for problem in &report.problems {
+        let shown = problem.path.display().to_string();
+        let message = format!("{shown}: {}", problem.message);
+        diagnostic::print(
+            diagnostic::Level::Error,
+            problem.code,
+            &shown,
+            &message,
+            None,
+        );
+    }
+    if let Some(e) = report.check_failed {
+        return Err(e);
+    }
+    if notes {
+        if report.without_cargo {
+            eprintln!(
+                "crateify: cargo not found, read the targets from {}{}",
+                package_dir.join("Cargo.toml").display(),
+                if cargo_check {
+                    " and skipped --cargo-check"
+                } else {
+                    ""
+                }
+            );
+        }
+        if let Some(compiled) = report.compiled {
+            eprintln!("crateify: cargo check compiled {compiled} file(s)");
+        }
+    }
+    match report.problems.len() {
+        0 => Ok(()),
+        n => Err(CrateifyError::Invalid(n)),
+    }
+}
+
+/// `--files-from`: the `.rs` files listed one per line in `source` (`-` for stdin), relative to
+/// `root` or absolute inside it. They need not exist any more, a deleted file changes its
+/// directory's mod.rs as much as a new one.
+fn listed_files(source: &str, root: &Path) -> Result<Vec<PathBuf>, CrateifyError> {
+    let list = if source == "-" {
+        io::read_to_string(io::stdin()).map_err(|e| CrateifyError::io(Path::new("<stdin>"), e))?
+    } else {
+        fs::read_to_string(source).map_err(|e| CrateifyError::io(Path::new(source), e))?
+    };
+    let mut files = Vec::new();
+    for (n, line) in list.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let invalid = |reason: &str| {
+            CrateifyError::Usage(format!("--files-from {source}:{}: {line} {reason}", n + 1))
+        };
+        let path = Path::new(line);
+        let relative = if path.is_absolute() {
+            path.strip_prefix(root)
+                .map_err(|_| invalid(&format!("is outside of {}", root.display())))?
+        } else {
+            path
+        };
+        if relative
+            .components()
+            .any(|c| !matches!(c, Component::Normal(_) | Component::CurDir))
+        {
+            return Err(invalid(&format!("is outside of {}", root.display())));
+        }
+        if relative.extension().is_none_or(|e| e != "rs") {
+            return Err(invalid("is not a .rs file"));
+        }
+        files.push(relative.to_path_buf());
+    }
+    Ok(files)
+}
+
+/// The Cargo.toml whose library is `root_file`: the one `--cargo-init` writes next to the crate
+/// directory `dir`, or one in `dir` itself
+fn crate_manifest(dir: &Path, root_file: &Path) -> Result<Option<PathBuf>, CrateifyError> {
+    let root_file = fs::canonicalize(root_file).map_err(|e| CrateifyError::io(root_file, e))?;
+    for manifest in [manifest::manifest_path(dir)?, dir.join("Cargo.toml")] {
+        let Ok(content) = fs::read_to_string(&manifest) else {
+            continue;
+        };
+        let package_dir = fs::canonicalize(manifest.parent().unwrap_or(Path::new("")))
+            .map_err(|e| CrateifyError::io(&manifest, e))?;
+        let Ok(lib) = root_file.strip_prefix(&package_dir) else {
+            continue;
+        };
+        let lib: Vec<_> = lib
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect();
+        let lib = lib.join("/");
+        // cargo's default, or a `[lib]` path like the one --cargo-init writes
+        if lib == "src/lib.rs" || content.contains(&format!("\"{lib}\"")) {
+            return Ok(Some(manifest));
+        }
+    }
+    Ok(None)
+}
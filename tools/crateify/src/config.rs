@@ -0,0 +1,206 @@
+//! `crateify.toml`: the options a project passes on every run, kept next to it instead of in each
+//! script that runs crateify.
+//!
+//! Each key is a long option without its leading `--`, and each value what follows the option on
+//! the command line: `true` for a switch (`false` leaves it out), a string or integer for an
+//! option that takes a value, and an array of strings for one that can be repeated.
+//!
+//! ```toml
+//! root = "lib"
+//! merge-collisions = true
+//! file-attr = ["ffi/**=allow(clippy::missing_safety_doc)"]
+//! ```
+//!
+//! The file is `crateify.toml` in the current directory, if there is one, or the one
+//! `--config <path>` names. Its options go before those of the command line, and an option given
+//! there replaces the file's, all of its values and its alternatives included: `--check` drops
+//! `dry-run = true`, `--exclude` every `exclude` of the file. The options are read from the
+//! first command line of [`USAGE`], so that every option crateify has is a key and nothing else is.
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use toml::{Table, Value};
+
+use crate::error::{CrateifyError, USAGE};
+
+/// Read from the current directory when `--config` doesn't name another file
+pub const FILE_NAME: &str = "crateify.toml";
+
+/// What an option takes, and so which values its key accepts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Switch,
+    Value,
+    Repeated,
+    /// `--prune-empty[=dry]`, a switch with an optional value
+    SwitchOrValue,
+}
+
+/// Options that contradict or replace each other, of which the command line giving one drops
+/// all from the file
+const ALTERNATIVES: &[&[&str]] = &[
+    &["quiet", "verbose"],
+    &["since", "files-from"],
+    &["staged", "worktree"],
+    &["keep-going", "fail-fast"],
+    &["deny-shadowing", "rename-shadowing"],
+    &["keep-empty", "prune-empty"],
+    &["dry-run", "check"],
+    &["cargo-init", "workspace"],
+];
+
+/// The arguments `args` stands for with the config file read: the file's options that the
+/// command line leaves alone, followed by `args` without `--config`
+pub fn with_config(mut args: Vec<String>) -> Result<Vec<String>, CrateifyError> {
+    let options_end = args.iter().position(|a| a == "--").unwrap_or(args.len());
+    let explicit = match args[..options_end].iter().position(|a| a == "--config") {
+        Some(i) if i + 1 < options_end => {
+            let path = PathBuf::from(args.remove(i + 1));
+            args.remove(i);
+            Some(path)
+        }
+        Some(_) => {
+            return Err(CrateifyError::Usage(
+                "--config expects a file path".to_string(),
+            ));
+        }
+        None => None,
+    };
+    // neither needs options, nor should a broken file keep them from working
+    if args
+        .iter()
+        .take_while(|a| *a != "--")
+        .any(|a| matches!(a.as_str(), "--help" | "-h" | "--print-schema"))
+    {
+        return Ok(args);
+    }
+    let path = explicit.as_deref().unwrap_or(Path::new(FILE_NAME));
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound && explicit.is_none() => return Ok(args),
+        Err(e) => return Err(CrateifyError::io(path, e)),
+    };
+    let table: Table = content.parse().map_err(|e: toml::de::Error| {
+        let message = match e.span() {
+            Some(span) => format!(
+                "line {}: {}",
+                content[..span.start].matches('\n').count() + 1,
+                e.message()
+            ),
+            None => e.message().to_string(),
+        };
+        CrateifyError::Config {
+            file: path.to_path_buf(),
+            message,
+        }
+    })?;
+
+    let given = given_options(&args);
+    let mut merged = Vec::new();
+    for (key, value) in &table {
+        let fail = |message: String| CrateifyError::Config {
+            file: path.to_path_buf(),
+            message,
+        };
+        let kind = kind_of(key).ok_or_else(|| fail(format!("unknown key {key}")))?;
+        if overridden(key, &given) {
+            continue;
+        }
+        let flag = format!("--{key}");
+        match (kind, value) {
+            (Kind::Switch | Kind::SwitchOrValue, Value::Boolean(on)) => {
+                if *on {
+                    merged.push(flag);
+                }
+            }
+            (Kind::SwitchOrValue, Value::String(s)) => merged.push(format!("{flag}={s}")),
+            (Kind::Value, Value::String(s)) => merged.extend([flag, s.clone()]),
+            (Kind::Value, Value::Integer(n)) => merged.extend([flag, n.to_string()]),
+            (Kind::Repeated, Value::String(s)) => merged.extend([flag, s.clone()]),
+            (Kind::Repeated, Value::Array(values)) => {
+                for value in values {
+                    let Value::String(s) = value else {
+                        return Err(fail(format!("{key} expects an array of strings")));
+                    };
+                    merged.extend([flag.clone(), s.clone()]);
+                }
+            }
+            (Kind::Switch, _) => return Err(fail(format!("{key} expects true or false"))),
+            (Kind::SwitchOrValue, _) => {
+                return Err(fail(format!("{key} expects true, false or a string")));
+            }
+            (Kind::Value, _) => return Err(fail(format!("{key} expects a string or an integer"))),
+            (Kind::Repeated, _) => {
+                return Err(fail(format!(
+                    "{key} expects a string or an array of strings"
+                )));
+            }
+        }
+    }
+    merged.extend(args);
+    Ok(merged)
+}
+
+/// The options of the command line `args` by name, `exclude` for `--exclude`
+fn given_options(args: &[String]) -> BTreeSet<&str> {
+    args.iter()
+        .take_while(|a| *a != "--")
+        .filter_map(|arg| match arg.as_str() {
+            "-v" | "-vv" => Some("verbose"),
+            "-q" => Some("quiet"),
+            arg => arg
+                .strip_prefix("--")
+                .map(|a| a.split('=').next().unwrap_or(a)),
+        })
+        .collect()
+}
+
+/// Whether the command line gives `key` or one of its alternatives
+fn overridden(key: &str, given: &BTreeSet<&str>) -> bool {
+    given.contains(key)
+        || ALTERNATIVES
+            .iter()
+            .any(|group| group.contains(&key) && group.iter().any(|o| given.contains(o)))
+}
+
+/// What the option `name` takes, going by how it is written in [`USAGE`], or `None` if crateify
+/// has no such option (or none a config file can give, like `--help`)
+fn kind_of(name: &str) -> Option<Kind> {
+    if name == "config" || !name.starts_with(|c: char| c.is_ascii_lowercase()) {
+        return None;
+    }
+    let line = USAGE.split(" | crateify ").next().unwrap_or(USAGE);
+    let flag = format!("--{name}");
+    let mut rest = line;
+    while let Some(at) = rest.find(&flag) {
+        let after = &rest[at + flag.len()..];
+        rest = after;
+        // `--fail-fast` is not `--fail`
+        if after.starts_with(|c: char| c.is_ascii_alphanumeric() || c == '-') {
+            continue;
+        }
+        if after.starts_with("[=") {
+            return Some(Kind::SwitchOrValue);
+        }
+        // a value is `<glob>` or a list of names, `lib|mod`, and a repeated option's group ends
+        // in `]...` right after it
+        let takes_value = after
+            .strip_prefix(' ')
+            .is_some_and(|v| v.starts_with(|c: char| c == '<' || c.is_ascii_lowercase()));
+        if !takes_value {
+            return Some(Kind::Switch);
+        }
+        let repeated = after
+            .find(']')
+            .is_some_and(|end| after[end..].starts_with("]..."));
+        return Some(if repeated {
+            Kind::Repeated
+        } else {
+            Kind::Value
+        });
+    }
+    None
+}
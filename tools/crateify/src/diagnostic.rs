@@ -0,0 +1,189 @@
+//! `--error-format human|json`: how warnings and errors are written on stderr.
+//!
+//! Both formats render the same diagnostic: a [`Level`], a [`Code`] naming the kind of problem,
+//! the path it is about, and the message. An error's code comes from its [`CrateifyError`]
+//! variant and a warning's from the site that reports it, so a program can match on codes
+//! without reading messages. Under `json`, every diagnostic is one line holding an object with
+//! `"schema"` ([`DIAGNOSTICS_SCHEMA`]) and `"version"` ([`DIAGNOSTICS_VERSION`]), and nothing
+//! else goes to stderr: no log lines, progress bar, or notes, and the summary of each pass is
+//! printed on stdout as a `summary` event instead.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use schemars::JsonSchema;
+use serde::Serialize;
+
+use crate::error::CrateifyError;
+use crate::log;
+
+/// Name of the diagnostic schema, carried by every line as `"schema"`
+pub const DIAGNOSTICS_SCHEMA: &str = "crateify-diagnostics";
+
+/// Version of the diagnostic schema; bump it whenever a field or a [`Code`] changes, codes
+/// being meant to match on
+pub const DIAGNOSTICS_VERSION: u32 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    Warning,
+    Error,
+}
+
+/// The kinds of warnings and errors, written in kebab-case: `case-collision`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum Code {
+    /// The command line could not be understood
+    Usage,
+    /// A directory could not be listed
+    ReadDir,
+    /// A generated file could not be written
+    Write,
+    /// Any other read or write failed
+    Io,
+    /// A name that is not valid UTF-8, so it can't be declared
+    InvalidModuleName,
+    /// Entries that would be declared as the same module
+    ModuleCollision,
+    /// Entries whose names only differ in case
+    CaseCollision,
+    /// Module files of both `--module-style`s, or `m.rs` next to `m/mod.rs`
+    MixedModuleStyles,
+    /// A directory that `--module-style file` can't declare under its name
+    RenamedDirectory,
+    /// A hand-written file that `--flatten` would stop reading
+    FlattenedFile,
+    /// A module named like a crate, which it shadows
+    ShadowedCrate,
+    /// A name Windows reserves for devices
+    ReservedName,
+    /// A line of a `.crateifyignore` that is not a valid pattern
+    InvalidIgnorePattern,
+    /// A symlinked directory that leads back to one above it
+    SymlinkCycle,
+    /// A `--prelude` selector that matches nothing
+    UnresolvedPrelude,
+    /// Under `--keep-going`, the directories that failed
+    Failed,
+    /// Under `--check`, the files that are out of date
+    Stale,
+    /// `--verify` or `--validate-crate` could not run cargo
+    CargoCheck,
+    /// `--verify` found module resolution errors
+    UnresolvedModules,
+    /// `--verify` and `--validate-crate`: a module declared without a file for it (E0583)
+    DanglingModule,
+    /// `--verify`: a name declared twice (E0428)
+    DuplicateModule,
+    /// `--verify`: a path through a module that doesn't exist (E0432, E0433)
+    UnresolvedPath,
+    /// `--validate-crate` found files where the package and the module tree disagree
+    InvalidCrate,
+    /// `--validate-crate`: a `.rs` file of the tree no generated module file declares
+    UndeclaredFile,
+    /// `--validate-crate --cargo-check`: a declared module `cargo check` never compiled
+    NeverCompiled,
+    /// `--validate-crate`: a crate root or binary no target builds, or a target without its file
+    TargetMisconfigured,
+    /// A module file crateify didn't generate, left untouched
+    UnmanagedFile,
+    /// A file or Cargo.toml that already exists, left untouched
+    ExistingFile,
+    /// A file that isn't read anymore, left in place
+    StaleFile,
+    /// A file whose items can't be re-exported, since it doesn't parse
+    UnparsedFile,
+    /// Re-exported items that would clash
+    ReexportCollision,
+    /// A module name `--snake-case` would rename
+    NotSnakeCase,
+    /// A file named like a crate entry point, not declared
+    EntryPointName,
+    /// A directory below `--max-depth`
+    MaxDepth,
+    /// rustfmt is missing, or failed on a generated file
+    Rustfmt,
+    /// `--since` could not ask git what changed
+    GitDiff,
+    /// Ctrl-C can't be handled under `--watch`
+    SignalHandler,
+}
+
+/// `--error-format`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorFormat {
+    Human,
+    Json,
+}
+
+// a process-wide setting like the log level, since the error ending a run is printed by main
+static JSON: AtomicBool = AtomicBool::new(false);
+
+pub fn set_format(format: ErrorFormat) {
+    JSON.store(format == ErrorFormat::Json, Ordering::Relaxed);
+}
+
+pub fn format() -> ErrorFormat {
+    if JSON.load(Ordering::Relaxed) {
+        ErrorFormat::Json
+    } else {
+        ErrorFormat::Human
+    }
+}
+
+#[derive(Serialize, JsonSchema)]
+#[schemars(rename = "CrateifyDiagnostic")]
+struct Line<'a> {
+    schema: &'static str,
+    version: u32,
+    level: Level,
+    code: Code,
+    /// What the diagnostic is about, relative to the translation directory; `""` for a run
+    path: &'a str,
+    message: &'a str,
+}
+
+/// Prints a diagnostic about `path` on stderr; a human-readable one is followed by `hint`
+pub fn print(level: Level, code: Code, path: &str, message: &str, hint: Option<&str>) {
+    match format() {
+        ErrorFormat::Human => {
+            let label = match level {
+                Level::Warning => "warning",
+                Level::Error => "error",
+            };
+            log::stderr!("crateify: {label}: {message}");
+            if let Some(hint) = hint {
+                log::stderr!("crateify: hint: {hint}");
+            }
+        }
+        ErrorFormat::Json => {
+            let line = Line {
+                schema: DIAGNOSTICS_SCHEMA,
+                version: DIAGNOSTICS_VERSION,
+                level,
+                code,
+                path,
+                message,
+            };
+            let json = serde_json::to_string(&line).expect("diagnostics serialize");
+            log::stderr!("{json}");
+        }
+    }
+}
+
+/// Prints `err`, about `path`, with its hint
+pub fn print_error(path: &str, err: &CrateifyError) {
+    print(
+        Level::Error,
+        err.code(),
+        path,
+        &err.to_string(),
+        Some(&err.hint()),
+    );
+}
+
+/// JSON Schema describing a single line of diagnostics
+pub fn json_schema() -> serde_json::Value {
+    schemars::schema_for!(Line).to_value()
+}
@@ -0,0 +1,479 @@
+//! Reads and writes of the tree through directory handles, so that no tree is too deep for
+//! crateify.
+//!
+//! A path the kernel rejects as longer than `PATH_MAX` (4096 bytes on Linux, 1024 on macOS)
+//! still names a file: the directories leading to it are opened a stretch of components at a
+//! time, each relative to the handle of the one before (`openat`), and the file itself relative
+//! to the handle of its own directory (`openat`, `statat`, `mkdirat`, `renameat`, `unlinkat`).
+//! A path short enough is passed on in one go, as `std::fs` would. The functions are those of
+//! `std::fs` the traversal needs, taking the same paths.
+//!
+//! Without `openat` and the rest, on Windows, they are those of `std::fs`, the long paths of
+//! which are the `\\?\` ones.
+
+use std::io::{self, Read};
+use std::path::Path;
+
+#[cfg(unix)]
+use unix as imp;
+
+#[cfg(not(unix))]
+use portable as imp;
+
+pub use imp::{
+    canonicalize, create, create_dir, exists, forget, is_dir, is_file, is_symlink, open,
+};
+pub use imp::{permissions, read_dir, remove_dir_all, remove_file, rename};
+
+pub fn read(path: &Path) -> io::Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    open(path)?.read_to_end(&mut bytes)?;
+    Ok(bytes)
+}
+
+pub fn read_to_string(path: &Path) -> io::Result<String> {
+    let mut text = String::new();
+    open(path)?.read_to_string(&mut text)?;
+    Ok(text)
+}
+
+/// Creates `path` and the directories above it that are missing, from the top down
+pub fn create_dir_all(path: &Path) -> io::Result<()> {
+    let missing: Vec<&Path> = path
+        .ancestors()
+        .take_while(|dir| !dir.as_os_str().is_empty() && !is_dir(dir))
+        .collect();
+    for dir in missing.into_iter().rev() {
+        match create_dir(dir) {
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists && is_dir(dir) => {}
+            result => result?,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+mod unix {
+    use std::ffi::{OsStr, OsString};
+    use std::fs::{self, File, Permissions};
+    use std::io;
+    use std::os::fd::{AsFd, BorrowedFd, OwnedFd};
+    use std::os::unix::ffi::{OsStrExt, OsStringExt};
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::{Component, Path, PathBuf};
+    use std::sync::{Arc, Mutex, PoisonError};
+
+    use rustix::fs::{AtFlags, CWD, Dir, FileType, Mode, OFlags};
+
+    /// The longest path handed to the kernel in one call, below the `PATH_MAX` of every unix
+    const MAX_PATH: usize = 1000;
+
+    /// How many of the directories [`OPENED`] keeps open
+    const OPENED_KEPT: usize = 32;
+
+    /// The directories of long paths opened last, the latest last. A path below one of them is
+    /// opened from it rather than from the top, which makes a directory and its entries the same
+    /// few calls however deep they are.
+    static OPENED: Mutex<Vec<(PathBuf, Arc<OwnedFd>)>> = Mutex::new(Vec::new());
+
+    /// The long paths canonicalized last, each with what it resolved to, so that a path below
+    /// one of them is resolved from there on rather than from the root
+    static RESOLVED: Mutex<Vec<(PathBuf, PathBuf)>> = Mutex::new(Vec::new());
+
+    /// Symlinks followed while resolving one path before giving up, as Linux does
+    const MAX_LINKS: usize = 40;
+
+    /// The directory a path is relative to
+    enum Base {
+        Cwd,
+        Dir(Arc<OwnedFd>),
+    }
+
+    impl AsFd for Base {
+        fn as_fd(&self) -> BorrowedFd<'_> {
+            match self {
+                Base::Cwd => CWD,
+                Base::Dir(fd) => fd.as_fd(),
+            }
+        }
+    }
+
+    /// `path` cut into paths short enough to open one relative to the other, in order; `path`
+    /// itself when it is short enough already
+    fn stretches(path: &Path) -> Vec<PathBuf> {
+        if path.as_os_str().len() <= MAX_PATH {
+            return vec![path.to_path_buf()];
+        }
+        let mut stretches = vec![PathBuf::new()];
+        for component in path.components() {
+            let last = stretches.last_mut().expect("starts with one");
+            let len = last.as_os_str().len() + component.as_os_str().len() + 1;
+            if len > MAX_PATH && !last.as_os_str().is_empty() {
+                stretches.push(PathBuf::new());
+            }
+            stretches
+                .last_mut()
+                .expect("starts with one")
+                .push(component);
+        }
+        stretches
+    }
+
+    /// Closes the directories [`OPENED`] keeps and drops the paths [`RESOLVED`] keeps, which
+    /// may have been renamed or replaced since
+    pub fn forget() {
+        OPENED
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clear();
+        RESOLVED
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clear();
+    }
+
+    /// `entry` kept as the latest of `kept`, the oldest dropped if there are too many
+    fn keep<T>(kept: &Mutex<Vec<(PathBuf, T)>>, entry: (PathBuf, T)) {
+        let mut kept = kept.lock().unwrap_or_else(PoisonError::into_inner);
+        if kept.len() == OPENED_KEPT {
+            kept.remove(0);
+        }
+        kept.push(entry);
+    }
+
+    /// `path` opened as a directory, from the deepest of the directories [`OPENED`] above it
+    fn open_dir(path: &Path) -> io::Result<Arc<OwnedFd>> {
+        if path.as_os_str().len() <= MAX_PATH {
+            return Ok(Arc::new(open_subdir(CWD, path)?));
+        }
+        let opened = OPENED.lock().unwrap_or_else(PoisonError::into_inner);
+        let nearest = opened
+            .iter()
+            .rev()
+            .find_map(|(dir, fd)| Some((below(path, dir)?, Arc::clone(fd))));
+        drop(opened);
+        let (mut dir, rest) = match nearest {
+            Some((rest, fd)) if rest.as_os_str().is_empty() => return Ok(fd),
+            Some((rest, fd)) => (Base::Dir(fd), rest),
+            None => (Base::Cwd, path),
+        };
+        for stretch in stretches(rest) {
+            dir = Base::Dir(Arc::new(open_subdir(&dir, &stretch)?));
+        }
+        let Base::Dir(fd) = dir else {
+            unreachable!("a path has at least one stretch");
+        };
+        keep(&OPENED, (path.to_path_buf(), Arc::clone(&fd)));
+        Ok(fd)
+    }
+
+    /// `path` relative to `dir` if it is `dir` or below it, compared as bytes, which is all
+    /// paths joined from the same crate directory need
+    fn below<'a>(path: &'a Path, dir: &Path) -> Option<&'a Path> {
+        let (path, dir) = (path.as_os_str().as_bytes(), dir.as_os_str().as_bytes());
+        let (head, rest) = path.split_at_checked(dir.len())?;
+        // the paths kept share all but their last few names, so those are told apart first
+        let tail = dir.len().saturating_sub(64);
+        if head[tail..] != dir[tail..] || head != dir {
+            return None;
+        }
+        match rest {
+            [] => Some(Path::new("")),
+            [b'/', rest @ ..] => Some(Path::new(OsStr::from_bytes(rest))),
+            _ => None,
+        }
+    }
+
+    /// The directory `path` below `dir` opened, `dir` itself again for an empty `path`
+    fn open_subdir(dir: impl AsFd, path: &Path) -> io::Result<OwnedFd> {
+        let path = if path.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            path
+        };
+        let flags = OFlags::DIRECTORY | OFlags::RDONLY | OFlags::CLOEXEC;
+        Ok(rustix::fs::openat(dir, path, flags, Mode::empty())?)
+    }
+
+    /// The directory `path` is in, and the name of `path` in it: the current directory and
+    /// `path` itself for a path short enough
+    fn at(path: &Path) -> io::Result<(Base, PathBuf)> {
+        if path.as_os_str().len() <= MAX_PATH {
+            return Ok((Base::Cwd, path.to_path_buf()));
+        }
+        match (path.parent(), path.file_name()) {
+            (Some(parent), Some(name)) => Ok((Base::Dir(open_dir(parent)?), PathBuf::from(name))),
+            // ends in `..`: nothing to take its name in another directory
+            _ => Ok((Base::Dir(open_dir(path)?), PathBuf::from("."))),
+        }
+    }
+
+    fn file_type(path: &Path, follow: bool) -> io::Result<FileType> {
+        let (base, name) = at(path)?;
+        let flags = if follow {
+            AtFlags::empty()
+        } else {
+            AtFlags::SYMLINK_NOFOLLOW
+        };
+        let stat = rustix::fs::statat(&base, &name, flags)?;
+        Ok(FileType::from_raw_mode(stat.st_mode))
+    }
+
+    pub fn exists(path: &Path) -> bool {
+        file_type(path, true).is_ok()
+    }
+
+    pub fn is_dir(path: &Path) -> bool {
+        file_type(path, true).is_ok_and(|t| t == FileType::Directory)
+    }
+
+    pub fn is_file(path: &Path) -> bool {
+        file_type(path, true).is_ok_and(|t| t == FileType::RegularFile)
+    }
+
+    pub fn is_symlink(path: &Path) -> bool {
+        file_type(path, false).is_ok_and(|t| t == FileType::Symlink)
+    }
+
+    pub fn permissions(path: &Path) -> io::Result<Permissions> {
+        let (base, name) = at(path)?;
+        let stat = rustix::fs::statat(&base, &name, AtFlags::empty())?;
+        Ok(Permissions::from_mode(stat.st_mode & 0o7777))
+    }
+
+    /// The entries of the directory `path`, as `path` joined with their names
+    pub fn read_dir(path: &Path) -> io::Result<Vec<PathBuf>> {
+        let dir = Dir::read_from(&*open_dir(path)?)?;
+        let mut entries = Vec::new();
+        for entry in dir {
+            let name = entry?.file_name().to_bytes().to_vec();
+            if name != b"." && name != b".." {
+                entries.push(path.join(OsString::from_vec(name)));
+            }
+        }
+        Ok(entries)
+    }
+
+    fn open_with(path: &Path, flags: OFlags, mode: Mode) -> io::Result<File> {
+        let (base, name) = at(path)?;
+        let fd = rustix::fs::openat(&base, &name, flags | OFlags::CLOEXEC, mode)?;
+        Ok(File::from(fd))
+    }
+
+    /// `path` opened for reading
+    pub fn open(path: &Path) -> io::Result<File> {
+        open_with(path, OFlags::RDONLY, Mode::empty())
+    }
+
+    /// `path` created, or truncated if it exists, for writing
+    pub fn create(path: &Path) -> io::Result<File> {
+        let flags = OFlags::WRONLY | OFlags::CREATE | OFlags::TRUNC;
+        open_with(path, flags, Mode::from_raw_mode(0o666))
+    }
+
+    pub fn create_dir(path: &Path) -> io::Result<()> {
+        let (base, name) = at(path)?;
+        Ok(rustix::fs::mkdirat(
+            &base,
+            &name,
+            Mode::from_raw_mode(0o777),
+        )?)
+    }
+
+    pub fn rename(from: &Path, to: &Path) -> io::Result<()> {
+        let (from_base, from_name) = at(from)?;
+        let (to_base, to_name) = at(to)?;
+        Ok(rustix::fs::renameat(
+            &from_base, &from_name, &to_base, &to_name,
+        )?)
+    }
+
+    pub fn remove_file(path: &Path) -> io::Result<()> {
+        let (base, name) = at(path)?;
+        Ok(rustix::fs::unlinkat(&base, &name, AtFlags::empty())?)
+    }
+
+    /// Removes the directory `path` and everything in it. Symlinks are removed, not followed.
+    pub fn remove_dir_all(path: &Path) -> io::Result<()> {
+        if path.as_os_str().len() <= MAX_PATH {
+            return fs::remove_dir_all(path);
+        }
+        let (base, name) = at(path)?;
+        // a symlink in the tree is removed itself, the directory it leads to left alone
+        let open = |parent: BorrowedFd<'_>, name: &OsStr| {
+            let flags = OFlags::DIRECTORY | OFlags::RDONLY | OFlags::NOFOLLOW | OFlags::CLOEXEC;
+            rustix::fs::openat(parent, name, flags, Mode::empty())
+        };
+        // the directories being emptied, each with its name in the one before
+        let mut stack = vec![(open(base.as_fd(), name.as_os_str())?, name.into_os_string())];
+        while let Some((dir, _)) = stack.last() {
+            let mut subdir = None;
+            for entry in Dir::read_from(dir)? {
+                let entry = entry?;
+                let entry_name = OsStr::from_bytes(entry.file_name().to_bytes());
+                if entry_name == "." || entry_name == ".." {
+                    continue;
+                }
+                let is_dir = match entry.file_type() {
+                    FileType::Unknown => {
+                        let stat = rustix::fs::statat(dir, entry_name, AtFlags::SYMLINK_NOFOLLOW)?;
+                        FileType::from_raw_mode(stat.st_mode) == FileType::Directory
+                    }
+                    file_type => file_type == FileType::Directory,
+                };
+                if is_dir {
+                    subdir = Some(entry_name.to_os_string());
+                    break;
+                }
+                rustix::fs::unlinkat(dir, entry_name, AtFlags::empty())?;
+            }
+            match subdir {
+                Some(subdir) => {
+                    let fd = open(dir.as_fd(), &subdir)?;
+                    stack.push((fd, subdir));
+                }
+                None => {
+                    let (_, emptied) = stack.pop().expect("not empty");
+                    let parent = stack.last().map_or(base.as_fd(), |(fd, _)| fd.as_fd());
+                    rustix::fs::unlinkat(parent, &emptied, AtFlags::REMOVEDIR)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The absolute path of `path` with every symlink resolved, like [`fs::canonicalize`], for
+    /// a result of any length
+    pub fn canonicalize(path: &Path) -> io::Result<PathBuf> {
+        // `realpath` takes one `lstat` of the whole path so far per component before it gives up
+        if path.as_os_str().len() <= MAX_PATH {
+            match fs::canonicalize(path) {
+                Err(e)
+                    if e.raw_os_error() == Some(rustix::io::Errno::NAMETOOLONG.raw_os_error()) => {}
+                result => return result,
+            }
+        }
+        let kept = RESOLVED.lock().unwrap_or_else(PoisonError::into_inner);
+        let nearest = kept
+            .iter()
+            .rev()
+            .find_map(|(from, to)| Some((below(path, from)?, to.clone())));
+        drop(kept);
+        let (mut resolved, rest) = match nearest {
+            Some((rest, to)) => (to, rest),
+            None if path.is_absolute() => (PathBuf::from("/"), path),
+            None => (std::env::current_dir()?, path),
+        };
+        let mut dir = open_dir(&resolved)?;
+        // the components left to resolve, the next one last
+        let mut pending = names_reversed(rest);
+        let mut links = 0;
+        while let Some(name) = pending.pop() {
+            if name == ".." {
+                resolved.pop();
+                dir = Arc::new(open_subdir(&dir, Path::new(".."))?);
+                continue;
+            }
+            let stat = rustix::fs::statat(&dir, &name, AtFlags::SYMLINK_NOFOLLOW)?;
+            match FileType::from_raw_mode(stat.st_mode) {
+                FileType::Symlink => {
+                    links += 1;
+                    if links > MAX_LINKS {
+                        return Err(rustix::io::Errno::LOOP.into());
+                    }
+                    let target = rustix::fs::readlinkat(&dir, &name, Vec::new())?;
+                    let target = PathBuf::from(OsString::from_vec(target.into_bytes()));
+                    if target.is_absolute() {
+                        resolved = PathBuf::from("/");
+                        dir = open_dir(&resolved)?;
+                    }
+                    pending.extend(names_reversed(&target));
+                }
+                FileType::Directory => {
+                    resolved.push(&name);
+                    dir = Arc::new(open_subdir(&dir, Path::new(&name))?);
+                }
+                // a file ends the path, or it isn't a directory to go on from
+                _ if pending.is_empty() => resolved.push(&name),
+                _ => return Err(rustix::io::Errno::NOTDIR.into()),
+            }
+        }
+        if resolved.as_os_str().len() > MAX_PATH {
+            keep(&RESOLVED, (path.to_path_buf(), resolved.clone()));
+        }
+        Ok(resolved)
+    }
+
+    /// The names and `..`s of `path`, last first
+    fn names_reversed(path: &Path) -> Vec<OsString> {
+        path.components()
+            .rev()
+            .filter_map(|c| match c {
+                Component::Normal(name) => Some(name.to_os_string()),
+                Component::ParentDir => Some(OsString::from("..")),
+                Component::RootDir | Component::CurDir | Component::Prefix(_) => None,
+            })
+            .collect()
+    }
+}
+
+#[cfg(not(unix))]
+mod portable {
+    use std::fs::{self, File, Permissions};
+    use std::io;
+    use std::path::{Path, PathBuf};
+
+    pub fn forget() {}
+
+    pub fn exists(path: &Path) -> bool {
+        path.exists()
+    }
+
+    pub fn is_dir(path: &Path) -> bool {
+        path.is_dir()
+    }
+
+    pub fn is_file(path: &Path) -> bool {
+        path.is_file()
+    }
+
+    pub fn is_symlink(path: &Path) -> bool {
+        path.is_symlink()
+    }
+
+    pub fn permissions(path: &Path) -> io::Result<Permissions> {
+        Ok(fs::metadata(path)?.permissions())
+    }
+
+    pub fn read_dir(path: &Path) -> io::Result<Vec<PathBuf>> {
+        fs::read_dir(path)?.map(|e| e.map(|e| e.path())).collect()
+    }
+
+    pub fn open(path: &Path) -> io::Result<File> {
+        File::open(path)
+    }
+
+    pub fn create(path: &Path) -> io::Result<File> {
+        File::create(path)
+    }
+
+    pub fn create_dir(path: &Path) -> io::Result<()> {
+        fs::create_dir(path)
+    }
+
+    pub fn rename(from: &Path, to: &Path) -> io::Result<()> {
+        fs::rename(from, to)
+    }
+
+    pub fn remove_file(path: &Path) -> io::Result<()> {
+        fs::remove_file(path)
+    }
+
+    pub fn remove_dir_all(path: &Path) -> io::Result<()> {
+        fs::remove_dir_all(path)
+    }
+
+    pub fn canonicalize(path: &Path) -> io::Result<PathBuf> {
+        fs::canonicalize(path)
+    }
+}
@@ -0,0 +1,394 @@
+use std::borrow::Cow;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use crate::diagnostic::Code;
+
+/// The command lines crateify accepts
+pub const USAGE: &str = "usage: crateify [-v|-vv|--quiet] [--config <path>] [--error-format human|json] [--events <path|->] [--manifest <path.json>] [--graph <path> [--graph-format dot|json]] [--native-paths] [--since <ref> [--staged|--worktree] | --files-from <path|->] [--watch [--poll] [--poll-interval <ms>]] [--keep-going|--fail-fast] [--skip-invalid-names] [--merge-collisions] [--allow-case-collisions] [--snake-case] [--deny-shadowing|--rename-shadowing] [--rename-reserved] [--keep-empty|--prune-empty[=dry]] [--clean] [--no-default-ignores] [--follow-symlinks] [--exclude <glob>]... [--force] [--dry-run|--check] [--output <dir> [--relative-paths]] [--jobs <n>] [--max-depth <n>] [--root lib|mod] [--module-style mod-rs|file] [--flatten] [--header <text>] [--inner-attr <attr>]... [--inner-attr-file <path>] [--file-attr <glob>=<attr>]... [--remove-file-attrs] [--no-header] [--strict-version] [--fmt] [--visibility pub|pub-crate|private] [--visibility-file <path>] [--reexport none|glob|items] [--prelude <path>] [--provenance <map.json>] [--cfg-map <name>=<cfg>]... [--cfg-suffix <suffix>=<cfg>]... [--test-pattern <glob>]... [--no-test-detection] [--verify] [--cargo-init [--crate-name <name>] [--edition <year>] | --workspace [--edition <year>]] [--hybrid] [--] <crate_directory>... | crateify --validate-crate <package_dir> --manifest <path.json> [--cargo-check] [--no-default-ignores] [--exclude <glob>]... | crateify --print-schema <name> | crateify --help";
+
+/// Everything that can make a crateify run fail
+#[derive(Debug)]
+pub enum CrateifyError {
+    /// The command line could not be understood
+    Usage(String),
+    /// The config file `file` could not be understood
+    Config { file: PathBuf, message: String },
+    /// Listing the entries of directory `path` failed
+    ReadDir { path: PathBuf, source: io::Error },
+    /// Writing the generated file `path` failed, which is left as it was
+    Write { path: PathBuf, source: io::Error },
+    /// Any other read or write of `path` failed
+    Io { path: PathBuf, source: io::Error },
+    /// Entries of one directory whose names are not valid UTF-8, so they can't be declared
+    InvalidNames(Vec<PathBuf>),
+    /// Several entries of `dir` would be declared as the same module
+    ModuleCollision {
+        dir: PathBuf,
+        ident: String,
+        entries: Vec<String>,
+    },
+    /// Entries of `dir` whose names only differ in case, which can't coexist on case-insensitive
+    /// filesystems
+    CaseCollision { dir: PathBuf, entries: Vec<String> },
+    /// The module file `path` is of the other `--module-style`, and the two can't be mixed
+    MixedModuleStyles(PathBuf),
+    /// Under `--module-style file`, directory `dir` would need a `#[path]` to be declared as
+    /// `ident`
+    RenamedDirectory { dir: PathBuf, ident: String },
+    /// Under `--flatten`, the hand-written `path` would no longer be read once its directory is
+    /// declared inline
+    Flattened(PathBuf),
+    /// Under `--deny-shadowing`, the directory entry `entry` would be declared as module `ident`,
+    /// which shadows the crate of that name
+    ShadowedCrate { entry: PathBuf, ident: String },
+    /// Entries of one directory whose names Windows reserves for devices, which can't be checked
+    /// out there
+    ReservedNames(Vec<PathBuf>),
+    /// Line `line` of the ignore file `file` is not a valid pattern
+    InvalidIgnorePattern {
+        file: PathBuf,
+        line: usize,
+        reason: String,
+    },
+    /// Following the symlinked directory `link` under `--follow-symlinks` leads back to `target`,
+    /// a directory it is in
+    SymlinkCycle { link: PathBuf, target: PathBuf },
+    /// The `--prelude` selector `selector` doesn't resolve in the module tree
+    UnresolvedPrelude { selector: String, reason: String },
+    /// `--keep-going` finished, but this many directories failed; each was reported as it happened
+    Failed(usize),
+    /// `--keep-going` finished without a directory failing, but this many errors that fail none,
+    /// such as symlink cycles, were reported as they happened
+    Errors(usize),
+    /// `--check` found this many files that differ from what crateify would generate
+    Stale(usize),
+    /// `--verify` or `--validate-crate` could not run `cargo <command>` on the package of
+    /// `manifest`
+    Cargo {
+        manifest: PathBuf,
+        command: &'static str,
+        reason: String,
+    },
+    /// `--verify` found this many module resolution errors; each was reported as it was found
+    Unresolved(usize),
+    /// `--validate-crate` found this many files where the package and the module tree disagree;
+    /// each was reported as it was found
+    Invalid(usize),
+}
+
+impl CrateifyError {
+    pub fn io(path: &Path, source: io::Error) -> Self {
+        CrateifyError::Io {
+            path: path.to_path_buf(),
+            source,
+        }
+    }
+
+    pub fn read_dir(path: &Path, source: io::Error) -> Self {
+        CrateifyError::ReadDir {
+            path: path.to_path_buf(),
+            source,
+        }
+    }
+
+    pub fn write(path: &Path, source: io::Error) -> Self {
+        CrateifyError::Write {
+            path: path.to_path_buf(),
+            source,
+        }
+    }
+
+    /// A one-line suggestion printed after the error message: for a usage error, the part of
+    /// [`USAGE`] that the flag it names is in
+    pub fn hint(&self) -> Cow<'static, str> {
+        let hint = match self {
+            CrateifyError::Usage(message) => return usage_hint(message),
+            CrateifyError::Config { .. } => {
+                "fix the file; its keys are the long options without their leading --, as crateify --help lists them"
+            }
+            CrateifyError::ReadDir { .. } => {
+                "check that the directory exists and is readable by the current user"
+            }
+            CrateifyError::Write { .. } => {
+                "check that its directory is writable by the current user and the disk isn't full"
+            }
+            CrateifyError::Io { .. } => {
+                "check that the path exists and is readable and writable by the current user"
+            }
+            CrateifyError::InvalidNames(_) => {
+                "rename the entries, or pass --skip-invalid-names to leave them out of mod.rs"
+            }
+            CrateifyError::ModuleCollision { .. } => {
+                "rename all but one of the entries so that their module names differ, or pass --merge-collisions if they are a file and a directory"
+            }
+            CrateifyError::CaseCollision { .. } => {
+                "rename all but one of the entries, or pass --allow-case-collisions to declare them under distinct names"
+            }
+            CrateifyError::MixedModuleStyles(_) => {
+                "remove the module files of the other style, or rerun with the --module-style that generated them"
+            }
+            CrateifyError::RenamedDirectory { .. } => {
+                "rename the directory to a valid identifier, or use --module-style mod-rs"
+            }
+            CrateifyError::Flattened(_) => {
+                "move its code into a file of its own, run without --flatten, or pass --force to declare the directory inline all the same"
+            }
+            CrateifyError::ShadowedCrate { .. } => {
+                "rename the entry, or pass --rename-shadowing to declare it with a trailing underscore"
+            }
+            CrateifyError::ReservedNames(_) => {
+                "rename the entries, or pass --rename-reserved to declare them with a trailing underscore"
+            }
+            CrateifyError::InvalidIgnorePattern { .. } => {
+                "fix the pattern, or escape the character with a backslash to match it literally"
+            }
+            CrateifyError::SymlinkCycle { .. } => "remove the link, or leave it out with --exclude",
+            CrateifyError::UnresolvedPrelude { .. } => {
+                "fix or remove the line of the --prelude file; a module or item has to be declared, and public, to be re-exported"
+            }
+            CrateifyError::Failed(_) => "see the errors above for the directories that failed",
+            CrateifyError::Errors(_) => "see the errors above",
+            CrateifyError::Stale(_) => "run crateify without --check to regenerate them",
+            CrateifyError::Cargo { .. } => "run cargo on the manifest to see what is wrong with it",
+            CrateifyError::Unresolved(_) => {
+                "fix or remove the declarations above; hand-written module files and keep blocks are left as they are"
+            }
+            CrateifyError::Invalid(_) => {
+                "rerun crateify with --manifest to declare the files again, or fix the targets of the Cargo.toml"
+            }
+        };
+        hint.into()
+    }
+
+    /// Process exit code reported for this error: 2 for usage errors, 1 for everything else
+    pub fn exit_code(&self) -> ExitCode {
+        match self {
+            CrateifyError::Usage(_) | CrateifyError::Config { .. } => ExitCode::from(2),
+            CrateifyError::ReadDir { .. }
+            | CrateifyError::Write { .. }
+            | CrateifyError::Io { .. }
+            | CrateifyError::InvalidNames(_)
+            | CrateifyError::ModuleCollision { .. }
+            | CrateifyError::CaseCollision { .. }
+            | CrateifyError::MixedModuleStyles(_)
+            | CrateifyError::RenamedDirectory { .. }
+            | CrateifyError::Flattened(_)
+            | CrateifyError::ShadowedCrate { .. }
+            | CrateifyError::ReservedNames(_)
+            | CrateifyError::InvalidIgnorePattern { .. }
+            | CrateifyError::SymlinkCycle { .. }
+            | CrateifyError::UnresolvedPrelude { .. }
+            | CrateifyError::Failed(_)
+            | CrateifyError::Errors(_)
+            | CrateifyError::Stale(_)
+            | CrateifyError::Cargo { .. }
+            | CrateifyError::Unresolved(_)
+            | CrateifyError::Invalid(_) => ExitCode::from(1),
+        }
+    }
+
+    /// The code of this error under `--error-format json`
+    pub fn code(&self) -> Code {
+        match self {
+            CrateifyError::Usage(_) | CrateifyError::Config { .. } => Code::Usage,
+            CrateifyError::ReadDir { .. } => Code::ReadDir,
+            CrateifyError::Write { .. } => Code::Write,
+            CrateifyError::Io { .. } => Code::Io,
+            CrateifyError::InvalidNames(_) => Code::InvalidModuleName,
+            CrateifyError::ModuleCollision { .. } => Code::ModuleCollision,
+            CrateifyError::CaseCollision { .. } => Code::CaseCollision,
+            CrateifyError::MixedModuleStyles(_) => Code::MixedModuleStyles,
+            CrateifyError::RenamedDirectory { .. } => Code::RenamedDirectory,
+            CrateifyError::Flattened(_) => Code::FlattenedFile,
+            CrateifyError::ShadowedCrate { .. } => Code::ShadowedCrate,
+            CrateifyError::ReservedNames(_) => Code::ReservedName,
+            CrateifyError::InvalidIgnorePattern { .. } => Code::InvalidIgnorePattern,
+            CrateifyError::SymlinkCycle { .. } => Code::SymlinkCycle,
+            CrateifyError::UnresolvedPrelude { .. } => Code::UnresolvedPrelude,
+            CrateifyError::Failed(_) | CrateifyError::Errors(_) => Code::Failed,
+            CrateifyError::Stale(_) => Code::Stale,
+            CrateifyError::Cargo { .. } => Code::CargoCheck,
+            CrateifyError::Unresolved(_) => Code::UnresolvedModules,
+            CrateifyError::Invalid(_) => Code::InvalidCrate,
+        }
+    }
+}
+
+impl fmt::Display for CrateifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CrateifyError::Usage(msg) => write!(f, "{msg}"),
+            CrateifyError::Config { file, message } => write!(f, "{}: {message}", file.display()),
+            CrateifyError::ReadDir { path, source } => {
+                write!(f, "cannot read directory {}: {source}", path.display())
+            }
+            CrateifyError::Write { path, source } => {
+                write!(f, "cannot write {}: {source}", path.display())
+            }
+            CrateifyError::Io { path, source } => {
+                write!(f, "I/O error on {}: {source}", path.display())
+            }
+            CrateifyError::InvalidNames(paths) => {
+                write!(f, "names are not valid UTF-8 and cannot be module names:")?;
+                for path in paths {
+                    write!(f, " {}", path.display())?;
+                }
+                Ok(())
+            }
+            CrateifyError::ModuleCollision {
+                dir,
+                ident,
+                entries,
+            } => write!(
+                f,
+                "{}: {} would all be declared as module {ident}",
+                dir.display(),
+                entries.join(", ")
+            ),
+            CrateifyError::CaseCollision { dir, entries } => write!(
+                f,
+                "{}: {} only differ in case and collide on case-insensitive filesystems",
+                dir.display(),
+                entries.join(", ")
+            ),
+            CrateifyError::MixedModuleStyles(path) => write!(
+                f,
+                "{} is a module file of the other --module-style, and the two can't be mixed in one tree",
+                path.display()
+            ),
+            CrateifyError::RenamedDirectory { dir, ident } => write!(
+                f,
+                "{} would be declared as {ident} with a #[path], which --module-style file can't do for a directory",
+                dir.display()
+            ),
+            CrateifyError::Flattened(path) => write!(
+                f,
+                "{} is not generated by crateify, and --flatten would declare its directory inline without it",
+                path.display()
+            ),
+            CrateifyError::ShadowedCrate { entry, ident } => write!(
+                f,
+                "{} would be declared as module {ident}, which shadows the {ident} crate",
+                entry.display()
+            ),
+            CrateifyError::ReservedNames(paths) => {
+                write!(
+                    f,
+                    "names are reserved for devices on Windows and cannot be checked out there:"
+                )?;
+                for path in paths {
+                    write!(f, " {}", path.display())?;
+                }
+                Ok(())
+            }
+            CrateifyError::InvalidIgnorePattern { file, line, reason } => {
+                write!(f, "{}:{line}: invalid pattern: {reason}", file.display())
+            }
+            CrateifyError::SymlinkCycle { link, target } => write!(
+                f,
+                "{}: symlink cycle, it links back to {}",
+                link.display(),
+                target.display()
+            ),
+            CrateifyError::UnresolvedPrelude { selector, reason } => {
+                write!(f, "--prelude {selector}: {reason}")
+            }
+            CrateifyError::Failed(1) => write!(f, "1 directory failed"),
+            CrateifyError::Failed(n) => write!(f, "{n} directories failed"),
+            CrateifyError::Errors(1) => write!(f, "1 error"),
+            CrateifyError::Errors(n) => write!(f, "{n} errors"),
+            CrateifyError::Stale(1) => write!(f, "1 generated file is out of date"),
+            CrateifyError::Stale(n) => write!(f, "{n} generated files are out of date"),
+            CrateifyError::Cargo {
+                manifest,
+                command,
+                reason,
+            } => {
+                write!(
+                    f,
+                    "{}: cargo {command} failed: {reason}",
+                    manifest.display()
+                )
+            }
+            CrateifyError::Unresolved(1) => write!(f, "1 module resolution error"),
+            CrateifyError::Unresolved(n) => write!(f, "{n} module resolution errors"),
+            CrateifyError::Invalid(1) => write!(f, "1 file doesn't line up with the package"),
+            CrateifyError::Invalid(n) => write!(f, "{n} files don't line up with the package"),
+        }
+    }
+}
+
+impl std::error::Error for CrateifyError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CrateifyError::ReadDir { source, .. }
+            | CrateifyError::Write { source, .. }
+            | CrateifyError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// The hint of the usage error `message`: the part of [`USAGE`] with the flag it names first,
+/// or the general shape of a command line if it names none
+fn usage_hint(message: &str) -> Cow<'static, str> {
+    let groups = usage_groups();
+    let flag = message
+        .split(|c: char| c.is_whitespace() || matches!(c, ':' | '=' | ','))
+        .find(|word| word.len() > 1 && word.starts_with('-') && *word != "--");
+    let Some(flag) = flag else {
+        return "usage: crateify [<option>]... [--] <crate_directory>...; crateify --help lists \
+                the options"
+            .into();
+    };
+    if let Some(group) = groups.iter().find(|group| names_flag(group, flag)) {
+        return format!("usage of {flag}: crateify {group} ...").into();
+    }
+    // an option of one of the other command lines, `--validate-crate`
+    match USAGE
+        .split(" | crateify ")
+        .skip(1)
+        .find(|line| names_flag(&format!(" {line} "), flag))
+    {
+        Some(line) => format!("usage of {flag}: crateify {line}").into(),
+        None => format!("{flag} is not an option of crateify; crateify --help lists them").into(),
+    }
+}
+
+/// The outermost bracketed groups of the first command line of [`USAGE`], `[--jobs <n>]`
+fn usage_groups() -> Vec<&'static str> {
+    let mut groups = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in USAGE.char_indices() {
+        match c {
+            '[' => {
+                if depth == 0 {
+                    start = i;
+                }
+                depth += 1;
+            }
+            ']' => {
+                depth -= 1;
+                if depth == 0 {
+                    groups.push(&USAGE[start..=i]);
+                }
+            }
+            '|' if depth == 0 => break,
+            _ => {}
+        }
+    }
+    groups
+}
+
+/// Whether `flag` is one of the flags of the usage `group`, as a whole word
+fn names_flag(group: &str, flag: &str) -> bool {
+    group.match_indices(flag).any(|(i, _)| {
+        let before = group[..i].chars().next_back();
+        let after = group[i + flag.len()..].chars().next();
+        matches!(before, Some('[' | '|' | ' ')) && matches!(after, Some(']' | '[' | '|' | ' '))
+    })
+}
@@ -0,0 +1,154 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use schemars::JsonSchema;
+use serde::Serialize;
+
+use crate::error::CrateifyError;
+
+/// Name of the event schema, carried by every event as `"schema"`
+pub const EVENTS_SCHEMA: &str = "crateify-events";
+
+/// Version of the event schema below; bump it whenever an event gains, loses, or changes a field
+pub const EVENTS_VERSION: u32 = 3;
+
+/// Progress events written as newline-delimited JSON by `--events <path|->`.
+///
+/// Every line is a single object carrying `"schema"` ([`EVENTS_SCHEMA`]), `"version"`
+/// ([`EVENTS_VERSION`]), and `"event"` (the variant name in snake_case) next to the variant's
+/// own fields. Paths are relative to the translation directory. Every pass over the tree (one
+/// per run, plus one per rerun under `--watch`) ends with exactly one `summary` event, even when
+/// directories failed, unless writing the stream itself fails. `crateify --print-schema
+/// crateify-events` prints the JSON Schema of a line.
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// A `.rs` file was declared as a module: `{"event":"file","path":"net/tcp.rs"}`
+    File { path: String },
+    /// A `mod.rs` was written: `{"event":"mod_rs","path":"net/mod.rs","modules":2}`
+    ModRs { path: String, modules: usize },
+    /// An entry was skipped: `{"event":"warning","path":"...","message":"..."}`
+    Warning { path: String, message: String },
+    /// A directory could not be processed: `{"event":"error","path":"net","message":"..."}`
+    Error { path: String, message: String },
+    /// Totals for one pass over the tree:
+    /// `{"event":"summary","directories":3,"files":7,"mod_rs":3,"created":1,"updated":0,"unchanged":2,"modules":9,"skipped":1,"warnings":0,"failed":0,"elapsed_ms":4}`
+    Summary {
+        /// Directories visited, including the ones that failed
+        directories: usize,
+        files: usize,
+        mod_rs: usize,
+        /// `mod_rs` by what writing them changed, or would change under `--dry-run` and `--check`
+        created: usize,
+        updated: usize,
+        unchanged: usize,
+        /// Modules declared in the `mod_rs` files
+        modules: usize,
+        /// Entries that were neither declared nor descended into: excluded, ignored, or not Rust
+        skipped: usize,
+        warnings: usize,
+        /// Directories that reported an `error` event
+        failed: usize,
+        elapsed_ms: u64,
+    },
+}
+
+#[derive(Serialize, JsonSchema)]
+#[schemars(rename = "CrateifyEvent")]
+struct Envelope<'a> {
+    schema: &'static str,
+    version: u32,
+    #[serde(flatten)]
+    event: &'a Event,
+}
+
+/// Destination of the event stream; events are dropped when no `--events` target was given, or
+/// kept for the [`Report`](crate::Report) of a library run
+pub struct Events {
+    out: Option<(PathBuf, Box<dyn Write + Send>)>,
+    collected: Option<Vec<Event>>,
+}
+
+impl Events {
+    pub fn disabled() -> Self {
+        Events {
+            out: None,
+            collected: None,
+        }
+    }
+
+    /// Keeps every event, for [`Events::take_collected`]
+    pub fn collect() -> Self {
+        Events {
+            out: None,
+            collected: Some(Vec::new()),
+        }
+    }
+
+    /// The events kept since the last call
+    pub fn take_collected(&mut self) -> Vec<Event> {
+        self.collected
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
+    /// Opens `target` for writing events, with `-` meaning stdout
+    pub fn open(target: &str) -> Result<Self, CrateifyError> {
+        let out: Box<dyn Write + Send> = if target == "-" {
+            Box::new(io::stdout())
+        } else {
+            let file =
+                fs::File::create(target).map_err(|e| CrateifyError::io(Path::new(target), e))?;
+            Box::new(file)
+        };
+        Ok(Events {
+            out: Some((PathBuf::from(target), out)),
+            collected: None,
+        })
+    }
+
+    /// Writes `event` as one line and flushes it so readers see it immediately
+    pub fn emit(&mut self, event: Event) -> Result<(), CrateifyError> {
+        if let Some(collected) = &mut self.collected {
+            collected.push(event);
+            return Ok(());
+        }
+        let Some((path, out)) = &mut self.out else {
+            return Ok(());
+        };
+
+        let envelope = Envelope {
+            schema: EVENTS_SCHEMA,
+            version: EVENTS_VERSION,
+            event: &event,
+        };
+        serde_json::to_writer(&mut *out, &envelope)
+            .map_err(io::Error::from)
+            .and_then(|()| out.write_all(b"\n"))
+            .and_then(|()| out.flush())
+            .map_err(|e| CrateifyError::io(path, e))
+    }
+}
+
+/// Prints `event` on stdout as a line of the event stream, for the summary under
+/// `--error-format json`
+pub fn print(event: &Event) -> Result<(), CrateifyError> {
+    let envelope = Envelope {
+        schema: EVENTS_SCHEMA,
+        version: EVENTS_VERSION,
+        event,
+    };
+    let mut stdout = io::stdout().lock();
+    serde_json::to_writer(&mut stdout, &envelope)
+        .map_err(io::Error::from)
+        .and_then(|()| stdout.write_all(b"\n"))
+        .and_then(|()| stdout.flush())
+        .map_err(|e| CrateifyError::io(Path::new("<stdout>"), e))
+}
+
+/// JSON Schema describing a single line of the event stream
+pub fn json_schema() -> serde_json::Value {
+    schemars::schema_for!(Envelope).to_value()
+}
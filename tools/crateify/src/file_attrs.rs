@@ -0,0 +1,204 @@
+//! `--file-attr <glob>=<attr>` and `--remove-file-attrs`: inner attributes in the translated
+//! files themselves, such as `#![allow(non_snake_case)]` in the modules of an `ffi` directory.
+//!
+//! These files are the translator's rather than crateify's, so each attribute goes on a line of
+//! its own marked with [`MARKER`]: after the file's inner attributes and `//!` lines, before its
+//! first item, and only where the file doesn't have that attribute yet, so that reruns change
+//! nothing. `--remove-file-attrs` deletes the marked lines and leaves everything else alone.
+//! Globs match like `--exclude` patterns, against the path of each declared `.rs` file.
+
+use std::path::Path;
+
+use globset::{GlobBuilder, GlobMatcher};
+
+use crate::Context;
+use crate::dir_handle;
+use crate::error::CrateifyError;
+use crate::inner_attrs;
+use crate::log::info;
+
+/// Ends every line `--file-attr` adds, so that `--remove-file-attrs` finds them
+pub const MARKER: &str = "// crateify: file-attr";
+
+/// The `--file-attr` rules of a run
+#[derive(Default)]
+pub struct FileAttrs {
+    /// Each glob with the inside of the brackets of its attribute
+    rules: Vec<(GlobMatcher, String)>,
+    /// `--remove-file-attrs`
+    remove: bool,
+}
+
+impl FileAttrs {
+    /// Adds a `--file-attr` rule, `<glob>=<attr>`
+    pub fn add(&mut self, rule: &str) -> Result<(), CrateifyError> {
+        let Some((pattern, attr)) = rule.split_once('=') else {
+            return Err(CrateifyError::Usage(format!(
+                "--file-attr expects <glob>=<attr>, got {rule}"
+            )));
+        };
+        let pattern = pattern.trim();
+        let glob = GlobBuilder::new(pattern)
+            .literal_separator(true)
+            .build()
+            .map_err(|e| {
+                CrateifyError::Usage(format!("--file-attr {pattern} is not a valid glob: {e}"))
+            })?;
+        let attr = inner_attrs::parse(attr)
+            .map_err(|reason| CrateifyError::Usage(format!("--file-attr {rule}: {reason}")))?;
+        self.rules.push((glob.compile_matcher(), attr));
+        Ok(())
+    }
+
+    /// Deletes the lines added by earlier runs instead, for `--remove-file-attrs`
+    pub fn remove(&mut self) {
+        self.remove = true;
+    }
+
+    /// Whether there are no files to edit
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty() && !self.remove
+    }
+
+    /// `source`, the file at `relative`, with the attributes of the matching rules added, or
+    /// under `--remove-file-attrs` without the lines added earlier; `None` if nothing changes
+    fn apply(&self, relative: &str, source: &str) -> Option<String> {
+        if self.remove {
+            let kept: String = source
+                .split_inclusive('\n')
+                .filter(|line| !is_marked(line))
+                .collect();
+            return (kept != source).then_some(kept);
+        }
+        let (end, present) = leading_attrs(source);
+        let mut missing: Vec<&str> = Vec::new();
+        for (glob, attr) in &self.rules {
+            if glob.is_match(relative)
+                && !present
+                    .iter()
+                    .chain(&missing)
+                    .any(|other| same_attr(other, attr))
+            {
+                missing.push(attr);
+            }
+        }
+        if missing.is_empty() {
+            return None;
+        }
+        let mut edited = source[..end].to_string();
+        if !edited.is_empty() && !edited.ends_with('\n') {
+            edited.push('\n');
+        }
+        for attr in missing {
+            edited.push_str(&format!("#![{attr}] {MARKER}\n"));
+        }
+        edited.push_str(&source[end..]);
+        Some(edited)
+    }
+}
+
+/// Edits the `.rs` file at `path` for the `--file-attr` rules, or `--remove-file-attrs`
+pub fn edit(path: &Path, ctx: &Context) -> Result<(), CrateifyError> {
+    let source = dir_handle::read_to_string(path).map_err(|e| CrateifyError::io(path, e))?;
+    let Some(edited) = ctx.file_attrs.apply(&ctx.relative_path(path), &source) else {
+        return Ok(());
+    };
+    let change = ctx.output.write(path, &ctx.display_path(path), &edited)?;
+    if ctx.output.is_stale(change) {
+        ctx.count(|c| c.stale += 1);
+    }
+    info!(
+        "{}: inner attributes {}",
+        path.display(),
+        if ctx.file_attrs.remove {
+            "removed"
+        } else {
+            "added"
+        }
+    );
+    Ok(())
+}
+
+/// Whether `line` is one that `--file-attr` added
+fn is_marked(line: &str) -> bool {
+    let line = line.trim();
+    line.starts_with("#![") && line.ends_with(MARKER)
+}
+
+/// Whether the attributes `a` and `b` only differ in whitespace
+fn same_attr(a: &str, b: &str) -> bool {
+    let bare = |attr: &str| {
+        attr.chars()
+            .filter(|c| !c.is_whitespace())
+            .collect::<String>()
+    };
+    bare(a) == bare(b)
+}
+
+/// Where the inner attributes, `//!` lines and comments at the top of `source` end, at the end
+/// of the line of the last inner attribute or `//!` line; and the attributes themselves, without
+/// their `#![` and `]`
+fn leading_attrs(source: &str) -> (usize, Vec<&str>) {
+    let line_end = |from: usize| {
+        source[from..]
+            .find('\n')
+            .map_or(source.len(), |i| from + i + 1)
+    };
+    let mut pos = 0;
+    let mut end = 0;
+    let mut attrs = Vec::new();
+    loop {
+        let rest = &source[pos..];
+        let trimmed = rest.trim_start();
+        let start = pos + rest.len() - trimmed.len();
+        if trimmed.starts_with("//") {
+            pos = line_end(start);
+            if trimmed.starts_with("//!") {
+                end = pos;
+            }
+        } else if trimmed.starts_with("/*") {
+            let Some(close) = trimmed.find("*/") else {
+                break;
+            };
+            pos = start + close + 2;
+            if trimmed.starts_with("/*!") {
+                end = line_end(pos);
+            }
+        } else if trimmed.starts_with("#![") {
+            let Some(len) = attr_len(trimmed) else {
+                break;
+            };
+            attrs.push(&trimmed[3..len - 1]);
+            pos = start + len;
+            end = line_end(pos);
+        } else {
+            break;
+        }
+    }
+    (end, attrs)
+}
+
+/// The length of the `#![...]` that `text` starts with, brackets inside string literals aside;
+/// `None` if it isn't closed
+fn attr_len(text: &str) -> Option<usize> {
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, c) in text.char_indices().skip(2) {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            _ if in_string => {}
+            '[' => depth += 1,
+            ']' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i + 1);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
@@ -0,0 +1,46 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// What `--since <ref>` compares the ref against
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiffMode {
+    /// The working tree, including untracked files that aren't ignored
+    Worktree,
+    /// The index only
+    Staged,
+}
+
+fn git(root: &Path, args: &[&str]) -> Result<String, String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(root)
+        .args(args)
+        .output()
+        .map_err(|e| format!("could not run git: {e}"))?;
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).trim().to_string());
+    }
+    String::from_utf8(output.stdout).map_err(|_| "git printed a non UTF-8 path".to_string())
+}
+
+/// Paths below `root`, relative to it, that changed since `since`.
+///
+/// Fails with a human-readable reason when git is missing, `root` is outside a work tree, or
+/// `since` doesn't name a commit, in which case callers fall back to processing everything.
+pub fn changed_paths(root: &Path, since: &str, mode: DiffMode) -> Result<Vec<PathBuf>, String> {
+    git(root, &["rev-parse", "--is-inside-work-tree"])?;
+
+    let mut diff = vec!["diff", "--name-only", "--relative"];
+    if mode == DiffMode::Staged {
+        diff.push("--cached");
+    }
+    diff.extend([since, "--"]);
+    let mut changed = git(root, &diff)?;
+
+    // new files are the most common reason to rerun crateify, and git diff doesn't list them
+    if mode == DiffMode::Worktree {
+        changed.push_str(&git(root, &["ls-files", "--others", "--exclude-standard"])?);
+    }
+
+    Ok(changed.lines().map(PathBuf::from).collect())
+}
@@ -0,0 +1,260 @@
+//! `--graph <path>`: which modules of the generated tree use which, from the `use crate::...`
+//! and `use super::...` items of each module file.
+//!
+//! The paths are resolved against the modules crateify declared in the same pass: a path leads
+//! to the deepest module it names, `crate::net::tcp` for `use crate::net::tcp::connect;`. One
+//! that names an item of the root module is an edge to `crate`. Paths that don't lead anywhere,
+//! such as `super` above the crate or `crate::gone::f` where `gone` is not a module, and files
+//! that don't parse, end up among the warnings of the graph rather than failing the run. Only
+//! `use` items at the top of a file count, not paths written out in code.
+//!
+//! `--graph-format dot` (the default) writes a Graphviz digraph with the warnings as comments,
+//! `--graph-format json` one object carrying `"schema"` ([`GRAPH_SCHEMA`]) and `"version"`
+//! ([`GRAPH_VERSION`]) next to `"modules": [...]`, `"edges": [{"from": ..., "to": ...}]` and
+//! `"warnings": [{"module": ..., "message": ...}]`. `crateify --print-schema crateify-graph`
+//! prints its JSON Schema.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+use std::path::Path;
+
+use schemars::JsonSchema;
+use serde::Serialize;
+use syn::{Item, UseTree};
+
+use crate::error::CrateifyError;
+use crate::write;
+
+/// Identifies the JSON graph in its `"schema"` field
+pub const GRAPH_SCHEMA: &str = "crateify-graph";
+
+/// Version of the JSON graph below; bump it whenever a field is added, removed, or changed.
+pub const GRAPH_VERSION: u32 = 1;
+
+/// What `--graph` writes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GraphFormat {
+    Dot,
+    Json,
+}
+
+impl GraphFormat {
+    pub const NAMES: &[&str] = &["dot", "json"];
+
+    pub fn parse(name: &str) -> Option<GraphFormat> {
+        match name {
+            "dot" => Some(GraphFormat::Dot),
+            "json" => Some(GraphFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+/// The modules of a pass and the `use` paths of their files, until [`ModuleGraph::write`]
+/// resolves them
+#[derive(Debug, Default)]
+pub struct ModuleGraph {
+    /// Every declared module by path, `crate::net::tcp`, the root included
+    modules: BTreeSet<String>,
+    /// The `crate` and `super` paths used by each module file, as their segments
+    uses: BTreeMap<String, Vec<Vec<String>>>,
+    /// Modules whose file couldn't be read for its paths, with why
+    unreadable: BTreeMap<String, String>,
+}
+
+/// A module using another
+#[derive(Serialize, JsonSchema)]
+struct Edge {
+    /// The module whose file has the `use` item, `crate::app`
+    from: String,
+    /// The module its path leads to, `crate::net::tcp`
+    to: String,
+}
+
+/// A path that leads nowhere, or a module file that couldn't be read
+#[derive(Serialize, JsonSchema)]
+struct Warning {
+    module: String,
+    message: String,
+}
+
+/// The JSON graph, `--graph-format json`
+#[derive(Serialize, JsonSchema)]
+#[schemars(rename = "CrateifyGraph")]
+struct Resolved {
+    schema: String,
+    version: u32,
+    /// Every declared module by path, the root `crate` included, sorted
+    modules: Vec<String>,
+    /// Sorted by `from`, then `to`
+    edges: Vec<Edge>,
+    warnings: Vec<Warning>,
+}
+
+impl ModuleGraph {
+    pub fn add_module(&mut self, module: String) {
+        self.modules.insert(module);
+    }
+
+    /// Records the paths `source`, the file of `module`, uses
+    pub fn add_file(&mut self, module: &str, source: &str) {
+        match used_paths(source) {
+            Ok(paths) => {
+                self.uses.insert(module.to_string(), paths);
+            }
+            Err(e) => {
+                self.unreadable
+                    .insert(module.to_string(), format!("does not parse: {e}"));
+            }
+        }
+    }
+
+    /// Records that the file of `module` couldn't be read, because of `reason`
+    pub fn add_unreadable(&mut self, module: &str, reason: String) {
+        self.unreadable.insert(module.to_string(), reason);
+    }
+
+    pub fn clear(&mut self) {
+        *self = ModuleGraph::default();
+    }
+
+    /// The module `path`, used by `module`, leads to, or why it leads nowhere
+    fn resolve(&self, module: &str, path: &[String]) -> Result<String, String> {
+        let shown = path.join("::");
+        let mut current: Vec<&str> = module.split("::").collect();
+        let mut rest = path;
+        if rest.first().is_some_and(|s| s == "crate") {
+            current.truncate(1);
+            rest = &rest[1..];
+        } else {
+            while rest.first().is_some_and(|s| s == "super") {
+                if current.len() == 1 {
+                    return Err(format!("{shown} goes above the crate root"));
+                }
+                current.pop();
+                rest = &rest[1..];
+            }
+        }
+        let anchor = current.join("::");
+        let mut target = anchor.clone();
+        while let Some((segment, after)) = rest.split_first() {
+            let child = format!("{target}::{segment}");
+            if !self.modules.contains(&child) {
+                break;
+            }
+            target = child;
+            rest = after;
+        }
+        // an item of the anchor is one segment, anything longer goes through a module that isn't
+        if target == anchor && rest.len() > 1 {
+            return Err(format!("{shown}: {} is not a module", rest[0]));
+        }
+        Ok(target)
+    }
+
+    /// Resolves every path and writes the graph to `path` in `format`, replacing it in one step
+    pub fn write(&self, path: &Path, format: GraphFormat) -> Result<(), CrateifyError> {
+        let mut edges = BTreeSet::new();
+        let mut warnings = Vec::new();
+        for (module, reason) in &self.unreadable {
+            warnings.push(Warning {
+                module: module.clone(),
+                message: reason.clone(),
+            });
+        }
+        for (module, paths) in &self.uses {
+            for used in paths {
+                match self.resolve(module, used) {
+                    Ok(target) if target != *module => {
+                        edges.insert((module.as_str(), target));
+                    }
+                    Ok(_) => {}
+                    Err(message) => warnings.push(Warning {
+                        module: module.clone(),
+                        message,
+                    }),
+                }
+            }
+        }
+        let content = match format {
+            GraphFormat::Dot => {
+                let quoted = |s: &str| serde_json::Value::from(s).to_string();
+                let mut dot = "digraph crate {\n".to_string();
+                for warning in &warnings {
+                    dot.push_str(&format!(
+                        "    // warning: {}: {}\n",
+                        warning.module,
+                        warning.message.replace('\n', " ")
+                    ));
+                }
+                for module in &self.modules {
+                    dot.push_str(&format!("    {};\n", quoted(module)));
+                }
+                for (from, to) in &edges {
+                    dot.push_str(&format!("    {} -> {};\n", quoted(from), quoted(to)));
+                }
+                dot.push_str("}\n");
+                dot
+            }
+            GraphFormat::Json => {
+                let resolved = Resolved {
+                    schema: GRAPH_SCHEMA.to_string(),
+                    version: GRAPH_VERSION,
+                    modules: self.modules.iter().cloned().collect(),
+                    edges: edges
+                        .into_iter()
+                        .map(|(from, to)| Edge {
+                            from: from.to_string(),
+                            to,
+                        })
+                        .collect(),
+                    warnings,
+                };
+                let mut json = serde_json::to_string_pretty(&resolved)
+                    .map_err(|e| CrateifyError::io(path, io::Error::from(e)))?;
+                json.push('\n');
+                json
+            }
+        };
+        write::write_atomic(path, content.as_bytes())
+    }
+}
+
+/// The JSON Schema of the JSON graph, for `--print-schema`
+pub fn json_schema() -> serde_json::Value {
+    schemars::schema_for!(Resolved).to_value()
+}
+
+/// The paths starting from `crate` or `super` that the top-level `use` items of `source` bring
+/// in, each as its segments; a glob or `self` stands for the module it is in
+fn used_paths(source: &str) -> Result<Vec<Vec<String>>, syn::Error> {
+    let file = syn::parse_file(source)?;
+    let mut paths = Vec::new();
+    for item in &file.items {
+        if let Item::Use(item) = item {
+            expand(&item.tree, &mut Vec::new(), &mut paths);
+        }
+    }
+    paths.retain(|p: &Vec<String>| p.first().is_some_and(|s| s == "crate" || s == "super"));
+    Ok(paths)
+}
+
+/// Adds the whole paths of `tree` below `prefix` to `paths`
+fn expand(tree: &UseTree, prefix: &mut Vec<String>, paths: &mut Vec<Vec<String>>) {
+    match tree {
+        UseTree::Path(path) => {
+            prefix.push(path.ident.to_string());
+            expand(&path.tree, prefix, paths);
+            prefix.pop();
+        }
+        UseTree::Group(group) => {
+            for tree in &group.items {
+                expand(tree, prefix, paths);
+            }
+        }
+        UseTree::Name(name) if name.ident == "self" => paths.push(prefix.clone()),
+        UseTree::Name(name) => paths.push([&prefix[..], &[name.ident.to_string()]].concat()),
+        UseTree::Rename(rename) => paths.push([&prefix[..], &[rename.ident.to_string()]].concat()),
+        UseTree::Glob(_) => paths.push(prefix.clone()),
+    }
+}
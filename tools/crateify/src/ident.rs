@@ -0,0 +1,271 @@
+//! Turning file and directory names into `mod` declarations that parse.
+
+use crate::visibility::Visibility;
+
+/// Strict and reserved keywords of the 2018 and later editions, which `r#` makes usable as
+/// module names
+const RAW_KEYWORDS: &[&str] = &[
+    "abstract", "as", "async", "await", "become", "box", "break", "const", "continue", "do", "dyn",
+    "else", "enum", "extern", "false", "final", "fn", "for", "gen", "if", "impl", "in", "let",
+    "loop", "macro", "match", "mod", "move", "mut", "override", "priv", "pub", "ref", "return",
+    "static", "struct", "trait", "true", "try", "type", "typeof", "unsafe", "unsized", "use",
+    "virtual", "where", "while", "yield",
+];
+
+/// Keywords that can't be raw identifiers either; their modules get a new name and a `#[path]`
+const PATH_KEYWORDS: &[&str] = &["crate", "self", "super", "Self"];
+
+/// Crates every crate can name in paths without declaring them, which a module of the same name
+/// shadows in its parent: `core::ffi::c_int` next to `pub mod core;` is looked up in the module
+const STANDARD_CRATES: &[&str] = &["alloc", "core", "proc_macro", "std"];
+
+/// What to do with a module named like a crate, see [`shadowed_crate`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shadowing {
+    /// Declare it all the same, with a warning
+    Warn,
+    /// Fail its directory
+    Deny,
+    /// Declare it with a trailing `_` and a `#[path]`: `core_`
+    Rename,
+}
+
+/// Whether a module declared as `ident` shadows one of the [`STANDARD_CRATES`] or the crate's own
+/// package `crate_name`, under which its library is known to its binaries and tests
+pub fn shadowed_crate(ident: &str, crate_name: Option<&str>) -> bool {
+    STANDARD_CRATES.contains(&ident)
+        || crate_name.is_some_and(|name| name.replace('-', "_") == ident)
+}
+
+/// Device names Windows reserves in every directory, whatever the case or extension: `con.rs`
+/// and `Aux/` can't be created there, so a crate holding them can't be checked out
+const WINDOWS_DEVICES: &[&str] = &["aux", "con", "nul", "prn"];
+
+/// Whether Windows reserves the directory entry of a module named `name`, the stem of its file
+/// or its directory name: `con`, `NUL`, `com1`, `lpt9.old`
+pub fn windows_reserved(name: &str) -> bool {
+    let base = name.split('.').next().unwrap_or(name).to_ascii_lowercase();
+    if WINDOWS_DEVICES.contains(&base.as_str()) {
+        return true;
+    }
+    ["com", "lpt"].iter().any(|port| {
+        base.strip_prefix(port)
+            .is_some_and(|n| n.len() == 1 && matches!(n.as_bytes()[0], b'1'..=b'9'))
+    })
+}
+
+/// A module found on disk: a `.rs` file or a directory with its own mod.rs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Module {
+    /// File stem or directory name
+    name: String,
+    is_dir: bool,
+    /// Identifier to declare the module as instead of one derived from `name`
+    alias: Option<String>,
+}
+
+impl Module {
+    /// The module of `<stem>.rs`
+    pub fn file(stem: &str) -> Module {
+        Module {
+            name: stem.to_string(),
+            is_dir: false,
+            alias: None,
+        }
+    }
+
+    /// The module of directory `name`
+    pub fn dir(name: &str) -> Module {
+        Module {
+            name: name.to_string(),
+            is_dir: true,
+            alias: None,
+        }
+    }
+
+    /// File stem or directory name
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.is_dir
+    }
+
+    /// Declares the module as `ident`, which must be a valid non-keyword identifier, with a
+    /// `#[path]` to its entry
+    pub fn rename(&mut self, ident: String) {
+        self.alias = Some(ident);
+    }
+
+    /// The name of the directory entry this module comes from: `net` or `tcp.rs`
+    pub fn file_name(&self) -> String {
+        if self.is_dir {
+            self.name.clone()
+        } else {
+            format!("{}.rs", self.name)
+        }
+    }
+
+    /// The directory entry this module comes from, as shown in messages: `net/` or `tcp.rs`
+    pub fn entry(&self) -> String {
+        if self.is_dir {
+            format!("{}/", self.name)
+        } else {
+            format!("{}.rs", self.name)
+        }
+    }
+
+    /// The identifier the module is declared as, without any `r#`, plus whether it differs from
+    /// the name on disk so that the declaration needs a `#[path]`
+    pub fn ident(&self) -> (String, bool) {
+        let name = self.name.as_str();
+        if let Some(alias) = &self.alias {
+            (alias.clone(), true)
+        } else if !is_identifier(name) {
+            (sanitize(name), true)
+        } else if PATH_KEYWORDS.contains(&name) {
+            (format!("{name}_"), true)
+        } else {
+            (name.to_string(), false)
+        }
+    }
+
+    /// The snake_case identifier `--snake-case` declares the module as, or `None` if it already
+    /// is one: `ParserState` -> `parser_state`, `HTTPServer` -> `http_server`
+    pub fn snake_case_ident(&self) -> Option<String> {
+        let (ident, _) = self.ident();
+        if is_snake_case(&ident) {
+            return None;
+        }
+        let chars: Vec<char> = ident.chars().collect();
+        let mut snake = String::new();
+        for (i, &c) in chars.iter().enumerate() {
+            if c.is_ascii_uppercase() && i > 0 {
+                let prev = chars[i - 1];
+                let next_lower = chars.get(i + 1).is_some_and(char::is_ascii_lowercase);
+                // a word starts after a lowercase letter or digit, or at the last capital of an
+                // acronym followed by lowercase letters
+                if prev.is_ascii_lowercase()
+                    || prev.is_ascii_digit()
+                    || (prev.is_ascii_uppercase() && next_lower)
+                {
+                    snake.push('_');
+                }
+            }
+            if c == '_' && snake.ends_with('_') && snake.len() > 1 {
+                continue;
+            }
+            snake.push(c.to_ascii_lowercase());
+        }
+        // `Crate` and `Self` must not turn into keywords that can't be raw identifiers
+        if PATH_KEYWORDS.contains(&snake.as_str()) {
+            snake.push('_');
+        }
+        Some(snake)
+    }
+
+    /// The identifier as written in the parent's mod.rs, in `mod` declarations and `use` paths:
+    /// `r#match` for a keyword
+    pub fn path_segment(&self) -> String {
+        let (ident, _) = self.ident();
+        if RAW_KEYWORDS.contains(&ident.as_str()) {
+            format!("r#{ident}")
+        } else {
+            ident
+        }
+    }
+
+    /// The lines declaring this module in its parent's mod.rs, where `base` leads from the
+    /// directory of the parent's file to this module's directory entry: empty, or `foo/` in a
+    /// `foo.rs`
+    pub fn declaration(&self, visibility: Visibility, base: &str) -> String {
+        let vis = visibility.prefix();
+        let segment = self.path_segment();
+        if self.ident().1 {
+            let path = if self.is_dir {
+                format!("{base}{}/mod.rs", self.name)
+            } else {
+                format!("{base}{}", self.entry())
+            };
+            self.declaration_at(visibility, &path)
+        } else {
+            format!("{vis}mod {segment};\n")
+        }
+    }
+
+    /// The lines declaring this module with a `#[path]` to `path`, wherever it is
+    pub fn declaration_at(&self, visibility: Visibility, path: &str) -> String {
+        let vis = visibility.prefix();
+        let segment = self.path_segment();
+        format!("#[path = {path:?}]\n{vis}mod {segment};\n")
+    }
+
+    /// The lines declaring this directory module inline in its parent's file under `--flatten`,
+    /// with the lines of `body` inside; a `#[path]` on an inline module names the directory its
+    /// own modules are found in
+    pub fn inline_declaration(&self, visibility: Visibility, body: &str) -> String {
+        let vis = visibility.prefix();
+        let segment = self.path_segment();
+        let mut lines = String::new();
+        if self.ident().1 {
+            lines.push_str(&format!("#[path = {:?}]\n", self.name));
+        }
+        if body.is_empty() {
+            lines.push_str(&format!("{vis}mod {segment} {{}}\n"));
+            return lines;
+        }
+        lines.push_str(&format!("{vis}mod {segment} {{\n"));
+        for line in body.lines() {
+            lines.push_str("    ");
+            lines.push_str(line);
+            lines.push('\n');
+        }
+        lines.push_str("}\n");
+        lines
+    }
+}
+
+/// The package name of a `--workspace` crate in directory `name`, sanitized like a module name
+/// since its library is referred to by it; cargo rejects keywords even as raw identifiers
+pub fn package_name(name: &str) -> String {
+    let (ident, _) = Module::dir(name).ident();
+    if RAW_KEYWORDS.contains(&ident.as_str()) {
+        format!("{ident}_")
+    } else {
+        ident
+    }
+}
+
+/// Whether rustc's `non_snake_case` lint accepts `ident`: no capitals, and no run of
+/// underscores except at either end
+fn is_snake_case(ident: &str) -> bool {
+    let inner = ident.trim_matches('_');
+    !inner.contains("__") && !inner.chars().any(|c| c.is_ascii_uppercase())
+}
+
+/// Whether `name` can be written as a module name as is (possibly as a raw identifier); only
+/// ASCII identifiers count, so that declarations never depend on Unicode identifier rules
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    let Some(first) = chars.next() else {
+        return false;
+    };
+    name != "_"
+        && (first.is_ascii_alphabetic() || first == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// `http-parser` -> `http_parser`, `9p` -> `_9p`, `foo.bar` -> `foo_bar`
+fn sanitize(name: &str) -> String {
+    let mut ident: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    // every replaced character became `_`, so the result can't be a keyword; it can still
+    // start with a digit or be `_` alone
+    if ident.is_empty() || ident == "_" || ident.starts_with(|c: char| c.is_ascii_digit()) {
+        ident.insert(0, '_');
+    }
+    ident
+}
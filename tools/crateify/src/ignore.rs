@@ -0,0 +1,243 @@
+//! Entries the traversal leaves out of the module tree.
+//!
+//! Build output, dependencies, and VCS or editor metadata are not part of a crate's module tree,
+//! and `target/` in particular can hold thousands of directories. `--no-default-ignores` turns
+//! this off. On top of that, `--exclude <glob>` leaves out whatever the user names, and so do
+//! the [`IGNORE_FILE`]s of the tree.
+
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+
+use globset::{Glob, GlobBuilder, GlobMatcher, GlobSet, GlobSetBuilder};
+
+use crate::dir_handle;
+use crate::error::CrateifyError;
+
+/// Directory names skipped besides hidden ones
+const DEFAULT_IGNORES: &[&str] = &["target", "node_modules", ".git"];
+
+/// Whether the directory at `path` is hidden or in [`DEFAULT_IGNORES`]
+pub fn is_ignored_dir(path: &Path) -> bool {
+    let Some(name) = path.file_name() else {
+        return false;
+    };
+    let name = name.to_string_lossy();
+    name.starts_with('.') || DEFAULT_IGNORES.contains(&name.as_ref())
+}
+
+/// The `--exclude` patterns, matched against paths relative to the crate directory with `/`
+/// separators: `*` and `?` stay within one path component, `**` spans any number of them, and a
+/// trailing `/` only matches directories
+pub struct Excludes {
+    patterns: Vec<String>,
+    set: GlobSet,
+}
+
+impl Excludes {
+    pub fn new(patterns: Vec<String>) -> Result<Excludes, CrateifyError> {
+        let mut builder = GlobSetBuilder::new();
+        for pattern in &patterns {
+            builder.add(glob(pattern)?);
+        }
+        let set = builder
+            .build()
+            .map_err(|e| CrateifyError::Usage(format!("invalid --exclude patterns: {e}")))?;
+        Ok(Excludes { patterns, set })
+    }
+
+    /// The first pattern matching `relative`, a directory if `is_dir`
+    pub fn matching(&self, relative: &str, is_dir: bool) -> Option<&str> {
+        self.set
+            .matches(relative)
+            .into_iter()
+            .map(|i| self.patterns[i].as_str())
+            .find(|pattern| is_dir || !pattern.ends_with('/'))
+    }
+}
+
+fn glob(pattern: &str) -> Result<Glob, CrateifyError> {
+    // the trailing `/` of `experimental/` is checked in `matching`; paths never end with one
+    GlobBuilder::new(pattern.trim_end_matches('/'))
+        .literal_separator(true)
+        .build()
+        .map_err(|e| CrateifyError::Usage(format!("invalid --exclude pattern {pattern}: {e}")))
+}
+
+/// The file of gitignore-style patterns that leaves entries of its directory, and of those below
+/// it, out of the module tree
+pub const IGNORE_FILE: &str = ".crateifyignore";
+
+/// A line of an [`IGNORE_FILE`]
+struct Rule {
+    /// As written, for the log
+    pattern: String,
+    /// `!`: re-includes what an earlier rule, or an ignore file further up, ignores
+    negated: bool,
+    /// A trailing `/`: only matches directories
+    dir_only: bool,
+    glob: GlobMatcher,
+}
+
+/// The rules of one [`IGNORE_FILE`], in order
+struct IgnoreFile {
+    path: PathBuf,
+    rules: Vec<Rule>,
+}
+
+impl IgnoreFile {
+    /// The rules of `text`, read from `path`, as git reads a `.gitignore`: blank lines and `#`
+    /// comments are skipped, a pattern with a `/` other than a trailing one is anchored to the
+    /// directory of the file, and any other pattern matches at any depth below it
+    fn parse(path: &Path, text: &str) -> Result<IgnoreFile, CrateifyError> {
+        let mut rules = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let mut pattern = trim_trailing_spaces(line);
+            if pattern.is_empty() || pattern.starts_with('#') {
+                continue;
+            }
+            let negated = pattern.starts_with('!');
+            // `!` negates, and a backslash makes a leading `!` or `#` part of the name
+            if negated || pattern.starts_with("\\!") || pattern.starts_with("\\#") {
+                pattern = &pattern[1..];
+            }
+            let dir_only = pattern.ends_with('/');
+            let pattern = pattern.trim_end_matches('/');
+            let glob = if pattern.contains('/') {
+                pattern.trim_start_matches('/').to_string()
+            } else {
+                format!("**/{pattern}")
+            };
+            let glob = GlobBuilder::new(&glob)
+                .literal_separator(true)
+                .build()
+                .map_err(|e| CrateifyError::InvalidIgnorePattern {
+                    file: path.to_path_buf(),
+                    line: i + 1,
+                    reason: e.kind().to_string(),
+                })?
+                .compile_matcher();
+            rules.push(Rule {
+                pattern: line.trim_end().to_string(),
+                negated,
+                dir_only,
+                glob,
+            });
+        }
+        Ok(IgnoreFile {
+            path: path.to_path_buf(),
+            rules,
+        })
+    }
+
+    /// The last rule matching `relative`, a path from the directory of the file with `/`
+    /// separators, and a directory if `is_dir`
+    fn matching(&self, relative: &str, is_dir: bool) -> Option<&Rule> {
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| (is_dir || !rule.dir_only) && rule.glob.is_match(relative))
+    }
+}
+
+/// `line` without its trailing spaces, except one escaped with a backslash
+fn trim_trailing_spaces(line: &str) -> &str {
+    let trimmed = line.trim_end();
+    if trimmed.ends_with('\\') && trimmed.len() < line.len() {
+        &line[..trimmed.len() + 1]
+    } else {
+        trimmed
+    }
+}
+
+/// What ignores an entry: a pattern and the [`IGNORE_FILE`] it is in
+#[derive(Debug)]
+pub struct Ignored {
+    pub pattern: String,
+    pub file: PathBuf,
+}
+
+/// The [`IGNORE_FILE`]s that apply in each directory of the current pass, from the top down,
+/// each read the first time an entry below its directory is looked up
+#[derive(Default)]
+pub struct IgnoreFiles {
+    chains: Mutex<HashMap<PathBuf, Arc<Vec<Arc<IgnoreFile>>>>>,
+}
+
+impl IgnoreFiles {
+    /// Forgets the files read so far, which may have changed since
+    pub fn clear(&mut self) {
+        self.chains
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clear();
+    }
+
+    /// What ignores `path`, a directory if `is_dir`: the nearest ignore file with a rule that
+    /// matches it decides, from the directory of `path` up to `top`, and within that file the
+    /// last such rule. `None` if no rule matches or that rule is negated.
+    pub fn matching(
+        &self,
+        top: &Path,
+        path: &Path,
+        is_dir: bool,
+    ) -> Result<Option<Ignored>, CrateifyError> {
+        let Some(dir) = path.parent() else {
+            return Ok(None);
+        };
+        for file in self.chain(top, dir)?.iter().rev() {
+            let base = file.path.parent().unwrap_or(Path::new(""));
+            let relative = path.strip_prefix(base).unwrap_or(path);
+            let relative = relative
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            if let Some(rule) = file.matching(&relative, is_dir) {
+                return Ok((!rule.negated).then(|| Ignored {
+                    pattern: rule.pattern.clone(),
+                    file: file.path.clone(),
+                }));
+            }
+        }
+        Ok(None)
+    }
+
+    /// The ignore files of `dir` and the directories above it up to `top`, reading those of the
+    /// directories not looked up yet; parents are visited first, so that is usually just `dir`
+    fn chain(&self, top: &Path, dir: &Path) -> Result<Arc<Vec<Arc<IgnoreFile>>>, CrateifyError> {
+        let mut unread = Vec::new();
+        let mut chain = Arc::new(Vec::new());
+        {
+            let chains = self.chains.lock().unwrap_or_else(PoisonError::into_inner);
+            for ancestor in dir.ancestors() {
+                if let Some(known) = chains.get(ancestor) {
+                    chain = Arc::clone(known);
+                    break;
+                }
+                unread.push(ancestor);
+                if ancestor == top {
+                    break;
+                }
+            }
+        }
+        for dir in unread.into_iter().rev() {
+            let path = dir.join(IGNORE_FILE);
+            match dir_handle::read_to_string(&path) {
+                Ok(text) => {
+                    let mut files = Vec::clone(&chain);
+                    files.push(Arc::new(IgnoreFile::parse(&path, &text)?));
+                    chain = Arc::new(files);
+                }
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(CrateifyError::io(&path, e)),
+            }
+            self.chains
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .insert(dir.to_path_buf(), Arc::clone(&chain));
+        }
+        Ok(chain)
+    }
+}
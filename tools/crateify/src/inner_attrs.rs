@@ -0,0 +1,77 @@
+//! `--inner-attr` and `--inner-attr-file`: crate-level attributes written as `#![...]` lines at the
+//! top of each root module, such as `#![allow(dead_code)]` for freshly translated code.
+//!
+//! The file lists one attribute per line, with a `#` outside of string literals starting a
+//! comment. An attribute may be given bare, `allow(dead_code)`, or in full,
+//! `#![allow(dead_code)]`; either way it has to parse as attribute syntax.
+
+use std::fs;
+use std::path::Path;
+
+use crate::error::CrateifyError;
+
+/// The inner attributes of every root module, in the order given
+#[derive(Default)]
+pub struct InnerAttrs(Vec<String>);
+
+impl InnerAttrs {
+    /// Adds an `--inner-attr` attribute
+    pub fn add(&mut self, attr: &str) -> Result<(), CrateifyError> {
+        let attr = parse(attr)
+            .map_err(|reason| CrateifyError::Usage(format!("--inner-attr {attr}: {reason}")))?;
+        self.0.push(attr);
+        Ok(())
+    }
+
+    /// Adds the attributes listed in the file at `path`
+    pub fn read_file(&mut self, path: &Path) -> Result<(), CrateifyError> {
+        let content = fs::read_to_string(path).map_err(|e| CrateifyError::io(path, e))?;
+        for (n, line) in content.lines().enumerate() {
+            let line = line.trim();
+            let line = line[..comment_start(line)].trim();
+            if line.is_empty() {
+                continue;
+            }
+            let attr = parse(line).map_err(|reason| {
+                CrateifyError::Usage(format!("{}:{}: {reason}", path.display(), n + 1))
+            })?;
+            self.0.push(attr);
+        }
+        Ok(())
+    }
+
+    /// The `#![...]` lines, each with its newline
+    pub fn lines(&self) -> String {
+        self.0.iter().map(|attr| format!("#![{attr}]\n")).collect()
+    }
+}
+
+/// Where the comment on a line of an attribute file starts: at the first `#` outside a string
+/// literal, other than the one of a `#![` the line starts with; the end of the line if none does
+fn comment_start(line: &str) -> usize {
+    let skip = if line.starts_with("#![") { 1 } else { 0 };
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, c) in line.char_indices().skip(skip) {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '#' if !in_string => return i,
+            _ => {}
+        }
+    }
+    line.len()
+}
+
+/// The inside of the brackets of `attr`, checked to be attribute syntax
+pub fn parse(attr: &str) -> Result<String, String> {
+    let attr = attr.trim();
+    let inner = attr
+        .strip_prefix("#![")
+        .and_then(|rest| rest.strip_suffix(']'))
+        .unwrap_or(attr)
+        .trim();
+    syn::parse_str::<syn::Meta>(inner).map_err(|e| format!("{inner} is not an attribute: {e}"))?;
+    Ok(inner.to_string())
+}
@@ -0,0 +1,1881 @@
+//! Lays out the module tree of a crate translated file by file: a mod.rs in each directory
+//! declaring its files and subdirectories, and a root module on top.
+//!
+//! The `crateify` command is a thin wrapper over [`cli::run`]. Programs that would rather not
+//! scrape its stderr set up a run with [`Crateify`] and get back a [`Report`]:
+//!
+//! ```no_run
+//! use ideas_crateify::{Crateify, Visibility};
+//!
+//! let report = Crateify::new("translated/src")
+//!     .exclude("**/generated/**")
+//!     .visibility(Visibility::PubCrate)
+//!     .dry_run(true)
+//!     .run()?;
+//! for (path, content) in &report.generated {
+//!     println!("{}:\n{content}", path.display());
+//! }
+//! # Ok::<(), ideas_crateify::CrateifyError>(())
+//! ```
+
+mod builder;
+pub mod cli;
+mod config;
+mod diagnostic;
+mod dir_handle;
+mod error;
+mod events;
+mod file_attrs;
+mod git;
+mod graph;
+mod ident;
+mod ignore;
+mod inner_attrs;
+mod log;
+mod managed;
+mod manifest;
+mod module_tree;
+mod overlay;
+mod platform;
+mod prelude;
+mod progress;
+mod provenance;
+mod prune;
+mod reexport;
+mod rustfmt;
+mod test_modules;
+mod validate;
+mod verify;
+mod visibility;
+mod watch;
+mod write;
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Instant;
+
+use diagnostic::{Code, ErrorFormat};
+use events::Events;
+use file_attrs::FileAttrs;
+use graph::{GraphFormat, ModuleGraph};
+use ident::Module;
+use ignore::{Excludes, IgnoreFiles, Ignored};
+use inner_attrs::InnerAttrs;
+use log::{debug, info, stderr};
+use managed::{Existing, GENERATED_MARKER};
+use manifest::BinTarget;
+use module_tree::{Binary, ModuleTree};
+use overlay::Overlay;
+use platform::CfgRules;
+use prelude::{PRELUDE_FILE, Prelude};
+use provenance::Provenance;
+use reexport::Export;
+use test_modules::TestModules;
+use visibility::Visibilities;
+use write::Output;
+
+pub use builder::{Crateify, Report};
+pub use error::CrateifyError;
+pub use events::Event;
+pub use ident::Shadowing;
+pub use module_tree::{ChildModule, Directory};
+pub use progress::Progress;
+pub use prune::PruneEmpty;
+pub use reexport::Reexport;
+pub use visibility::Visibility;
+pub use write::Change;
+
+/// What to do once a directory fails
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FailurePolicy {
+    /// Report the failure and carry on with the rest of the tree
+    KeepGoing,
+    /// Report the failure and stop visiting directories, still writing the summary
+    FailFast,
+}
+
+/// A directory given on the command line
+struct Root {
+    dir: PathBuf,
+    /// The crate directories: `dir` itself, or one per member under `--workspace`
+    crates: Vec<PathBuf>,
+    /// Write lib.rs instead of mod.rs in the crate directories
+    lib_root: bool,
+    /// Directories to regenerate; `None` means all of them
+    affected: Option<HashSet<PathBuf>>,
+}
+
+/// State shared by every directory of a single crateify run
+struct Context {
+    /// In the order given, none inside another
+    roots: Vec<Root>,
+    /// `--workspace`: every crate directory gets a root module, even without modules
+    workspace: bool,
+    /// `--cargo-init` and `--workspace`: directories holding a main.rs become binaries, see
+    /// [`manifest`]
+    binaries: bool,
+    /// The directories of the current pass that do
+    bin_dirs: Mutex<BTreeSet<PathBuf>>,
+    /// `--hybrid`: the C files left untranslated are compiled by a generated build.rs
+    hybrid: bool,
+    /// The `.c` and `.h` files found in the current pass under `--hybrid`
+    c_files: Mutex<BTreeSet<PathBuf>>,
+    /// Shared by the workers of a pass, which take turns so that lines never interleave
+    events: Mutex<Events>,
+    /// Drawn on stderr, or handed to a library callback, see [`progress`]
+    progress: progress::Tracker,
+    native_paths: bool,
+    /// `--quiet`: nothing on stderr but errors
+    quiet: bool,
+    /// A library run: not even errors go to stderr, they are all in its [`Report`]
+    report: bool,
+    /// The content of each module file of the current pass by path, for the [`Report`]
+    generated: Mutex<BTreeMap<PathBuf, String>>,
+    /// Skip hidden directories, `target/` and the like, see [`ignore`]
+    default_ignores: bool,
+    excludes: Excludes,
+    /// The `.crateifyignore` files read in the current pass
+    ignore_files: IgnoreFiles,
+    /// Leave entries with non-UTF-8 names out of mod.rs instead of failing their directory
+    skip_invalid_names: bool,
+    /// Write a mod.rs without declarations in directories without modules, instead of leaving
+    /// them out of their parent
+    keep_empty: bool,
+    /// Delete, or only report, the directories below each crate that contribute no modules
+    prune_empty: PruneEmpty,
+    /// Report declarations that went away, and delete generated mod.rs files left without modules
+    clean: bool,
+    /// `--module-style file`: the module file of `foo/` is `foo.rs` next to it, see
+    /// [`Context::module_file`]
+    file_modules: bool,
+    /// `--flatten`: directory modules below a crate directory are declared inline in its root
+    /// module instead of having files of their own, see [`gen_interm_mod_rs`]
+    flatten: bool,
+    /// The bodies of the directories of the current pass declared inline, until their parents
+    /// take them
+    inline_modules: Mutex<HashMap<PathBuf, String>>,
+    /// Rename file modules that share their name with a sibling directory module
+    merge_collisions: bool,
+    /// Declare entries whose names only differ in case under distinct names instead of failing
+    allow_case_collisions: bool,
+    /// Declare modules whose names aren't snake_case under a snake_case name
+    snake_case: bool,
+    /// What to do with modules named like `core`, `std` or the crate itself
+    shadowing: Shadowing,
+    /// `--crate-name`: the package, which modules shadow like a standard crate
+    crate_name: Option<String>,
+    /// Declare modules whose entries Windows reserves with a trailing `_` instead of failing
+    rename_reserved: bool,
+    /// Overwrite mod.rs files that crateify didn't generate
+    force: bool,
+    /// Crate documentation put at the top of the root module
+    header: Option<String>,
+    /// `#![...]` lines that follow it
+    inner_attrs: InnerAttrs,
+    /// `--file-attr` and `--remove-file-attrs`
+    file_attrs: FileAttrs,
+    /// Start generated files with the bare marker instead of a version and input path
+    no_header: bool,
+    /// `--fmt`: format generated files with rustfmt before comparing and writing them
+    fmt: bool,
+    /// Set once a missing rustfmt has been reported, so that it only is once a run
+    rustfmt_missing: AtomicBool,
+    /// Count a header recording another crateify version as a change, and rewrite it
+    strict_version: bool,
+    /// What goes in front of each `mod` declaration
+    visibilities: Visibilities,
+    /// The `use` lines that go after them
+    reexport: Reexport,
+    /// `--prelude`, with the module tree its selectors resolve in
+    prelude: Option<Prelude>,
+    /// `#[cfg]` predicates of platform-specific modules
+    cfgs: CfgRules,
+    /// `--provenance`: the C source each declaration gets a doc comment about
+    provenance: Option<Provenance>,
+    /// Which modules are declared as `#[cfg(test)] mod`
+    tests: TestModules,
+    /// Where generated files go: the disk, or stdout as diffs under `--dry-run`
+    output: Output,
+    /// `--output`: the directory the generated files go to instead of the crate directory
+    overlay: Option<Overlay>,
+    policy: FailurePolicy,
+    /// Runs the directories of a pass in parallel
+    workers: rayon::ThreadPool,
+    /// Descend into symlinked directories, see [`visit_dir`]
+    follow_symlinks: bool,
+    /// `--max-depth`: how many levels below a crate directory to go at most
+    max_depth: Option<usize>,
+    /// Canonical paths of the directories visited so far under `--follow-symlinks`
+    visited: Mutex<HashSet<PathBuf>>,
+    /// Where `--manifest` writes the module tree after each pass
+    manifest: Option<PathBuf>,
+    /// The directories of the current pass that go in the manifest, by root
+    manifest_dirs: Mutex<BTreeMap<String, Vec<Directory>>>,
+    /// Where `--graph` writes which modules use which after each pass, and how
+    graph: Option<(PathBuf, GraphFormat)>,
+    /// The modules of the current pass and the paths their files use, for the graph
+    module_graph: Mutex<ModuleGraph>,
+    /// Set by the first failure under `--fail-fast`
+    stopped: AtomicBool,
+    /// Totals of the current pass
+    counts: Mutex<Counts>,
+}
+
+/// What one pass over the tree did, reported in its summary
+#[derive(Debug, Default)]
+struct Counts {
+    directories: usize,
+    files: usize,
+    mod_rs: usize,
+    /// The module files of `mod_rs` by what writing them changed (or would change)
+    created: usize,
+    updated: usize,
+    unchanged: usize,
+    /// Modules declared in them, keep blocks aside
+    modules: usize,
+    /// Entries neither declared nor descended into, but for the crate's own files
+    skipped: usize,
+    warnings: usize,
+    /// Directories that failed, which were visited or are crate directories
+    failed: usize,
+    /// Other errors, such as a symlink cycle, that failed no directory of their own
+    errors: usize,
+    /// Files that `--check` found missing or different
+    stale: usize,
+    /// Entries left out by each `--exclude` pattern that matched any
+    excluded: BTreeMap<String, usize>,
+}
+
+impl Counts {
+    /// Fails with [`CrateifyError::Failed`] if any directory did, with [`CrateifyError::Errors`]
+    /// if there were other errors, or with [`CrateifyError::Stale`] if `--check` found files out
+    /// of date; the failures and files have already been reported one by one
+    fn into_result(self) -> Result<(), CrateifyError> {
+        if self.failed > 0 {
+            Err(CrateifyError::Failed(self.failed))
+        } else if self.errors > 0 {
+            Err(CrateifyError::Errors(self.errors))
+        } else if self.stale > 0 {
+            Err(CrateifyError::Stale(self.stale))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl Context {
+    /// The root `path` is in
+    fn root_of(&self, path: &Path) -> &Root {
+        self.roots
+            .iter()
+            .find(|root| path.starts_with(&root.dir))
+            .unwrap_or(&self.roots[0])
+    }
+
+    /// What ignores the entry `path`, a directory if `is_dir`, among the `.crateifyignore` files
+    /// from its directory up to its translation directory
+    fn ignored(&self, path: &Path, is_dir: bool) -> Result<Option<Ignored>, CrateifyError> {
+        self.ignore_files
+            .matching(&self.root_of(path).dir, path, is_dir)
+    }
+
+    /// Whether `dir` is the root directory of a crate
+    fn is_crate_root(&self, dir: &Path) -> bool {
+        self.root_of(dir).crates.iter().any(|c| c == dir)
+    }
+
+    /// The crate directory `path` is in
+    fn crate_of(&self, path: &Path) -> &Path {
+        let root = self.root_of(path);
+        root.crates
+            .iter()
+            .find(|c| path.starts_with(c))
+            .unwrap_or(&root.dir)
+    }
+
+    /// The module file crateify writes for `dir`: its lib.rs or mod.rs for a crate directory, and
+    /// below that `dir/mod.rs`, or `dir.rs` next to it under `--module-style file`; all of them in
+    /// the mirror of `dir` under `--output`
+    fn module_file(&self, dir: &Path) -> PathBuf {
+        self.mirror(&self.module_file_in_place(dir))
+    }
+
+    /// Where the files crateify generates for `path` go: `path` itself, or its mirror under
+    /// `--output`
+    fn mirror(&self, path: &Path) -> PathBuf {
+        match &self.overlay {
+            Some(overlay) => overlay.mirror(path),
+            None => path.to_path_buf(),
+        }
+    }
+
+    /// [`Context::module_file`] in the crate directory itself
+    fn module_file_in_place(&self, dir: &Path) -> PathBuf {
+        if self.is_crate_root(dir) {
+            dir.join(if self.root_of(dir).lib_root {
+                "lib.rs"
+            } else {
+                "mod.rs"
+            })
+        } else if self.file_modules {
+            let mut name = dir.file_name().unwrap_or_default().to_os_string();
+            name.push(".rs");
+            dir.with_file_name(name)
+        } else {
+            dir.join("mod.rs")
+        }
+    }
+
+    /// Renders `path` for reports: relative to its root, or with several roots, starting with the
+    /// root as given; with `/` separators on every platform unless `--native-paths` was given
+    fn display_path(&self, path: &Path) -> String {
+        if self.roots.len() > 1 {
+            return self.render(path);
+        }
+        self.local_path(path)
+    }
+
+    /// [`Context::display_path`] relative to the root of `path` even when there are several, or
+    /// to its mirror under `--output`
+    fn local_path(&self, path: &Path) -> String {
+        if let Some(relative) = self.overlay.as_ref().and_then(|o| o.relative(path)) {
+            return self.render(relative);
+        }
+        self.render(path.strip_prefix(&self.root_of(path).dir).unwrap_or(path))
+    }
+
+    fn render(&self, path: &Path) -> String {
+        if self.native_paths {
+            path.to_string_lossy().into_owned()
+        } else {
+            slash_path(path)
+        }
+    }
+
+    /// `path` relative to its root with `/` separators, as `--exclude` patterns see it
+    fn relative_path(&self, path: &Path) -> String {
+        slash_path(path.strip_prefix(&self.root_of(path).dir).unwrap_or(path))
+    }
+
+    /// The `[[bin]]` targets found below `crate_dir` in the current pass
+    fn bin_targets(&self, crate_dir: &Path) -> Vec<BinTarget> {
+        let dirs = self.bin_dirs.lock().unwrap_or_else(PoisonError::into_inner);
+        manifest::bin_targets(crate_dir, dirs.iter().filter(|d| d.starts_with(crate_dir)))
+    }
+
+    /// The `.c` and `.h` files found below `crate_dir` in the current pass, sorted
+    fn c_files(&self, crate_dir: &Path) -> Vec<PathBuf> {
+        let files = self.c_files.lock().unwrap_or_else(PoisonError::into_inner);
+        files
+            .iter()
+            .filter(|f| f.starts_with(crate_dir))
+            .cloned()
+            .collect()
+    }
+
+    /// The Rust path of the module of directory `dir`: `crate::net::r#async`
+    fn module_path(&self, dir: &Path) -> String {
+        let root = self.root_of(dir);
+        let crate_root = root.crates.iter().find(|c| dir.starts_with(c));
+        let relative = dir
+            .strip_prefix(crate_root.unwrap_or(&root.dir))
+            .unwrap_or(dir);
+        let mut path = "crate".to_string();
+        for component in relative.components() {
+            let name = component.as_os_str().to_string_lossy();
+            path.push_str("::");
+            path.push_str(&Module::dir(&name).path_segment());
+        }
+        path
+    }
+
+    /// Updates the totals of the current pass
+    fn count(&self, update: impl FnOnce(&mut Counts)) {
+        update(&mut self.counts.lock().unwrap_or_else(PoisonError::into_inner));
+    }
+
+    fn emit(&self, event: Event) -> Result<(), CrateifyError> {
+        self.events
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .emit(event)
+    }
+
+    /// Whether to print notes on stderr, about what the run did that is neither a warning nor an
+    /// error: not under `--quiet`, nor under `--error-format json`
+    fn notes(&self) -> bool {
+        !self.quiet && diagnostic::format() == ErrorFormat::Human
+    }
+
+    /// Whether `--fail-fast` saw a failure, so that no further directories get visited
+    fn stopped(&self) -> bool {
+        self.stopped.load(Ordering::Relaxed)
+    }
+
+    /// Prints a warning on stderr and reports it in the event stream
+    fn warn(&self, code: Code, path: &Path, message: String) -> Result<(), CrateifyError> {
+        if !self.quiet {
+            diagnostic::print(
+                diagnostic::Level::Warning,
+                code,
+                &self.display_path(path),
+                &message,
+                None,
+            );
+        }
+        self.count(|c| c.warnings += 1);
+        self.emit(Event::Warning {
+            path: self.display_path(path),
+            message,
+        })
+    }
+
+    /// `content`, to be written at `path`, formatted by rustfmt under `--fmt`; as it is, with a
+    /// warning, when rustfmt can't format it
+    fn format(&self, path: &Path, content: String) -> Result<String, CrateifyError> {
+        if !self.fmt {
+            return Ok(content);
+        }
+        let dir = path
+            .parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .unwrap_or(Path::new("."));
+        match rustfmt::format(&content, dir) {
+            Ok(formatted) => Ok(formatted),
+            Err(rustfmt::Unformatted::Missing(rustfmt)) => {
+                if !self.rustfmt_missing.swap(true, Ordering::Relaxed) {
+                    let message = format!(
+                        "--fmt: {} not found, writing files unformatted",
+                        rustfmt.to_string_lossy()
+                    );
+                    self.warn(Code::Rustfmt, path, message)?;
+                }
+                Ok(content)
+            }
+            Err(rustfmt::Unformatted::Failed(reason)) => {
+                let message = format!(
+                    "{}: rustfmt failed, written unformatted: {reason}",
+                    self.display_path(path)
+                );
+                self.warn(Code::Rustfmt, path, message)?;
+                Ok(content)
+            }
+        }
+    }
+
+    /// Prints the error that made directory `dir` fail and reports it in the event stream; only
+    /// an error writing the event stream itself is passed on
+    fn fail(&self, dir: &Path, err: CrateifyError) -> Result<(), CrateifyError> {
+        self.count(|c| c.failed += 1);
+        self.report_error(dir, err)
+    }
+
+    /// [`Context::fail`] for an error about `path` that fails no directory of its own, so that
+    /// it isn't counted as one
+    fn error(&self, path: &Path, err: CrateifyError) -> Result<(), CrateifyError> {
+        self.count(|c| c.errors += 1);
+        self.report_error(path, err)
+    }
+
+    fn report_error(&self, path: &Path, err: CrateifyError) -> Result<(), CrateifyError> {
+        if !self.report {
+            diagnostic::print_error(&self.display_path(path), &err);
+        }
+        if self.policy == FailurePolicy::FailFast {
+            self.stopped.store(true, Ordering::Relaxed);
+        }
+        self.emit(Event::Error {
+            path: self.display_path(path),
+            message: err.to_string(),
+        })
+    }
+}
+
+/// `path` with `/` separators
+fn slash_path(path: &Path) -> String {
+    let mut joined = String::new();
+    for component in path.components() {
+        if component == Component::RootDir {
+            joined.push('/');
+            continue;
+        }
+        if !(joined.is_empty() || joined.ends_with('/')) {
+            joined.push('/');
+        }
+        joined.push_str(&component.as_os_str().to_string_lossy());
+    }
+    joined
+}
+
+/// Every directory whose mod.rs may change because of `changed` paths (relative to `root`): the
+/// parent directory of each path and all of its ancestors up to `root`
+fn affected_dirs(root: &Path, changed: &[PathBuf]) -> HashSet<PathBuf> {
+    let mut dirs = HashSet::new();
+    for path in changed {
+        let parent = path.parent().unwrap_or(Path::new(""));
+        for dir in parent.ancestors() {
+            dirs.insert(root.join(dir));
+        }
+        // its patterns reach every directory below its own
+        if path.file_name().is_some_and(|n| n == ignore::IGNORE_FILE) {
+            let mut below = vec![root.join(parent)];
+            while let Some(dir) = below.pop() {
+                let Ok(entries) = dir_handle::read_dir(&dir) else {
+                    continue;
+                };
+                for path in entries {
+                    if dir_handle::is_dir(&path) && !dir_handle::is_symlink(&path) {
+                        dirs.insert(path.clone());
+                        below.push(path);
+                    }
+                }
+            }
+        }
+    }
+    dirs
+}
+
+/// The public items of the module file at `path` under `--reexport items`, and none otherwise;
+/// a file that doesn't parse is reported and re-exports nothing
+fn read_exports(path: &Path, ctx: &Context) -> Result<Vec<Export>, CrateifyError> {
+    if ctx.reexport != Reexport::Items {
+        return Ok(Vec::new());
+    }
+    match dir_handle::read_to_string(path) {
+        Ok(source) => exports_of(path, &source, ctx),
+        Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+            let message = format!(
+                "{}: not UTF-8, none of its items are re-exported",
+                ctx.display_path(path)
+            );
+            ctx.warn(Code::UnparsedFile, path, message)?;
+            Ok(Vec::new())
+        }
+        Err(e) => Err(CrateifyError::io(path, e)),
+    }
+}
+
+/// [`read_exports`] of a module file whose contents are `source`
+fn exports_of(path: &Path, source: &str, ctx: &Context) -> Result<Vec<Export>, CrateifyError> {
+    if ctx.reexport != Reexport::Items {
+        return Ok(Vec::new());
+    }
+    match reexport::public_items(source) {
+        Ok(exports) => Ok(exports),
+        Err(e) => {
+            let message = format!(
+                "{}: does not parse, none of its items are re-exported: {e}",
+                ctx.display_path(path)
+            );
+            ctx.warn(Code::UnparsedFile, path, message)?;
+            Ok(Vec::new())
+        }
+    }
+}
+
+/// Writes the mod.rs of directory `path` declaring `mods`, where `dir_exports` holds the public
+/// items of each directory module by name under `--reexport items`.
+///
+/// Returns `None` if the directory ends up without a module file its parent can declare, and
+/// otherwise the public items of that file, see [`read_exports`].
+// inspired by https://github.com/stepancheg/rust-protobuf/blob/7131fb244fb1246d2835f5ad7426e607ee7c4a1f/protobuf-codegen/src/gen/mod_rs.rs
+fn gen_interm_mod_rs(
+    path: &Path,
+    mods: Vec<Module>,
+    mut dir_exports: HashMap<String, Vec<Export>>,
+    ctx: &Context,
+) -> Result<Option<Vec<Export>>, CrateifyError> {
+    let mod_path = ctx.module_file(path);
+    let existing = Existing::read(&mod_path)?;
+
+    // skip if we have no mods; a workspace member needs its lib.rs all the same
+    if mods.is_empty() && !ctx.keep_empty && !(ctx.workspace && ctx.is_crate_root(path)) {
+        debug!("{}: no modules, not writing it", mod_path.display());
+        // what is already there still counts: hand-written code, or modules behind a #[cfg]
+        return match existing {
+            Existing::Missing => Ok(None),
+            Existing::Managed { keep, .. } if !keep.is_empty() => {
+                read_exports(&mod_path, ctx).map(Some)
+            }
+            Existing::Managed { .. } if ctx.clean => {
+                let shown = ctx.display_path(&mod_path);
+                if ctx.output.remove(&mod_path, &shown)? {
+                    ctx.count(|c| c.stale += 1);
+                }
+                if ctx.output == Output::Disk && ctx.notes() {
+                    stderr!("crateify: {shown}: removed, no modules left");
+                }
+                Ok(None)
+            }
+            // stale, but only --clean deletes anything
+            Existing::Managed { .. } => Ok(None),
+            // never deleted, not even by --clean
+            Existing::Unmanaged { .. } => read_exports(&mod_path, ctx).map(Some),
+        };
+    }
+
+    // under --flatten, the declarations go in a block of the parent's file instead
+    let inline = ctx.flatten && !ctx.is_crate_root(path);
+    let (old_header, keep, declared) = match existing {
+        _ if inline => {
+            leave_flattened(&mod_path, existing, ctx)?;
+            (None, Vec::new(), HashSet::new())
+        }
+        Existing::Missing => (None, Vec::new(), HashSet::new()),
+        Existing::Managed {
+            header,
+            keep,
+            declared,
+        } => (Some(header), keep, declared),
+        Existing::Unmanaged { keep, .. } if ctx.force => (None, keep, HashSet::new()),
+        Existing::Unmanaged { declared, .. } => {
+            let mut message = format!(
+                "{}: not generated by crateify, left untouched (--force overwrites it)",
+                ctx.display_path(&mod_path)
+            );
+            // the siblings it leaves out are in no module tree at all
+            let undeclared: Vec<String> = mods
+                .iter()
+                .filter(|m| !declared.contains(&m.ident().0))
+                .map(Module::entry)
+                .collect();
+            if !undeclared.is_empty() {
+                message += &format!(", and it doesn't declare {}", undeclared.join(", "));
+            }
+            ctx.warn(Code::UnmanagedFile, &mod_path, message)?;
+            return read_exports(&mod_path, ctx).map(Some);
+        }
+    };
+
+    let mut sorted = mods;
+    if ctx.merge_collisions {
+        // parser.rs next to parser/ becomes parser_file and the directory keeps the plain name;
+        // both need a #[path], rustc rejects a plain `mod parser;` when both exist (E0761)
+        let files: HashSet<String> = sorted
+            .iter()
+            .filter(|m| !m.is_dir())
+            .map(|m| m.ident().0)
+            .collect();
+        let dirs: HashSet<String> = sorted
+            .iter()
+            .filter(|m| m.is_dir())
+            .map(|m| m.ident().0)
+            .collect();
+        for m in &mut sorted {
+            let ident = m.ident().0;
+            if !files.contains(&ident) || !dirs.contains(&ident) {
+                continue;
+            }
+            if m.is_dir() {
+                m.rename(ident);
+            } else {
+                debug!("{}: renaming {} to {ident}_file", path.display(), m.entry());
+                m.rename(format!("{ident}_file"));
+            }
+        }
+    }
+    for m in &mut sorted {
+        let Some(snake) = m.snake_case_ident() else {
+            continue;
+        };
+        if ctx.snake_case {
+            debug!("{}: renaming {} to {snake}", path.display(), m.entry());
+            m.rename(snake);
+        } else {
+            let entry = path.join(m.file_name());
+            let message = format!(
+                "{}: module name {} is not snake_case (--snake-case declares it as {snake})",
+                ctx.display_path(&entry),
+                m.ident().0
+            );
+            ctx.warn(Code::NotSnakeCase, &entry, message)?;
+        }
+    }
+    // `::core` still reaches the crate, but translated code says `core::ffi::c_int`, which next
+    // to the module is looked up in it
+    let mut renamed_from = HashMap::new();
+    for m in &mut sorted {
+        let ident = m.ident().0;
+        if !ident::shadowed_crate(&ident, ctx.crate_name.as_deref()) {
+            continue;
+        }
+        let entry = path.join(m.file_name());
+        match ctx.shadowing {
+            Shadowing::Warn => {
+                let message = format!(
+                    "{}: module {ident} shadows the {ident} crate, `{ident}::` paths next to it \
+                     resolve to the module (--rename-shadowing declares it as {ident}_)",
+                    ctx.display_path(&entry)
+                );
+                ctx.warn(Code::ShadowedCrate, &entry, message)?;
+            }
+            Shadowing::Deny => return Err(CrateifyError::ShadowedCrate { entry, ident }),
+            Shadowing::Rename => {
+                debug!("{}: renaming {} to {ident}_", path.display(), m.entry());
+                m.rename(format!("{ident}_"));
+                renamed_from.insert(m.file_name(), ident);
+            }
+        }
+    }
+    // `con.rs` compiles anywhere, but git can't create it on Windows
+    let mut reserved = Vec::new();
+    for m in &mut sorted {
+        if !ident::windows_reserved(m.name()) {
+            continue;
+        }
+        if ctx.rename_reserved {
+            let ident = m.ident().0;
+            debug!("{}: renaming {} to {ident}_", path.display(), m.entry());
+            m.rename(format!("{ident}_"));
+            renamed_from.insert(m.file_name(), ident);
+        } else {
+            reserved.push(path.join(m.file_name()));
+        }
+    }
+    if !reserved.is_empty() {
+        return Err(CrateifyError::ReservedNames(reserved));
+    }
+    // `Parser.rs` and `parser.rs` are one file on macOS and Windows
+    let mut by_lowercase: BTreeMap<String, Vec<usize>> = BTreeMap::new();
+    for (i, m) in sorted.iter().enumerate() {
+        by_lowercase
+            .entry(m.file_name().to_lowercase())
+            .or_default()
+            .push(i);
+    }
+    for group in by_lowercase.into_values().filter(|g| g.len() > 1) {
+        if !ctx.allow_case_collisions {
+            return Err(CrateifyError::CaseCollision {
+                dir: path.to_path_buf(),
+                entries: group.iter().map(|&i| sorted[i].entry()).collect(),
+            });
+        }
+        // the first in sorted order keeps its name, the others are numbered after it
+        let ident = sorted[group[0]].ident().0;
+        for (n, &i) in group.iter().enumerate().skip(1) {
+            let renamed = format!("{}_{}", ident.to_lowercase(), n + 1);
+            debug!(
+                "{}: renaming {} to {renamed}",
+                path.display(),
+                sorted[i].entry()
+            );
+            sorted[i].rename(renamed);
+        }
+    }
+    sorted.sort_by_cached_key(|m| m.ident().0);
+    // a file reached through a #[path] resolves its own modules like a mod.rs would, below its
+    // own directory rather than below foo/
+    if ctx.file_modules
+        && let Some(m) = sorted.iter().find(|m| m.is_dir() && m.ident().1)
+    {
+        return Err(CrateifyError::RenamedDirectory {
+            dir: path.join(m.name()),
+            ident: m.path_segment(),
+        });
+    }
+    // two entries declared under one name would not compile, and either choice loses a module
+    if let Some(pair) = sorted.windows(2).find(|p| p[0].ident().0 == p[1].ident().0) {
+        let ident = pair[0].ident().0;
+        return Err(CrateifyError::ModuleCollision {
+            dir: path.to_path_buf(),
+            entries: sorted
+                .iter()
+                .filter(|m| m.ident().0 == ident)
+                .map(Module::entry)
+                .collect(),
+            ident,
+        });
+    }
+    if ctx.prelude.is_some()
+        && ctx.is_crate_root(path)
+        && let Some(m) = sorted.iter().find(|m| m.ident().0 == "prelude")
+    {
+        return Err(CrateifyError::ModuleCollision {
+            dir: path.to_path_buf(),
+            entries: vec![m.entry(), PRELUDE_FILE.to_string()],
+            ident: "prelude".to_string(),
+        });
+    }
+
+    // modules declared by hand in a keep block (behind a #[cfg], say) must not appear twice
+    let kept = managed::declared_modules(&keep);
+    sorted.retain(|m| !kept.contains(&m.ident().0));
+    if ctx.graph.is_some() {
+        record_uses(path, &sorted, ctx);
+    }
+    if ctx.clean && ctx.output == Output::Disk && ctx.notes() {
+        let mut gone: Vec<&String> = declared
+            .iter()
+            .filter(|name| !kept.contains(*name) && !sorted.iter().any(|m| m.ident().0 == **name))
+            .collect();
+        gone.sort();
+        for name in gone {
+            stderr!(
+                "crateify: {}: removed stale declaration of {name}",
+                ctx.display_path(&mod_path)
+            );
+        }
+    }
+    let mut header = if ctx.no_header {
+        GENERATED_MARKER.to_string()
+    } else {
+        match ctx.relative_path(path) {
+            input if input.is_empty() => managed::header("."),
+            input => managed::header(&input),
+        }
+    };
+    // a file that only a newer (or older) crateify would write differently is left alone
+    if let Some(old) =
+        old_header.filter(|old| !ctx.strict_version && managed::same_but_version(old, &header))
+    {
+        header = old;
+    }
+    let mut content = if inline {
+        String::new()
+    } else {
+        format!("{header}\n")
+    };
+    if ctx.is_crate_root(path) {
+        for line in ctx.header.iter().flat_map(|header| header.lines()) {
+            content.push_str(format!("//! {line}").trim_end());
+            content.push('\n');
+        }
+        content.push_str(&ctx.inner_attrs.lines());
+    }
+    // a #[path] in foo.rs starts from the directory foo.rs is in, not from foo/
+    let (path_base, path_base_dir) = if ctx.file_modules && !ctx.is_crate_root(path) {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        (
+            format!("{name}/"),
+            path.parent().unwrap_or(path).to_path_buf(),
+        )
+    } else {
+        (String::new(), path.to_path_buf())
+    };
+    let visibilities: Vec<Visibility> = sorted
+        .iter()
+        .map(|m| {
+            if ctx.tests.is_test(&m.file_name(), m.is_dir()) {
+                return Visibility::Private;
+            }
+            let relative = ctx.relative_path(&path.join(m.file_name()));
+            ctx.visibilities.of(&relative, m.is_dir())
+        })
+        .collect();
+    // a private `use` would only add an unused import
+    let mut exports = Vec::new();
+    for (m, visibility) in sorted.iter().zip(&visibilities) {
+        exports.push(if *visibility == Visibility::Private {
+            Vec::new()
+        } else if m.is_dir() {
+            dir_exports.remove(&m.file_name()).unwrap_or_default()
+        } else {
+            read_exports(&path.join(m.file_name()), ctx)?
+        });
+    }
+    drop_clashing_exports(&mod_path, &sorted, &kept, &mut exports, ctx)?;
+    let mut inlined = HashSet::new();
+    for ((m, visibility), exports) in sorted.iter().zip(&visibilities).zip(&exports) {
+        if let Some(provenance) = &ctx.provenance {
+            let relative = ctx.relative_path(&path.join(m.file_name()));
+            content.push_str(&provenance.comment(&relative, m.is_dir()));
+        }
+        // the re-exports of a gated module are gated along with it
+        let cfg = ctx
+            .cfgs
+            .of(m.name(), m.is_dir())
+            .map(|cfg| format!("#[cfg({cfg})]\n"))
+            .unwrap_or_default();
+        content.push_str(&cfg);
+        // private, so that nothing outside of tests can come to depend on it
+        if ctx.tests.is_test(&m.file_name(), m.is_dir()) {
+            content.push_str("#[cfg(test)]\n");
+        }
+        let body = m
+            .is_dir()
+            .then(|| {
+                ctx.inline_modules
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .remove(&path.join(m.file_name()))
+            })
+            .flatten();
+        if let Some(body) = body {
+            content.push_str(&m.inline_declaration(*visibility, &body));
+            inlined.insert(m.file_name());
+        } else if let Some(overlay) = ctx.overlay.as_ref().filter(|_| !m.is_dir()) {
+            // rustc looks for it next to the mirrored module file, where it isn't
+            let base = overlay.mirror(&path_base_dir);
+            let file = overlay.path_attr(&base, &path.join(m.file_name()));
+            content.push_str(&m.declaration_at(*visibility, &file));
+        } else {
+            content.push_str(&m.declaration(*visibility, &path_base));
+        }
+        if *visibility == Visibility::Private || ctx.reexport == Reexport::None {
+            continue;
+        }
+        // `self::` keeps a module named like a crate (`core`, say) from making the path ambiguous
+        let vis = visibility.prefix();
+        let segment = m.path_segment();
+        let names: Vec<&str> = exports.iter().map(|e| e.name.as_str()).collect();
+        let used = match (ctx.reexport, &names[..]) {
+            (Reexport::None, _) | (Reexport::Items, []) => continue,
+            (Reexport::Glob, _) => "*".to_string(),
+            (Reexport::Items, [name]) => name.to_string(),
+            (Reexport::Items, _) => format!("{{{}}}", names.join(", ")),
+        };
+        content.push_str(&cfg);
+        content.push_str(&format!("{vis}use self::{segment}::{used};\n"));
+    }
+    let prelude_at = content.len();
+    for line in &keep {
+        content.push_str(line);
+        content.push('\n');
+    }
+    // the selectors resolve once the rest of the tree is known, and before the root declares
+    // a prelude that one of them would keep from being written
+    if let Some(prelude) = &ctx.prelude
+        && ctx.is_crate_root(path)
+    {
+        prelude.record(path, &sorted, &visibilities, &content);
+        match prelude.resolve(path) {
+            Ok(()) => content.insert_str(prelude_at, "pub mod prelude;\n"),
+            Err(err) => ctx.error(path, err)?,
+        }
+    }
+    if inline {
+        let root_file = ctx.module_file(ctx.crate_of(path));
+        if ctx.manifest.is_some() || ctx.report {
+            // the status is that of the root module, known once it is written
+            let change = Change::Unchanged;
+            record_directory(
+                path,
+                &root_file,
+                change,
+                &sorted,
+                &inlined,
+                &renamed_from,
+                ctx,
+            );
+        }
+        debug!(
+            "{}: declared inline with {} module(s)",
+            path.display(),
+            sorted.len()
+        );
+        ctx.count(|c| c.modules += sorted.len());
+        if let Some(prelude) = &ctx.prelude {
+            prelude.record(path, &sorted, &visibilities, &content);
+        }
+        let exports = exports_of(&root_file, &content, ctx)?;
+        ctx.inline_modules
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(path.to_path_buf(), content);
+        return Ok(Some(exports));
+    }
+    let content = ctx.format(&mod_path, content)?;
+    if ctx.overlay.is_some()
+        && ctx.output == Output::Disk
+        && let Some(dir) = mod_path.parent()
+    {
+        dir_handle::create_dir_all(dir).map_err(|e| CrateifyError::io(dir, e))?;
+    }
+    let change = ctx
+        .output
+        .write(&mod_path, &ctx.display_path(&mod_path), &content)?;
+    if ctx.output.is_stale(change) {
+        ctx.count(|c| c.stale += 1);
+    }
+    if let Some(prelude) = &ctx.prelude {
+        prelude.record(path, &sorted, &visibilities, &content);
+    }
+    if ctx.manifest.is_some() || ctx.report {
+        record_directory(
+            path,
+            &mod_path,
+            change,
+            &sorted,
+            &inlined,
+            &renamed_from,
+            ctx,
+        );
+    }
+    if ctx.report {
+        ctx.generated
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(mod_path.clone(), content.clone());
+    }
+
+    info!(
+        "{}: {} with {} module(s)",
+        mod_path.display(),
+        match change {
+            Change::Created => "created",
+            Change::Updated => "updated",
+            Change::Unchanged => "unchanged",
+        },
+        sorted.len()
+    );
+    ctx.count(|c| {
+        c.mod_rs += 1;
+        c.modules += sorted.len();
+        match change {
+            Change::Created => c.created += 1,
+            Change::Updated => c.updated += 1,
+            Change::Unchanged => c.unchanged += 1,
+        }
+    });
+    ctx.emit(Event::ModRs {
+        path: ctx.display_path(&mod_path),
+        modules: sorted.len(),
+    })?;
+    exports_of(&mod_path, &content, ctx).map(Some)
+}
+
+/// Deals with the mod.rs that a directory declared inline under `--flatten` has from earlier
+/// runs, which rustc no longer reads: a generated one is stale and goes under `--clean`, and a
+/// hand-written one fails the directory unless `--force` is given
+fn leave_flattened(
+    mod_path: &Path,
+    existing: Existing,
+    ctx: &Context,
+) -> Result<(), CrateifyError> {
+    let shown = ctx.display_path(mod_path);
+    match existing {
+        Existing::Missing => Ok(()),
+        // never deleted, not even by --clean
+        Existing::Unmanaged { .. } if ctx.force => Ok(()),
+        Existing::Unmanaged { .. } => Err(CrateifyError::Flattened(mod_path.to_path_buf())),
+        Existing::Managed { .. } if ctx.clean => {
+            if ctx.output.remove(mod_path, &shown)? {
+                ctx.count(|c| c.stale += 1);
+            }
+            if ctx.output == Output::Disk && ctx.notes() {
+                stderr!("crateify: {shown}: removed, its directory is declared inline");
+            }
+            Ok(())
+        }
+        Existing::Managed { .. } => {
+            let message = format!(
+                "{shown}: no longer read, its directory is declared inline (--clean removes it)"
+            );
+            ctx.warn(Code::StaleFile, mod_path, message)
+        }
+    }
+}
+
+/// Adds directory `path`, whose module file `mod_path` declares `mods`, to `--manifest`; the
+/// directory modules named in `inlined` are declared in a block of that same file, and
+/// `renamed_from` has the identifiers of the renamed ones by entry
+fn record_directory(
+    path: &Path,
+    mod_path: &Path,
+    status: Change,
+    mods: &[Module],
+    inlined: &HashSet<String>,
+    renamed_from: &HashMap<String, String>,
+    ctx: &Context,
+) {
+    let modules = mods
+        .iter()
+        .map(|m| {
+            let source = if inlined.contains(&m.file_name()) {
+                mod_path.to_path_buf()
+            } else if m.is_dir() {
+                ctx.module_file(&path.join(m.file_name()))
+            } else {
+                path.join(m.file_name())
+            };
+            // renaming the entry to its identifier makes the #[path] unnecessary
+            let portable = ctx.rename_reserved && ident::windows_reserved(m.name());
+            ChildModule {
+                name: m.path_segment(),
+                source: ctx.local_path(&source),
+                renamed_from: renamed_from.get(&m.file_name()).cloned(),
+                physical_source: ctx
+                    .overlay
+                    .as_ref()
+                    .map(|overlay| ctx.render(&overlay.physical(&source))),
+                portable_name: portable.then(|| {
+                    let ident = m.ident().0;
+                    if m.is_dir() {
+                        ident
+                    } else {
+                        format!("{ident}.rs")
+                    }
+                }),
+            }
+        })
+        .collect();
+    let directory = Directory {
+        path: ctx.local_path(path),
+        module_path: ctx.module_path(path),
+        module_file: ctx.local_path(mod_path),
+        status,
+        modules,
+    };
+    let mut roots = ctx
+        .manifest_dirs
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
+    let dirs = roots.entry(ctx.render(&ctx.root_of(path).dir)).or_default();
+    // the directories declared inline in it were recorded before it was written
+    for dir in dirs
+        .iter_mut()
+        .filter(|d| d.module_file == directory.module_file)
+    {
+        dir.status = status;
+    }
+    dirs.push(directory);
+}
+
+/// Leaves out of `exports` (the items each of `mods` would re-export) every name that two of
+/// them export, or that is also a module declared in `mod_path` (including the `kept` ones): any
+/// use of such a name would be an error, so it is reported and stays reachable through its path
+fn drop_clashing_exports(
+    mod_path: &Path,
+    mods: &[Module],
+    kept: &HashSet<String>,
+    exports: &mut [Vec<Export>],
+    ctx: &Context,
+) -> Result<(), CrateifyError> {
+    let dir = mod_path.parent().unwrap_or(Path::new(""));
+    let mut sources: BTreeMap<String, Vec<usize>> = BTreeMap::new();
+    for (i, items) in exports.iter().enumerate() {
+        for item in items {
+            sources.entry(item.name.clone()).or_default().push(i);
+        }
+    }
+    let modules: HashSet<String> = mods
+        .iter()
+        .map(Module::path_segment)
+        .chain(kept.iter().cloned())
+        .collect();
+    let mut dropped = HashSet::new();
+    for (name, from) in sources {
+        let entries: Vec<String> = from
+            .iter()
+            .map(|&i| ctx.display_path(&dir.join(mods[i].file_name())))
+            .collect();
+        let value_only = exports[from[0]]
+            .iter()
+            .any(|e| e.name == name && e.value_only);
+        let clash = if from.len() > 1 {
+            format!("{name} is public in both {}", entries.join(" and "))
+        } else if modules.contains(&name) && !value_only {
+            format!("{name} from {} is also the name of a module", entries[0])
+        } else {
+            continue;
+        };
+        let message = format!("{}: {clash}, not re-exported", ctx.display_path(mod_path));
+        ctx.warn(Code::ReexportCollision, mod_path, message)?;
+        dropped.insert(name);
+    }
+    for items in exports {
+        items.retain(|item| !dropped.contains(&item.name));
+    }
+    Ok(())
+}
+
+/// A directory whose entries have been sorted out, waiting for its subdirectories
+struct Pending {
+    mods: Vec<Module>,
+    subdirs: Vec<PathBuf>,
+    /// Entries that would be modules if their names were valid UTF-8
+    invalid: Vec<PathBuf>,
+    /// Canonical paths of the directory and the ones above it, under `--follow-symlinks`
+    chain: Vec<PathBuf>,
+}
+
+/// What visiting a directory found
+enum Visit {
+    /// Its result, without looking below it
+    Done(Option<Vec<Export>>),
+    /// Its entries, whose subdirectories come first
+    Open(Pending),
+}
+
+/// What [`finish_dir`] returns for a directory
+type DirResult = Result<Option<Vec<Export>>, CrateifyError>;
+
+/// A directory on the work-list of [`crateify`]
+struct Task {
+    path: PathBuf,
+    /// 0 for the crate directory
+    depth: usize,
+    /// The canonical paths of the directories above it, under `--follow-symlinks`
+    ancestors: Arc<Vec<PathBuf>>,
+    /// The directory above it, and its place among that one's subdirectories
+    parent: Option<(Arc<Task>, usize)>,
+    /// Set while its subdirectories are being done
+    pending: Mutex<Option<Pending>>,
+    /// The result of each subdirectory, in order
+    results: Mutex<Vec<Option<(PathBuf, DirResult)>>>,
+    /// Subdirectories not done yet
+    remaining: AtomicUsize,
+}
+
+/// Goes through the pre-generated Rust translation directory `crate_dir` and generates the
+/// required mod.rs files at each directory layer, children before parents
+///
+/// The directories are a work-list rather than a recursion, so that the depth of the tree is only
+/// bounded by the heap: each one is a task of the pool, and whichever of its subdirectories is
+/// done last finishes it. Returns what [`finish_dir`] does for `crate_dir`.
+fn crateify(crate_dir: &Path, ctx: &Context) -> DirResult {
+    let done = Mutex::new(None);
+    rayon::in_place_scope(|scope| {
+        let root = Task {
+            path: crate_dir.to_path_buf(),
+            depth: 0,
+            ancestors: Arc::default(),
+            parent: None,
+            pending: Mutex::default(),
+            results: Mutex::default(),
+            remaining: AtomicUsize::new(0),
+        };
+        run_task(scope, Arc::new(root), ctx, &done);
+    });
+    done.into_inner()
+        .unwrap_or_else(PoisonError::into_inner)
+        .expect("the crate directory is done once its scope ends")
+}
+
+/// Visits the directory of `task`, and either spawns its subdirectories or finishes it right away
+fn run_task<'s>(
+    scope: &rayon::Scope<'s>,
+    task: Arc<Task>,
+    ctx: &'s Context,
+    done: &'s Mutex<Option<DirResult>>,
+) {
+    let result = match visit_dir(&task.path, &task.ancestors, task.depth, ctx) {
+        Ok(Visit::Open(mut pending)) if !pending.subdirs.is_empty() => {
+            let subdirs = std::mem::take(&mut pending.subdirs);
+            let ancestors = Arc::new(std::mem::take(&mut pending.chain));
+            task.remaining.store(subdirs.len(), Ordering::Release);
+            *task.results.lock().unwrap_or_else(PoisonError::into_inner) =
+                subdirs.iter().map(|_| None).collect();
+            *task.pending.lock().unwrap_or_else(PoisonError::into_inner) = Some(pending);
+            // a pool takes the task spawned last first, so that with a single thread (as under
+            // --fail-fast) the tree is gone through depth-first and in order
+            for (index, path) in subdirs.into_iter().enumerate().rev() {
+                let child = Task {
+                    path,
+                    depth: task.depth + 1,
+                    ancestors: Arc::clone(&ancestors),
+                    parent: Some((Arc::clone(&task), index)),
+                    pending: Mutex::default(),
+                    results: Mutex::default(),
+                    remaining: AtomicUsize::new(0),
+                };
+                scope.spawn(move |scope| run_task(scope, Arc::new(child), ctx, done));
+            }
+            return;
+        }
+        Ok(Visit::Open(pending)) => finish_dir(&task.path, pending, Vec::new(), ctx),
+        Ok(Visit::Done(module)) => Ok(module),
+        Err(err) => Err(err),
+    };
+    complete(task, result, ctx, done);
+}
+
+/// Hands the `result` of `task` to the directory above it, finishing that one (and so on up the
+/// tree) if it was the last subdirectory left
+fn complete(
+    mut task: Arc<Task>,
+    mut result: DirResult,
+    ctx: &Context,
+    done: &Mutex<Option<DirResult>>,
+) {
+    loop {
+        let Some((parent, index)) = task.parent.clone() else {
+            *done.lock().unwrap_or_else(PoisonError::into_inner) = Some(result);
+            return;
+        };
+        // a failing subtree doesn't keep its siblings from finishing; it is declared as before
+        // the failure, and the run fails either way
+        let module = match result {
+            Ok(module) => Ok(module),
+            Err(err) => ctx.fail(&task.path, err).map(|()| Some(Vec::new())),
+        };
+        parent
+            .results
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)[index] = Some((task.path.clone(), module));
+        if parent.remaining.fetch_sub(1, Ordering::AcqRel) != 1 {
+            return;
+        }
+        let pending = parent
+            .pending
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take()
+            .expect("a directory is pending until its last subdirectory is done");
+        let results = std::mem::take(
+            &mut *parent
+                .results
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner),
+        )
+        .into_iter()
+        .map(|r| r.expect("every subdirectory is done"))
+        .collect();
+        result = finish_dir(&parent.path, pending, results, ctx);
+        task = parent;
+    }
+}
+
+/// Adds `mods`, declared in the module of directory `path`, to the module graph, along with the
+/// paths their files use
+fn record_uses(path: &Path, mods: &[Module], ctx: &Context) {
+    let parent = ctx.module_path(path);
+    let mut graph = ctx
+        .module_graph
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
+    graph.add_module(parent.clone());
+    for m in mods {
+        let module = format!("{parent}::{}", m.path_segment());
+        graph.add_module(module.clone());
+        if m.is_dir() {
+            continue;
+        }
+        match dir_handle::read_to_string(&path.join(m.file_name())) {
+            Ok(source) => graph.add_file(&module, &source),
+            Err(e) => graph.add_unreadable(&module, format!("cannot be read: {e}")),
+        }
+    }
+}
+
+/// Sorts out the entries of the directory `input_path`, `depth` levels below its crate directory,
+/// declaring its files. Under `--follow-symlinks`, `ancestors` are the canonical paths of the
+/// directories above it.
+fn visit_dir(
+    input_path: &Path,
+    ancestors: &[PathBuf],
+    depth: usize,
+    ctx: &Context,
+) -> Result<Visit, CrateifyError> {
+    ctx.progress
+        .visit(input_path, |path| ctx.display_path(path));
+    // a flattened root module is written from the whole tree below it, changed or not, and
+    // so are the module graph and the prelude; the files --file-attr edits are all of them
+    if let Some(affected) = &ctx.root_of(input_path).affected
+        && !affected.contains(input_path)
+        && !ctx.flatten
+        && ctx.graph.is_none()
+        && ctx.prelude.is_none()
+        && ctx.file_attrs.is_empty()
+    {
+        debug!("{}: unchanged, skipped", input_path.display());
+        let mod_path = ctx.module_file(input_path);
+        if !dir_handle::exists(&mod_path) {
+            return Ok(Visit::Done(None));
+        }
+        return read_exports(&mod_path, ctx).map(|exports| Visit::Done(Some(exports)));
+    }
+    if ctx.stopped() {
+        return Ok(Visit::Done(Some(Vec::new())));
+    }
+    let mut chain = Vec::new();
+    if ctx.follow_symlinks {
+        let canonical =
+            dir_handle::canonicalize(input_path).map_err(|e| CrateifyError::io(input_path, e))?;
+        // reached through a link as well as directly, or through two links: writing its mod.rs
+        // from two workers at once would race, and the content is the same either way
+        let first_visit = ctx
+            .visited
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(canonical.clone());
+        if !first_visit {
+            debug!(
+                "{}: already visited through another path",
+                input_path.display()
+            );
+            return Ok(Visit::Done(Some(Vec::new())));
+        }
+        chain.extend_from_slice(ancestors);
+        chain.push(canonical);
+    }
+
+    let mut mods = Vec::<Module>::new();
+    let mut subdirs = Vec::new();
+    let mut invalid = Vec::new();
+    ctx.count(|c| c.directories += 1);
+    debug!("{}: visiting directory", input_path.display());
+
+    // sorted so that reports, and where --fail-fast stops, don't depend on the filesystem
+    let mut entries =
+        dir_handle::read_dir(input_path).map_err(|e| CrateifyError::read_dir(input_path, e))?;
+    entries.sort();
+    for path in entries {
+        let relative = ctx.relative_path(&path);
+        let is_dir = dir_handle::is_dir(&path);
+        if let Some(pattern) = ctx.excludes.matching(&relative, is_dir) {
+            debug!("{}: excluded by {pattern}", path.display());
+            ctx.count(|c| {
+                *c.excluded.entry(pattern.to_string()).or_default() += 1;
+                c.skipped += 1;
+            });
+        } else if let Some(Ignored { pattern, file }) = ctx.ignored(&path, is_dir)? {
+            debug!(
+                "{}: ignored by {pattern} in {}",
+                path.display(),
+                file.display()
+            );
+            ctx.count(|c| c.skipped += 1);
+        } else if is_dir && ctx.default_ignores && ignore::is_ignored_dir(&path) {
+            debug!("{}: ignored directory, skipped", path.display());
+            ctx.count(|c| c.skipped += 1);
+        } else if is_dir && dir_handle::is_symlink(&path) && !ctx.follow_symlinks {
+            info!(
+                "{}: symlinked directory, not followed (--follow-symlinks follows it)",
+                path.display()
+            );
+            ctx.count(|c| c.skipped += 1);
+        } else if is_dir && dir_handle::is_symlink(&path) {
+            let target =
+                dir_handle::canonicalize(&path).map_err(|e| CrateifyError::io(&path, e))?;
+            if chain.contains(&target) {
+                ctx.error(
+                    &path,
+                    CrateifyError::SymlinkCycle {
+                        link: path.clone(),
+                        target,
+                    },
+                )?;
+            } else {
+                subdirs.push(path);
+            }
+        } else if is_dir {
+            subdirs.push(path);
+        } else if path.file_name().is_some_and(|n| n == "mod.rs") {
+            // rustc rejects foo.rs next to foo/mod.rs (E0761)
+            if ctx.file_modules && !ctx.is_crate_root(input_path) {
+                return Err(CrateifyError::MixedModuleStyles(path));
+            }
+            // generated by an earlier run (or about to be) or hand-written, never a module of
+            // itself
+            debug!("{}: existing mod.rs, not declared", path.display());
+        } else if path.extension().is_some_and(|e| e == "rs")
+            && subdirs.contains(&path.with_extension(""))
+            && ctx.file_modules
+        {
+            // generated for the directory (or about to be), or hand-written and left alone
+            debug!(
+                "{}: module file of its directory, not declared",
+                path.display()
+            );
+        } else if path.extension().is_some_and(|e| e == "rs")
+            && subdirs.contains(&path.with_extension(""))
+            && matches!(Existing::read(&path)?, Existing::Managed { .. })
+        {
+            return Err(CrateifyError::MixedModuleStyles(path));
+        } else if path
+            .file_name()
+            .is_some_and(|n| n == "lib.rs" || n == "main.rs")
+        {
+            if ctx.is_crate_root(input_path) {
+                // the crate's entry points: lib.rs is the file being generated (or the library
+                // next to a mod.rs root), main.rs a binary using it
+                debug!("{}: crate entry point, not declared", path.display());
+            } else if ctx.binaries && path.file_name().is_some_and(|n| n == "main.rs") {
+                debug!("{}: entry point of a binary, not declared", path.display());
+            } else {
+                // `pub mod main;` would compile, but below the root these names almost always
+                // come from a translated main.c that was never meant to be a module
+                let message = format!(
+                    "{}: named like a crate entry point, not declared (rename it to declare it)",
+                    ctx.display_path(&path)
+                );
+                ctx.warn(Code::EntryPointName, &path, message)?;
+                ctx.count(|c| c.skipped += 1);
+            }
+        } else if ctx.prelude.is_some()
+            && ctx.is_crate_root(input_path)
+            && path.file_name().is_some_and(|n| n == PRELUDE_FILE)
+        {
+            debug!("{}: prelude, not declared", path.display());
+        } else if write::is_stray_tmp(&path) {
+            // left behind by an interrupted run, the file it was meant to replace is intact
+            debug!("{}: removing stale temporary file", path.display());
+            ctx.output.remove_file(&path)?;
+        } else {
+            // we've reached the deepest directory, so we treat each .rs
+            // source file as its own module; files without an extension (LICENSE, Makefile)
+            // are skipped like any other non-Rust file
+            if path.extension().is_some_and(|e| e == "rs") {
+                let mod_name = path.file_stem().unwrap();
+                if let Some(m) = mod_name.to_str() {
+                    debug!("{}: declaring file module {m}", path.display());
+                    mods.push(Module::file(m));
+                    ctx.count(|c| c.files += 1);
+                    ctx.emit(Event::File {
+                        path: ctx.display_path(&path),
+                    })?;
+                    if !ctx.file_attrs.is_empty() {
+                        file_attrs::edit(&path, ctx)?;
+                    }
+                } else {
+                    invalid.push(path);
+                }
+            } else if ctx.hybrid && path.extension().is_some_and(|e| e == "c" || e == "h") {
+                debug!("{}: not translated yet, built by build.rs", path.display());
+                ctx.c_files
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .insert(path);
+            } else {
+                debug!("{}: not a .rs file, skipped", path.display());
+                ctx.count(|c| c.skipped += 1);
+            }
+        }
+    }
+
+    if let Some(max) = ctx.max_depth
+        && depth >= max
+    {
+        for path in subdirs.drain(..) {
+            let message = format!(
+                "{}: deeper than --max-depth {max}, not descended into",
+                ctx.display_path(&path)
+            );
+            ctx.warn(Code::MaxDepth, &path, message)?;
+            ctx.count(|c| c.skipped += 1);
+        }
+    }
+    ctx.progress.found(subdirs.len());
+    Ok(Visit::Open(Pending {
+        mods,
+        subdirs,
+        invalid,
+        chain,
+    }))
+}
+
+/// Declares the subdirectories of `input_path` that came back with a module file from `results`,
+/// in the order of `pending`'s, and generates its own with [`gen_interm_mod_rs`]
+///
+/// Returns `None` if `input_path` has no module file afterwards, so that its parent only
+/// declares directories that resolve, and otherwise the items it re-exports.
+fn finish_dir(
+    input_path: &Path,
+    pending: Pending,
+    results: Vec<(PathBuf, DirResult)>,
+    ctx: &Context,
+) -> DirResult {
+    let Pending {
+        mut mods,
+        mut invalid,
+        ..
+    } = pending;
+    if ctx.stopped() {
+        return Ok(Some(Vec::new()));
+    }
+    let mut dir_exports = HashMap::new();
+    for (path, module) in results {
+        let module = module?;
+        if ctx.binaries && dir_handle::is_file(&path.join("main.rs")) {
+            info!(
+                "{}: holds main.rs, a binary rather than a module",
+                path.display()
+            );
+            ctx.bin_dirs
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .insert(path);
+            continue;
+        }
+        // save the sub mod name so we can include it in the mod.rs
+        let mod_name = path.file_name().unwrap();
+        let Some(exports) = module else {
+            // `pub mod` on a directory without mod.rs fails with "file not found for module"
+            debug!("{}: no modules below it, not declared", path.display());
+            continue;
+        };
+        if let Some(m) = mod_name.to_str() {
+            debug!("{}: declaring directory module {m}", path.display());
+            mods.push(Module::dir(m));
+            dir_exports.insert(m.to_string(), exports);
+        } else {
+            invalid.push(path);
+        }
+    }
+
+    // files were declared before the subdirectories came back; restore the order of the entries
+    mods.sort_by_cached_key(Module::file_name);
+    invalid.sort();
+
+    // leaving them out would only surface as "file not found for module" once the parent of
+    // this directory is compiled
+    if !invalid.is_empty() && !ctx.skip_invalid_names {
+        return Err(CrateifyError::InvalidNames(invalid));
+    }
+    for path in invalid {
+        let message = format!(
+            "{}: name is not valid UTF-8, skipped",
+            ctx.display_path(&path)
+        );
+        ctx.warn(Code::InvalidModuleName, &path, message)?;
+        ctx.count(|c| c.skipped += 1);
+    }
+    gen_interm_mod_rs(input_path, mods, dir_exports, ctx)
+}
+
+/// Regenerates the mod.rs files of each root's `affected` directories (or every crate's whole
+/// tree), runs `then`, reports the pass's summary over all roots, and returns its totals; only an
+/// error writing the event stream (or from `then`) fails the pass itself
+fn run_pass(
+    ctx: &mut Context,
+    then: impl FnOnce(&Context) -> Result<(), CrateifyError>,
+) -> Result<Counts, CrateifyError> {
+    let started = Instant::now();
+    *ctx.stopped.get_mut() = false;
+    ctx.ignore_files.clear();
+    dir_handle::forget();
+    ctx.visited
+        .get_mut()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clear();
+    ctx.bin_dirs
+        .get_mut()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clear();
+    ctx.c_files
+        .get_mut()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clear();
+    ctx.inline_modules
+        .get_mut()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clear();
+    ctx.module_graph
+        .get_mut()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clear();
+    if let Some(prelude) = &mut ctx.prelude {
+        prelude.clear();
+    }
+    let shared: &Context = ctx;
+    shared
+        .progress
+        .start(shared.roots.iter().map(|root| root.crates.len()).sum());
+    for crate_dir in shared.roots.iter().flat_map(|root| &root.crates) {
+        if let Err(err) = shared.workers.install(|| crateify(crate_dir, shared)) {
+            shared.fail(crate_dir, err)?;
+        }
+    }
+    if shared.prelude.is_some() && !shared.stopped() {
+        for crate_dir in shared.roots.iter().flat_map(|root| &root.crates) {
+            if let Err(err) = prelude::write(crate_dir, shared) {
+                shared.error(crate_dir, err)?;
+            }
+        }
+    }
+    if shared.prune_empty != PruneEmpty::Keep && !shared.stopped() {
+        for crate_dir in shared.roots.iter().flat_map(|root| &root.crates) {
+            if let Err(err) = prune::prune(crate_dir, shared) {
+                shared.error(crate_dir, err)?;
+            }
+        }
+    }
+    shared.progress.finish();
+    then(shared)?;
+
+    let counts = std::mem::take(ctx.counts.get_mut().unwrap_or_else(PoisonError::into_inner));
+    let elapsed = started.elapsed();
+    if let Some(manifest) = &ctx.manifest {
+        let mut binaries: BTreeMap<String, Vec<Binary>> = BTreeMap::new();
+        let mut c_sources: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for root in &ctx.roots {
+            for crate_dir in &root.crates {
+                for file in ctx.c_files(crate_dir) {
+                    if file.extension().is_some_and(|e| e == "c") {
+                        c_sources
+                            .entry(ctx.render(&root.dir))
+                            .or_default()
+                            .push(ctx.local_path(&file));
+                    }
+                }
+                for bin in ctx.bin_targets(crate_dir) {
+                    let dir = crate_dir.join(&bin.dir);
+                    binaries
+                        .entry(ctx.render(&root.dir))
+                        .or_default()
+                        .push(Binary {
+                            path: ctx.local_path(&dir),
+                            name: bin.name,
+                            main: ctx.local_path(&dir.join("main.rs")),
+                            module_file: None,
+                        });
+                }
+            }
+        }
+        let dirs = ctx
+            .manifest_dirs
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner);
+        ModuleTree::new(std::mem::take(dirs), binaries, c_sources).write(manifest)?;
+    }
+    if let Some((graph, format)) = &ctx.graph {
+        ctx.module_graph
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner)
+            .write(graph, *format)?;
+    }
+    let summary = Event::Summary {
+        directories: counts.directories,
+        files: counts.files,
+        mod_rs: counts.mod_rs,
+        created: counts.created,
+        updated: counts.updated,
+        unchanged: counts.unchanged,
+        modules: counts.modules,
+        skipped: counts.skipped,
+        warnings: counts.warnings,
+        failed: counts.failed,
+        elapsed_ms: elapsed.as_millis().try_into().unwrap_or(u64::MAX),
+    };
+    if diagnostic::format() == ErrorFormat::Json && !ctx.report {
+        events::print(&summary)?;
+    }
+    ctx.emit(summary)?;
+
+    if !ctx.notes() {
+        return Ok(counts);
+    }
+    // a symlink cycle, say, is no directory of its own
+    let errors = match counts.errors {
+        0 => String::new(),
+        n => format!(", {n} other error(s)"),
+    };
+    eprintln!(
+        "crateify: {} directories processed: {} succeeded, {} failed{errors}, {} warning(s); \
+         mod.rs: {} created, {} updated, {} unchanged; {} module(s) declared, {} entr{} skipped \
+         in {:.2}s",
+        counts.directories,
+        // a crate directory that fails before it is listed is not among them
+        counts.directories.saturating_sub(counts.failed),
+        counts.failed,
+        counts.warnings,
+        counts.created,
+        counts.updated,
+        counts.unchanged,
+        counts.modules,
+        counts.skipped,
+        if counts.skipped == 1 { "y" } else { "ies" },
+        elapsed.as_secs_f64()
+    );
+    for (pattern, n) in &counts.excluded {
+        eprintln!(
+            "crateify: --exclude {pattern}: {n} entr{} excluded",
+            if *n == 1 { "y" } else { "ies" }
+        );
+    }
+    Ok(counts)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    /// The declarations `gen_interm_mod_rs` writes in the mod.rs of a directory holding `mods`,
+    /// without its header
+    fn declarations(mods: Vec<Module>) -> String {
+        let dir = tempfile::tempdir().unwrap();
+        for m in &mods {
+            let entry = dir.path().join(m.file_name());
+            if m.is_dir() {
+                fs::create_dir(&entry).unwrap();
+            } else {
+                fs::write(&entry, "").unwrap();
+            }
+        }
+        let ctx = Crateify::new(dir.path()).context().unwrap();
+        gen_interm_mod_rs(dir.path(), mods, HashMap::new(), &ctx).unwrap();
+        let text = fs::read_to_string(dir.path().join("mod.rs")).unwrap();
+        let (header, declarations) = text.split_once('\n').unwrap();
+        assert!(header.starts_with(GENERATED_MARKER), "{text}");
+        declarations.to_string()
+    }
+
+    #[test]
+    fn slash_path_separates_components_with_slashes() {
+        assert_eq!(slash_path(Path::new("a/b/mod.rs")), "a/b/mod.rs");
+        assert_eq!(slash_path(Path::new("a//b/./mod.rs")), "a/b/mod.rs");
+        assert_eq!(slash_path(Path::new("/tree/a")), "/tree/a");
+        assert_eq!(slash_path(Path::new("")), "");
+        // joined with the platform's separator, `\` on Windows
+        let joined: PathBuf = ["a", "b", "mod.rs"].iter().collect();
+        assert_eq!(slash_path(&joined), "a/b/mod.rs");
+        if cfg!(windows) {
+            assert_eq!(
+                slash_path(Path::new(r"C:\tree\a\mod.rs")),
+                "C:/tree/a/mod.rs"
+            );
+            assert_eq!(slash_path(Path::new(r"a\b/mod.rs")), "a/b/mod.rs");
+        } else {
+            // a file name character anywhere else
+            assert_eq!(slash_path(Path::new(r"a\b/mod.rs")), r"a\b/mod.rs");
+        }
+    }
+
+    #[test]
+    fn display_path_is_relative_with_native_separators_on_request() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a").join("b").join("mod.rs");
+        let ctx = Crateify::new(dir.path()).context().unwrap();
+        assert_eq!(ctx.display_path(&path), "a/b/mod.rs");
+        let ctx = Crateify {
+            native_paths: true,
+            ..Crateify::new(dir.path())
+        }
+        .context()
+        .unwrap();
+        let native: PathBuf = ["a", "b", "mod.rs"].iter().collect();
+        assert_eq!(ctx.display_path(&path), native.to_string_lossy());
+    }
+
+    #[test]
+    fn keywords_are_raw_identifiers() {
+        assert_eq!(
+            declarations(vec![Module::file("match"), Module::dir("type")]),
+            "pub mod r#match;\npub mod r#type;\n"
+        );
+    }
+
+    #[test]
+    fn names_that_are_no_identifiers_get_a_path() {
+        assert_eq!(
+            declarations(vec![
+                Module::dir("net-io"),
+                Module::file("self"),
+                Module::file("2d")
+            ]),
+            "#[path = \"2d.rs\"]\npub mod _2d;\n\
+             #[path = \"net-io/mod.rs\"]\npub mod net_io;\n\
+             #[path = \"self.rs\"]\npub mod self_;\n"
+        );
+    }
+
+    #[test]
+    fn declarations_are_sorted_by_identifier() {
+        // a-z.rs comes before a_b.rs on disk, but declares a_z
+        assert_eq!(
+            declarations(vec![
+                Module::file("zeta"),
+                Module::file("a-z"),
+                Module::dir("beta"),
+                Module::file("a_b"),
+            ]),
+            "pub mod a_b;\n#[path = \"a-z.rs\"]\npub mod a_z;\npub mod beta;\npub mod zeta;\n"
+        );
+    }
+}
@@ -0,0 +1,140 @@
+//! A minimal stderr logger configured through the `IDEAS_LOG` environment variable.
+//!
+//! `IDEAS_LOG` takes one of `error`, `warn`, `info`, `debug`, or `trace` (case-insensitive);
+//! unset, empty, or `off` keeps crateify quiet. Log lines only ever go to stderr so they never
+//! mix with machine-readable output on stdout.
+//!
+//! The last line of stderr can also hold a status line, the progress bar of a run on a terminal;
+//! log lines and the messages of [`stderr!`] are printed above it, so that it stays last.
+
+use std::env;
+use std::fmt;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::{Mutex, PoisonError};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error = 1,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    fn name(self) -> &'static str {
+        match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+            Level::Trace => "TRACE",
+        }
+    }
+
+    fn parse(s: &str) -> Option<Level> {
+        match s.to_ascii_lowercase().as_str() {
+            "error" => Some(Level::Error),
+            "warn" => Some(Level::Warn),
+            "info" => Some(Level::Info),
+            "debug" => Some(Level::Debug),
+            "trace" => Some(Level::Trace),
+            _ => None,
+        }
+    }
+}
+
+// 0 means logging is off
+static MAX_LEVEL: AtomicU8 = AtomicU8::new(0);
+
+// the status line currently drawn, if any
+static STATUS: Mutex<Option<String>> = Mutex::new(None);
+
+/// Erases the line the cursor is on
+const ERASE_LINE: &str = "\r\x1b[2K";
+
+/// Reads `IDEAS_LOG` and sets the maximum level that gets printed
+pub fn init_from_env() {
+    let level = env::var("IDEAS_LOG").ok().and_then(|v| Level::parse(&v));
+    MAX_LEVEL.store(level.map_or(0, |l| l as u8), Ordering::Relaxed);
+}
+
+/// `-v`: prints everything up to `level`, even if `IDEAS_LOG` asks for less
+pub fn raise_to(level: Level) {
+    MAX_LEVEL.fetch_max(level as u8, Ordering::Relaxed);
+}
+
+/// `--quiet`: prints nothing above `level`, even if `IDEAS_LOG` asks for more
+pub fn cap_at(level: Level) {
+    MAX_LEVEL.fetch_min(level as u8, Ordering::Relaxed);
+}
+
+/// `--error-format json`: prints nothing at all, whatever `IDEAS_LOG` asks for, since stderr
+/// only holds diagnostics then
+pub fn turn_off() {
+    MAX_LEVEL.store(0, Ordering::Relaxed);
+}
+
+pub fn enabled(level: Level) -> bool {
+    level as u8 <= MAX_LEVEL.load(Ordering::Relaxed)
+}
+
+pub fn write(level: Level, args: fmt::Arguments<'_>) {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    line(format_args!(
+        "[{}.{:03} {:5} crateify] {args}",
+        now.as_secs(),
+        now.subsec_millis(),
+        level.name()
+    ));
+}
+
+/// Prints `args` as a line on stderr, above the status line if there is one
+pub fn line(args: fmt::Arguments<'_>) {
+    let status = STATUS.lock().unwrap_or_else(PoisonError::into_inner);
+    let mut stderr = io::stderr().lock();
+    // a closed stderr isn't worth a panic in the middle of a pass
+    let _ = match &*status {
+        Some(shown) => write!(stderr, "{ERASE_LINE}{args}\n{shown}"),
+        None => writeln!(stderr, "{args}"),
+    };
+}
+
+/// Draws `status` as the last line of stderr in place of the one before, or takes it off
+pub fn set_status(status: Option<String>) {
+    let mut shown = STATUS.lock().unwrap_or_else(PoisonError::into_inner);
+    if shown.is_none() && status.is_none() {
+        return;
+    }
+    let mut stderr = io::stderr().lock();
+    let _ = write!(stderr, "{ERASE_LINE}{}", status.as_deref().unwrap_or(""));
+    let _ = stderr.flush();
+    *shown = status;
+}
+
+macro_rules! log_at {
+    ($level:expr, $($arg:tt)+) => {
+        if $crate::log::enabled($level) {
+            $crate::log::write($level, format_args!($($arg)+));
+        }
+    };
+}
+
+/// `eprintln!` for messages printed during a pass, which must not run into its progress bar
+macro_rules! stderr {
+    ($($arg:tt)+) => { $crate::log::line(format_args!($($arg)+)) };
+}
+
+macro_rules! info {
+    ($($arg:tt)+) => { $crate::log::log_at!($crate::log::Level::Info, $($arg)+) };
+}
+
+macro_rules! debug {
+    ($($arg:tt)+) => { $crate::log::log_at!($crate::log::Level::Debug, $($arg)+) };
+}
+
+pub(crate) use {debug, info, log_at, stderr};
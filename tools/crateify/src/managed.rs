@@ -0,0 +1,132 @@
+//! Telling mod.rs files crateify generated apart from hand-written ones.
+//!
+//! Every generated file starts with a [`header`] line, which begins with [`GENERATED_MARKER`] and
+//! records the crateify version and the directory the file was generated from; files written
+//! before that (and under `--no-header`) carry the bare marker. A mod.rs without either is
+//! treated as hand-written and left alone unless `--force` is given. Lines between [`KEEP_START`] and
+//! [`KEEP_END`] survive regeneration, and modules declared there are not declared again.
+
+use std::collections::HashSet;
+use std::io;
+use std::path::Path;
+
+use crate::dir_handle;
+use crate::error::CrateifyError;
+
+/// Start of the first line of every file crateify writes, and all of it under `--no-header`
+pub const GENERATED_MARKER: &str = "// @generated by crateify";
+
+/// The first line of the module file generated from `input`, a directory relative to the
+/// translation directory: `// @generated by crateify 0.1.0 from net; do not edit`
+pub fn header(input: &str) -> String {
+    format!(
+        "{GENERATED_MARKER} {} from {input}; do not edit",
+        env!("CARGO_PKG_VERSION")
+    )
+}
+
+/// Whether `line` is a [`header`] or the bare [`GENERATED_MARKER`] of older files
+fn is_header(line: &str) -> bool {
+    let line = line.trim_end();
+    line == GENERATED_MARKER
+        || line
+            .strip_prefix(GENERATED_MARKER)
+            .is_some_and(|rest| rest.starts_with(' ') && rest.ends_with("; do not edit"))
+}
+
+/// Whether the headers `old` and `new` only differ in the crateify version they record
+pub fn same_but_version(old: &str, new: &str) -> bool {
+    let provenance = |header: &str| {
+        let rest = header.strip_prefix(GENERATED_MARKER)?.strip_prefix(' ')?;
+        rest.split_once(' ').map(|(_, from)| from.to_string())
+    };
+    provenance(old).is_some_and(|from| provenance(new) == Some(from))
+}
+
+/// Opens a block of hand-written lines that regeneration keeps
+pub const KEEP_START: &str = "// crateify: keep-start";
+
+/// Closes a block opened by [`KEEP_START`]
+pub const KEEP_END: &str = "// crateify: keep-end";
+
+/// What is at a mod.rs path before crateify writes it
+pub enum Existing {
+    Missing,
+    /// Written by crateify, with its header line, its keep blocks and the modules it declares
+    Managed {
+        header: String,
+        keep: Vec<String>,
+        declared: HashSet<String>,
+    },
+    /// Hand-written (or not UTF-8), with its keep blocks and the modules it declares
+    Unmanaged {
+        keep: Vec<String>,
+        declared: HashSet<String>,
+    },
+}
+
+impl Existing {
+    pub fn read(path: &Path) -> Result<Existing, CrateifyError> {
+        let content = match dir_handle::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Existing::Missing),
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                return Ok(Existing::Unmanaged {
+                    keep: Vec::new(),
+                    declared: HashSet::new(),
+                });
+            }
+            Err(e) => return Err(CrateifyError::io(path, e)),
+        };
+
+        let keep = keep_lines(&content);
+        let lines: Vec<String> = content.lines().map(str::to_string).collect();
+        let declared = declared_modules(&lines);
+        // only on the first line: a hand-written file may well quote the marker further down
+        if let Some(header) = content.lines().next().filter(|l| is_header(l)) {
+            Ok(Existing::Managed {
+                header: header.trim_end().to_string(),
+                keep,
+                declared,
+            })
+        } else {
+            Ok(Existing::Unmanaged { keep, declared })
+        }
+    }
+}
+
+/// The lines of every keep block in `content`, markers included; an unterminated block runs to
+/// the end of the file
+fn keep_lines(content: &str) -> Vec<String> {
+    let mut keep = Vec::new();
+    let mut inside = false;
+    for line in content.lines() {
+        if line.trim() == KEEP_START {
+            inside = true;
+        }
+        if inside {
+            keep.push(line.to_string());
+        }
+        if line.trim() == KEEP_END {
+            inside = false;
+        }
+    }
+    keep
+}
+
+/// Names of the modules declared by `mod <name>;` items on `lines`, without any `r#`
+pub fn declared_modules(lines: &[String]) -> HashSet<String> {
+    lines
+        .iter()
+        .filter_map(|line| {
+            let item = line.trim().strip_suffix(';')?;
+            let (before, name) = item.rsplit_once("mod ")?;
+            // `mod` must be a whole word, as in `#[cfg(unix)] pub(crate) mod foo`
+            if !(before.is_empty() || before.ends_with([' ', ']', ')'])) {
+                return None;
+            }
+            let name = name.trim();
+            Some(name.strip_prefix("r#").unwrap_or(name).to_string())
+        })
+        .collect()
+}
@@ -0,0 +1,260 @@
+//! `--cargo-init` and `--workspace`: minimal Cargo.toml files that turn the generated module trees
+//! into crates.
+//!
+//! A directory below a crate directory that holds a `main.rs` is a translated executable rather
+//! than a module: its parent leaves it out, and the manifest gets a `[[bin]]` target for it. The
+//! other files next to that `main.rs` still get a `mod.rs` of their own, for the binary to declare
+//! with `#[path = "mod.rs"] mod ...;`.
+//!
+//! Under `--hybrid`, the `.c` files not translated yet are compiled by a `build.rs` next to the
+//! manifest, which gets the `cc` crate as a build dependency for it.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use crate::error::CrateifyError;
+use crate::ident;
+use crate::write::{Change, Output};
+
+/// Editions the generated declarations compile under; raw identifiers need 2018
+pub const EDITIONS: &[&str] = &["2018", "2021", "2024"];
+
+pub const DEFAULT_EDITION: &str = "2021";
+
+/// Where `--cargo-init` puts the manifest: next to the crate directory, the way Cargo.toml sits
+/// next to `src/`
+pub fn manifest_path(crate_dir: &Path) -> Result<PathBuf, CrateifyError> {
+    let crate_dir = fs::canonicalize(crate_dir).map_err(|e| CrateifyError::io(crate_dir, e))?;
+    match crate_dir.parent() {
+        Some(parent) => Ok(parent.join("Cargo.toml")),
+        None => Err(CrateifyError::Usage(
+            "--cargo-init needs a crate directory that has a parent to hold Cargo.toml".to_string(),
+        )),
+    }
+}
+
+/// A `[[bin]]` target of a crate, built from the `main.rs` of a directory below it
+#[derive(Debug)]
+pub struct BinTarget {
+    /// The directory's name as a package name; a `src/` directory is named after its parent
+    pub name: String,
+    /// The directory, relative to the crate directory
+    pub dir: PathBuf,
+}
+
+/// The `[[bin]]` targets of the binary directories `dirs` below `crate_dir`, in order. A name that
+/// an earlier target already took is made of the directory's whole path instead, `tools-cli`
+pub fn bin_targets<'a>(
+    crate_dir: &Path,
+    dirs: impl IntoIterator<Item = &'a PathBuf>,
+) -> Vec<BinTarget> {
+    let mut targets: Vec<BinTarget> = Vec::new();
+    for dir in dirs {
+        let relative = dir.strip_prefix(crate_dir).unwrap_or(dir).to_path_buf();
+        let named = match dir.file_name() {
+            Some(name) if name == "src" => dir.parent().unwrap_or(dir),
+            _ => dir,
+        };
+        let name = named
+            .file_name()
+            .map(|n| ident::package_name(&n.to_string_lossy()))
+            .unwrap_or_default();
+        let name = if targets.iter().any(|t| t.name == name) {
+            relative
+                .components()
+                .map(|c| ident::package_name(&c.as_os_str().to_string_lossy()))
+                .collect::<Vec<_>>()
+                .join("-")
+        } else {
+            name
+        };
+        targets.push(BinTarget {
+            name,
+            dir: relative,
+        });
+    }
+    targets
+}
+
+/// Whether cargo accepts `name` as a package name
+fn is_package_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with(|c: char| c.is_ascii_digit())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+/// The name of the package of `manifest`: `crate_name`, or the name of the directory holding it,
+/// checked before the pass so that a bad one fails the run before anything is written
+pub fn package_name(manifest: &Path, crate_name: Option<&str>) -> Result<String, CrateifyError> {
+    let name = match crate_name {
+        Some(name) => name.to_string(),
+        None => manifest
+            .parent()
+            .and_then(Path::file_name)
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+    };
+    if !is_package_name(&name) {
+        return Err(CrateifyError::Usage(format!(
+            "{name:?} is not a valid package name, pass one with --crate-name"
+        )));
+    }
+    Ok(name)
+}
+
+/// The path of `crate_dir` relative to the directory of the package file `file` (Cargo.toml or
+/// build.rs): `src`, or empty for a crate directory holding it
+fn crate_path(file: &Path, crate_dir: &Path) -> Result<PathBuf, CrateifyError> {
+    let package_dir = file.parent().unwrap_or(Path::new(""));
+    let crate_dir = fs::canonicalize(crate_dir).map_err(|e| CrateifyError::io(crate_dir, e))?;
+    let package_dir =
+        fs::canonicalize(package_dir).map_err(|e| CrateifyError::io(package_dir, e))?;
+    Ok(crate_dir
+        .strip_prefix(&package_dir)
+        .unwrap_or(&crate_dir)
+        .to_path_buf())
+}
+
+/// `path` below `crate_path` with `/` separators, the way Cargo.toml and build.rs spell paths
+fn package_path(crate_path: &Path, path: &Path) -> String {
+    let mut rendered = String::new();
+    for component in crate_path.join(path).components() {
+        // the crate directory is absolute when it isn't below the package, under --output
+        match component {
+            Component::Prefix(prefix) => rendered.push_str(&prefix.as_os_str().to_string_lossy()),
+            Component::RootDir => rendered.push('/'),
+            component => {
+                if !rendered.is_empty() && !rendered.ends_with('/') {
+                    rendered.push('/');
+                }
+                rendered.push_str(&component.as_os_str().to_string_lossy());
+            }
+        }
+    }
+    rendered
+}
+
+/// Writes the manifest at `manifest` (shown as `display`), declaring the root module `lib` as the
+/// library and each of `bins`, below the crate directory `sources` (which holds `lib` unless it
+/// was written to `--output`), as a binary, with `cc` as a build dependency if `hybrid`.
+///
+/// The package is named `crate_name`, or after the directory holding the manifest. Returns how
+/// that changes the manifest, like [`Output::write`].
+#[allow(clippy::too_many_arguments)]
+pub fn write_manifest(
+    output: Output,
+    manifest: &Path,
+    display: &str,
+    lib: &Path,
+    sources: &Path,
+    bins: &[BinTarget],
+    crate_name: Option<&str>,
+    edition: &str,
+    hybrid: bool,
+) -> Result<Change, CrateifyError> {
+    let name = package_name(manifest, crate_name)?;
+    // `src/lib.rs` next to the manifest, or `lib.rs` for a crate directory holding it
+    let lib_dir = crate_path(manifest, lib.parent().unwrap_or(Path::new("")))?;
+    // the same, unless the library was written to --output
+    let bin_dir = crate_path(manifest, sources)?;
+    // relative to the manifest with `/` separators, as a TOML string; JSON string escapes are
+    // valid TOML basic-string escapes
+    let toml_path = |dir: &Path, path: &Path| serde_json::Value::from(package_path(dir, path));
+    let lib_path = toml_path(&lib_dir, Path::new(lib.file_name().unwrap_or_default()));
+    let mut content = format!(
+        "[package]\nname = \"{name}\"\nversion = \"0.1.0\"\nedition = \"{edition}\"\n\n\
+         [lib]\npath = {lib_path}\n"
+    );
+    for bin in bins {
+        content.push_str(&format!(
+            "\n[[bin]]\nname = \"{}\"\npath = {}\n",
+            bin.name,
+            toml_path(&bin_dir, &bin.dir.join("main.rs"))
+        ));
+    }
+    if hybrid {
+        content.push_str("\n[build-dependencies]\ncc = \"1\"\n");
+    }
+    output.write(manifest, display, &content)
+}
+
+/// Writes the `build.rs` at `build_rs` (shown as `display`) that compiles the `.c` files among
+/// `c_files`, the C sources and headers found below `crate_dir`: a static library per directory,
+/// named after its path below `crate_dir`, with every directory holding a `.h` file on the
+/// include path. Returns how that changes the file, like [`Output::write`].
+pub fn write_build_script(
+    output: Output,
+    build_rs: &Path,
+    display: &str,
+    crate_dir: &Path,
+    c_files: &[PathBuf],
+) -> Result<Change, CrateifyError> {
+    let crate_path = crate_path(build_rs, crate_dir)?;
+    // relative to build.rs, which cargo runs from the package directory, as a Rust string
+    let rust_path = |path: &Path| format!("{:?}", package_path(&crate_path, path));
+    let relative = |file: &PathBuf| file.strip_prefix(crate_dir).unwrap_or(file).to_path_buf();
+    let mut groups: BTreeMap<PathBuf, Vec<PathBuf>> = BTreeMap::new();
+    let mut include_dirs = Vec::new();
+    for file in c_files.iter().map(relative) {
+        let dir = file.parent().unwrap_or(Path::new("")).to_path_buf();
+        if file.extension().is_some_and(|e| e == "h") {
+            if !include_dirs.contains(&dir) {
+                include_dirs.push(dir);
+            }
+        } else {
+            groups.entry(dir).or_default().push(file);
+        }
+    }
+
+    let mut content = "//! Compiles the C sources that are not translated to Rust yet, one static \
+                       library per directory.\n\nfn main() {\n"
+        .to_string();
+    for (dir, files) in &groups {
+        content.push_str("    cc::Build::new()\n");
+        for include in &include_dirs {
+            content.push_str(&format!("        .include({})\n", rust_path(include)));
+        }
+        for file in files {
+            content.push_str(&format!("        .file({})\n", rust_path(file)));
+        }
+        // `c` for the crate directory itself, `c_net_proto` for net/proto
+        let library: Vec<String> = std::iter::once("c".to_string())
+            .chain(
+                dir.components()
+                    .map(|c| ident::package_name(&c.as_os_str().to_string_lossy())),
+            )
+            .collect();
+        content.push_str(&format!("        .compile({:?});\n", library.join("_")));
+    }
+    for watched in include_dirs.iter().chain(groups.values().flatten()) {
+        let line = format!(
+            "cargo:rerun-if-changed={}",
+            package_path(&crate_path, watched)
+        );
+        content.push_str(&format!("    println!({line:?});\n"));
+    }
+    content.push_str("}\n");
+    output.write(build_rs, display, &content)
+}
+
+/// Writes the workspace manifest at `manifest` (shown as `display`) listing `members`, the
+/// directories next to it that hold a crate each
+pub fn write_workspace_manifest(
+    output: Output,
+    manifest: &Path,
+    display: &str,
+    members: &[String],
+) -> Result<Change, CrateifyError> {
+    let members: Vec<String> = members
+        .iter()
+        .map(|m| serde_json::Value::from(m.as_str()).to_string())
+        .collect();
+    let content = format!(
+        "[workspace]\nresolver = \"2\"\nmembers = [{}]\n",
+        members.join(", ")
+    );
+    output.write(manifest, display, &content)
+}
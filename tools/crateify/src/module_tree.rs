@@ -0,0 +1,144 @@
+//! `--manifest <path.json>`: which file became which Rust module.
+//!
+//! The file is one JSON object carrying `"schema"` ([`MANIFEST_SCHEMA`]) and `"version"`
+//! ([`MANIFEST_VERSION`]) next to the directories whose module file crateify generated in the
+//! last pass, keyed by the translation directory they are in, as given on the command line.
+//! Their paths are relative to that directory.
+//! Under `--dry-run` and `--check`, it describes what would be generated. `crateify
+//! --print-schema crateify-manifest` prints its JSON Schema.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::path::Path;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::error::CrateifyError;
+use crate::write::{self, Change};
+
+/// Name of the manifest schema, carried by the manifest as `"schema"`
+pub const MANIFEST_SCHEMA: &str = "crateify-manifest";
+
+/// Version of the manifest schema below; bump it whenever a field is added, removed, or changed.
+/// Version 2 keyed the directories by translation directory, version 3 added the binaries, version
+/// 4 the C sources, version 5 the renames of shadowing modules, version 6 the portable names of
+/// entries reserved on Windows, version 7 the physical sources under `--output`, version 8 moved
+/// the module files of binaries from the roots to the binaries.
+pub const MANIFEST_VERSION: u32 = 8;
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[schemars(rename = "CrateifyManifest")]
+pub struct ModuleTree {
+    pub schema: String,
+    pub version: u32,
+    /// The directories of each translation directory, sorted by path, its own first; those of
+    /// binaries are in `binaries`
+    pub roots: BTreeMap<String, Vec<Directory>>,
+    /// The directories of each translation directory that became `[[bin]]` targets instead of
+    /// modules under `--cargo-init` and `--workspace`, sorted by path
+    pub binaries: BTreeMap<String, Vec<Binary>>,
+    /// The `.c` files of each translation directory that the `build.rs` of `--hybrid` compiles,
+    /// sorted
+    pub c_sources: BTreeMap<String, Vec<String>>,
+}
+
+/// A directory and the module file crateify generated for it:
+/// `{"path":"net","module_path":"crate::net","module_file":"net/mod.rs","status":"created","modules":[...]}`
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct Directory {
+    /// Empty for the translation directory itself
+    pub path: String,
+    /// `crate` for the translation directory
+    pub module_path: String,
+    pub module_file: String,
+    /// What the run did (or would do) to `module_file`
+    pub status: Change,
+    /// The modules declared in `module_file`, in declaration order, without the ones declared by
+    /// hand in keep blocks
+    pub modules: Vec<ChildModule>,
+}
+
+/// A directory holding a `main.rs`, left out of its parent module:
+/// `{"path":"tools/text_processor/src","name":"text_processor","main":"tools/text_processor/src/main.rs"}`
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct Binary {
+    pub path: String,
+    /// The name of the `[[bin]]` target, made a valid package name
+    pub name: String,
+    pub main: String,
+    /// The module file generated for the files next to `main`, for it to declare with a
+    /// `#[path]`; neither it nor the module files below it are modules of the library
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub module_file: Option<String>,
+}
+
+/// A module declared in a generated file: `{"name":"tcp","source":"net/tcp.rs"}`
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct ChildModule {
+    /// The identifier the module is declared as, `r#match` for a keyword
+    pub name: String,
+    /// The `.rs` file of the module, `mod.rs` for a directory
+    pub source: String,
+    /// The name `--rename-shadowing` or `--rename-reserved` declared it under `name` instead of,
+    /// `core` for `core_`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub renamed_from: Option<String>,
+    /// For an entry `--rename-reserved` renamed, what to rename it to on disk so that it can be
+    /// checked out on Windows and declared without a `#[path]`: `con_.rs` for `con.rs`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub portable_name: Option<String>,
+    /// Under `--output`, the absolute path rustc reads the module from: the file itself in the
+    /// crate directory for a file module, its generated module file in the output directory for a
+    /// directory
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub physical_source: Option<String>,
+}
+
+impl ModuleTree {
+    pub fn new(
+        mut roots: BTreeMap<String, Vec<Directory>>,
+        mut binaries: BTreeMap<String, Vec<Binary>>,
+        c_sources: BTreeMap<String, Vec<String>>,
+    ) -> ModuleTree {
+        for (root, bins) in &mut binaries {
+            let Some(directories) = roots.get_mut(root) else {
+                continue;
+            };
+            for bin in bins {
+                let bin_dir = Path::new(&bin.path);
+                bin.module_file = directories
+                    .iter()
+                    .find(|d| Path::new(&d.path) == bin_dir)
+                    .map(|d| d.module_file.clone());
+                directories.retain(|d| !Path::new(&d.path).starts_with(bin_dir));
+            }
+        }
+        for directories in roots.values_mut() {
+            directories.sort_by(|a, b| a.path.cmp(&b.path));
+        }
+        for bins in binaries.values_mut() {
+            bins.sort_by(|a, b| a.path.cmp(&b.path));
+        }
+        ModuleTree {
+            schema: MANIFEST_SCHEMA.to_string(),
+            version: MANIFEST_VERSION,
+            roots,
+            binaries,
+            c_sources,
+        }
+    }
+
+    /// Writes the manifest to `path`, replacing it in one step like a generated mod.rs
+    pub fn write(&self, path: &Path) -> Result<(), CrateifyError> {
+        let mut json = serde_json::to_string_pretty(self)
+            .map_err(|e| CrateifyError::io(path, io::Error::from(e)))?;
+        json.push('\n');
+        write::write_atomic(path, json.as_bytes())
+    }
+}
+
+/// JSON Schema describing the manifest
+pub fn json_schema() -> serde_json::Value {
+    schemars::schema_for!(ModuleTree).to_value()
+}
@@ -0,0 +1,111 @@
+//! `--output <dir>`: the generated files go to a separate directory instead of the crate
+//! directory, which is only read.
+//!
+//! The output directory mirrors the crate directory: the module file of `net/` is written to
+//! `<dir>/net/mod.rs`, and `--cargo-init` puts Cargo.toml next to `<dir>` rather than next to the
+//! crate directory. Directory modules resolve within the mirror as they would in place, while
+//! each file module is declared with a `#[path]` to the real file, absolute, or under
+//! `--relative-paths` relative to the directory rustc resolves it from.
+
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use crate::dir_handle;
+use crate::error::CrateifyError;
+
+/// Where the files generated for a crate directory go under `--output`
+#[derive(Debug)]
+pub struct Overlay {
+    /// The crate directory, as given
+    source: PathBuf,
+    /// The output directory, as given
+    dir: PathBuf,
+    /// Both of them canonicalized
+    canonical_source: PathBuf,
+    canonical_dir: PathBuf,
+    /// `--relative-paths`
+    relative: bool,
+}
+
+impl Overlay {
+    /// The overlay of crate directory `source` in `dir`, which is created if it doesn't exist yet
+    pub fn new(source: &Path, dir: &Path, relative: bool) -> Result<Overlay, CrateifyError> {
+        let canonical_source =
+            fs::canonicalize(source).map_err(|e| CrateifyError::io(source, e))?;
+        // checked before creating it, which would already change the crate directory
+        let existing = dir
+            .ancestors()
+            .find(|a| a.exists())
+            .unwrap_or(Path::new("."));
+        let missing = dir.strip_prefix(existing).unwrap_or(Path::new(""));
+        let canonical_existing =
+            fs::canonicalize(existing).map_err(|e| CrateifyError::io(existing, e))?;
+        if canonical_existing
+            .join(missing)
+            .starts_with(&canonical_source)
+        {
+            return Err(CrateifyError::Usage(format!(
+                "--output {} is inside the crate directory {}, which it must leave untouched",
+                dir.display(),
+                source.display()
+            )));
+        }
+        dir_handle::create_dir_all(dir).map_err(|e| CrateifyError::io(dir, e))?;
+        let canonical_dir = fs::canonicalize(dir).map_err(|e| CrateifyError::io(dir, e))?;
+        Ok(Overlay {
+            source: source.to_path_buf(),
+            dir: dir.to_path_buf(),
+            canonical_source,
+            canonical_dir,
+            relative,
+        })
+    }
+
+    /// Where the generated file that belongs at `path` in the crate directory goes
+    pub fn mirror(&self, path: &Path) -> PathBuf {
+        match path.strip_prefix(&self.source) {
+            Ok(relative) if relative.as_os_str().is_empty() => self.dir.clone(),
+            Ok(relative) => self.dir.join(relative),
+            Err(_) => path.to_path_buf(),
+        }
+    }
+
+    /// `path`, in the crate directory or its mirror, relative to either
+    pub fn relative<'a>(&self, path: &'a Path) -> Option<&'a Path> {
+        path.strip_prefix(&self.source)
+            .or_else(|_| path.strip_prefix(&self.dir))
+            .ok()
+    }
+
+    /// `path`, in the crate directory or its mirror, as an absolute path
+    pub fn physical(&self, path: &Path) -> PathBuf {
+        if let Ok(relative) = path.strip_prefix(&self.source) {
+            self.canonical_source.join(relative)
+        } else if let Ok(relative) = path.strip_prefix(&self.dir) {
+            self.canonical_dir.join(relative)
+        } else {
+            path.to_path_buf()
+        }
+    }
+
+    /// The `#[path]` that leads rustc from `base`, the mirror of a directory of the crate, to
+    /// `file`, a file in the crate directory
+    pub fn path_attr(&self, base: &Path, file: &Path) -> String {
+        let target = self.physical(file);
+        if !self.relative {
+            return target.to_string_lossy().into_owned();
+        }
+        let from = self.physical(base);
+        let common = from
+            .components()
+            .zip(target.components())
+            .take_while(|(a, b)| a == b)
+            .count();
+        let up = from.components().skip(common).map(|_| "..".to_string());
+        let down = target.components().skip(common).map(|c| match c {
+            Component::Normal(name) => name.to_string_lossy().into_owned(),
+            other => other.as_os_str().to_string_lossy().into_owned(),
+        });
+        up.chain(down).collect::<Vec<_>>().join("/")
+    }
+}
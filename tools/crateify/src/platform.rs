@@ -0,0 +1,95 @@
+//! Platform-specific modules, declared behind a `#[cfg]` so that each platform only compiles its
+//! own.
+//!
+//! Directories named like a platform ([`DEFAULT_CFGS`], changed with `--cfg-map name=cfg`) are
+//! gated by their name; `--cfg-suffix suffix=cfg` gates files whose stem ends with `_suffix`, as
+//! `io_win32.rs` with `--cfg-suffix 'win32=windows'`.
+
+use std::collections::BTreeMap;
+
+use crate::error::CrateifyError;
+
+/// Directory names gated out of the box, with their `cfg` predicate
+const DEFAULT_CFGS: &[(&str, &str)] = &[
+    ("linux", "target_os = \"linux\""),
+    ("macos", "target_os = \"macos\""),
+    ("unix", "unix"),
+    ("windows", "windows"),
+];
+
+/// `cfg` predicates of the modules that get one
+pub struct CfgRules {
+    dirs: BTreeMap<String, String>,
+    /// In the order given, the first match wins
+    suffixes: Vec<(String, String)>,
+}
+
+impl Default for CfgRules {
+    fn default() -> Self {
+        CfgRules {
+            dirs: DEFAULT_CFGS
+                .iter()
+                .map(|(name, cfg)| (name.to_string(), cfg.to_string()))
+                .collect(),
+            suffixes: Vec::new(),
+        }
+    }
+}
+
+impl CfgRules {
+    /// Adds a `--cfg-map name=cfg` rule, replacing any for that directory name; an empty `cfg`
+    /// leaves the directory ungated
+    pub fn map_dir(&mut self, rule: &str) -> Result<(), CrateifyError> {
+        let (name, cfg) = parse_rule("--cfg-map", rule)?;
+        if cfg.is_empty() {
+            self.dirs.remove(&name);
+        } else {
+            self.dirs.insert(name, cfg);
+        }
+        Ok(())
+    }
+
+    /// Adds a `--cfg-suffix suffix=cfg` rule
+    pub fn add_suffix(&mut self, rule: &str) -> Result<(), CrateifyError> {
+        let (suffix, cfg) = parse_rule("--cfg-suffix", rule)?;
+        if cfg.is_empty() {
+            return Err(CrateifyError::Usage(format!(
+                "--cfg-suffix {rule} has no cfg predicate after the `=`"
+            )));
+        }
+        self.suffixes.push((format!("_{suffix}"), cfg));
+        Ok(())
+    }
+
+    /// The predicate gating the module of directory `name`, or of the file with stem `name`
+    pub fn of(&self, name: &str, is_dir: bool) -> Option<&str> {
+        if is_dir {
+            return self.dirs.get(name).map(String::as_str);
+        }
+        self.suffixes
+            .iter()
+            .find(|(suffix, _)| name.len() > suffix.len() && name.ends_with(suffix.as_str()))
+            .map(|(_, cfg)| cfg.as_str())
+    }
+}
+
+/// Splits `name=cfg` and checks that `cfg` parses as the inside of a `#[cfg(...)]`
+fn parse_rule(flag: &str, rule: &str) -> Result<(String, String), CrateifyError> {
+    let Some((name, cfg)) = rule.split_once('=') else {
+        return Err(CrateifyError::Usage(format!(
+            "{flag} expects <name>=<cfg predicate>, got {rule}"
+        )));
+    };
+    let (name, cfg) = (name.trim(), cfg.trim());
+    if name.is_empty() {
+        return Err(CrateifyError::Usage(format!(
+            "{flag} {rule} has no name before the `=`"
+        )));
+    }
+    if !cfg.is_empty() {
+        syn::parse_str::<syn::Meta>(cfg).map_err(|e| {
+            CrateifyError::Usage(format!("{flag} {rule}: {cfg} is not a cfg predicate: {e}"))
+        })?;
+    }
+    Ok((name.to_string(), cfg.to_string()))
+}
@@ -0,0 +1,382 @@
+//! `--prelude <path>`: a generated `prelude.rs` in each crate directory, declared from the root
+//! module as `pub mod prelude;`, with the `pub use` lines that code using the crate would
+//! otherwise repeat in every file.
+//!
+//! The file lists one selector per line, a path from the crate root with `#` starting a
+//! comment: `types::*` re-exports everything of a module, and `errors::ErrorCode` one item or
+//! module of it. `*` in a segment matches any run of characters, so `ffi::*::*` re-exports
+//! every module of `ffi` and `types::c_*` every item of `types` that starts with `c_`.
+//! Selectors are resolved against the module tree of the pass, just before the root module is
+//! generated, and one that matches nothing is an error: a typo would otherwise only show as an
+//! unresolved import. The root module then doesn't declare the prelude, so that the crate
+//! still compiles without it. The lines start
+//! with `super::`, which is the root module whether that is a lib.rs or a mod.rs.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+
+use globset::{GlobBuilder, GlobMatcher};
+
+use crate::dir_handle;
+use crate::events::Event;
+use crate::ident::Module;
+use crate::log::info;
+use crate::managed::{self, Existing, GENERATED_MARKER};
+use crate::reexport;
+use crate::visibility::Visibility;
+use crate::write::Change;
+use crate::{Code, Context, CrateifyError};
+
+/// The file generated next to the root module
+pub const PRELUDE_FILE: &str = "prelude.rs";
+
+/// A line of the `--prelude` file, split into its segments
+struct Selector {
+    text: String,
+    segments: Vec<Segment>,
+}
+
+enum Segment {
+    Name(String),
+    Glob(GlobMatcher),
+    /// A bare `*`, which as the last segment becomes a glob import
+    All,
+}
+
+impl Segment {
+    fn matches(&self, name: &str) -> bool {
+        let name = name.strip_prefix("r#").unwrap_or(name);
+        match self {
+            Segment::Name(segment) => segment.strip_prefix("r#").unwrap_or(segment) == name,
+            Segment::Glob(glob) => glob.is_match(name),
+            Segment::All => true,
+        }
+    }
+}
+
+/// A module declared in a generated module file
+struct Child {
+    /// As written in paths, `r#match` for a keyword
+    segment: String,
+    /// Its directory, or its `.rs` file
+    entry: PathBuf,
+    is_dir: bool,
+    /// Paths only lead through a module the prelude can see, and only a `pub` one can be
+    /// re-exported itself
+    visibility: Visibility,
+}
+
+/// What a generated module file declares, for resolving selectors
+struct Scope {
+    children: Vec<Child>,
+    /// The generated content, whose `pub use` lines are items of the module as well
+    content: String,
+}
+
+/// The `--prelude` of a run, and the module tree of its current pass
+pub struct Prelude {
+    selectors: Vec<Selector>,
+    scopes: Mutex<HashMap<PathBuf, Scope>>,
+    /// The `pub use` lines of each crate directory whose selectors all resolved
+    uses: Mutex<HashMap<PathBuf, String>>,
+}
+
+impl Prelude {
+    /// The prelude of `selectors`, each checked to be a path
+    pub fn new(selectors: Vec<String>) -> Result<Prelude, CrateifyError> {
+        let selectors = selectors
+            .into_iter()
+            .map(|text| parse(&text).map_err(CrateifyError::Usage))
+            .collect::<Result<_, _>>()?;
+        Ok(Prelude {
+            selectors,
+            scopes: Mutex::default(),
+            uses: Mutex::default(),
+        })
+    }
+
+    /// The selectors listed in the file at `path`
+    pub fn read_file(path: &Path) -> Result<Vec<String>, CrateifyError> {
+        let content = fs::read_to_string(path).map_err(|e| CrateifyError::io(path, e))?;
+        let mut selectors = Vec::new();
+        for (n, line) in content.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            parse(line).map_err(|reason| {
+                CrateifyError::Usage(format!("{}:{}: {reason}", path.display(), n + 1))
+            })?;
+            selectors.push(line.to_string());
+        }
+        Ok(selectors)
+    }
+
+    /// Forgets the module tree of the last pass
+    pub fn clear(&mut self) {
+        self.scopes
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clear();
+        self.uses
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clear();
+    }
+
+    /// Resolves the selectors for `crate_dir`, once the module files of everything below its
+    /// root have been recorded and that of the root itself, so that [`write`] has the lines
+    pub fn resolve(&self, crate_dir: &Path) -> Result<(), CrateifyError> {
+        let uses = self.resolved_uses(crate_dir)?;
+        self.uses
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(crate_dir.to_path_buf(), uses);
+        Ok(())
+    }
+
+    /// Records that the module file generated for directory `dir` declares `mods`, each with
+    /// its visibility, and reads `content`
+    pub fn record(&self, dir: &Path, mods: &[Module], visibilities: &[Visibility], content: &str) {
+        let children = mods
+            .iter()
+            .zip(visibilities)
+            .map(|(m, visibility)| Child {
+                segment: m.path_segment(),
+                entry: dir.join(m.file_name()),
+                is_dir: m.is_dir(),
+                visibility: *visibility,
+            })
+            .collect();
+        self.scopes
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(
+                dir.to_path_buf(),
+                Scope {
+                    children,
+                    content: content.to_string(),
+                },
+            );
+    }
+
+    /// The `pub use` lines of the prelude of `crate_dir`, in the order of the selectors
+    fn resolved_uses(&self, crate_dir: &Path) -> Result<String, CrateifyError> {
+        let scopes = self.scopes.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut lines = Vec::new();
+        let mut seen = HashSet::new();
+        // explicitly re-exported names, by the path they come from
+        let mut names: HashMap<String, String> = HashMap::new();
+        for selector in &self.selectors {
+            let unresolved = |reason: String| CrateifyError::UnresolvedPrelude {
+                selector: selector.text.clone(),
+                reason,
+            };
+            let (last, inner) = selector
+                .segments
+                .split_last()
+                .expect("selectors are not empty");
+            // the modules matching the segments before the last: a path, and a directory or file
+            let mut modules = vec![("crate".to_string(), crate_dir.to_path_buf(), true)];
+            for (depth, segment) in inner.iter().enumerate() {
+                let mut next = Vec::new();
+                for (path, entry, is_dir) in &modules {
+                    let Some(scope) = is_dir.then(|| scopes.get(entry)).flatten() else {
+                        continue;
+                    };
+                    // the private modules of the root are visible to the prelude, next to them
+                    let children = scope.children.iter().filter(|c| {
+                        (depth == 0 || c.visibility != Visibility::Private)
+                            && segment.matches(&c.segment)
+                    });
+                    for child in children {
+                        next.push((
+                            format!("{path}::{}", child.segment),
+                            child.entry.clone(),
+                            child.is_dir,
+                        ));
+                    }
+                }
+                if next.is_empty() {
+                    let parents: Vec<&str> = modules.iter().map(|(p, ..)| p.as_str()).collect();
+                    return Err(unresolved(format!(
+                        "no module in {} matches it",
+                        parents.join(", ")
+                    )));
+                }
+                modules = next;
+            }
+
+            let mut matched = Vec::new();
+            for (path, entry, is_dir) in &modules {
+                // `crate::` would be another crate's root once the root module is a mod.rs
+                let from = path.replacen("crate", "super", 1);
+                if let Segment::All = last {
+                    matched.push((format!("pub use {from}::*;"), None));
+                    continue;
+                }
+                let mut candidates = BTreeSet::new();
+                if *is_dir {
+                    if let Some(scope) = scopes.get(entry) {
+                        candidates.extend(
+                            scope
+                                .children
+                                .iter()
+                                .filter(|c| c.visibility == Visibility::Pub)
+                                .map(|c| c.segment.clone()),
+                        );
+                        candidates.extend(items(&scope.content).map_err(|e| {
+                            unresolved(format!("the module file of {path} does not parse: {e}"))
+                        })?);
+                    }
+                } else {
+                    let source = dir_handle::read_to_string(entry)
+                        .map_err(|e| CrateifyError::io(entry, e))?;
+                    candidates.extend(items(&source).map_err(|e| {
+                        unresolved(format!("{} does not parse: {e}", entry.display()))
+                    })?);
+                }
+                for name in candidates.into_iter().filter(|name| last.matches(name)) {
+                    matched.push((format!("pub use {from}::{name};"), Some((name, path))));
+                }
+            }
+            if matched.is_empty() {
+                let paths: Vec<&str> = modules.iter().map(|(p, ..)| p.as_str()).collect();
+                return Err(unresolved(format!(
+                    "no public item or module in {} matches it",
+                    paths.join(", ")
+                )));
+            }
+            for (line, name) in matched {
+                if let Some((name, path)) = name {
+                    let name = name.strip_prefix("r#").unwrap_or(&name).to_string();
+                    if let Some(other) = names.get(&name).filter(|other| *other != path) {
+                        return Err(unresolved(format!(
+                            "{name} is re-exported from both {other} and {path}"
+                        )));
+                    }
+                    names.insert(name, path.clone());
+                }
+                if seen.insert(line.clone()) {
+                    lines.push(line);
+                }
+            }
+        }
+        Ok(lines.iter().map(|line| format!("{line}\n")).collect())
+    }
+}
+
+/// The selector `text`, or why it isn't one
+fn parse(text: &str) -> Result<Selector, String> {
+    let text = text.trim();
+    let path = text.strip_prefix("crate::").unwrap_or(text);
+    let mut segments = Vec::new();
+    for segment in path.split("::") {
+        let bare = segment.strip_prefix("r#").unwrap_or(segment);
+        let valid = !bare.is_empty()
+            && bare
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '*')
+            && !bare.starts_with(|c: char| c.is_ascii_digit());
+        if !valid {
+            return Err(format!(
+                "{text} is not a path of modules and items, such as types::* or errors::ErrorCode"
+            ));
+        }
+        segments.push(if bare == "*" {
+            Segment::All
+        } else if bare.contains('*') {
+            let glob = GlobBuilder::new(bare)
+                .build()
+                .map_err(|e| format!("{text}: {e}"))?;
+            Segment::Glob(glob.compile_matcher())
+        } else {
+            Segment::Name(segment.to_string())
+        });
+    }
+    Ok(Selector {
+        text: text.to_string(),
+        segments,
+    })
+}
+
+/// The names of the public items of the module file `source`
+fn items(source: &str) -> Result<Vec<String>, syn::Error> {
+    let items = reexport::public_items(source)?;
+    Ok(items.into_iter().map(|item| item.name).collect())
+}
+
+/// Writes the prelude of `crate_dir` once its module tree is known; nothing if its root module
+/// wasn't generated or one of the selectors didn't resolve, which has been reported already
+pub fn write(crate_dir: &Path, ctx: &Context) -> Result<(), CrateifyError> {
+    let Some(prelude) = &ctx.prelude else {
+        return Ok(());
+    };
+    let Some(uses) = prelude
+        .uses
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .get(crate_dir)
+        .cloned()
+    else {
+        return Ok(());
+    };
+    let path = ctx.mirror(&crate_dir.join(PRELUDE_FILE));
+    let shown = ctx.display_path(&path);
+    let (old_header, keep) = match Existing::read(&path)? {
+        Existing::Missing => (None, Vec::new()),
+        Existing::Managed { header, keep, .. } => (Some(header), keep),
+        Existing::Unmanaged { keep, .. } if ctx.force => (None, keep),
+        Existing::Unmanaged { .. } => {
+            let message = format!(
+                "{shown}: not generated by crateify, left untouched (--force overwrites it)"
+            );
+            return ctx.warn(Code::UnmanagedFile, &path, message);
+        }
+    };
+    let mut header = if ctx.no_header {
+        GENERATED_MARKER.to_string()
+    } else {
+        match ctx.relative_path(crate_dir) {
+            input if input.is_empty() => managed::header("."),
+            input => managed::header(&input),
+        }
+    };
+    if let Some(old) =
+        old_header.filter(|old| !ctx.strict_version && managed::same_but_version(old, &header))
+    {
+        header = old;
+    }
+    let mut content = format!("{header}\n");
+    content.push_str(&uses);
+    for line in &keep {
+        content.push_str(line);
+        content.push('\n');
+    }
+    let content = ctx.format(&path, content)?;
+    let change = ctx.output.write(&path, &shown, &content)?;
+    if ctx.output.is_stale(change) {
+        ctx.count(|c| c.stale += 1);
+    }
+    if ctx.report {
+        ctx.generated
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(path.clone(), content);
+    }
+    info!("{}: prelude {change:?}", path.display());
+    ctx.count(|c| {
+        c.mod_rs += 1;
+        match change {
+            Change::Created => c.created += 1,
+            Change::Updated => c.updated += 1,
+            Change::Unchanged => c.unchanged += 1,
+        }
+    });
+    ctx.emit(Event::ModRs {
+        path: shown,
+        modules: 0,
+    })
+}
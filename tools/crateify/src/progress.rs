@@ -0,0 +1,140 @@
+//! Progress of a pass: how many directories it has visited out of those found so far, the one it
+//! is in, and how long it has been running.
+//!
+//! The total grows as the pass reads directories and finds their subdirectories, so it needs no
+//! walk of its own before the real one. The command line draws it as a bar on the last line of
+//! stderr (see [`log::set_status`]), but only when stderr is a terminal and `--quiet` isn't
+//! given; a library run hands each update to the callback given to
+//! [`Crateify::on_progress`](crate::Crateify::on_progress) instead.
+
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use crate::log;
+
+/// Where a pass is, after it entered another directory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Progress {
+    /// Directories visited so far, the current one included
+    pub done: usize,
+    /// Directories found so far, visited or not; at least `done`
+    pub total: usize,
+    /// The current directory, as shown in messages and events
+    pub path: String,
+    /// Since the pass started
+    pub elapsed: Duration,
+}
+
+/// What becomes of the updates
+pub(crate) enum Sink {
+    Off,
+    /// Drawn on stderr, at most every [`REDRAW_INTERVAL`]
+    Bar {
+        drawn: Option<Instant>,
+    },
+    Callback(Box<dyn FnMut(&Progress) + Send>),
+}
+
+/// Keeps redrawing from slowing down a pass over many small directories
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Width of the bar itself, between its brackets
+const BAR_WIDTH: usize = 24;
+
+/// Longest path the bar shows, keeping its end
+const PATH_WIDTH: usize = 40;
+
+/// The progress of the current pass, updated by every worker
+pub(crate) struct Tracker {
+    sink: Mutex<Sink>,
+    found: AtomicUsize,
+    done: AtomicUsize,
+    started: Mutex<Instant>,
+}
+
+impl Tracker {
+    pub fn new(sink: Sink) -> Tracker {
+        Tracker {
+            sink: Mutex::new(sink),
+            found: AtomicUsize::new(0),
+            done: AtomicUsize::new(0),
+            started: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Starts counting a pass over the crate directories `roots`
+    pub fn start(&self, roots: usize) {
+        self.found.store(roots, Ordering::Relaxed);
+        self.done.store(0, Ordering::Relaxed);
+        *self.started.lock().unwrap_or_else(PoisonError::into_inner) = Instant::now();
+    }
+
+    /// Adds `subdirs` directories found in one just read to the total
+    pub fn found(&self, subdirs: usize) {
+        self.found.fetch_add(subdirs, Ordering::Relaxed);
+    }
+
+    /// Counts directory `path`, shown as `shown`, as visited
+    pub fn visit(&self, path: &Path, shown: impl FnOnce(&Path) -> String) {
+        let mut sink = self.sink.lock().unwrap_or_else(PoisonError::into_inner);
+        let done = self.done.fetch_add(1, Ordering::Relaxed) + 1;
+        let progress = || Progress {
+            done,
+            total: self.found.load(Ordering::Relaxed).max(done),
+            path: shown(path),
+            elapsed: self
+                .started
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .elapsed(),
+        };
+        match &mut *sink {
+            Sink::Off => {}
+            Sink::Bar { drawn } => {
+                if drawn.is_some_and(|at| at.elapsed() < REDRAW_INTERVAL) {
+                    return;
+                }
+                *drawn = Some(Instant::now());
+                log::set_status(Some(bar(&progress())));
+            }
+            Sink::Callback(callback) => callback(&progress()),
+        }
+    }
+
+    /// Takes the bar off stderr at the end of a pass, before its summary
+    pub fn finish(&self) {
+        let mut sink = self.sink.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Sink::Bar { drawn } = &mut *sink {
+            *drawn = None;
+            log::set_status(None);
+        }
+    }
+}
+
+/// `crateify: [=========>              ] 1234/3386 directories 01:02 net/proto`
+fn bar(progress: &Progress) -> String {
+    let filled = BAR_WIDTH * progress.done / progress.total.max(1);
+    let mut drawn = "=".repeat(filled.saturating_sub(1));
+    if filled > 0 {
+        drawn.push(if filled == BAR_WIDTH { '=' } else { '>' });
+    }
+    let secs = progress.elapsed.as_secs();
+    let chars: Vec<char> = progress.path.chars().collect();
+    let path = if chars.is_empty() {
+        ".".to_string()
+    } else if chars.len() > PATH_WIDTH {
+        let tail: String = chars[chars.len() - (PATH_WIDTH - 3)..].iter().collect();
+        format!("...{tail}")
+    } else {
+        progress.path.clone()
+    };
+    format!(
+        "crateify: [{drawn:<BAR_WIDTH$}] {}/{} directories {:02}:{:02} {path}",
+        progress.done,
+        progress.total,
+        secs / 60,
+        secs % 60
+    )
+}
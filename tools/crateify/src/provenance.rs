@@ -0,0 +1,42 @@
+//! `--provenance <map.json>`: where each module was translated from, as a doc comment on its
+//! declaration.
+//!
+//! The map is the sidecar the translator writes next to its output, one JSON object from the
+//! paths of the generated `.rs` files to their C sources, both relative to the translation
+//! directory: `{"net/tcp.rs": "src/net/tcp.c"}`. Directories are looked up by their path (`net`).
+//! A module without an entry is annotated with its own path instead, `net/tcp.rs` or `net/`.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use crate::error::CrateifyError;
+
+/// The origin of each generated file, read from a `--provenance` map
+pub struct Provenance {
+    origins: HashMap<String, String>,
+}
+
+impl Provenance {
+    pub fn read(path: &Path) -> Result<Provenance, CrateifyError> {
+        let content = fs::read_to_string(path).map_err(|e| CrateifyError::io(path, e))?;
+        let origins = serde_json::from_str(&content).map_err(|e| {
+            CrateifyError::Usage(format!(
+                "{}: expected an object of strings: {e}",
+                path.display()
+            ))
+        })?;
+        Ok(Provenance { origins })
+    }
+
+    /// The doc comment that goes before the declaration of the module at `relative`, a path
+    /// relative to the translation directory with `/` separators that is a directory if `is_dir`
+    pub fn comment(&self, relative: &str, is_dir: bool) -> String {
+        let origin = match self.origins.get(relative) {
+            Some(origin) => origin.clone(),
+            None if is_dir => format!("{relative}/"),
+            None => relative.to_string(),
+        };
+        format!("/// Translated from {origin}\n")
+    }
+}
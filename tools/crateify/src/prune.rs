@@ -0,0 +1,107 @@
+//! `--prune-empty`: after a pass, deletes the directories below each crate directory that
+//! contribute no modules, such as the headers-only folders a translation leaves behind.
+//!
+//! A directory goes when nothing below it is a `.rs` file or anything else worth keeping: only
+//! files matched by `--exclude`, temporary files of interrupted runs, generated module files
+//! without keep blocks, and directories that go as well. Anything else, a hand-written module
+//! file, a `.h`, a file only `.crateifyignore` matches, an excluded or ignored directory or a
+//! symlink, keeps it and its ancestors.
+//! `--prune-empty=dry` only reports what would go, as do `--dry-run` and `--check`.
+
+use std::path::{Path, PathBuf};
+
+use crate::Context;
+use crate::dir_handle;
+use crate::error::CrateifyError;
+use crate::ignore;
+use crate::log::stderr;
+use crate::managed::Existing;
+use crate::write::{self, Output};
+
+/// What to do with the directories below a crate directory that contribute no modules
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PruneEmpty {
+    /// Leave them where they are
+    Keep,
+    /// Delete them, reporting each
+    Remove,
+    /// Only report them
+    Report,
+}
+
+/// Prunes the empty directories below `crate_dir`, each reported with the topmost of them
+pub fn prune(crate_dir: &Path, ctx: &Context) -> Result<(), CrateifyError> {
+    let mut pruned = Vec::new();
+    prunable(crate_dir, ctx, &mut pruned)?;
+    pruned.sort();
+    for dir in pruned {
+        let shown = ctx.display_path(&dir);
+        if ctx.prune_empty == PruneEmpty::Report || ctx.output != Output::Disk {
+            if ctx.notes() {
+                stderr!("crateify: {shown}/: no modules below it (--prune-empty removes it)");
+            }
+            continue;
+        }
+        // under --module-style file, its module file is next to it
+        let module_file = ctx.module_file(&dir);
+        if !module_file.starts_with(&dir) && generated(&module_file)? {
+            dir_handle::remove_file(&module_file)
+                .map_err(|e| CrateifyError::io(&module_file, e))?;
+        }
+        dir_handle::remove_dir_all(&dir).map_err(|e| CrateifyError::io(&dir, e))?;
+        if ctx.notes() {
+            stderr!("crateify: {shown}/: removed, no modules below it");
+        }
+    }
+    Ok(())
+}
+
+/// Whether everything in `dir` may be deleted along with it; the directories below it that may
+/// be while `dir` itself can't, or is a crate directory, go to `pruned`
+fn prunable(dir: &Path, ctx: &Context, pruned: &mut Vec<PathBuf>) -> Result<bool, CrateifyError> {
+    let mut entries = dir_handle::read_dir(dir).map_err(|e| CrateifyError::read_dir(dir, e))?;
+    entries.sort();
+    let (subdirs, files): (Vec<PathBuf>, Vec<PathBuf>) = entries
+        .into_iter()
+        .partition(|path| dir_handle::is_dir(path));
+
+    let mut removable = true;
+    let mut empty = Vec::new();
+    for path in subdirs {
+        let kept = dir_handle::is_symlink(&path)
+            || ctx
+                .excludes
+                .matching(&ctx.relative_path(&path), true)
+                .is_some()
+            || ctx.ignored(&path, true)?.is_some()
+            || (ctx.default_ignores && ignore::is_ignored_dir(&path));
+        if !kept && prunable(&path, ctx, pruned)? {
+            empty.push(path);
+        } else {
+            removable = false;
+        }
+    }
+    for path in files {
+        let module_file =
+            path == ctx.module_file(dir) || empty.iter().any(|d| ctx.module_file(d) == path);
+        if !(ctx
+            .excludes
+            .matching(&ctx.relative_path(&path), false)
+            .is_some()
+            || write::is_stray_tmp(&path)
+            || (module_file && generated(&path)?))
+        {
+            removable = false;
+        }
+    }
+
+    if !removable || ctx.is_crate_root(dir) {
+        pruned.extend(empty);
+    }
+    Ok(removable)
+}
+
+/// Whether `path` is a module file crateify generated and nothing was added to by hand
+fn generated(path: &Path) -> Result<bool, CrateifyError> {
+    Ok(matches!(Existing::read(path)?, Existing::Managed { keep, .. } if keep.is_empty()))
+}
@@ -0,0 +1,124 @@
+//! `--reexport`: `pub use` lines that flatten the module tree's API.
+//!
+//! `glob` re-exports everything of each module with `pub use self::m::*;`. `items` parses
+//! module files and re-exports their public items by name, since two glob re-exports of one
+//! name make every use of it ambiguous. Only `pub` items count, and a directory module
+//! re-exports what its own mod.rs makes public, so that items bubble up to the root.
+
+use syn::{ForeignItem, Item, UseTree};
+
+/// What `--reexport` puts after each `mod` declaration
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reexport {
+    None,
+    Glob,
+    Items,
+}
+
+impl Reexport {
+    pub const NAMES: &[&str] = &["none", "glob", "items"];
+
+    pub fn parse(name: &str) -> Option<Reexport> {
+        match name {
+            "none" => Some(Reexport::None),
+            "glob" => Some(Reexport::Glob),
+            "items" => Some(Reexport::Items),
+            _ => None,
+        }
+    }
+}
+
+/// A public item of a module file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Export {
+    pub name: String,
+    /// Only in the value namespace (a function, constant or static), so that a module of the
+    /// same name doesn't clash with it, as `sha256()` next to `pub mod sha256;`
+    pub value_only: bool,
+}
+
+/// The public items of the Rust file `source`, sorted by name, without modules (which keep
+/// being reached through their paths) and without items behind a `#[cfg]`, which may not exist
+pub fn public_items(source: &str) -> Result<Vec<Export>, syn::Error> {
+    let file = syn::parse_file(source)?;
+    let mut names = Vec::new();
+    for item in &file.items {
+        let (attrs, vis, ident, value_only) = match item {
+            Item::Const(i) => (&i.attrs, &i.vis, &i.ident, true),
+            Item::Enum(i) => (&i.attrs, &i.vis, &i.ident, false),
+            Item::Fn(i) => (&i.attrs, &i.vis, &i.sig.ident, true),
+            Item::Static(i) => (&i.attrs, &i.vis, &i.ident, true),
+            Item::Struct(i) => (&i.attrs, &i.vis, &i.ident, false),
+            Item::Trait(i) => (&i.attrs, &i.vis, &i.ident, false),
+            Item::TraitAlias(i) => (&i.attrs, &i.vis, &i.ident, false),
+            Item::Type(i) => (&i.attrs, &i.vis, &i.ident, false),
+            Item::Union(i) => (&i.attrs, &i.vis, &i.ident, false),
+            // what translated C declares in `extern "C"` blocks is as public as its functions
+            Item::ForeignMod(block) if !is_cfg(&block.attrs) => {
+                for foreign in &block.items {
+                    let (attrs, vis, ident, value_only) = match foreign {
+                        ForeignItem::Fn(i) => (&i.attrs, &i.vis, &i.sig.ident, true),
+                        ForeignItem::Static(i) => (&i.attrs, &i.vis, &i.ident, true),
+                        ForeignItem::Type(i) => (&i.attrs, &i.vis, &i.ident, false),
+                        _ => continue,
+                    };
+                    if is_pub(vis) && !is_cfg(attrs) {
+                        names.push(Export {
+                            name: ident.to_string(),
+                            value_only,
+                        });
+                    }
+                }
+                continue;
+            }
+            Item::Use(i) if is_pub(&i.vis) && !is_cfg(&i.attrs) => {
+                used_names(&i.tree, &mut names);
+                continue;
+            }
+            _ => continue,
+        };
+        if is_pub(vis) && !is_cfg(attrs) {
+            names.push(Export {
+                name: ident.to_string(),
+                value_only,
+            });
+        }
+    }
+    names.sort_by(|a, b| a.name.cmp(&b.name));
+    // a function and a struct can share a name, and then the name is in both namespaces
+    names.dedup_by(|next, kept| {
+        let same = next.name == kept.name;
+        kept.value_only &= !same || next.value_only;
+        same
+    });
+    Ok(names)
+}
+
+fn is_pub(vis: &syn::Visibility) -> bool {
+    matches!(vis, syn::Visibility::Public(_))
+}
+
+fn is_cfg(attrs: &[syn::Attribute]) -> bool {
+    attrs.iter().any(|a| a.path().is_ident("cfg"))
+}
+
+/// The names a `use` tree brings in, in whichever namespaces; globs and `self` can't be named
+/// without resolving them
+fn used_names(tree: &UseTree, names: &mut Vec<Export>) {
+    let name = match tree {
+        UseTree::Path(path) => return used_names(&path.tree, names),
+        UseTree::Group(group) => {
+            for tree in &group.items {
+                used_names(tree, names);
+            }
+            return;
+        }
+        UseTree::Name(name) if name.ident != "self" => name.ident.to_string(),
+        UseTree::Rename(rename) if rename.rename != "_" => rename.rename.to_string(),
+        UseTree::Name(_) | UseTree::Rename(_) | UseTree::Glob(_) => return,
+    };
+    names.push(Export {
+        name,
+        value_only: false,
+    });
+}
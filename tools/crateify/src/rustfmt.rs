@@ -0,0 +1,71 @@
+//! `--fmt`: generated module files formatted by rustfmt before they are compared and written, so
+//! that `cargo fmt --check` passes right after a run.
+//!
+//! rustfmt reads the file from stdin and looks for a `rustfmt.toml` (or `.rustfmt.toml`) from the
+//! directory the file goes in upwards, as `cargo fmt` would for the file itself. Like `cargo fmt`,
+//! it formats for the edition of the nearest Cargo.toml, which decides how `use` lists are sorted,
+//! and `$RUSTFMT` picks the binary.
+
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// Why a file could not be formatted
+pub enum Unformatted {
+    /// No rustfmt to run, which holds for every file of the run
+    Missing(OsString),
+    /// rustfmt ran and rejected the file, with what it said
+    Failed(String),
+}
+
+/// `content` as rustfmt formats it when written in `dir`
+pub fn format(content: &str, dir: &Path) -> Result<String, Unformatted> {
+    let rustfmt = env::var_os("RUSTFMT").unwrap_or_else(|| "rustfmt".into());
+    let mut child = Command::new(&rustfmt)
+        .args(["--emit", "stdout", "--edition", &edition(dir)])
+        // rustfmt looks for its configuration from where it runs when reading stdin
+        .current_dir(dir)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => Unformatted::Missing(rustfmt),
+            _ => Unformatted::Failed(e.to_string()),
+        })?;
+    let written = child
+        .stdin
+        .take()
+        .map(|mut stdin| stdin.write_all(content.as_bytes()));
+    let output = child
+        .wait_with_output()
+        .map_err(|e| Unformatted::Failed(e.to_string()))?;
+    if let Some(Err(e)) = written {
+        return Err(Unformatted::Failed(e.to_string()));
+    }
+    if !output.status.success() {
+        return Err(Unformatted::Failed(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+    String::from_utf8(output.stdout).map_err(|e| Unformatted::Failed(e.to_string()))
+}
+
+/// The `edition` of the nearest Cargo.toml above `dir`, 2021 when there is none or it doesn't say;
+/// read line by line, as that is all the manifests crateify writes need
+fn edition(dir: &Path) -> String {
+    let manifest = dir
+        .ancestors()
+        .find_map(|d| fs::read_to_string(d.join("Cargo.toml")).ok());
+    manifest
+        .iter()
+        .flat_map(|m| m.lines())
+        .find_map(|line| {
+            let (key, value) = line.split_once('=')?;
+            (key.trim() == "edition").then(|| value.trim().trim_matches('"').to_string())
+        })
+        .unwrap_or_else(|| "2021".to_string())
+}
@@ -0,0 +1,53 @@
+//! Test files and directories, declared as `#[cfg(test)] mod foo_test;` so that only test builds
+//! compile them.
+//!
+//! A module is a test module if its file or directory name matches one of the patterns:
+//! [`DEFAULT_PATTERNS`] and those of `--test-pattern`, globs where a trailing `/` only matches
+//! directories. `--no-test-detection` turns them all off.
+
+use globset::{Glob, GlobMatcher};
+
+use crate::error::CrateifyError;
+
+/// Patterns detected out of the box: `foo_test.rs` next to `foo.rs`, and `tests/` directories
+pub const DEFAULT_PATTERNS: &[&str] = &["*_test.rs", "tests/"];
+
+/// The test module patterns of a run
+pub struct TestModules {
+    /// Each glob with whether it only matches directories
+    patterns: Vec<(GlobMatcher, bool)>,
+}
+
+impl TestModules {
+    /// The default patterns, or none at all under `--no-test-detection`
+    pub fn new(detect: bool) -> TestModules {
+        let mut tests = TestModules {
+            patterns: Vec::new(),
+        };
+        if detect {
+            for pattern in DEFAULT_PATTERNS {
+                tests
+                    .add(pattern)
+                    .expect("the default test patterns are valid globs");
+            }
+        }
+        tests
+    }
+
+    /// Adds a `--test-pattern` glob
+    pub fn add(&mut self, pattern: &str) -> Result<(), CrateifyError> {
+        let glob = Glob::new(pattern.trim_end_matches('/')).map_err(|e| {
+            CrateifyError::Usage(format!("--test-pattern {pattern} is not a valid glob: {e}"))
+        })?;
+        self.patterns
+            .push((glob.compile_matcher(), pattern.ends_with('/')));
+        Ok(())
+    }
+
+    /// Whether the entry named `file_name` (with its `.rs` for a file) holds a test module
+    pub fn is_test(&self, file_name: &str, is_dir: bool) -> bool {
+        self.patterns
+            .iter()
+            .any(|(glob, dir_only)| (is_dir || !dir_only) && glob.is_match(file_name))
+    }
+}
@@ -0,0 +1,564 @@
+//! `--validate-crate <package_dir>`: cross-reference the module tree of a `--manifest` against
+//! the package that is to build it.
+//!
+//! The targets come from `cargo metadata`, or from reading the Cargo.toml when there is no cargo
+//! to run. Every crate root of the manifest must be the `[lib]` of a target and every binary a
+//! `[[bin]]`, every declared module file must exist, and every `.rs` file of the tree must be
+//! declared. With `--cargo-check`, `cargo check --all-targets` then tells which files rustc
+//! actually read, from the dep-info next to each artifact: declared modules it never read are
+//! reported, and so are files of the tree it read that crateify didn't declare, through a
+//! hand-written `mod` or `#[path]`.
+
+use std::collections::BTreeSet;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use serde_json::Value;
+
+use crate::diagnostic::Code;
+use crate::error::CrateifyError;
+use crate::ignore::{self, Excludes, IgnoreFiles};
+use crate::log::debug;
+use crate::module_tree::{MANIFEST_SCHEMA, MANIFEST_VERSION, ModuleTree};
+
+/// A build target of the package: `kind` is `lib`, `bin`, `custom-build`, `test`, ...
+#[derive(Debug)]
+struct Target {
+    kind: String,
+    name: String,
+    src_path: PathBuf,
+}
+
+/// A file that doesn't line up between the manifest and the package
+#[derive(Debug)]
+pub struct Problem {
+    pub code: Code,
+    /// As given: below the crate directory as the manifest names it, absolute outside of it
+    pub path: PathBuf,
+    pub message: String,
+}
+
+/// What validating a package found
+#[derive(Debug, Default)]
+pub struct Report {
+    pub problems: Vec<Problem>,
+    /// Whether the targets were read from Cargo.toml, since cargo couldn't be run
+    pub without_cargo: bool,
+    /// The files `cargo check` compiled, if it ran
+    pub compiled: Option<usize>,
+    /// Why `cargo check` failed, leaving what it compiles unknown
+    pub check_failed: Option<CrateifyError>,
+}
+
+/// A crate directory of the manifest, as given and canonical
+struct Tree {
+    given: PathBuf,
+    canonical: PathBuf,
+}
+
+impl Tree {
+    /// `path`, canonical, as given: `src/net/tcp.rs` for `/work/src/net/tcp.rs`
+    fn shown(&self, path: &Path) -> PathBuf {
+        match path.strip_prefix(&self.canonical) {
+            Ok(relative) => self.given.join(relative),
+            Err(_) => path.to_path_buf(),
+        }
+    }
+}
+
+/// Validates the package in `package_dir` against the module tree read from `manifest`,
+/// leaving out the files `--exclude` and the default ignores would. Under `cargo_check`, runs
+/// `cargo check` on it as well; that it fails is part of the report, not an error, so that the
+/// problems found without it still are.
+pub fn validate(
+    package_dir: &Path,
+    manifest: &Path,
+    excludes: &Excludes,
+    default_ignores: bool,
+    cargo_check: bool,
+) -> Result<Report, CrateifyError> {
+    let tree = read_module_tree(manifest)?;
+    let cargo_toml = package_dir.join("Cargo.toml");
+    let mut report = Report::default();
+    let (targets, workspace_root) = match metadata(&cargo_toml)? {
+        Some(metadata) => metadata,
+        None => {
+            report.without_cargo = true;
+            let root = canonical(package_dir)?;
+            (read_targets(&cargo_toml)?, root)
+        }
+    };
+
+    let mut trees = Vec::new();
+    // every file a module file, a module, or a target is read from
+    let mut declared = BTreeSet::new();
+    // the directories of binaries, whose files the module file next to main.rs declares
+    let mut binary_dirs = Vec::new();
+    // the modules of the manifest by file, `crate::net::tcp`
+    let mut modules = Vec::new();
+    for (given, directories) in &tree.roots {
+        let root = Tree {
+            given: PathBuf::from(given),
+            canonical: canonical(Path::new(given))?,
+        };
+        for dir in directories {
+            let module_file = root.canonical.join(&dir.module_file);
+            if dir.module_path == "crate" {
+                check_library(&root, &module_file, &targets, &mut report);
+            }
+            declared.insert(normalized(&module_file));
+            for module in &dir.modules {
+                let file = match &module.physical_source {
+                    Some(physical) => PathBuf::from(physical),
+                    None => root.canonical.join(&module.source),
+                };
+                let module_path = format!("{}::{}", dir.module_path, module.name);
+                if !file.exists() {
+                    report.problems.push(Problem {
+                        code: Code::DanglingModule,
+                        path: root.shown(&file),
+                        message: format!("{module_path} is declared, but the file is missing"),
+                    });
+                }
+                declared.insert(normalized(&file));
+                modules.push((file, module_path));
+            }
+        }
+        for bin in tree.binaries.get(given).into_iter().flatten() {
+            let main = root.canonical.join(&bin.main);
+            if !targets
+                .iter()
+                .any(|t| t.kind == "bin" && same_file(&t.src_path, &main))
+            {
+                report.problems.push(Problem {
+                    code: Code::TargetMisconfigured,
+                    path: root.shown(&main),
+                    message: format!(
+                        "binary {} has no [[bin]] target building it in {}",
+                        bin.name,
+                        cargo_toml.display()
+                    ),
+                });
+            }
+            declared.insert(normalized(&main));
+            declared.extend(
+                bin.module_file
+                    .iter()
+                    .map(|f| normalized(&root.canonical.join(f))),
+            );
+            binary_dirs.push(normalized(&root.canonical.join(&bin.path)));
+        }
+        trees.push(root);
+    }
+    for target in &targets {
+        if !target.src_path.exists() {
+            report.problems.push(Problem {
+                code: Code::TargetMisconfigured,
+                path: shown(&trees, &target.src_path),
+                message: format!(
+                    "the {} target {} is built from this file, which doesn't exist",
+                    target.kind, target.name
+                ),
+            });
+        }
+        declared.insert(normalized(&target.src_path));
+    }
+
+    let mut on_disk = Vec::new();
+    for root in &trees {
+        let ignore_files = IgnoreFiles::default();
+        let walk = Walk {
+            top: &root.canonical,
+            excludes,
+            default_ignores,
+            ignore_files: &ignore_files,
+        };
+        walk.rust_files(&root.canonical, &mut on_disk)?;
+    }
+    let outside_binaries = |file: &Path| !binary_dirs.iter().any(|dir| file.starts_with(dir));
+    let mut undeclared: BTreeSet<PathBuf> = on_disk
+        .into_iter()
+        .filter(|file| !declared.contains(file) && outside_binaries(file))
+        .collect();
+
+    let mut compiled = None;
+    if cargo_check && !report.without_cargo {
+        match compiled_files(&cargo_toml, &workspace_root) {
+            Ok(files) => compiled = Some(files),
+            Err(e) => report.check_failed = Some(e),
+        }
+    }
+    if let Some(compiled) = &compiled {
+        report.compiled = Some(compiled.len());
+        for (file, module_path) in &modules {
+            if file.exists() && !compiled.contains(&normalized(file)) {
+                report.problems.push(Problem {
+                    code: Code::NeverCompiled,
+                    path: shown(&trees, file),
+                    message: format!(
+                        "{module_path} is declared, but cargo check never compiled it"
+                    ),
+                });
+            }
+        }
+        let compiled_undeclared = compiled.iter().filter(|file| {
+            trees.iter().any(|root| file.starts_with(&root.canonical))
+                && !declared.contains(*file)
+                && outside_binaries(file)
+        });
+        undeclared.extend(compiled_undeclared.cloned());
+    }
+    for file in undeclared {
+        let message = match &compiled {
+            Some(compiled) if compiled.contains(&file) => {
+                "cargo check compiled it, but no generated module file declares it"
+            }
+            _ => "no module file declares it, so it is never compiled",
+        };
+        report.problems.push(Problem {
+            code: Code::UndeclaredFile,
+            path: shown(&trees, &file),
+            message: message.to_string(),
+        });
+    }
+    Ok(report)
+}
+
+/// Reports the crate root `module_file` of `root` if no `[lib]` target builds it
+fn check_library(root: &Tree, module_file: &Path, targets: &[Target], report: &mut Report) {
+    let libs: Vec<_> = targets.iter().filter(|t| t.kind == "lib").collect();
+    if libs.iter().any(|t| same_file(&t.src_path, module_file)) {
+        return;
+    }
+    let message = match libs.as_slice() {
+        [] => "the crate root has no [lib] target building it".to_string(),
+        libs => format!(
+            "the crate root has no [lib] target building it; the package's [lib] is {}",
+            libs.iter()
+                .map(|t| root.shown(&t.src_path).display().to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    };
+    report.problems.push(Problem {
+        code: Code::TargetMisconfigured,
+        path: root.shown(module_file),
+        message,
+    });
+}
+
+fn read_module_tree(manifest: &Path) -> Result<ModuleTree, CrateifyError> {
+    let invalid = |message: String| {
+        CrateifyError::io(
+            manifest,
+            io::Error::new(io::ErrorKind::InvalidData, message),
+        )
+    };
+    let json = fs::read_to_string(manifest).map_err(|e| CrateifyError::io(manifest, e))?;
+    let value: Value = serde_json::from_str(&json).map_err(|e| invalid(e.to_string()))?;
+    if value["schema"] != MANIFEST_SCHEMA || value["version"] != MANIFEST_VERSION {
+        return Err(invalid(format!(
+            "not a {MANIFEST_SCHEMA} of version {MANIFEST_VERSION}, rerun crateify with \
+             --manifest to write one"
+        )));
+    }
+    serde_json::from_value(value).map_err(|e| invalid(e.to_string()))
+}
+
+fn cargo() -> Command {
+    Command::new(env::var_os("CARGO").unwrap_or_else(|| "cargo".into()))
+}
+
+/// The targets of every package of `cargo_toml` and the root of its workspace, paths in the
+/// dep-info being relative to it, or `None` if there is no cargo to ask
+fn metadata(cargo_toml: &Path) -> Result<Option<(Vec<Target>, PathBuf)>, CrateifyError> {
+    let output = cargo()
+        .args([
+            "metadata",
+            "--format-version",
+            "1",
+            "--no-deps",
+            "--manifest-path",
+        ])
+        .arg(cargo_toml)
+        .output();
+    let output = match output {
+        Ok(output) => output,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(CrateifyError::io(Path::new("cargo"), e)),
+    };
+    if !output.status.success() {
+        return Err(cargo_failed(cargo_toml, "metadata", &output.stderr));
+    }
+    let metadata: Value = serde_json::from_slice(&output.stdout)
+        .map_err(|e| CrateifyError::io(Path::new("cargo metadata"), io::Error::from(e)))?;
+    let mut targets = Vec::new();
+    for package in metadata["packages"].as_array().into_iter().flatten() {
+        for target in package["targets"].as_array().into_iter().flatten() {
+            let (Some(name), Some(src_path)) =
+                (target["name"].as_str(), target["src_path"].as_str())
+            else {
+                continue;
+            };
+            for kind in target["kind"].as_array().into_iter().flatten() {
+                let kind = kind.as_str().unwrap_or_default();
+                targets.push(Target {
+                    // `rlib`, `cdylib`, `proc-macro` and the like are all [lib]
+                    kind: match kind {
+                        "lib" | "rlib" | "dylib" | "cdylib" | "staticlib" | "proc-macro" => "lib",
+                        kind => kind,
+                    }
+                    .to_string(),
+                    name: name.to_string(),
+                    src_path: PathBuf::from(src_path),
+                });
+            }
+        }
+    }
+    let workspace_root = PathBuf::from(metadata["workspace_root"].as_str().unwrap_or_default());
+    Ok(Some((targets, workspace_root)))
+}
+
+/// The targets `cargo_toml` declares, and those of its workspace members, read the way cargo
+/// would (explicit `[lib]` and `[[bin]]` paths, `src/lib.rs`, `src/main.rs` and `build.rs`),
+/// for when cargo itself can't be run
+fn read_targets(cargo_toml: &Path) -> Result<Vec<Target>, CrateifyError> {
+    let content = fs::read_to_string(cargo_toml).map_err(|e| CrateifyError::io(cargo_toml, e))?;
+    let table: toml::Table = content.parse().map_err(|e: toml::de::Error| {
+        CrateifyError::io(
+            cargo_toml,
+            io::Error::new(io::ErrorKind::InvalidData, e.message().to_string()),
+        )
+    })?;
+    let dir = canonical(cargo_toml.parent().unwrap_or(Path::new("")))?;
+    let mut targets = Vec::new();
+    if let Some(package) = table.get("package").and_then(|p| p.as_table()) {
+        let name = package
+            .get("name")
+            .and_then(|n| n.as_str())
+            .unwrap_or_default();
+        let lib = table.get("lib").and_then(|l| l.as_table());
+        let lib_path = lib.and_then(|l| l.get("path")).and_then(|p| p.as_str());
+        if lib.is_some() || dir.join("src/lib.rs").exists() {
+            let lib_name = lib.and_then(|l| l.get("name")).and_then(|n| n.as_str());
+            targets.push(Target {
+                kind: "lib".to_string(),
+                name: lib_name.map_or_else(|| name.replace('-', "_"), str::to_string),
+                src_path: dir.join(lib_path.unwrap_or("src/lib.rs")),
+            });
+        }
+        for bin in table
+            .get("bin")
+            .and_then(|b| b.as_array())
+            .into_iter()
+            .flatten()
+            .filter_map(|b| b.as_table())
+        {
+            let bin_name = bin.get("name").and_then(|n| n.as_str()).unwrap_or(name);
+            let path = match bin.get("path").and_then(|p| p.as_str()) {
+                Some(path) => path.to_string(),
+                None if bin_name == name => "src/main.rs".to_string(),
+                None => format!("src/bin/{bin_name}.rs"),
+            };
+            targets.push(Target {
+                kind: "bin".to_string(),
+                name: bin_name.to_string(),
+                src_path: dir.join(path),
+            });
+        }
+        let main = dir.join("src/main.rs");
+        if main.exists() && !targets.iter().any(|t| t.src_path == main) {
+            targets.push(Target {
+                kind: "bin".to_string(),
+                name: name.to_string(),
+                src_path: main,
+            });
+        }
+        let build = match package.get("build") {
+            Some(toml::Value::String(path)) => Some(dir.join(path)),
+            Some(_) => None,
+            None => Some(dir.join("build.rs")).filter(|b| b.exists()),
+        };
+        targets.extend(build.map(|src_path| Target {
+            kind: "custom-build".to_string(),
+            name: "build-script-build".to_string(),
+            src_path,
+        }));
+    }
+    let members = table
+        .get("workspace")
+        .and_then(|w| w.get("members"))
+        .and_then(|m| m.as_array());
+    for member in members.into_iter().flatten().filter_map(|m| m.as_str()) {
+        targets.extend(read_targets(&dir.join(member).join("Cargo.toml"))?);
+    }
+    Ok(targets)
+}
+
+/// The source files `cargo check --all-targets` compiled for the packages of `cargo_toml`,
+/// canonical, from the dep-info files of their artifacts
+fn compiled_files(
+    cargo_toml: &Path,
+    workspace_root: &Path,
+) -> Result<BTreeSet<PathBuf>, CrateifyError> {
+    let output = cargo()
+        .args([
+            "check",
+            "--all-targets",
+            "--message-format=json",
+            "--manifest-path",
+        ])
+        .arg(cargo_toml)
+        .output()
+        .map_err(|e| CrateifyError::io(Path::new("cargo"), e))?;
+    if !output.status.success() {
+        return Err(cargo_failed(cargo_toml, "check", &output.stderr));
+    }
+    let mut compiled = BTreeSet::new();
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        let Ok(message) = serde_json::from_str::<Value>(line) else {
+            continue;
+        };
+        if message["reason"] != "compiler-artifact" {
+            continue;
+        }
+        // dependencies from crates.io and git are compiled too, but their files aren't ours
+        let local = message["package_id"]
+            .as_str()
+            .is_some_and(|id| id.contains("path+file://"));
+        if !local {
+            continue;
+        }
+        for artifact in message["filenames"].as_array().into_iter().flatten() {
+            let Some(dep_info) = artifact.as_str().and_then(|a| dep_info(Path::new(a))) else {
+                continue;
+            };
+            let content =
+                fs::read_to_string(&dep_info).map_err(|e| CrateifyError::io(&dep_info, e))?;
+            debug!("{}: read the files compiled", dep_info.display());
+            compiled
+                .extend(dependencies(&content).map(|file| normalized(&workspace_root.join(file))));
+        }
+    }
+    Ok(compiled)
+}
+
+/// The dep-info of the artifact `artifact`: `deps/foo-1a2b.d` for `deps/libfoo-1a2b.rmeta`, or
+/// next to it for a build script
+fn dep_info(artifact: &Path) -> Option<PathBuf> {
+    let stem = artifact.file_stem()?.to_string_lossy();
+    let dir = artifact.parent()?;
+    [
+        stem.strip_prefix("lib").map(|s| dir.join(format!("{s}.d"))),
+        Some(dir.join(format!("{stem}.d"))),
+    ]
+    .into_iter()
+    .flatten()
+    .find(|d| d.is_file())
+}
+
+/// The prerequisites of the rules of the Makefile-style dep-info `content`, with the spaces of
+/// their paths unescaped
+fn dependencies(content: &str) -> impl Iterator<Item = String> + '_ {
+    content
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| line.split_once(": ").map(|(_, files)| files))
+        .flat_map(|files| {
+            let mut paths = Vec::new();
+            let mut path = String::new();
+            let mut chars = files.chars();
+            while let Some(c) = chars.next() {
+                match c {
+                    '\\' => path.extend(chars.next()),
+                    ' ' => paths.extend((!path.is_empty()).then(|| std::mem::take(&mut path))),
+                    c => path.push(c),
+                }
+            }
+            paths.extend((!path.is_empty()).then_some(path));
+            paths
+        })
+}
+
+fn cargo_failed(cargo_toml: &Path, command: &'static str, stderr: &[u8]) -> CrateifyError {
+    let stderr = String::from_utf8_lossy(stderr);
+    let reason = stderr
+        .lines()
+        .find(|l| l.starts_with("error"))
+        .or_else(|| stderr.lines().rfind(|l| !l.trim().is_empty()))
+        .unwrap_or("no output")
+        .trim()
+        .to_string();
+    CrateifyError::Cargo {
+        manifest: cargo_toml.to_path_buf(),
+        command,
+        reason,
+    }
+}
+
+/// The `.rs` files below a crate directory that the traversal of a run would visit
+struct Walk<'a> {
+    top: &'a Path,
+    excludes: &'a Excludes,
+    default_ignores: bool,
+    ignore_files: &'a IgnoreFiles,
+}
+
+impl Walk<'_> {
+    fn rust_files(&self, dir: &Path, files: &mut Vec<PathBuf>) -> Result<(), CrateifyError> {
+        let entries = fs::read_dir(dir).map_err(|e| CrateifyError::read_dir(dir, e))?;
+        for entry in entries {
+            let entry = entry.map_err(|e| CrateifyError::read_dir(dir, e))?;
+            let path = entry.path();
+            let file_type = entry.file_type().map_err(|e| CrateifyError::io(&path, e))?;
+            // as in a run, a symlinked directory is not followed
+            let is_dir = file_type.is_dir();
+            let relative = path.strip_prefix(self.top).unwrap_or(&path);
+            let relative = relative
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            if self.excludes.matching(&relative, is_dir).is_some()
+                || self
+                    .ignore_files
+                    .matching(self.top, &path, is_dir)?
+                    .is_some()
+            {
+                continue;
+            }
+            if is_dir {
+                if !(self.default_ignores && ignore::is_ignored_dir(&path)) {
+                    self.rust_files(&path, files)?;
+                }
+            } else if path.extension().is_some_and(|e| e == "rs") {
+                files.push(normalized(&path));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn canonical(path: &Path) -> Result<PathBuf, CrateifyError> {
+    fs::canonicalize(path).map_err(|e| CrateifyError::io(path, e))
+}
+
+/// `path` canonical if it exists, to compare against what cargo reports
+fn normalized(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+fn same_file(a: &Path, b: &Path) -> bool {
+    normalized(a) == normalized(b)
+}
+
+/// `path` as given below the crate directory it is in, or absolute if it is in none
+fn shown(trees: &[Tree], path: &Path) -> PathBuf {
+    trees
+        .iter()
+        .find(|tree| path.starts_with(&tree.canonical))
+        .map_or_else(|| path.to_path_buf(), |tree| tree.shown(path))
+}
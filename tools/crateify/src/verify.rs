@@ -0,0 +1,172 @@
+//! `--verify`: `cargo check` the generated crate and report the errors that come from its module
+//! tree.
+//!
+//! The check runs on the crate's own Cargo.toml when there is one, or on a throwaway package
+//! whose library is the root module. Only errors about declaring and finding modules are
+//! reported, and only in files of the tree: what the translated code itself gets wrong is not
+//! crateify's to fix.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+
+use serde_json::Value;
+
+use crate::diagnostic::Code;
+use crate::error::CrateifyError;
+use crate::log::info;
+
+/// rustc errors of module trees: no file for a module (E0583), a name declared twice (E0428),
+/// both `m.rs` and `m/mod.rs` (E0761), and paths through modules that don't exist (E0432, E0433)
+const MODULE_ERRORS: &[&str] = &["E0583", "E0428", "E0761", "E0432", "E0433"];
+
+/// A module resolution error, at the line of the declaration or `use` it is about
+#[derive(Debug)]
+pub struct Diagnostic {
+    /// Canonical path of the file
+    pub file: PathBuf,
+    pub line: u64,
+    pub code: String,
+    pub message: String,
+    /// The offending line itself
+    pub text: String,
+}
+
+impl Diagnostic {
+    /// The code crateify reports it under, one of the [`MODULE_ERRORS`]
+    pub fn kind(&self) -> Code {
+        match self.code.as_str() {
+            "E0583" => Code::DanglingModule,
+            "E0428" => Code::DuplicateModule,
+            "E0761" => Code::MixedModuleStyles,
+            _ => Code::UnresolvedPath,
+        }
+    }
+}
+
+/// What `cargo check` found in the tree
+#[derive(Debug, Default)]
+pub struct Report {
+    pub diagnostics: Vec<Diagnostic>,
+    /// Errors that aren't about the module tree
+    pub other_errors: usize,
+}
+
+/// Checks the package (or under `workspace`, every member of the workspace) of `manifest`,
+/// keeping the errors in files under `tree`
+pub fn check_manifest(
+    manifest: &Path,
+    workspace: bool,
+    tree: &Path,
+) -> Result<Report, CrateifyError> {
+    let mut command = cargo_check(manifest);
+    if workspace {
+        command.arg("--workspace");
+    }
+    run(command, manifest, tree)
+}
+
+/// Checks the tree whose root module is `root_file` as the library of a throwaway package,
+/// removed afterwards
+pub fn check_tree(root_file: &Path, edition: &str, tree: &Path) -> Result<Report, CrateifyError> {
+    let root_file = fs::canonicalize(root_file).map_err(|e| CrateifyError::io(root_file, e))?;
+    let package = env::temp_dir().join(format!("crateify-verify-{}", process::id()));
+    let manifest = package.join("Cargo.toml");
+    // JSON string escapes are valid TOML basic-string escapes
+    let lib_path = Value::from(root_file.to_string_lossy());
+    let content = format!(
+        "[package]\nname = \"crateify-verify\"\nversion = \"0.0.0\"\nedition = \"{edition}\"\n\n\
+         [lib]\npath = {lib_path}\n"
+    );
+    fs::create_dir_all(&package)
+        .and_then(|()| fs::write(&manifest, content))
+        .map_err(|e| CrateifyError::io(&manifest, e))?;
+    let mut command = cargo_check(&manifest);
+    command.env("CARGO_TARGET_DIR", package.join("target"));
+    let report = run(command, &manifest, tree);
+    if let Err(e) = fs::remove_dir_all(&package) {
+        info!("{}: cannot remove it: {e}", package.display());
+    }
+    report
+}
+
+fn cargo_check(manifest: &Path) -> Command {
+    let cargo = env::var_os("CARGO").unwrap_or_else(|| "cargo".into());
+    let mut command = Command::new(cargo);
+    command
+        .args(["check", "--message-format=json", "--manifest-path"])
+        .arg(manifest);
+    command
+}
+
+fn run(mut command: Command, manifest: &Path, tree: &Path) -> Result<Report, CrateifyError> {
+    let output = command
+        .output()
+        .map_err(|e| CrateifyError::io(Path::new("cargo"), e))?;
+    let tree = fs::canonicalize(tree).map_err(|e| CrateifyError::io(tree, e))?;
+    let base = manifest.parent().unwrap_or(Path::new(""));
+    let mut report = Report::default();
+    let mut errors = 0;
+    // one JSON message per line; cargo's own progress goes to stderr
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        let Ok(message) = serde_json::from_str::<Value>(line) else {
+            continue;
+        };
+        if message["reason"] != "compiler-message" || message["message"]["level"] != "error" {
+            continue;
+        }
+        errors += 1;
+        match module_error(&message["message"], base, &tree) {
+            Some(diagnostic) => report.diagnostics.push(diagnostic),
+            None => report.other_errors += 1,
+        }
+    }
+    if errors == 0 && !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let reason = stderr
+            .lines()
+            .rfind(|l| !l.trim().is_empty())
+            .unwrap_or("no output")
+            .trim()
+            .to_string();
+        return Err(CrateifyError::Cargo {
+            manifest: manifest.to_path_buf(),
+            command: "check",
+            reason,
+        });
+    }
+    Ok(report)
+}
+
+/// The diagnostic of the compiler message `message`, if it is a module error in a file under
+/// `tree`, whose paths are relative to the directory of the package's manifest `base` or one of
+/// its parents, the workspace root
+fn module_error(message: &Value, base: &Path, tree: &Path) -> Option<Diagnostic> {
+    let code = message["code"]["code"].as_str()?;
+    if !MODULE_ERRORS.contains(&code) {
+        return None;
+    }
+    let span = message["spans"]
+        .as_array()?
+        .iter()
+        .find(|s| s["is_primary"] == true)?;
+    let name = Path::new(span["file_name"].as_str()?);
+    let file = base
+        .ancestors()
+        .find_map(|dir| fs::canonicalize(dir.join(name)).ok())?;
+    if !file.starts_with(tree) {
+        return None;
+    }
+    Some(Diagnostic {
+        file,
+        line: span["line_start"].as_u64()?,
+        code: code.to_string(),
+        message: message["message"].as_str()?.to_string(),
+        text: span["text"][0]["text"]
+            .as_str()
+            .unwrap_or_default()
+            .trim()
+            .to_string(),
+    })
+}
@@ -0,0 +1,107 @@
+//! `--visibility` and `--visibility-file`: how public each generated `mod` declaration is.
+//!
+//! The file maps globs to visibilities, one `<glob> <visibility>` pair per line, with `#`
+//! starting a comment. Globs are matched like `--exclude` patterns against the path of the
+//! module's file or directory relative to the crate directory, and the last matching line wins
+//! over earlier ones and over `--visibility`.
+
+use std::fs;
+use std::path::Path;
+
+use globset::{GlobBuilder, GlobMatcher};
+
+use crate::error::CrateifyError;
+
+/// Visibility of a generated `mod` declaration
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Visibility {
+    /// `pub mod`, part of the crate's API
+    Pub,
+    /// `pub(crate) mod`
+    PubCrate,
+    /// `mod`, only visible to its parent module
+    Private,
+}
+
+impl Visibility {
+    pub const NAMES: &[&str] = &["pub", "pub-crate", "private"];
+
+    pub fn parse(name: &str) -> Option<Visibility> {
+        match name {
+            "pub" => Some(Visibility::Pub),
+            "pub-crate" => Some(Visibility::PubCrate),
+            "private" => Some(Visibility::Private),
+            _ => None,
+        }
+    }
+
+    /// What goes in front of `mod`, trailing space included
+    pub fn prefix(self) -> &'static str {
+        match self {
+            Visibility::Pub => "pub ",
+            Visibility::PubCrate => "pub(crate) ",
+            Visibility::Private => "",
+        }
+    }
+}
+
+/// `--visibility` together with the overrides of `--visibility-file`
+pub struct Visibilities {
+    default: Visibility,
+    /// Each glob with whether it only matches directories, which a trailing `/` asks for
+    overrides: Vec<(GlobMatcher, bool, Visibility)>,
+}
+
+impl Visibilities {
+    pub fn new(default: Visibility) -> Visibilities {
+        Visibilities {
+            default,
+            overrides: Vec::new(),
+        }
+    }
+
+    /// Adds the overrides listed in the file at `path`
+    pub fn read_overrides(&mut self, path: &Path) -> Result<(), CrateifyError> {
+        let content = fs::read_to_string(path).map_err(|e| CrateifyError::io(path, e))?;
+        for (n, line) in content.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let invalid = |reason: String| {
+                CrateifyError::Usage(format!("{}:{}: {reason}", path.display(), n + 1))
+            };
+            let (pattern, visibility) = match line.split_whitespace().collect::<Vec<_>>()[..] {
+                [pattern, visibility] => (pattern, visibility),
+                _ => {
+                    return Err(invalid(format!(
+                        "expected <glob> <visibility>, got {line:?}"
+                    )));
+                }
+            };
+            let Some(visibility) = Visibility::parse(visibility) else {
+                return Err(invalid(format!(
+                    "expected one of {}, got {visibility}",
+                    Visibility::NAMES.join(", ")
+                )));
+            };
+            let glob = GlobBuilder::new(pattern.trim_end_matches('/'))
+                .literal_separator(true)
+                .build()
+                .map_err(|e| invalid(format!("invalid pattern {pattern}: {e}")))?;
+            self.overrides
+                .push((glob.compile_matcher(), pattern.ends_with('/'), visibility));
+        }
+        Ok(())
+    }
+
+    /// The visibility to declare the module at `relative` with, a path relative to the crate
+    /// directory with `/` separators that is a directory if `is_dir`
+    pub fn of(&self, relative: &str, is_dir: bool) -> Visibility {
+        self.overrides
+            .iter()
+            .rev()
+            .find(|(glob, dir_only, _)| (is_dir || !dir_only) && glob.is_match(relative))
+            .map_or(self.default, |(_, _, visibility)| *visibility)
+    }
+}
@@ -0,0 +1,199 @@
+//! `--watch`: keeps the mod.rs files of a tree up to date while files are added, removed, and
+//! renamed.
+//!
+//! The platform's file notifications (inotify, FSEvents, ReadDirectoryChangesW) say when to look,
+//! and the directory listing then says what changed. Where notifications can't be had, or with
+//! `--poll` (network mounts and some containers never deliver them), the listing is polled every
+//! interval instead, at one `read_dir` per directory per interval. Either way only the set of paths
+//! matters, so edits to the contents of existing files don't trigger a rerun.
+
+use std::collections::{BTreeSet, HashSet};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+use notify::event::ModifyKind;
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+use crate::dir_handle;
+use crate::error::CrateifyError;
+use crate::ignore;
+use crate::write;
+
+/// Watches a tree for created and removed paths
+pub struct Poller {
+    root: PathBuf,
+    /// Module file crateify writes in the root, `mod.rs` or `lib.rs`
+    root_output: PathBuf,
+    /// `--module-style file`, where crateify writes `foo.rs` next to each `foo/`
+    file_modules: bool,
+    /// Leave out the directories the traversal skips, see [`ignore`]
+    default_ignores: bool,
+    /// Descend into symlinked directories, as the traversal does with `--follow-symlinks`
+    follow_symlinks: bool,
+    interval: Duration,
+    /// The notification watcher and its events, `None` when polling
+    native: Option<(RecommendedWatcher, Receiver<notify::Result<Event>>)>,
+    last: BTreeSet<PathBuf>,
+}
+
+impl Poller {
+    /// Starts watching `root`, whose module file crateify writes at `root_file`; anything that
+    /// changes after this call is reported by [`Poller::wait`]. Unless `poll` is set, it listens
+    /// for file notifications, and falls back to polling when they can't be set up; the error it
+    /// fell back on is returned along with the poller.
+    pub fn new(
+        root: &Path,
+        root_file: &Path,
+        file_modules: bool,
+        default_ignores: bool,
+        follow_symlinks: bool,
+        poll: bool,
+        interval: Duration,
+    ) -> Result<(Poller, Option<notify::Error>), CrateifyError> {
+        let (native, fallback) = if poll {
+            (None, None)
+        } else {
+            match listen(root) {
+                Ok(native) => (Some(native), None),
+                Err(e) => (None, Some(e)),
+            }
+        };
+        let mut poller = Poller {
+            root: root.to_path_buf(),
+            root_output: root_file
+                .strip_prefix(root)
+                .unwrap_or(root_file)
+                .to_path_buf(),
+            file_modules,
+            default_ignores,
+            follow_symlinks,
+            interval,
+            native,
+            last: BTreeSet::new(),
+        };
+        poller.last = poller.scan()?;
+        Ok((poller, fallback))
+    }
+
+    /// Blocks until the tree changes and then stays unchanged for one interval, so that a burst
+    /// of changes (a `git checkout`, an editor's save dance) triggers a single rerun.
+    ///
+    /// Returns the created and removed paths relative to the root, or `None` once `stop` is set.
+    pub fn wait(&mut self, stop: &AtomicBool) -> Result<Option<Vec<PathBuf>>, CrateifyError> {
+        let mut pending = BTreeSet::new();
+        loop {
+            // notifications only say that something happened, the scan below tells what since
+            // the last one; while a burst is pending, the tree is listed every interval until quiet
+            if pending.is_empty() && self.native.is_some() {
+                if !self.notified(stop) {
+                    return Ok(None);
+                }
+            } else {
+                thread::sleep(self.interval);
+            }
+            if stop.load(Ordering::Relaxed) {
+                return Ok(None);
+            }
+
+            let current = self.scan()?;
+            if current == self.last {
+                if !pending.is_empty() {
+                    return Ok(Some(pending.into_iter().collect()));
+                }
+                continue;
+            }
+            pending.extend(self.last.symmetric_difference(&current).cloned());
+            self.last = current;
+        }
+    }
+
+    /// Blocks until a notification about a path other than crateify's own outputs arrives, and
+    /// returns `false` if `stop` is set first. A watcher that has gone away (the tree's root was
+    /// removed, say) counts as a notification, so that the scan reports the error.
+    fn notified(&self, stop: &AtomicBool) -> bool {
+        let Some((_, events)) = &self.native else {
+            return true;
+        };
+        while !stop.load(Ordering::Relaxed) {
+            match events.recv_timeout(self.interval) {
+                Ok(Ok(event)) if self.is_relevant(&event) => return true,
+                // an overflowing queue or the like: no telling what happened, so look
+                Ok(Err(_)) | Err(RecvTimeoutError::Disconnected) => return true,
+                Ok(Ok(_)) | Err(RecvTimeoutError::Timeout) => {}
+            }
+        }
+        false
+    }
+
+    /// Whether `event` may have created or removed a path the scan would list
+    fn is_relevant(&self, event: &Event) -> bool {
+        !matches!(
+            event.kind,
+            EventKind::Access(_) | EventKind::Modify(ModifyKind::Data(_) | ModifyKind::Metadata(_))
+        ) && (event.paths.is_empty()
+            || event.paths.iter().any(|path| {
+                !is_own_output(path, self.file_modules)
+                    && path.strip_prefix(&self.root).ok() != Some(&*self.root_output)
+            }))
+    }
+
+    /// Every directory and file below the root except crateify's own outputs (and the ignored
+    /// directories with `default_ignores`), relative to the root
+    fn scan(&self) -> Result<BTreeSet<PathBuf>, CrateifyError> {
+        let root = &self.root;
+        let mut paths = BTreeSet::new();
+        // canonical paths of the directories listed so far, so that symlink cycles end
+        let mut listed = HashSet::new();
+        let mut dirs = vec![root.to_path_buf()];
+        while let Some(dir) = dirs.pop() {
+            if self.follow_symlinks
+                && let Ok(canonical) = dir_handle::canonicalize(&dir)
+                && !listed.insert(canonical)
+            {
+                continue;
+            }
+            let entries = match dir_handle::read_dir(&dir) {
+                Ok(entries) => entries,
+                // removed between listing its parent and getting here, the next scan will tell
+                Err(e) if e.kind() == io::ErrorKind::NotFound && dir != *root => continue,
+                Err(e) => return Err(CrateifyError::io(&dir, e)),
+            };
+            for path in entries {
+                let relative = path.strip_prefix(root).unwrap_or(&path).to_path_buf();
+                if is_own_output(&path, self.file_modules) || relative == self.root_output {
+                    continue;
+                }
+                let is_dir = dir_handle::is_dir(&path);
+                if is_dir && self.default_ignores && ignore::is_ignored_dir(&path) {
+                    continue;
+                }
+                if is_dir && (self.follow_symlinks || !dir_handle::is_symlink(&path)) {
+                    dirs.push(path);
+                }
+                paths.insert(relative);
+            }
+        }
+        Ok(paths)
+    }
+}
+
+/// Subscribes to the notifications for everything below `root`
+fn listen(root: &Path) -> notify::Result<(RecommendedWatcher, Receiver<notify::Result<Event>>)> {
+    let (sender, events) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender)?;
+    watcher.watch(root, RecursiveMode::Recursive)?;
+    Ok((watcher, events))
+}
+
+/// Files crateify writes itself, which must not retrigger it
+fn is_own_output(path: &Path, file_modules: bool) -> bool {
+    path.file_name().is_some_and(|n| n == "mod.rs")
+        || write::is_stray_tmp(path)
+        || (file_modules
+            && path.extension().is_some_and(|e| e == "rs")
+            && dir_handle::is_dir(&path.with_extension("")))
+}
@@ -0,0 +1,205 @@
+use std::ffi::OsString;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::dir_handle;
+use crate::error::CrateifyError;
+
+/// Where generated files end up
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Output {
+    /// Written in place with [`write_atomic`], unless the file already holds exactly that
+    Disk,
+    /// `--dry-run`: printed to stdout as a unified diff against what is on disk, which is left
+    /// untouched
+    DryRun,
+    /// `--check`: compared with what is on disk, listing every file that is missing or differs on
+    /// stdout, and left untouched
+    Check,
+    /// A library dry run: compared with what is on disk, and neither written nor printed
+    Nowhere,
+}
+
+/// What writing a generated file changes, or would change under `--dry-run` and `--check`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Change {
+    Created,
+    Updated,
+    Unchanged,
+}
+
+impl Output {
+    /// Puts `contents` at `path`, which messages and diff headers show as `display`, and returns
+    /// how that changes the file
+    pub fn write(
+        self,
+        path: &Path,
+        display: &str,
+        contents: &str,
+    ) -> Result<Change, CrateifyError> {
+        let old = read_old(path)?;
+        let change = match &old {
+            None => Change::Created,
+            Some(old) if old == contents => Change::Unchanged,
+            Some(_) => Change::Updated,
+        };
+        match self {
+            // rewriting the same bytes would only bump the mtime, and make cargo rebuild the crate
+            Output::Disk if change == Change::Unchanged => {}
+            Output::Disk => write_atomic(path, contents.as_bytes())?,
+            Output::DryRun => report(&unified_diff(old.as_deref(), Some(contents), display))?,
+            Output::Check => report(&stale_summary(old.as_deref(), contents, display))?,
+            Output::Nowhere => {}
+        }
+        Ok(change)
+    }
+
+    /// Whether `--check` counts a file `change`d by [`Output::write`] as out of date
+    pub fn is_stale(self, change: Change) -> bool {
+        self == Output::Check && change != Change::Unchanged
+    }
+
+    /// Deletes the generated file `path`, shown as `display`; like [`Output::write`], returns
+    /// whether `--check` found it still there
+    pub fn remove(self, path: &Path, display: &str) -> Result<bool, CrateifyError> {
+        match self {
+            Output::Disk => {
+                dir_handle::remove_file(path).map_err(|e| CrateifyError::io(path, e))?;
+                Ok(false)
+            }
+            Output::DryRun => {
+                let old = read_old(path)?;
+                report(&unified_diff(old.as_deref(), None, display))?;
+                Ok(false)
+            }
+            Output::Check => {
+                report(&format!("{display}: stale, to be removed\n"))?;
+                Ok(true)
+            }
+            Output::Nowhere => Ok(false),
+        }
+    }
+
+    /// Deletes `path`, or does nothing in a dry run
+    pub fn remove_file(self, path: &Path) -> Result<(), CrateifyError> {
+        match self {
+            Output::Disk => dir_handle::remove_file(path).map_err(|e| CrateifyError::io(path, e)),
+            Output::DryRun | Output::Check | Output::Nowhere => Ok(()),
+        }
+    }
+}
+
+/// What is at `path` now, `None` if nothing is
+fn read_old(path: &Path) -> Result<Option<String>, CrateifyError> {
+    match dir_handle::read(path) {
+        Ok(bytes) => Ok(Some(String::from_utf8_lossy(&bytes).into_owned())),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(CrateifyError::io(path, e)),
+    }
+}
+
+/// Prints a dry run's or check's report on stdout
+fn report(text: &str) -> Result<(), CrateifyError> {
+    io::stdout()
+        .write_all(text.as_bytes())
+        .map_err(|e| CrateifyError::io(Path::new("<stdout>"), e))
+}
+
+/// The changes from `old` to `new` in the format of `diff -u`, with `None` for a file that
+/// doesn't exist before or after; empty when nothing changes
+fn unified_diff(old: Option<&str>, new: Option<&str>, display: &str) -> String {
+    // a file outside of the crate directory, such as the Cargo.toml next to it, is shown as it is
+    let side = |content: Option<&str>, prefix: &str| match content {
+        Some(_) if Path::new(display).is_absolute() => display.to_string(),
+        Some(_) => format!("{prefix}/{display}"),
+        None => "/dev/null".to_string(),
+    };
+    similar::TextDiff::from_lines(old.unwrap_or(""), new.unwrap_or(""))
+        .unified_diff()
+        .header(&side(old, "a"), &side(new, "b"))
+        .to_string()
+}
+
+/// `--check`'s report on one file: `net/mod.rs: missing`, or `mod.rs: out of date (+1 -0)`
+/// followed by the lines to add and remove; empty when nothing changes
+fn stale_summary(old: Option<&str>, new: &str, display: &str) -> String {
+    let Some(old) = old else {
+        return format!("{display}: missing\n");
+    };
+    let diff = similar::TextDiff::from_lines(old, new);
+    let mut lines = String::new();
+    let (mut added, mut removed) = (0, 0);
+    for change in diff.iter_all_changes() {
+        let sign = match change.tag() {
+            similar::ChangeTag::Equal => continue,
+            similar::ChangeTag::Insert => {
+                added += 1;
+                '+'
+            }
+            similar::ChangeTag::Delete => {
+                removed += 1;
+                '-'
+            }
+        };
+        lines.push_str(&format!(
+            "  {sign} {}\n",
+            change.value().trim_end_matches('\n')
+        ));
+    }
+    if added + removed == 0 {
+        return lines;
+    }
+    format!("{display}: out of date (+{added} -{removed})\n{lines}")
+}
+
+/// Suffix of the temporary files [`write_atomic`] creates next to their target
+const TMP_SUFFIX: &str = ".crateify-tmp";
+
+/// Temporary file used while writing `path`, e.g. `net/.mod.rs.crateify-tmp` for `net/mod.rs`
+fn tmp_path(path: &Path) -> PathBuf {
+    let mut name = OsString::from(".");
+    name.push(path.file_name().unwrap_or_default());
+    name.push(TMP_SUFFIX);
+    path.with_file_name(name)
+}
+
+/// Whether `path` is a temporary file left behind by an interrupted [`write_atomic`]
+pub fn is_stray_tmp(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|n| n.to_str())
+        .is_some_and(|n| n.starts_with('.') && n.ends_with(TMP_SUFFIX))
+}
+
+/// Replaces the contents of `path` so that readers only ever see the old or the new file.
+///
+/// The data is written and synced to a temporary file in the same directory, which then gets
+/// the permissions of the file it replaces and is renamed over it. If anything fails before the
+/// rename, `path` is left untouched. Either way, errors are reported on `path`.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> Result<(), CrateifyError> {
+    let tmp = tmp_path(path);
+    write_tmp(&tmp, path, contents).map_err(|e| CrateifyError::write(path, e))?;
+    dir_handle::rename(&tmp, path).map_err(|e| {
+        let _ = dir_handle::remove_file(&tmp);
+        CrateifyError::write(path, e)
+    })
+}
+
+fn write_tmp(tmp: &Path, path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut f = dir_handle::create(tmp)?;
+    f.write_all(contents)?;
+    f.sync_all()?;
+
+    if let Ok(permissions) = dir_handle::permissions(path) {
+        f.set_permissions(permissions)?;
+    }
+
+    // testing hook: simulates the process dying between writing the data and renaming it
+    if cfg!(debug_assertions) && std::env::var_os("CRATEIFY_FAIL_BEFORE_RENAME").is_some() {
+        return Err(io::Error::other("injected failure before rename"));
+    }
+    Ok(())
+}
@@ -0,0 +1,59 @@
+//! Helpers shared by the integration tests
+
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+/// Wraps the tree whose root module is `lib` in a throwaway package in `workdir` and runs
+/// `cargo check` on it
+pub fn check_compiles(workdir: &Path, lib: &Path) -> Result<(), String> {
+    let manifest = workdir.join("Cargo.toml");
+    fs::write(
+        &manifest,
+        format!(
+            "[package]\nname = \"generated_tree\"\nversion = \"0.0.0\"\nedition = \"2021\"\n\n\
+             [lib]\npath = {:?}\n",
+            lib
+        ),
+    )
+    .unwrap();
+    cargo_check(&manifest, &workdir.join("target"))
+}
+
+/// Runs `cargo check` on the package of `manifest`, building into `target_dir`
+pub fn cargo_check(manifest: &Path, target_dir: &Path) -> Result<(), String> {
+    cargo_check_with(manifest, target_dir, &[])
+}
+
+/// [`cargo_check`] with extra arguments, such as `--tests`
+pub fn cargo_check_with(manifest: &Path, target_dir: &Path, args: &[&str]) -> Result<(), String> {
+    cargo("check", manifest, target_dir, args)
+}
+
+/// Runs `cargo <command>` on the package of `manifest`, building into `target_dir`
+pub fn cargo(
+    command: &str,
+    manifest: &Path,
+    target_dir: &Path,
+    args: &[&str],
+) -> Result<(), String> {
+    let cargo = env::var("CARGO").unwrap_or_else(|_| "cargo".to_string());
+    let output = Command::new(cargo)
+        .args([command, "--quiet", "--offline"])
+        .args(args)
+        .arg("--manifest-path")
+        .arg(manifest)
+        .env("CARGO_TARGET_DIR", target_dir)
+        .env("RUSTFLAGS", "-A warnings")
+        .output()
+        .map_err(|e| e.to_string())?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(format!(
+            "cargo {command} failed:\n{}",
+            String::from_utf8_lossy(&output.stderr)
+        ))
+    }
+}
@@ -0,0 +1,4358 @@
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Output, Stdio};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+mod common;
+
+fn crateify(args: &[&Path]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_crateify"))
+        .args(args)
+        .output()
+        .expect("failed to run crateify")
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+/// The first line of files generated before the header recorded a version and input path
+const GENERATED: &str = "// @generated by crateify\n";
+
+/// The first line of the module file generated from directory `input`
+fn header(input: &str) -> String {
+    format!(
+        "// @generated by crateify {} from {input}; do not edit\n",
+        env!("CARGO_PKG_VERSION")
+    )
+}
+
+/// `content` without its first line, if that is a generated header
+fn strip_header(content: &str) -> Option<&str> {
+    let (first, body) = content.split_once('\n')?;
+    first
+        .starts_with("// @generated by crateify ")
+        .then_some(body)
+}
+
+/// Contents of the generated mod.rs at `path` below its header line
+fn generated_body(path: &Path) -> String {
+    let content = fs::read_to_string(path).unwrap();
+    match strip_header(&content) {
+        Some(body) => body.to_string(),
+        None => panic!("{} lacks the generated header:\n{content}", path.display()),
+    }
+}
+
+#[test]
+fn missing_argument_is_a_usage_error() {
+    let output = crateify(&[]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stderr(&output).contains("usage: crateify"));
+}
+
+#[test]
+fn unknown_short_flags_are_usage_errors() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::create_dir(dir.path().join("-x")).unwrap();
+    fs::write(dir.path().join("-x").join("tcp.rs"), "").unwrap();
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_crateify"))
+            .args(args)
+            .current_dir(dir.path())
+            .output()
+            .unwrap()
+    };
+
+    let output = run(&["-x"]);
+    assert_eq!(output.status.code(), Some(2), "{}", stderr(&output));
+    assert_eq!(
+        stderr(&output),
+        "crateify: error: unknown option -x (a crate directory whose name starts with - goes \
+         after --)\ncrateify: hint: -x is not an option of crateify; crateify --help lists them\n"
+    );
+    assert!(!dir.path().join("-x").join("mod.rs").exists());
+
+    let output = run(&["--", "-x"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        generated_body(&dir.path().join("-x").join("mod.rs")),
+        "pub mod tcp;\n"
+    );
+}
+
+#[test]
+fn usage_hints_name_the_flag_in_question() {
+    let hint = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_crateify"))
+            .args(args)
+            .output()
+            .unwrap();
+        assert_eq!(output.status.code(), Some(2), "{}", stderr(&output));
+        let stderr = stderr(&output);
+        stderr.lines().last().unwrap().to_string()
+    };
+    assert_eq!(
+        hint(&["--max-depth", "deep", "."]),
+        "crateify: hint: usage of --max-depth: crateify [--max-depth <n>] ..."
+    );
+    assert_eq!(
+        hint(&["--relative-paths", "."]),
+        "crateify: hint: usage of --relative-paths: crateify [--output <dir> [--relative-paths]] ..."
+    );
+    assert_eq!(
+        hint(&["--no-such-flag", "."]),
+        "crateify: hint: --no-such-flag is not an option of crateify; crateify --help lists them"
+    );
+}
+
+#[test]
+fn readme_synopsis_is_the_usage_line_of_help() {
+    let help = Command::new(env!("CARGO_BIN_EXE_crateify"))
+        .arg("--help")
+        .output()
+        .unwrap();
+    assert!(help.status.success());
+    let help = String::from_utf8(help.stdout).unwrap();
+    let usage = help
+        .lines()
+        .next()
+        .unwrap()
+        .strip_prefix("usage: ")
+        .unwrap();
+    let readme =
+        fs::read_to_string(Path::new(env!("CARGO_MANIFEST_DIR")).join("README.md")).unwrap();
+    let synopsis = readme
+        .split_once("## Usage\n\n``` bash\n")
+        .and_then(|(_, rest)| rest.split_once("```"))
+        .unwrap()
+        .0;
+    // one command line of the usage per line
+    assert_eq!(
+        synopsis.trim_end().replace("\ncrateify ", " | crateify "),
+        usage
+    );
+}
+
+#[test]
+fn missing_directory_reports_its_path() {
+    let dir = tempfile::tempdir().unwrap();
+    let missing = dir.path().join("does_not_exist");
+
+    let output = crateify(&[&missing]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains(&missing.display().to_string()));
+}
+
+#[test]
+fn unwritable_mod_rs_reports_its_path() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("foo.rs"), "").unwrap();
+    // a directory in place of mod.rs cannot be opened for writing, even as root
+    fs::create_dir(dir.path().join("mod.rs")).unwrap();
+
+    let output = crateify(&[dir.path()]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains(&dir.path().join("mod.rs").display().to_string()));
+}
+
+#[test]
+#[cfg(unix)]
+fn read_only_directories_report_their_paths() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempfile::tempdir().unwrap();
+    let locked = dir.path().join("locked");
+    let sealed = dir.path().join("sealed");
+    for sub in [&locked, &sealed] {
+        fs::create_dir(sub).unwrap();
+        fs::write(sub.join("foo.rs"), "").unwrap();
+    }
+    fs::set_permissions(&locked, fs::Permissions::from_mode(0o555)).unwrap();
+    fs::set_permissions(&sealed, fs::Permissions::from_mode(0o000)).unwrap();
+    let privileged = fs::write(locked.join("probe"), "").is_ok();
+
+    let output = crateify(&[dir.path()]);
+    for sub in [&locked, &sealed] {
+        fs::set_permissions(sub, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+    // permissions don't stop root
+    if privileged {
+        return;
+    }
+    assert_eq!(output.status.code(), Some(1));
+    let messages = stderr(&output);
+    // both failures are reported, not just the first
+    assert!(
+        messages.contains(&format!(
+            "cannot write {}: ",
+            locked.join("mod.rs").display()
+        )),
+        "{messages}"
+    );
+    assert!(
+        messages.contains(&format!("cannot read directory {}: ", sealed.display())),
+        "{messages}"
+    );
+    assert!(
+        messages.contains("crateify: error: 2 directories failed"),
+        "{messages}"
+    );
+}
+
+#[test]
+fn generates_sorted_mod_rs() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("zeta.rs"), "").unwrap();
+    fs::write(dir.path().join("alpha.rs"), "").unwrap();
+    fs::create_dir(dir.path().join("beta")).unwrap();
+    fs::write(dir.path().join("beta").join("gamma.rs"), "").unwrap();
+
+    let output = crateify(&[dir.path()]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        generated_body(&dir.path().join("mod.rs")),
+        "pub mod alpha;\npub mod beta;\npub mod zeta;\n"
+    );
+    assert_eq!(
+        generated_body(&dir.path().join("beta").join("mod.rs")),
+        "pub mod gamma;\n"
+    );
+}
+
+#[test]
+fn skips_files_without_or_with_other_extensions() {
+    let dir = tempfile::tempdir().unwrap();
+    for name in [
+        "parser.rs",
+        "lexer.rs",
+        "COPYING",
+        "Makefile",
+        "parser.c",
+        "lexer.h",
+    ] {
+        fs::write(dir.path().join(name), "").unwrap();
+    }
+
+    let output = crateify(&[dir.path()]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        generated_body(&dir.path().join("mod.rs")),
+        "pub mod lexer;\npub mod parser;\n"
+    );
+}
+
+#[test]
+fn events_stream_ends_with_summary() {
+    let dir = tempfile::tempdir().unwrap();
+    let tree = dir.path().join("tree");
+    fs::create_dir_all(tree.join("net")).unwrap();
+    fs::write(tree.join("util.rs"), "").unwrap();
+    fs::write(tree.join("net").join("tcp.rs"), "").unwrap();
+    fs::write(tree.join("net").join("udp.rs"), "").unwrap();
+    let events_path = dir.path().join("events.jsonl");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_crateify"))
+        .arg("--events")
+        .arg(&events_path)
+        .arg(&tree)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let events: Vec<serde_json::Value> = fs::read_to_string(&events_path)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert!(events.iter().all(|e| e["version"] == 3));
+
+    let kinds: Vec<&str> = events
+        .iter()
+        .map(|e| e["event"].as_str().unwrap())
+        .collect();
+    let position = |kind: &str, path: &str| {
+        events
+            .iter()
+            .position(|e| e["event"] == kind && e["path"] == path)
+            .unwrap()
+    };
+    // a directory's mod.rs is reported only after its files, and the parent after the child
+    assert!(position("file", "net/tcp.rs") < position("mod_rs", "net/mod.rs"));
+    assert!(position("file", "net/udp.rs") < position("mod_rs", "net/mod.rs"));
+    assert!(position("mod_rs", "net/mod.rs") < position("mod_rs", "mod.rs"));
+    assert_eq!(kinds.last(), Some(&"summary"));
+    assert_eq!(kinds.iter().filter(|k| **k == "summary").count(), 1);
+
+    let summary = events.last().unwrap();
+    assert_eq!(summary["directories"], 2);
+    assert_eq!(summary["files"], 3);
+    assert_eq!(summary["mod_rs"], 2);
+    assert_eq!(summary["created"], 2);
+    assert_eq!(summary["unchanged"], 0);
+    assert_eq!(summary["modules"], 4);
+    assert_eq!(summary["warnings"], 0);
+    assert_eq!(summary["failed"], 0);
+}
+
+#[test]
+fn only_the_summary_without_ideas_log() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("foo.rs"), "").unwrap();
+    fs::write(dir.path().join("notes.txt"), "").unwrap();
+
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_crateify"))
+            .args(args)
+            .arg(dir.path())
+            .env_remove("IDEAS_LOG")
+            .output()
+            .unwrap()
+    };
+    let output = run(&[]);
+    assert!(output.status.success());
+    let log = stderr(&output);
+    assert_eq!(log.lines().count(), 1, "{log}");
+    assert!(
+        log.starts_with(
+            "crateify: 1 directories processed: 1 succeeded, 0 failed, 0 warning(s); \
+             mod.rs: 1 created, 0 updated, 0 unchanged; 1 module(s) declared, 1 entry skipped in "
+        ),
+        "{log}"
+    );
+
+    let output = run(&["--quiet"]);
+    assert!(output.status.success());
+    assert!(output.stderr.is_empty(), "{}", stderr(&output));
+}
+
+#[test]
+fn verbose_says_what_became_of_each_entry() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::create_dir_all(dir.path().join(".git")).unwrap();
+    fs::create_dir_all(dir.path().join("net")).unwrap();
+    fs::write(dir.path().join("net").join("tcp.rs"), "").unwrap();
+    fs::write(dir.path().join("notes.txt"), "").unwrap();
+
+    let run = |arg: &str| {
+        let output = Command::new(env!("CARGO_BIN_EXE_crateify"))
+            .arg(arg)
+            .arg(dir.path())
+            .env_remove("IDEAS_LOG")
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{}", stderr(&output));
+        stderr(&output)
+    };
+    let log = run("-v");
+    assert!(log.contains("INFO"), "{log}");
+    assert!(!log.contains("DEBUG"), "{log}");
+    assert!(log.contains("mod.rs: created with 1 module(s)"), "{log}");
+    assert!(log.contains("2 entries skipped"), "{log}");
+
+    let log = run("-vv");
+    assert!(log.contains("mod.rs: unchanged with 1 module(s)"), "{log}");
+    assert!(log.contains("visiting directory"), "{log}");
+    assert!(log.contains("notes.txt: not a .rs file, skipped"), "{log}");
+    assert!(log.contains(".git: ignored directory, skipped"), "{log}");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_crateify"))
+        .args(["-v", "--quiet"])
+        .arg(dir.path())
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2), "{}", stderr(&output));
+}
+
+#[test]
+fn debug_log_goes_to_stderr_only() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("foo.rs"), "").unwrap();
+    fs::write(dir.path().join("notes.txt"), "").unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_crateify"))
+        .args(["--events", "-"])
+        .arg(dir.path())
+        .env("IDEAS_LOG", "debug")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let log = stderr(&output);
+    // the log lines, then the summary
+    let lines: Vec<&str> = log.lines().collect();
+    let (summary, lines) = lines.split_last().unwrap();
+    assert!(lines.iter().all(|line| line.starts_with('[')), "{log}");
+    assert!(
+        summary.starts_with("crateify: 1 directories processed"),
+        "{log}"
+    );
+    assert!(log.contains("DEBUG"));
+    assert!(log.contains("visiting directory"));
+    assert!(log.contains("notes.txt: not a .rs file, skipped"));
+
+    // stdout carries nothing but the event stream
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(!stdout.is_empty());
+    for line in stdout.lines() {
+        serde_json::from_str::<serde_json::Value>(line).unwrap();
+    }
+}
+
+#[test]
+fn warn_level_hides_debug_lines() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("foo.rs"), "").unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_crateify"))
+        .arg(dir.path())
+        .env("IDEAS_LOG", "warn")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert!(!stderr(&output).contains("DEBUG"));
+}
+
+fn print_schema(name: &str) -> serde_json::Value {
+    let output = Command::new(env!("CARGO_BIN_EXE_crateify"))
+        .args(["--print-schema", name])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    serde_json::from_slice(&output.stdout).unwrap()
+}
+
+fn run_with_events(tree: &Path) -> Vec<serde_json::Value> {
+    let output = Command::new(env!("CARGO_BIN_EXE_crateify"))
+        .args(["--events", "-"])
+        .arg(tree)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
+
+#[test]
+fn events_schema_is_pinned_to_its_version() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("foo.rs"), "").unwrap();
+    let events = run_with_events(dir.path());
+    let version = &events[0]["version"];
+
+    // changing the event types without bumping EVENTS_VERSION fails here; after bumping, add
+    // the new golden file next to the old one
+    let golden = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/schemas")
+        .join(format!("crateify-events.v{version}.json"));
+    let expected: serde_json::Value = serde_json::from_str(
+        &fs::read_to_string(&golden)
+            .unwrap_or_else(|_| panic!("missing golden schema {}", golden.display())),
+    )
+    .unwrap();
+    assert_eq!(print_schema("crateify-events"), expected);
+}
+
+#[test]
+fn events_match_their_schema() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::create_dir(dir.path().join("net")).unwrap();
+    fs::write(dir.path().join("net").join("tcp.rs"), "").unwrap();
+    let schema = print_schema("crateify-events");
+    let envelope_props = schema["properties"].as_object().unwrap();
+
+    for event in run_with_events(dir.path()) {
+        assert_eq!(event["schema"], "crateify-events");
+        let variant = schema["oneOf"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|v| v["properties"]["event"]["const"] == event["event"])
+            .unwrap_or_else(|| panic!("event not described by the schema: {event}"));
+        let props = variant["properties"].as_object().unwrap();
+
+        for key in event.as_object().unwrap().keys() {
+            assert!(
+                props.contains_key(key) || envelope_props.contains_key(key),
+                "{key} missing from schema"
+            );
+        }
+        for required in variant["required"].as_array().unwrap() {
+            assert!(event.get(required.as_str().unwrap()).is_some());
+        }
+    }
+}
+
+/// One line of stderr under `--error-format json`
+#[derive(Debug, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Diagnostic {
+    schema: String,
+    version: u32,
+    level: String,
+    code: String,
+    path: String,
+    message: String,
+}
+
+#[test]
+fn error_format_json_writes_one_diagnostic_per_line() {
+    let dir = tempfile::tempdir().unwrap();
+    copy_fixture("broken", dir.path());
+
+    let output = Command::new(env!("CARGO_BIN_EXE_crateify"))
+        .args(["--error-format", "json"])
+        .arg(dir.path())
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1), "{}", stderr(&output));
+    // nothing but diagnostics on stderr, not even the summary line
+    let diagnostics: Vec<Diagnostic> = stderr(&output)
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap_or_else(|e| panic!("{e}: {line}")))
+        .collect();
+    assert!(
+        diagnostics
+            .iter()
+            .all(|d| d.schema == "crateify-diagnostics" && d.version == 4),
+        "{diagnostics:?}"
+    );
+    // directories are visited in parallel, only the error that ends the run is always last
+    let mut found: Vec<(&str, &str, &str)> = diagnostics
+        .iter()
+        .map(|d| (d.level.as_str(), d.code.as_str(), d.path.as_str()))
+        .collect();
+    assert_eq!(found.pop(), Some(("error", "failed", "")));
+    found.sort();
+    assert_eq!(
+        found,
+        [
+            ("error", "module-collision", "net"),
+            ("warning", "entry-point-name", "util/main.rs"),
+            ("warning", "unmanaged-file", "legacy/mod.rs"),
+        ]
+    );
+    let collision = diagnostics
+        .iter()
+        .find(|d| d.code == "module-collision")
+        .unwrap();
+    assert!(
+        collision.message.ends_with(
+            "http-parser.rs, http_parser.rs would all be declared as module http_parser"
+        ),
+        "{}",
+        collision.message
+    );
+
+    // the summary is the only line on stdout
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let summary: serde_json::Value = serde_json::from_str(stdout.trim_end()).unwrap();
+    assert_eq!(summary["event"], "summary");
+    assert_eq!(
+        (summary["failed"].as_u64(), summary["warnings"].as_u64()),
+        (Some(1), Some(2))
+    );
+
+    // the same problems, read by people
+    let output = crateify(&[dir.path()]);
+    let text = stderr(&output);
+    assert!(
+        text.contains("crateify: warning: util/main.rs: named like"),
+        "{text}"
+    );
+    assert!(
+        text.contains("crateify: error: 1 directory failed"),
+        "{text}"
+    );
+
+    let output = Command::new(env!("CARGO_BIN_EXE_crateify"))
+        .args(["--error-format", "json", "--jobs", "many"])
+        .arg(dir.path())
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    let usage: Diagnostic = serde_json::from_str(stderr(&output).trim_end()).unwrap();
+    assert_eq!(
+        (usage.level.as_str(), usage.code.as_str()),
+        ("error", "usage")
+    );
+}
+
+#[test]
+fn diagnostics_schema_is_pinned_to_its_version() {
+    let golden =
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/schemas/crateify-diagnostics.v4.json");
+    let expected: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(golden).unwrap()).unwrap();
+    // codes are matched on; renaming or removing one means bumping DIAGNOSTICS_VERSION
+    assert_eq!(print_schema("crateify-diagnostics"), expected);
+}
+
+#[test]
+fn graph_schema_is_pinned_to_its_version() {
+    let golden = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/schemas/crateify-graph.v1.json");
+    let expected: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(golden).unwrap()).unwrap();
+    // like the manifest schema: bump GRAPH_VERSION and add a golden file for any change
+    assert_eq!(print_schema("crateify-graph"), expected);
+    let help = Command::new(env!("CARGO_BIN_EXE_crateify"))
+        .arg("--help")
+        .output()
+        .unwrap();
+    let help = String::from_utf8(help.stdout).unwrap();
+    assert!(
+        help.contains("<crateify-events|crateify-manifest|crateify-diagnostics|crateify-graph>"),
+        "{help}"
+    );
+}
+
+#[test]
+fn unknown_schema_is_a_usage_error() {
+    let output = Command::new(env!("CARGO_BIN_EXE_crateify"))
+        .args(["--print-schema", "nope"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn manifest_schema_is_pinned_to_its_version() {
+    let golden =
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/schemas/crateify-manifest.v8.json");
+    let expected: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(golden).unwrap()).unwrap();
+    // like the events schema: bump MANIFEST_VERSION and add a golden file for any change
+    assert_eq!(print_schema("crateify-manifest"), expected);
+    let help = Command::new(env!("CARGO_BIN_EXE_crateify"))
+        .arg("--help")
+        .output()
+        .unwrap();
+    assert!(help.status.success());
+    let help = String::from_utf8(help.stdout).unwrap();
+    assert!(
+        help.contains("--manifest <path.json> writes the module tree"),
+        "{help}"
+    );
+}
+
+fn run_with_manifest(tree: &Path, manifest: &Path, args: &[&str]) -> serde_json::Value {
+    let output = Command::new(env!("CARGO_BIN_EXE_crateify"))
+        .args(args)
+        .arg("--manifest")
+        .arg(manifest)
+        .arg(tree)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    serde_json::from_str(&fs::read_to_string(manifest).unwrap()).unwrap()
+}
+
+#[test]
+fn manifest_describes_the_generated_tree() {
+    let dir = tempfile::tempdir().unwrap();
+    let tree = dir.path().join("tree");
+    fs::create_dir_all(tree.join("net").join("async")).unwrap();
+    fs::write(tree.join("util.rs"), "").unwrap();
+    fs::write(tree.join("net").join("tcp.rs"), "").unwrap();
+    fs::write(tree.join("net").join("async").join("poll.rs"), "").unwrap();
+    let manifest = dir.path().join("modules.json");
+
+    // a dry run describes what it would write
+    let dry = run_with_manifest(&tree, &manifest, &["--dry-run"]);
+    assert!(!tree.join("mod.rs").exists());
+    assert_eq!(dry["schema"], "crateify-manifest");
+    assert_eq!(dry["version"], 8);
+    assert_eq!(dry["binaries"], serde_json::json!({}));
+    assert_eq!(dry["c_sources"], serde_json::json!({}));
+    // keyed by the crate directory as given
+    let root = tree.to_str().unwrap();
+    assert_eq!(dry["roots"].as_object().unwrap().len(), 1);
+    assert_eq!(
+        dry["roots"][root],
+        serde_json::json!([
+            {
+                "path": "",
+                "module_path": "crate",
+                "module_file": "mod.rs",
+                "status": "created",
+                "modules": [
+                    {"name": "net", "source": "net/mod.rs"},
+                    {"name": "util", "source": "util.rs"},
+                ],
+            },
+            {
+                "path": "net",
+                "module_path": "crate::net",
+                "module_file": "net/mod.rs",
+                "status": "created",
+                "modules": [
+                    {"name": "r#async", "source": "net/async/mod.rs"},
+                    {"name": "tcp", "source": "net/tcp.rs"},
+                ],
+            },
+            {
+                "path": "net/async",
+                "module_path": "crate::net::r#async",
+                "module_file": "net/async/mod.rs",
+                "status": "created",
+                "modules": [{"name": "poll", "source": "net/async/poll.rs"}],
+            },
+        ])
+    );
+
+    assert_eq!(run_with_manifest(&tree, &manifest, &[]), dry);
+    fs::write(tree.join("net").join("udp.rs"), "").unwrap();
+    let rerun = run_with_manifest(&tree, &manifest, &[]);
+    let status: Vec<_> = rerun["roots"][root]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|d| (d["path"].as_str().unwrap(), d["status"].as_str().unwrap()))
+        .collect();
+    assert_eq!(
+        status,
+        [
+            ("", "unchanged"),
+            ("net", "updated"),
+            ("net/async", "unchanged")
+        ]
+    );
+}
+
+#[test]
+fn graph_lists_the_modules_each_module_uses() {
+    let dir = tempfile::tempdir().unwrap();
+    let tree = dir.path().join("tree");
+    copy_fixture("graph", &tree);
+    let graph = dir.path().join("graph.json");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_crateify"))
+        .args(["--graph-format", "json", "--graph"])
+        .arg(&graph)
+        .arg(&tree)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    let graph: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&graph).unwrap()).unwrap();
+    assert_eq!(graph["schema"], "crateify-graph");
+    assert_eq!(graph["version"], 1);
+    assert_eq!(
+        graph["modules"],
+        serde_json::json!([
+            "crate",
+            "crate::app",
+            "crate::broken",
+            "crate::net",
+            "crate::net::tcp",
+            "crate::net::udp",
+            "crate::util"
+        ])
+    );
+    // tcp and udp use each other, which the C code got away with through its headers
+    assert_eq!(
+        graph["edges"],
+        serde_json::json!([
+            {"from": "crate::app", "to": "crate::net::tcp"},
+            {"from": "crate::app", "to": "crate::net::udp"},
+            {"from": "crate::net::tcp", "to": "crate::net::udp"},
+            {"from": "crate::net::tcp", "to": "crate::util"},
+            {"from": "crate::net::udp", "to": "crate::net::tcp"}
+        ])
+    );
+    let warnings: Vec<String> = graph["warnings"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|w| {
+            format!(
+                "{}: {}",
+                w["module"].as_str().unwrap(),
+                w["message"].as_str().unwrap()
+            )
+        })
+        .collect();
+    assert_eq!(warnings.len(), 3, "{warnings:?}");
+    assert!(
+        warnings[0].starts_with("crate::broken: does not parse"),
+        "{warnings:?}"
+    );
+    assert_eq!(
+        warnings[1..],
+        [
+            "crate::app: crate::missing::thing: missing is not a module",
+            "crate::util: super::super::nothing goes above the crate root"
+        ]
+    );
+
+    let dot = dir.path().join("graph.dot");
+    let output = Command::new(env!("CARGO_BIN_EXE_crateify"))
+        .arg("--graph")
+        .arg(&dot)
+        .arg(&tree)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    let dot = fs::read_to_string(&dot).unwrap();
+    assert!(dot.starts_with("digraph crate {\n"), "{dot}");
+    assert!(
+        dot.contains("    \"crate::net::udp\" -> \"crate::net::tcp\";\n"),
+        "{dot}"
+    );
+    assert!(
+        dot.contains(
+            "    // warning: crate::app: crate::missing::thing: missing is not a module\n"
+        ),
+        "{dot}"
+    );
+    assert!(dot.contains("    \"crate::broken\";\n"), "{dot}");
+}
+
+#[test]
+fn reported_paths_use_slashes_unless_native_paths() {
+    let dir = tempfile::tempdir().unwrap();
+    let tree = dir.path().join("tree");
+    fs::create_dir_all(tree.join("a").join("b")).unwrap();
+    fs::write(tree.join("a").join("b").join("leaf.rs"), "").unwrap();
+    let manifest = dir.path().join("modules.json");
+    let graph = dir.path().join("graph.json");
+
+    let run = |extra: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_crateify"))
+            .args(extra)
+            .args(["--events", "-", "--graph-format", "json", "--graph"])
+            .arg(&graph)
+            .arg("--manifest")
+            .arg(&manifest)
+            .arg(&tree)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{}", stderr(&output));
+        let events: Vec<serde_json::Value> = String::from_utf8(output.stdout)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let mod_rs: Vec<String> = events
+            .iter()
+            .filter(|e| e["event"] == "mod_rs")
+            .map(|e| e["path"].as_str().unwrap().to_string())
+            .collect();
+        let manifest: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&manifest).unwrap()).unwrap();
+        let module_files: Vec<String> = manifest["roots"][tree.to_str().unwrap()]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|d| d["module_file"].as_str().unwrap().to_string())
+            .collect();
+        let graph: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&graph).unwrap()).unwrap();
+        (mod_rs, module_files, graph)
+    };
+
+    let (mod_rs, module_files, graph) = run(&[]);
+    assert_eq!(mod_rs, ["a/b/mod.rs", "a/mod.rs", "mod.rs"]);
+    assert_eq!(module_files, ["mod.rs", "a/mod.rs", "a/b/mod.rs"]);
+    assert_eq!(
+        graph["modules"],
+        serde_json::json!(["crate", "crate::a", "crate::a::b", "crate::a::b::leaf"])
+    );
+
+    // the platform's own separator, which is `/` here unless this is Windows
+    let native = |path: &str| path.replace('/', std::path::MAIN_SEPARATOR_STR);
+    let (mod_rs, module_files, native_graph) = run(&["--native-paths"]);
+    assert_eq!(
+        mod_rs,
+        [native("a/b/mod.rs"), native("a/mod.rs"), native("mod.rs")]
+    );
+    assert_eq!(
+        module_files,
+        [native("mod.rs"), native("a/mod.rs"), native("a/b/mod.rs")]
+    );
+    // module paths have no separators to change
+    assert_eq!(native_graph, graph);
+}
+
+#[test]
+fn interrupted_write_keeps_original_and_is_reaped() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("foo.rs"), "").unwrap();
+    let original = format!("{GENERATED}pub mod old;\n");
+    fs::write(dir.path().join("mod.rs"), &original).unwrap();
+    let tmp = dir.path().join(".mod.rs.crateify-tmp");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_crateify"))
+        .arg(dir.path())
+        .env("CRATEIFY_FAIL_BEFORE_RENAME", "1")
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        fs::read_to_string(dir.path().join("mod.rs")).unwrap(),
+        original
+    );
+    assert!(tmp.exists());
+
+    let output = crateify(&[dir.path()]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(!tmp.exists());
+    assert!(
+        fs::read_to_string(dir.path().join("mod.rs"))
+            .unwrap()
+            .contains("pub mod foo;")
+    );
+}
+
+#[test]
+fn rerun_leaves_unchanged_files_unwritten() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::create_dir_all(dir.path().join("net").join("proto")).unwrap();
+    fs::write(dir.path().join("net").join("proto").join("http.rs"), "").unwrap();
+    fs::write(dir.path().join("net").join("tcp.rs"), "").unwrap();
+    fs::write(dir.path().join("util.rs"), "").unwrap();
+    let output = crateify(&[dir.path()]);
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    // far enough in the past that any write would show
+    let past = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+    let mod_rs_files = [
+        dir.path().join("mod.rs"),
+        dir.path().join("net").join("mod.rs"),
+        dir.path().join("net").join("proto").join("mod.rs"),
+    ];
+    for path in &mod_rs_files {
+        fs::File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(past)
+            .unwrap();
+    }
+    let output = crateify(&[dir.path()]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(
+        stderr(&output).contains("mod.rs: 0 created, 0 updated, 3 unchanged"),
+        "{}",
+        stderr(&output)
+    );
+    for path in &mod_rs_files {
+        let modified = fs::metadata(path).unwrap().modified().unwrap();
+        assert_eq!(modified, past, "{}", path.display());
+    }
+}
+
+#[test]
+#[cfg(unix)]
+fn rewrite_preserves_permissions() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempfile::tempdir().unwrap();
+    let mod_rs = dir.path().join("mod.rs");
+    fs::write(dir.path().join("foo.rs"), "").unwrap();
+    fs::write(&mod_rs, GENERATED).unwrap();
+    fs::set_permissions(&mod_rs, fs::Permissions::from_mode(0o640)).unwrap();
+
+    let output = crateify(&[dir.path()]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(generated_body(&mod_rs), "pub mod foo;\n");
+    let mode = fs::metadata(&mod_rs).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o640);
+}
+
+fn git(dir: &Path, args: &[&str]) {
+    let status = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args([
+            "-c",
+            "user.name=crateify",
+            "-c",
+            "user.email=crateify@example.com",
+        ])
+        .args(args)
+        .status()
+        .unwrap();
+    assert!(status.success(), "git {args:?}");
+}
+
+fn git_commit_all(dir: &Path) {
+    git(dir, &["add", "-A"]);
+    git(dir, &["commit", "-q", "-m", "snapshot"]);
+}
+
+#[test]
+fn since_only_regenerates_ancestors_of_changes() {
+    let repo = tempfile::tempdir().unwrap();
+    let root = &repo.path().join("src");
+    fs::create_dir_all(root.join("a").join("b")).unwrap();
+    fs::create_dir(root.join("c")).unwrap();
+    fs::write(root.join("a").join("b").join("old.rs"), "").unwrap();
+    fs::write(root.join("c").join("other.rs"), "").unwrap();
+    // c never changes below, so this must survive every --since run
+    fs::write(root.join("c").join("mod.rs"), "// untouched\n").unwrap();
+    git(repo.path(), &["init", "-q"]);
+    git_commit_all(repo.path());
+
+    fs::write(root.join("a").join("b").join("new.rs"), "").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_crateify"))
+        .args(["--since", "HEAD"])
+        .arg(root)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        generated_body(&root.join("a").join("b").join("mod.rs")),
+        "pub mod new;\npub mod old;\n"
+    );
+    assert_eq!(
+        generated_body(&root.join("a").join("mod.rs")),
+        "pub mod b;\n"
+    );
+    // the crate lives in src/, so its root module is lib.rs
+    assert!(root.join("lib.rs").exists());
+    assert_eq!(
+        fs::read_to_string(root.join("c").join("mod.rs")).unwrap(),
+        "// untouched\n"
+    );
+
+    // only the index counts with --staged, so the unstaged file in c is ignored
+    fs::write(root.join("a").join("staged.rs"), "").unwrap();
+    fs::write(root.join("c").join("unstaged.rs"), "").unwrap();
+    git(root, &["add", "a/staged.rs"]);
+    let output = Command::new(env!("CARGO_BIN_EXE_crateify"))
+        .args(["--since", "HEAD", "--staged"])
+        .arg(root)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(
+        fs::read_to_string(root.join("a").join("mod.rs"))
+            .unwrap()
+            .contains("pub mod staged;\n")
+    );
+    assert_eq!(
+        fs::read_to_string(root.join("c").join("mod.rs")).unwrap(),
+        "// untouched\n"
+    );
+}
+
+#[test]
+fn since_outside_git_falls_back_to_full_run() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::create_dir(dir.path().join("net")).unwrap();
+    fs::write(dir.path().join("net").join("tcp.rs"), "").unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_crateify"))
+        .args(["--since", "HEAD"])
+        .arg(dir.path())
+        .env("GIT_CEILING_DIRECTORIES", dir.path().parent().unwrap())
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stderr(&output).contains("warning: --since HEAD ignored"));
+    assert!(dir.path().join("net").join("mod.rs").exists());
+    assert!(dir.path().join("mod.rs").exists());
+}
+
+#[test]
+fn files_from_regenerates_only_the_ancestors_of_listed_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let tree = dir.path().join("tree");
+    fs::create_dir_all(tree.join("a").join("b")).unwrap();
+    fs::create_dir_all(tree.join("x")).unwrap();
+    fs::write(tree.join("a").join("b").join("deep.rs"), "").unwrap();
+    fs::write(tree.join("x").join("y.rs"), "").unwrap();
+    assert!(crateify(&[&tree]).status.success());
+
+    // regenerated by the translation, along with a sibling it never declared before
+    fs::write(tree.join("a").join("b").join("deep.rs"), "pub fn f() {}\n").unwrap();
+    fs::write(tree.join("a").join("b").join("unlisted.rs"), "").unwrap();
+    fs::write(tree.join("x").join("z.rs"), "").unwrap();
+    let events = dir.path().join("events.jsonl");
+    let mut child = Command::new(env!("CARGO_BIN_EXE_crateify"))
+        .args(["--files-from", "-", "--events"])
+        .arg(&events)
+        .arg(&tree)
+        .stdin(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"a/b/deep.rs\n")
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    let mut written: Vec<String> = fs::read_to_string(&events)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+        .filter(|event| event["event"] == "mod_rs")
+        .map(|event| event["path"].as_str().unwrap().to_string())
+        .collect();
+    written.sort();
+    assert_eq!(written, ["a/b/mod.rs", "a/mod.rs", "mod.rs"]);
+    assert_eq!(
+        generated_body(&tree.join("a").join("b").join("mod.rs")),
+        "pub mod deep;\npub mod unlisted;\n"
+    );
+    assert_eq!(
+        generated_body(&tree.join("x").join("mod.rs")),
+        "pub mod y;\n"
+    );
+}
+
+#[test]
+fn files_from_rejects_paths_it_cannot_regenerate() {
+    let dir = tempfile::tempdir().unwrap();
+    let tree = dir.path().join("tree");
+    fs::create_dir_all(&tree).unwrap();
+    fs::write(tree.join("lib.rs"), "").unwrap();
+    let list = dir.path().join("files.txt");
+    for (line, reason) in [
+        ("../outside.rs", "is outside of"),
+        ("/elsewhere/x.rs", "is outside of"),
+        ("notes.txt", "is not a .rs file"),
+    ] {
+        fs::write(&list, format!("lib.rs\n{line}\n")).unwrap();
+        let output = Command::new(env!("CARGO_BIN_EXE_crateify"))
+            .arg("--files-from")
+            .arg(&list)
+            .arg(&tree)
+            .output()
+            .unwrap();
+        assert_eq!(output.status.code(), Some(2), "{}", stderr(&output));
+        assert!(
+            stderr(&output).contains(&format!(":2: {line} {reason}")),
+            "{}",
+            stderr(&output)
+        );
+        assert!(!tree.join("mod.rs").exists());
+    }
+}
+
+/// Polls `check` for up to ten seconds
+fn eventually(what: &str, mut check: impl FnMut() -> bool) {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !check() {
+        assert!(Instant::now() < deadline, "timed out waiting for {what}");
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+fn spawn_watch(tree: &Path, args: &[&str]) -> Child {
+    Command::new(env!("CARGO_BIN_EXE_crateify"))
+        .args(["--watch", "--poll-interval", "20"])
+        .args(args)
+        .arg(tree)
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap()
+}
+
+#[test]
+fn watch_follows_added_and_removed_files() {
+    watch_follows_added_and_removed_files_with(&[]);
+}
+
+#[test]
+fn watch_follows_added_and_removed_files_when_polling() {
+    watch_follows_added_and_removed_files_with(&["--poll"]);
+}
+
+fn watch_follows_added_and_removed_files_with(args: &[&str]) {
+    let dir = tempfile::tempdir().unwrap();
+    let net = dir.path().join("net");
+    fs::create_dir(&net).unwrap();
+    fs::write(net.join("tcp.rs"), "").unwrap();
+    let read = |path: &Path| {
+        let content = fs::read_to_string(path).unwrap_or_default();
+        strip_header(&content).unwrap_or(&content).to_string()
+    };
+
+    let mut child = spawn_watch(dir.path(), args);
+    eventually("the first pass", || {
+        read(&net.join("mod.rs")) == "pub mod tcp;\n"
+    });
+
+    fs::write(net.join("udp.rs"), "").unwrap();
+    eventually("udp to be declared", || {
+        read(&net.join("mod.rs")) == "pub mod tcp;\npub mod udp;\n"
+    });
+
+    fs::create_dir(dir.path().join("util")).unwrap();
+    fs::write(dir.path().join("util").join("hash.rs"), "").unwrap();
+    eventually("util to be declared", || {
+        read(&dir.path().join("mod.rs")) == "pub mod net;\npub mod util;\n"
+    });
+
+    fs::remove_file(net.join("tcp.rs")).unwrap();
+    eventually("tcp to be dropped", || {
+        read(&net.join("mod.rs")) == "pub mod udp;\n"
+    });
+
+    child.kill().unwrap();
+    let output = child.wait_with_output().unwrap();
+    let summaries: Vec<_> = stderr(&output)
+        .lines()
+        .filter(|line| line.contains("path(s) changed"))
+        .map(str::to_string)
+        .collect();
+    // net/mod.rs changed, the root's was regenerated as it was
+    assert_eq!(
+        summaries.first().map(String::as_str),
+        Some("crateify: 1 path(s) changed, rewrote 1 mod.rs file(s)"),
+        "{summaries:?}"
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn watch_stops_cleanly_on_interrupt() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("util.rs"), "").unwrap();
+
+    let mut child = spawn_watch(dir.path(), &[]);
+    // the Ctrl-C handler is installed by the time crateify says it's watching
+    let lines = BufReader::new(child.stderr.take().unwrap()).lines();
+    assert!(
+        lines
+            .map(Result::unwrap)
+            .any(|line| line.contains("crateify: watching"))
+    );
+    let status = Command::new("kill")
+        .args(["-INT", &child.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    assert!(child.wait().unwrap().success());
+    assert!(dir.path().join("mod.rs").exists());
+}
+
+/// Five sibling directories where the second cannot get its mod.rs
+fn tree_failing_second_of_five(root: &Path) {
+    for name in ["d1", "d2", "d3", "d4", "d5"] {
+        fs::create_dir(root.join(name)).unwrap();
+        fs::write(root.join(name).join("lib_mod.rs"), "").unwrap();
+    }
+    fs::create_dir(root.join("d2").join("mod.rs")).unwrap();
+}
+
+fn run_with_policy(policy: &str) -> (tempfile::TempDir, Output, Vec<serde_json::Value>) {
+    let dir = tempfile::tempdir().unwrap();
+    let tree = dir.path().join("tree");
+    fs::create_dir(&tree).unwrap();
+    tree_failing_second_of_five(&tree);
+    let events_path = dir.path().join("events.jsonl");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_crateify"))
+        .arg(policy)
+        .arg("--events")
+        .arg(&events_path)
+        .arg(&tree)
+        .output()
+        .unwrap();
+    let events = fs::read_to_string(&events_path)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    (dir, output, events)
+}
+
+#[test]
+fn keep_going_processes_everything_and_fails_at_the_end() {
+    let (dir, output, events) = run_with_policy("--keep-going");
+    let tree = dir.path().join("tree");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains(&tree.join("d2").join("mod.rs").display().to_string()));
+    assert!(stderr(&output).contains("succeeded, 1 failed"));
+    for name in ["d1", "d3", "d4", "d5"] {
+        assert!(tree.join(name).join("mod.rs").is_file(), "{name}");
+    }
+    assert!(tree.join("mod.rs").is_file());
+
+    let errors: Vec<_> = events.iter().filter(|e| e["event"] == "error").collect();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0]["path"], "d2");
+    let summary = events.last().unwrap();
+    assert_eq!(summary["event"], "summary");
+    assert_eq!(summary["files"], 5);
+    assert_eq!(summary["failed"], 1);
+}
+
+#[test]
+fn fail_fast_stops_after_the_first_failure_but_still_reports() {
+    let (dir, output, events) = run_with_policy("--fail-fast");
+    let tree = dir.path().join("tree");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(tree.join("d1").join("mod.rs").is_file());
+    for name in ["d3", "d4", "d5"] {
+        assert!(!tree.join(name).join("mod.rs").exists(), "{name}");
+    }
+    assert!(!tree.join("mod.rs").exists());
+
+    // d3..d5 were never visited, so they don't show up in the report either
+    let paths: Vec<_> = events.iter().filter_map(|e| e["path"].as_str()).collect();
+    assert!(
+        paths
+            .iter()
+            .all(|p| !["d3", "d4", "d5"].iter().any(|d| p.starts_with(d))),
+        "{paths:?}"
+    );
+    let summary = events.last().unwrap();
+    assert_eq!(summary["event"], "summary");
+    assert_eq!(summary["files"], 2);
+    assert_eq!(summary["failed"], 1);
+}
+
+#[test]
+fn parallel_traversal_reports_every_failure() {
+    let dir = tempfile::tempdir().unwrap();
+    let tree = dir.path().join("tree");
+    fs::create_dir(&tree).unwrap();
+    tree_failing_second_of_five(&tree);
+    fs::create_dir(tree.join("d4").join("mod.rs")).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_crateify"))
+        .args(["--jobs", "4"])
+        .arg(&tree)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(
+        stderr(&output).contains("2 directories failed"),
+        "{}",
+        stderr(&output)
+    );
+    for name in ["d1", "d3", "d5"] {
+        assert!(tree.join(name).join("mod.rs").is_file(), "{name}");
+    }
+    assert_eq!(
+        generated_body(&tree.join("mod.rs")),
+        "pub mod d1;\npub mod d2;\npub mod d3;\npub mod d4;\npub mod d5;\n"
+    );
+}
+
+/// A tree with a non-UTF-8 file name and a non-UTF-8 directory name next to valid entries
+#[cfg(target_os = "linux")]
+fn tree_with_invalid_names(root: &Path) {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    fs::write(root.join("good.rs"), "").unwrap();
+    fs::write(root.join(OsStr::from_bytes(b"bad\xff.rs")), "").unwrap();
+    let dir = root.join(OsStr::from_bytes(b"sub\xfe"));
+    fs::create_dir(&dir).unwrap();
+    fs::write(dir.join("inner.rs"), "").unwrap();
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn invalid_names_fail_their_directory() {
+    let dir = tempfile::tempdir().unwrap();
+    tree_with_invalid_names(dir.path());
+
+    let output = crateify(&[dir.path()]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = stderr(&output);
+    assert!(stderr.contains("bad\u{fffd}.rs"), "{stderr}");
+    assert!(stderr.contains("sub\u{fffd}"), "{stderr}");
+    assert!(stderr.contains("--skip-invalid-names"), "{stderr}");
+    // an incomplete mod.rs would only fail later, when the parent module is compiled
+    assert!(!dir.path().join("mod.rs").exists());
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn skip_invalid_names_warns_per_entry() {
+    let dir = tempfile::tempdir().unwrap();
+    tree_with_invalid_names(dir.path());
+
+    let output = Command::new(env!("CARGO_BIN_EXE_crateify"))
+        .arg("--skip-invalid-names")
+        .arg(dir.path())
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    let warnings: Vec<_> = stderr(&output)
+        .lines()
+        .filter(|l| l.starts_with("crateify: warning:"))
+        .map(str::to_string)
+        .collect();
+    assert_eq!(warnings.len(), 2, "{warnings:?}");
+    assert_eq!(
+        generated_body(&dir.path().join("mod.rs")),
+        "pub mod good;\n"
+    );
+}
+
+/// Copies the checked-in fixture tree `name` into `dest`
+fn copy_fixture(name: &str, dest: &Path) {
+    fn copy(from: &Path, to: &Path) {
+        fs::create_dir_all(to).unwrap();
+        for entry in fs::read_dir(from).unwrap() {
+            let path = entry.unwrap().path();
+            let target = to.join(path.file_name().unwrap());
+            if path.is_dir() {
+                copy(&path, &target);
+            } else {
+                fs::copy(&path, &target).unwrap();
+            }
+        }
+    }
+    copy(
+        &Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures")
+            .join(name),
+        dest,
+    );
+}
+
+#[test]
+fn keyword_modules_compile() {
+    let dir = tempfile::tempdir().unwrap();
+    let tree = dir.path().join("tree");
+    copy_fixture("keywords", &tree);
+
+    let output = crateify(&[&tree]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        generated_body(&tree.join("mod.rs")),
+        "pub mod r#async;\n\
+         #[path = \"crate.rs\"]\npub mod crate_;\n\
+         pub mod r#loop;\n\
+         pub mod r#match;\n\
+         pub mod plain;\n\
+         #[path = \"self.rs\"]\npub mod self_;\n\
+         #[path = \"super/mod.rs\"]\npub mod super_;\n\
+         pub mod r#type;\n"
+    );
+    assert_eq!(
+        generated_body(&tree.join("async").join("mod.rs")),
+        "pub mod r#gen;\n"
+    );
+    common::check_compiles(dir.path(), &tree.join("mod.rs")).unwrap();
+}
+
+#[test]
+fn non_identifier_names_get_a_path_attribute() {
+    let dir = tempfile::tempdir().unwrap();
+    let tree = dir.path().join("tree");
+    copy_fixture("identifiers", &tree);
+
+    let output = crateify(&[&tree]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        generated_body(&tree.join("mod.rs")),
+        "#[path = \"9p.rs\"]\npub mod _9p;\n\
+         #[path = \"foo.bar.rs\"]\npub mod foo_bar;\n\
+         #[path = \"http-parser.rs\"]\npub mod http_parser;\n\
+         #[path = \"net-utils/mod.rs\"]\npub mod net_utils;\n"
+    );
+    common::check_compiles(dir.path(), &tree.join("mod.rs")).unwrap();
+}
+
+#[test]
+fn sanitized_name_collisions_are_errors() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("foo-bar.rs"), "").unwrap();
+    fs::write(dir.path().join("foo_bar.rs"), "").unwrap();
+    fs::write(dir.path().join("other.rs"), "").unwrap();
+
+    let output = crateify(&[dir.path()]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(
+        stderr(&output).contains("foo-bar.rs, foo_bar.rs would all be declared as module foo_bar"),
+        "{}",
+        stderr(&output)
+    );
+    assert!(!dir.path().join("mod.rs").exists());
+}
+
+fn tree_with_file_and_dir_module(root: &Path) {
+    fs::write(root.join("parser.rs"), "pub fn parse() {}\n").unwrap();
+    fs::create_dir(root.join("parser")).unwrap();
+    fs::write(root.join("parser").join("lexer.rs"), "pub fn lex() {}\n").unwrap();
+}
+
+#[test]
+fn file_and_directory_with_one_name_are_an_error() {
+    let dir = tempfile::tempdir().unwrap();
+    tree_with_file_and_dir_module(dir.path());
+
+    // used to write `pub mod parser;` twice, which fails with E0428
+    let output = crateify(&[dir.path()]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(
+        stderr(&output).contains("parser/, parser.rs would all be declared as module parser"),
+        "{}",
+        stderr(&output)
+    );
+    assert!(stderr(&output).contains("--merge-collisions"));
+    assert!(!dir.path().join("mod.rs").exists());
+}
+
+#[test]
+fn merge_collisions_renames_the_file_module() {
+    let dir = tempfile::tempdir().unwrap();
+    let tree = dir.path().join("tree");
+    fs::create_dir(&tree).unwrap();
+    tree_with_file_and_dir_module(&tree);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_crateify"))
+        .arg("--merge-collisions")
+        .arg(&tree)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        generated_body(&tree.join("mod.rs")),
+        "#[path = \"parser/mod.rs\"]\npub mod parser;\n\
+         #[path = \"parser.rs\"]\npub mod parser_file;\n"
+    );
+    common::check_compiles(dir.path(), &tree.join("mod.rs")).unwrap();
+}
+
+#[test]
+fn names_differing_only_in_case_are_errors() {
+    let dir = tempfile::tempdir().unwrap();
+    let tree = dir.path().join("tree");
+    fs::create_dir(&tree).unwrap();
+    for file in ["Parser.rs", "parser.rs", "util.rs"] {
+        fs::write(tree.join(file), "").unwrap();
+    }
+
+    let output = crateify(&[&tree]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(
+        stderr(&output).contains(&format!(
+            "{}: Parser.rs, parser.rs only differ in case and collide on case-insensitive \
+             filesystems",
+            tree.display()
+        )),
+        "{}",
+        stderr(&output)
+    );
+    assert!(!tree.join("mod.rs").exists());
+
+    let output = Command::new(env!("CARGO_BIN_EXE_crateify"))
+        .arg("--allow-case-collisions")
+        .arg(&tree)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        generated_body(&tree.join("mod.rs")),
+        "pub mod Parser;\n#[path = \"parser.rs\"]\npub mod parser_2;\npub mod util;\n"
+    );
+    common::check_compiles(dir.path(), &tree.join("mod.rs")).unwrap();
+}
+
+#[test]
+fn snake_case_renames_what_the_lint_would_flag() {
+    let dir = tempfile::tempdir().unwrap();
+    let tree = dir.path().join("tree");
+    fs::create_dir(&tree).unwrap();
+    for file in [
+        "HTTPServer.rs",
+        "ParserState.rs",
+        "sha256Update.rs",
+        "util.rs",
+    ] {
+        fs::write(tree.join(file), "").unwrap();
+    }
+
+    let output = crateify(&[&tree]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let warnings = stderr(&output);
+    assert!(
+        warnings.contains(
+            "crateify: warning: ParserState.rs: module name ParserState is not snake_case \
+             (--snake-case declares it as parser_state)"
+        ),
+        "{warnings}"
+    );
+    assert!(warnings.contains("as http_server)"), "{warnings}");
+    assert!(warnings.contains("as sha256_update)"), "{warnings}");
+    assert!(!warnings.contains("util"), "{warnings}");
+    assert_eq!(
+        generated_body(&tree.join("mod.rs")),
+        "pub mod HTTPServer;\npub mod ParserState;\npub mod sha256Update;\npub mod util;\n"
+    );
+
+    let output = Command::new(env!("CARGO_BIN_EXE_crateify"))
+        .arg("--snake-case")
+        .arg(&tree)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(!stderr(&output).contains("warning:"), "{}", stderr(&output));
+    assert_eq!(
+        generated_body(&tree.join("mod.rs")),
+        "#[path = \"HTTPServer.rs\"]\npub mod http_server;\n\
+         #[path = \"ParserState.rs\"]\npub mod parser_state;\n\
+         #[path = \"sha256Update.rs\"]\npub mod sha256_update;\n\
+         pub mod util;\n"
+    );
+    common::check_compiles(dir.path(), &tree.join("mod.rs")).unwrap();
+}
+
+#[test]
+fn modules_named_like_crates_are_reported_and_can_be_renamed() {
+    let dir = tempfile::tempdir().unwrap();
+    let src = dir.path().join("netlib").join("src");
+    fs::create_dir_all(src.join("core")).unwrap();
+    fs::create_dir_all(src.join("io")).unwrap();
+    fs::write(src.join("core").join("ffi.rs"), "").unwrap();
+    fs::write(src.join("io").join("std.rs"), "").unwrap();
+    fs::write(src.join("netlib.rs"), "").unwrap();
+    fs::write(src.join("util.rs"), "").unwrap();
+    let manifest = dir.path().join("modules.json");
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_crateify"))
+            .args(["--cargo-init", "--force", "--manifest"])
+            .arg(&manifest)
+            .args(args)
+            .arg(&src)
+            .output()
+            .unwrap()
+    };
+
+    let output = run(&[]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let warnings = stderr(&output);
+    assert!(
+        warnings.contains(
+            "crateify: warning: core: module core shadows the core crate, `core::` paths next \
+             to it resolve to the module (--rename-shadowing declares it as core_)"
+        ),
+        "{warnings}"
+    );
+    assert!(
+        warnings.contains("io/std.rs: module std shadows"),
+        "{warnings}"
+    );
+    // the package's own name only counts once it is known
+    assert!(!warnings.contains("module netlib"), "{warnings}");
+    assert_eq!(
+        generated_body(&src.join("lib.rs")),
+        "pub mod core;\npub mod io;\npub mod netlib;\npub mod util;\n"
+    );
+    let output = run(&["--crate-name", "netlib"]);
+    assert!(stderr(&output).contains("netlib.rs: module netlib shadows the netlib crate"));
+
+    let output = run(&["--deny-shadowing"]);
+    assert_eq!(output.status.code(), Some(1), "{}", stderr(&output));
+    assert!(
+        stderr(&output).contains("would be declared as module core, which shadows the core crate"),
+        "{}",
+        stderr(&output)
+    );
+
+    let output = run(&["--rename-shadowing", "--crate-name", "netlib"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(!stderr(&output).contains("warning:"), "{}", stderr(&output));
+    assert_eq!(
+        generated_body(&src.join("lib.rs")),
+        "#[path = \"core/mod.rs\"]\npub mod core_;\npub mod io;\n\
+         #[path = \"netlib.rs\"]\npub mod netlib_;\npub mod util;\n"
+    );
+    assert_eq!(
+        generated_body(&src.join("io").join("mod.rs")),
+        "#[path = \"std.rs\"]\npub mod std_;\n"
+    );
+    let manifest: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&manifest).unwrap()).unwrap();
+    assert_eq!(
+        manifest["roots"][src.to_str().unwrap()][0]["modules"],
+        serde_json::json!([
+            {"name": "core_", "source": "core/mod.rs", "renamed_from": "core"},
+            {"name": "io", "source": "io/mod.rs"},
+            {"name": "netlib_", "source": "netlib.rs", "renamed_from": "netlib"},
+            {"name": "util", "source": "util.rs"}
+        ])
+    );
+    common::cargo_check(
+        &dir.path().join("netlib").join("Cargo.toml"),
+        &dir.path().join("target"),
+    )
+    .unwrap();
+
+    let output = run(&["--rename-shadowing", "--deny-shadowing"]);
+    assert_eq!(output.status.code(), Some(2), "{}", stderr(&output));
+}
+
+#[test]
+fn prune_empty_removes_directories_without_modules() {
+    let dir = tempfile::tempdir().unwrap();
+    let src = dir.path().join("src");
+    fs::create_dir_all(src.join("net")).unwrap();
+    fs::write(src.join("net").join("tcp.rs"), "").unwrap();
+    fs::create_dir_all(src.join("include")).unwrap();
+    fs::create_dir_all(src.join("old")).unwrap();
+    fs::write(src.join("old").join("gone.rs"), "").unwrap();
+    fs::create_dir_all(src.join("objects")).unwrap();
+    fs::write(src.join("objects").join("tcp.o"), "").unwrap();
+    fs::create_dir_all(src.join("third_party").join("zlib").join("empty")).unwrap();
+    fs::write(src.join("third_party").join("zlib.h"), "").unwrap();
+    fs::create_dir_all(src.join("hand")).unwrap();
+    fs::write(src.join("hand").join("mod.rs"), "pub fn f() {}\n").unwrap();
+    // left out of the module tree, but not something --exclude said could go
+    fs::create_dir_all(src.join("docs")).unwrap();
+    fs::write(src.join("docs").join("NOTES.md"), "").unwrap();
+    fs::write(src.join(".crateifyignore"), "*.md\n").unwrap();
+    let output = crateify(&[&src]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    // only the module file generated by that run is left in old/
+    fs::remove_file(src.join("old").join("gone.rs")).unwrap();
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_crateify"))
+            .args(["--exclude", "**/*.o"])
+            .args(args)
+            .arg(&src)
+            .output()
+            .unwrap()
+    };
+
+    let output = run(&["--prune-empty=dry"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let reported = stderr(&output);
+    let lines: Vec<&str> = reported
+        .lines()
+        .filter(|l| l.contains("no modules below it"))
+        .collect();
+    assert_eq!(
+        lines,
+        [
+            "crateify: include/: no modules below it (--prune-empty removes it)",
+            "crateify: objects/: no modules below it (--prune-empty removes it)",
+            "crateify: old/: no modules below it (--prune-empty removes it)",
+            "crateify: third_party/zlib/: no modules below it (--prune-empty removes it)",
+        ]
+    );
+    assert!(src.join("old").join("mod.rs").exists());
+
+    let output = run(&["--prune-empty"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let warnings = stderr(&output);
+    for shown in ["include/", "objects/", "old/", "third_party/zlib/"] {
+        assert!(
+            warnings.contains(&format!("crateify: {shown}: removed, no modules below it")),
+            "{warnings}"
+        );
+        assert!(!src.join(shown).exists(), "{shown}");
+    }
+    assert!(src.join("third_party").join("zlib.h").exists());
+    assert!(src.join("hand").join("mod.rs").exists());
+    assert!(src.join("docs").join("NOTES.md").exists());
+    assert_eq!(
+        generated_body(&src.join("lib.rs")),
+        "pub mod hand;\npub mod net;\n"
+    );
+
+    let output = run(&["--keep-empty", "--prune-empty"]);
+    assert_eq!(output.status.code(), Some(2), "{}", stderr(&output));
+}
+
+#[test]
+fn output_writes_the_scaffolding_to_a_mirror_of_the_tree() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = fs::canonicalize(dir.path()).unwrap();
+    let src = root.join("raw").join("src");
+    fs::create_dir_all(src.join("net")).unwrap();
+    fs::create_dir_all(src.join("cli")).unwrap();
+    fs::write(src.join("net").join("tcp.rs"), "pub fn connect() {}\n").unwrap();
+    fs::write(src.join("util.rs"), "pub fn helper() {}\n").unwrap();
+    fs::write(src.join("cli").join("main.rs"), "fn main() {}\n").unwrap();
+    // every path below `dir` with its size, sorted
+    fn listing(dir: &Path) -> Vec<(PathBuf, u64)> {
+        let mut entries = Vec::new();
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                entries.extend(listing(&path));
+            }
+            entries.push((path.clone(), fs::metadata(&path).unwrap().len()));
+        }
+        entries.sort();
+        entries
+    }
+    let before = listing(&root.join("raw"));
+    let out = root.join("out").join("src");
+    let manifest = root.join("modules.json");
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_crateify"))
+            .args(["--cargo-init", "--force", "--manifest"])
+            .arg(&manifest)
+            .arg("--output")
+            .arg(&out)
+            .args(args)
+            .arg(&src)
+            .output()
+            .unwrap()
+    };
+
+    let output = run(&[]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(listing(&root.join("raw")), before);
+    assert_eq!(
+        generated_body(&out.join("lib.rs")),
+        format!(
+            "pub mod net;\n#[path = {:?}]\npub mod util;\n",
+            src.join("util.rs").display().to_string()
+        )
+    );
+    assert_eq!(
+        generated_body(&out.join("net").join("mod.rs")),
+        format!(
+            "#[path = {:?}]\npub mod tcp;\n",
+            src.join("net").join("tcp.rs").display().to_string()
+        )
+    );
+    let cargo_toml = fs::read_to_string(root.join("out").join("Cargo.toml")).unwrap();
+    assert!(
+        cargo_toml.contains("[lib]\npath = \"src/lib.rs\"\n"),
+        "{cargo_toml}"
+    );
+    assert!(
+        cargo_toml.contains(&format!(
+            "path = {:?}\n",
+            src.join("cli").join("main.rs").display().to_string()
+        )),
+        "{cargo_toml}"
+    );
+    let modules: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&manifest).unwrap()).unwrap();
+    assert_eq!(
+        modules["roots"][src.to_str().unwrap()][0]["modules"],
+        serde_json::json!([
+            {
+                "name": "net",
+                "source": "net/mod.rs",
+                "physical_source": out.join("net").join("mod.rs").to_str().unwrap()
+            },
+            {
+                "name": "util",
+                "source": "util.rs",
+                "physical_source": src.join("util.rs").to_str().unwrap()
+            }
+        ])
+    );
+    common::cargo_check(&root.join("out").join("Cargo.toml"), &root.join("target")).unwrap();
+
+    let output = run(&["--relative-paths"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        generated_body(&out.join("net").join("mod.rs")),
+        "#[path = \"../../../raw/src/net/tcp.rs\"]\npub mod tcp;\n"
+    );
+    common::cargo_check(&root.join("out").join("Cargo.toml"), &root.join("target")).unwrap();
+    assert_eq!(listing(&root.join("raw")), before);
+
+    for rejected in ["--clean", "--check"] {
+        let output = run(&[rejected]);
+        assert_eq!(
+            output.status.code(),
+            Some(2),
+            "{rejected}: {}",
+            stderr(&output)
+        );
+    }
+    let output = Command::new(env!("CARGO_BIN_EXE_crateify"))
+        .arg("--output")
+        .arg(src.join("overlay"))
+        .arg(&src)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2), "{}", stderr(&output));
+    assert!(!src.join("overlay").exists());
+}
+
+#[test]
+fn names_windows_reserves_fail_or_are_renamed() {
+    let dir = tempfile::tempdir().unwrap();
+    let src = dir.path().join("serial").join("src");
+    fs::create_dir_all(src.join("Aux")).unwrap();
+    fs::write(src.join("Aux").join("port.rs"), "").unwrap();
+    fs::write(src.join("con.rs"), "").unwrap();
+    fs::write(src.join("com1.rs"), "").unwrap();
+    // only the device names themselves are reserved
+    fs::write(src.join("console.rs"), "").unwrap();
+    fs::write(src.join("lpt10.rs"), "").unwrap();
+    let manifest = dir.path().join("modules.json");
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_crateify"))
+            .args(["--cargo-init", "--manifest"])
+            .arg(&manifest)
+            .args(args)
+            .arg(&src)
+            .output()
+            .unwrap()
+    };
+
+    let output = run(&[]);
+    assert_eq!(output.status.code(), Some(1), "{}", stderr(&output));
+    let names: Vec<_> = ["Aux", "com1.rs", "con.rs"]
+        .iter()
+        .map(|name| src.join(name).display().to_string())
+        .collect();
+    assert!(
+        stderr(&output).contains(&format!(
+            "names are reserved for devices on Windows and cannot be checked out there: {}",
+            names.join(" ")
+        )),
+        "{}",
+        stderr(&output)
+    );
+    assert!(!src.join("lib.rs").exists());
+
+    let output = run(&["--rename-reserved"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        generated_body(&src.join("lib.rs")),
+        "#[path = \"Aux/mod.rs\"]\npub mod Aux_;\n#[path = \"com1.rs\"]\npub mod com1_;\n\
+         #[path = \"con.rs\"]\npub mod con_;\npub mod console;\npub mod lpt10;\n"
+    );
+    let manifest: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&manifest).unwrap()).unwrap();
+    assert_eq!(
+        manifest["roots"][src.to_str().unwrap()][0]["modules"],
+        serde_json::json!([
+            {"name": "Aux_", "source": "Aux/mod.rs", "renamed_from": "Aux", "portable_name": "Aux_"},
+            {"name": "com1_", "source": "com1.rs", "renamed_from": "com1", "portable_name": "com1_.rs"},
+            {"name": "con_", "source": "con.rs", "renamed_from": "con", "portable_name": "con_.rs"},
+            {"name": "console", "source": "console.rs"},
+            {"name": "lpt10", "source": "lpt10.rs"}
+        ])
+    );
+    common::cargo_check(
+        &dir.path().join("serial").join("Cargo.toml"),
+        &dir.path().join("target"),
+    )
+    .unwrap();
+}
+
+#[test]
+fn regenerating_keeps_keep_blocks() {
+    let dir = tempfile::tempdir().unwrap();
+    let tree = dir.path().join("tree");
+    fs::create_dir(&tree).unwrap();
+    for name in ["tcp.rs", "udp.rs", "unix.rs"] {
+        fs::write(tree.join(name), "").unwrap();
+    }
+    assert!(crateify(&[&tree]).status.success());
+
+    // gate one module by hand; crateify must neither drop the gate nor declare unix again
+    let edited = format!(
+        "{}// crateify: keep-start\n#[cfg(unix)]\npub mod unix;\npub use tcp::*;\n\
+         // crateify: keep-end\n",
+        fs::read_to_string(tree.join("mod.rs")).unwrap()
+    );
+    fs::write(tree.join("mod.rs"), edited).unwrap();
+    fs::write(tree.join("quic.rs"), "").unwrap();
+
+    let output = crateify(&[&tree]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        generated_body(&tree.join("mod.rs")),
+        "pub mod quic;\npub mod tcp;\npub mod udp;\n\
+         // crateify: keep-start\n#[cfg(unix)]\npub mod unix;\npub use tcp::*;\n\
+         // crateify: keep-end\n"
+    );
+    common::check_compiles(dir.path(), &tree.join("mod.rs")).unwrap();
+}
+
+#[test]
+fn hand_written_mod_rs_is_left_alone() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::create_dir(dir.path().join("net")).unwrap();
+    fs::write(dir.path().join("net").join("tcp.rs"), "").unwrap();
+    fs::write(dir.path().join("net").join("mod.rs"), "mod tcp;\n").unwrap();
+
+    let output = crateify(&[dir.path()]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(
+        stderr(&output).contains("warning: net/mod.rs: not generated by crateify"),
+        "{}",
+        stderr(&output)
+    );
+    assert_eq!(
+        fs::read_to_string(dir.path().join("net").join("mod.rs")).unwrap(),
+        "mod tcp;\n"
+    );
+    // the rest of the tree is still generated
+    assert_eq!(generated_body(&dir.path().join("mod.rs")), "pub mod net;\n");
+}
+
+#[test]
+fn quoting_the_marker_below_the_first_line_does_not_make_a_file_generated() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("tcp.rs"), "").unwrap();
+    // the header of a generated file, quoted whole on a line of its own
+    let hand_written = format!(
+        "mod tcp;\n\npub const EXAMPLE: &str = r\"\n{}\";\n",
+        header(".")
+    );
+    fs::write(dir.path().join("mod.rs"), &hand_written).unwrap();
+
+    let output = crateify(&[dir.path()]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(
+        stderr(&output).contains("warning: mod.rs: not generated by crateify"),
+        "{}",
+        stderr(&output)
+    );
+    assert_eq!(
+        fs::read_to_string(dir.path().join("mod.rs")).unwrap(),
+        hand_written
+    );
+}
+
+#[test]
+fn populated_mod_rs_in_a_leaf_directory_is_not_a_module_of_itself() {
+    let dir = tempfile::tempdir().unwrap();
+    let tree = dir.path().join("tree");
+    fs::create_dir_all(tree.join("codec")).unwrap();
+    let hand_written = "pub mod base64;\n\npub fn encode_all(input: &[u8]) -> String {\n    \
+                        base64::encode(input)\n}\n";
+    fs::write(tree.join("codec").join("mod.rs"), hand_written).unwrap();
+    fs::write(
+        tree.join("codec").join("base64.rs"),
+        "pub fn encode(_: &[u8]) -> String {\n    String::new()\n}\n",
+    )
+    .unwrap();
+    fs::write(tree.join("codec").join("hex.rs"), "").unwrap();
+
+    let output = crateify(&[&tree]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(
+        stderr(&output).contains(
+            "codec/mod.rs: not generated by crateify, left untouched (--force overwrites it), \
+             and it doesn't declare hex.rs"
+        ),
+        "{}",
+        stderr(&output)
+    );
+    assert_eq!(
+        fs::read_to_string(tree.join("codec").join("mod.rs")).unwrap(),
+        hand_written
+    );
+    assert_eq!(generated_body(&tree.join("mod.rs")), "pub mod codec;\n");
+    common::check_compiles(dir.path(), &tree.join("mod.rs")).unwrap();
+}
+
+#[test]
+fn force_overwrites_hand_written_mod_rs() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("tcp.rs"), "").unwrap();
+    fs::write(dir.path().join("mod.rs"), "mod tcp;\n").unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_crateify"))
+        .arg("--force")
+        .arg(dir.path())
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(!stderr(&output).contains("warning:"), "{}", stderr(&output));
+    assert_eq!(generated_body(&dir.path().join("mod.rs")), "pub mod tcp;\n");
+}
+
+#[test]
+fn src_directory_gets_lib_rs() {
+    let dir = tempfile::tempdir().unwrap();
+    let src = dir.path().join("src");
+    fs::create_dir_all(src.join("net")).unwrap();
+    fs::write(src.join("net").join("tcp.rs"), "pub fn connect() {}\n").unwrap();
+    fs::write(src.join("util.rs"), "").unwrap();
+    fs::write(
+        src.join("main.rs"),
+        "fn main() {\n    generated_tree::net::tcp::connect();\n}\n",
+    )
+    .unwrap();
+
+    let output = crateify(&[&src]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        generated_body(&src.join("lib.rs")),
+        "pub mod net;\npub mod util;\n"
+    );
+    assert!(!src.join("mod.rs").exists());
+    assert_eq!(
+        generated_body(&src.join("net").join("mod.rs")),
+        "pub mod tcp;\n"
+    );
+    // main.rs is built as the binary next to the library
+    common::check_compiles(dir.path(), &src.join("lib.rs")).unwrap();
+}
+
+#[test]
+fn root_flag_overrides_the_directory_name() {
+    let dir = tempfile::tempdir().unwrap();
+    let src = dir.path().join("src");
+    fs::create_dir(&src).unwrap();
+    fs::write(src.join("util.rs"), "").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_crateify"))
+        .args(["--root", "mod"])
+        .arg(&src)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(src.join("mod.rs").exists());
+    assert!(!src.join("lib.rs").exists());
+
+    let tree = dir.path().join("tree");
+    fs::create_dir(&tree).unwrap();
+    fs::write(tree.join("util.rs"), "").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_crateify"))
+        .args([
+            "--root",
+            "lib",
+            "--header",
+            "Translated from libfoo.\n\nDo not edit.",
+        ])
+        .arg(&tree)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        generated_body(&tree.join("lib.rs")),
+        "//! Translated from libfoo.\n//!\n//! Do not edit.\npub mod util;\n"
+    );
+    assert!(!tree.join("mod.rs").exists());
+}
+
+/// `api/` with `handlers.rs` and `v1/routes.rs`, plus `util.rs` and `net/tcp.rs`
+fn tree_with_api(root: &Path) {
+    for sub in ["api/v1", "net"] {
+        fs::create_dir_all(root.join(sub)).unwrap();
+    }
+    for file in [
+        "api/handlers.rs",
+        "api/v1/routes.rs",
+        "net/tcp.rs",
+        "util.rs",
+    ] {
+        fs::write(root.join(file), "").unwrap();
+    }
+}
+
+#[test]
+fn visibility_sets_every_declaration() {
+    for (visibility, prefix) in [
+        ("pub", "pub mod"),
+        ("pub-crate", "pub(crate) mod"),
+        ("private", "mod"),
+    ] {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("tree");
+        tree_with_api(&root);
+        let output = Command::new(env!("CARGO_BIN_EXE_crateify"))
+            .args(["--visibility", visibility])
+            .arg(&root)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{}", stderr(&output));
+        assert_eq!(
+            generated_body(&root.join("mod.rs")),
+            format!("{prefix} api;\n{prefix} net;\n{prefix} util;\n")
+        );
+        assert_eq!(
+            generated_body(&root.join("api").join("v1").join("mod.rs")),
+            format!("{prefix} routes;\n")
+        );
+        common::check_compiles(dir.path(), &root.join("mod.rs")).unwrap();
+    }
+}
+
+#[test]
+fn visibility_file_overrides_the_flag() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path().join("tree");
+    tree_with_api(&root);
+    let overrides = dir.path().join("visibility");
+    fs::write(
+        &overrides,
+        "# the API stays public\napi pub\napi/** pub\n\n*.rs private\n",
+    )
+    .unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_crateify"))
+        .args(["--visibility", "pub-crate", "--visibility-file"])
+        .arg(&overrides)
+        .arg(&root)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        generated_body(&root.join("mod.rs")),
+        "pub mod api;\npub(crate) mod net;\nmod util;\n"
+    );
+    assert_eq!(
+        generated_body(&root.join("api").join("mod.rs")),
+        "pub mod handlers;\npub mod v1;\n"
+    );
+    assert_eq!(
+        generated_body(&root.join("api").join("v1").join("mod.rs")),
+        "pub mod routes;\n"
+    );
+    assert_eq!(
+        generated_body(&root.join("net").join("mod.rs")),
+        "pub(crate) mod tcp;\n"
+    );
+
+    fs::write(&overrides, "api public\n").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_crateify"))
+        .arg("--visibility-file")
+        .arg(&overrides)
+        .arg(&root)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert!(
+        stderr(&output)
+            .contains("visibility:1: expected one of pub, pub-crate, private, got public"),
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[test]
+fn provenance_documents_where_each_module_came_from() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path().join("tree");
+    tree_with_api(&root);
+    let map = dir.path().join("provenance.json");
+    fs::write(
+        &map,
+        r#"{"net/tcp.rs": "src/net/tcp.c", "api": "src/api", "util.rs": "src/util.c"}"#,
+    )
+    .unwrap();
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_crateify"))
+            .arg("--provenance")
+            .arg(&map)
+            .args(args)
+            .arg(&root)
+            .output()
+            .unwrap()
+    };
+
+    let output = run(&[]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        generated_body(&root.join("mod.rs")),
+        "/// Translated from src/api\npub mod api;\n/// Translated from net/\npub mod net;\n\
+         /// Translated from src/util.c\npub mod util;\n"
+    );
+    // files the translator didn't list are annotated with their own path
+    assert_eq!(
+        generated_body(&root.join("api").join("mod.rs")),
+        "/// Translated from api/handlers.rs\npub mod handlers;\n/// Translated from api/v1/\npub mod v1;\n"
+    );
+    assert_eq!(
+        generated_body(&root.join("net").join("mod.rs")),
+        "/// Translated from src/net/tcp.c\npub mod tcp;\n"
+    );
+    common::check_compiles(dir.path(), &root.join("mod.rs")).unwrap();
+    assert!(run(&["--check"]).status.success());
+
+    // the comments are generated content: a changed map makes the files stale
+    fs::write(&map, r#"{"net/tcp.rs": "src/net/tcp_ipv4.c"}"#).unwrap();
+    assert_eq!(run(&["--check"]).status.code(), Some(1));
+    assert!(run(&[]).status.success());
+    assert_eq!(
+        generated_body(&root.join("net").join("mod.rs")),
+        "/// Translated from src/net/tcp_ipv4.c\npub mod tcp;\n"
+    );
+
+    fs::write(&map, r#"["net/tcp.rs"]"#).unwrap();
+    let output = run(&[]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(
+        stderr(&output).contains("provenance.json: expected an object of strings"),
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[test]
+fn platform_directories_are_gated_by_cfg() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path().join("tree");
+    copy_fixture("platforms", &root);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_crateify"))
+        .args([
+            "--cfg-suffix",
+            "win32=windows",
+            "--cfg-suffix",
+            "posix=unix",
+        ])
+        .arg(&root)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        generated_body(&root.join("fs").join("mod.rs")),
+        "pub mod common;\n\
+         #[cfg(unix)]\npub mod io_posix;\n\
+         #[cfg(windows)]\npub mod io_win32;\n\
+         #[cfg(target_os = \"linux\")]\npub mod linux;\n\
+         #[cfg(target_os = \"macos\")]\npub mod macos;\n\
+         #[cfg(windows)]\npub mod windows;\n"
+    );
+    // the windows-only modules don't even have to be there for the gated declarations to compile
+    common::check_compiles(dir.path(), &root.join("mod.rs")).unwrap();
+}
+
+#[test]
+fn cfg_map_replaces_the_default_predicates() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path().join("tree");
+    copy_fixture("platforms", &root);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_crateify"))
+        .args([
+            "--cfg-map",
+            "macos=target_vendor = \"apple\"",
+            "--cfg-map",
+            "linux=",
+            "--cfg-map",
+            "common=unix",
+            "--reexport",
+            "glob",
+        ])
+        .arg(&root)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    // files are only gated by --cfg-suffix, and re-exports share their module's gate
+    assert_eq!(
+        generated_body(&root.join("fs").join("mod.rs")),
+        "pub mod common;\npub use self::common::*;\n\
+         pub mod io_posix;\npub use self::io_posix::*;\n\
+         pub mod io_win32;\npub use self::io_win32::*;\n\
+         pub mod linux;\npub use self::linux::*;\n\
+         #[cfg(target_vendor = \"apple\")]\npub mod macos;\n\
+         #[cfg(target_vendor = \"apple\")]\npub use self::macos::*;\n\
+         #[cfg(windows)]\npub mod windows;\n#[cfg(windows)]\npub use self::windows::*;\n"
+    );
+
+    let output = Command::new(env!("CARGO_BIN_EXE_crateify"))
+        .args(["--cfg-map", "linux=target_os ="])
+        .arg(&root)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert!(
+        stderr(&output).contains("--cfg-map linux=target_os =: target_os = is not a cfg predicate"),
+        "{}",
+        stderr(&output)
+    );
+}
+
+fn run_with_reexport(mode: &str, root: &Path) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_crateify"))
+        .args(["--reexport", mode])
+        .arg(root)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn reexport_glob_follows_each_declaration() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path().join("tree");
+    copy_fixture("reexports", &root);
+
+    let output = run_with_reexport("glob", &root);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        generated_body(&root.join("mod.rs")),
+        "pub mod hash;\npub use self::hash::*;\npub mod util;\npub use self::util::*;\n"
+    );
+    assert_eq!(
+        generated_body(&root.join("hash").join("mod.rs")),
+        "pub mod md5;\npub use self::md5::*;\npub mod sha256;\npub use self::sha256::*;\n"
+    );
+    common::check_compiles(dir.path(), &root.join("mod.rs")).unwrap();
+}
+
+#[test]
+fn reexport_items_names_public_items_and_skips_collisions() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path().join("tree");
+    copy_fixture("reexports", &root);
+
+    let output = run_with_reexport("items", &root);
+    assert!(output.status.success(), "{}", stderr(&output));
+    // both hash modules define `init`, so neither is re-exported
+    assert!(
+        stderr(&output).contains(
+            "crateify: warning: hash/mod.rs: init is public in both hash/md5.rs and \
+             hash/sha256.rs, not re-exported"
+        ),
+        "{}",
+        stderr(&output)
+    );
+    assert_eq!(
+        generated_body(&root.join("hash").join("mod.rs")),
+        "pub mod md5;\n\
+         pub use self::md5::{MD5_DIGEST_LEN, md5, md5_block_asm};\n\
+         pub mod sha256;\n\
+         pub use self::sha256::{Sha256Ctx, sha256};\n"
+    );
+    // what hash/ re-exports reaches the root, items behind a #[cfg] don't
+    assert_eq!(
+        generated_body(&root.join("mod.rs")),
+        "pub mod hash;\n\
+         pub use self::hash::{MD5_DIGEST_LEN, Sha256Ctx, md5, md5_block_asm, sha256};\n\
+         pub mod util;\n\
+         pub use self::util::{Digest, sizeof};\n"
+    );
+    common::check_compiles(dir.path(), &root.join("mod.rs")).unwrap();
+}
+
+#[test]
+fn module_styles_lay_out_the_same_tree_both_ways() {
+    let dir = tempfile::tempdir().unwrap();
+    let run = |tree: &Path, args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_crateify"))
+            .args(args)
+            .arg(tree)
+            .output()
+            .unwrap()
+    };
+
+    let mod_rs = dir.path().join("mod_rs");
+    copy_fixture("module_styles", &mod_rs);
+    let output = run(&mod_rs, &["--module-style", "mod-rs"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        generated_body(&mod_rs.join("net").join("mod.rs")),
+        "pub mod proto;\npub mod tcp;\npub mod udp;\n#[path = \"udp-lite.rs\"]\npub mod udp_lite;\n"
+    );
+    let check = dir.path().join("check_mod_rs");
+    fs::create_dir(&check).unwrap();
+    common::check_compiles(&check, &mod_rs.join("mod.rs")).unwrap();
+
+    // foo.rs next to foo/, with every #[path] starting from the directory foo.rs is in
+    let file = dir.path().join("file");
+    copy_fixture("module_styles", &file);
+    let output = run(&file, &["--module-style", "file"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        generated_body(&file.join("mod.rs")),
+        "pub mod net;\npub mod util;\n"
+    );
+    assert_eq!(
+        generated_body(&file.join("net.rs")),
+        "pub mod proto;\npub mod tcp;\npub mod udp;\n\
+         #[path = \"net/udp-lite.rs\"]\npub mod udp_lite;\n"
+    );
+    assert_eq!(
+        generated_body(&file.join("net").join("proto.rs")),
+        "pub mod http;\npub mod r#type;\n"
+    );
+    assert!(!file.join("net").join("mod.rs").exists());
+    let output = run(&file, &["--module-style", "file", "--check"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let check = dir.path().join("check_file");
+    fs::create_dir(&check).unwrap();
+    common::check_compiles(&check, &file.join("mod.rs")).unwrap();
+
+    // each style refuses the module files of the other
+    let output = run(&file, &[]);
+    assert_eq!(output.status.code(), Some(1), "{}", stderr(&output));
+    assert!(
+        stderr(&output).contains("is a module file of the other --module-style"),
+        "{}",
+        stderr(&output)
+    );
+    let output = run(&mod_rs, &["--module-style", "file"]);
+    assert_eq!(output.status.code(), Some(1), "{}", stderr(&output));
+    assert!(
+        stderr(&output).contains("net/mod.rs is a module file of the other --module-style"),
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[test]
+fn flatten_declares_the_whole_tree_in_the_root_module() {
+    let dir = tempfile::tempdir().unwrap();
+    let tree = dir.path().join("tree");
+    copy_fixture("module_styles", &tree);
+    fs::create_dir_all(tree.join("http-parser")).unwrap();
+    fs::write(tree.join("http-parser").join("parse.rs"), "").unwrap();
+    fs::create_dir_all(tree.join("net").join("match")).unwrap();
+    fs::write(tree.join("net").join("match").join("arm.rs"), "").unwrap();
+    let manifest = dir.path().join("modules.json");
+
+    let modules = run_with_manifest(&tree, &manifest, &["--flatten"]);
+    // inside an inline module, paths start from the directory named after it, or named by the
+    // #[path] on it
+    assert_eq!(
+        generated_body(&tree.join("mod.rs")),
+        "#[path = \"http-parser\"]\n\
+         pub mod http_parser {\n    pub mod parse;\n}\n\
+         pub mod net {\n    \
+             pub mod r#match {\n        pub mod arm;\n    }\n    \
+             pub mod proto {\n        pub mod http;\n        pub mod r#type;\n    }\n    \
+             pub mod tcp;\n    pub mod udp;\n    \
+             #[path = \"udp-lite.rs\"]\n    pub mod udp_lite;\n\
+         }\n\
+         pub mod util;\n"
+    );
+    assert!(!tree.join("net").join("mod.rs").exists());
+    assert!(!tree.join("net").join("proto").join("mod.rs").exists());
+    common::check_compiles(dir.path(), &tree.join("mod.rs")).unwrap();
+
+    // every directory is declared in the root module, and changes along with it
+    let dirs = modules["roots"][tree.to_str().unwrap()].as_array().unwrap();
+    assert_eq!(dirs.len(), 5);
+    for d in dirs {
+        assert_eq!(d["module_file"], "mod.rs", "{d}");
+        assert_eq!(d["status"], "created", "{d}");
+    }
+    let net = dirs.iter().find(|d| d["path"] == "net").unwrap();
+    assert_eq!(net["modules"][0]["source"], "mod.rs");
+    assert_eq!(net["modules"][2]["source"], "net/tcp.rs");
+
+    let modules = run_with_manifest(&tree, &manifest, &["--flatten"]);
+    for d in modules["roots"][tree.to_str().unwrap()].as_array().unwrap() {
+        assert_eq!(d["status"], "unchanged", "{d}");
+    }
+}
+
+#[test]
+fn flatten_replaces_the_mod_rs_files_of_an_earlier_run() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::create_dir_all(dir.path().join("net")).unwrap();
+    fs::write(dir.path().join("net").join("tcp.rs"), "").unwrap();
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_crateify"))
+            .args(args)
+            .arg(dir.path())
+            .output()
+            .unwrap()
+    };
+    assert!(run(&[]).status.success());
+
+    let output = run(&["--flatten"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(
+        stderr(&output).contains(
+            "net/mod.rs: no longer read, its directory is declared inline (--clean removes it)"
+        ),
+        "{}",
+        stderr(&output)
+    );
+    assert_eq!(
+        generated_body(&dir.path().join("mod.rs")),
+        "pub mod net {\n    pub mod tcp;\n}\n"
+    );
+    let output = run(&["--flatten", "--clean"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(
+        stderr(&output).contains("net/mod.rs: removed, its directory is declared inline"),
+        "{}",
+        stderr(&output)
+    );
+    assert!(!dir.path().join("net").join("mod.rs").exists());
+
+    // the code of a hand-written one would be lost
+    fs::write(dir.path().join("net").join("mod.rs"), "pub mod tcp;\n").unwrap();
+    let output = run(&["--flatten", "--clean"]);
+    assert_eq!(output.status.code(), Some(1), "{}", stderr(&output));
+    assert!(
+        stderr(&output).contains("--flatten would declare its directory inline without it"),
+        "{}",
+        stderr(&output)
+    );
+    assert_eq!(
+        fs::read_to_string(dir.path().join("net").join("mod.rs")).unwrap(),
+        "pub mod tcp;\n"
+    );
+}
+
+#[test]
+fn file_module_style_leaves_hand_written_files_alone() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::create_dir_all(dir.path().join("net")).unwrap();
+    fs::write(dir.path().join("net").join("tcp.rs"), "").unwrap();
+    fs::write(dir.path().join("net.rs"), "pub mod tcp;\n").unwrap();
+    fs::create_dir_all(dir.path().join("http-parser")).unwrap();
+
+    let run = || {
+        Command::new(env!("CARGO_BIN_EXE_crateify"))
+            .args(["--module-style", "file"])
+            .arg(dir.path())
+            .output()
+            .unwrap()
+    };
+    let output = run();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(
+        stderr(&output).contains("net.rs: not generated by crateify, left untouched"),
+        "{}",
+        stderr(&output)
+    );
+    assert_eq!(
+        fs::read_to_string(dir.path().join("net.rs")).unwrap(),
+        "pub mod tcp;\n"
+    );
+    assert_eq!(generated_body(&dir.path().join("mod.rs")), "pub mod net;\n");
+
+    // a #[path] to http-parser.rs would make it look for its modules next to itself
+    fs::write(dir.path().join("http-parser").join("parse.rs"), "").unwrap();
+    let output = run();
+    assert_eq!(output.status.code(), Some(1), "{}", stderr(&output));
+    assert!(
+        stderr(&output).contains("would be declared as http_parser with a #[path]"),
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[test]
+fn cargo_init_makes_a_directory_of_files_a_crate() {
+    let dir = tempfile::tempdir().unwrap();
+    let out = dir.path().join("out");
+    fs::create_dir_all(out.join("net")).unwrap();
+    fs::write(out.join("util.rs"), "pub fn helper() {}\n").unwrap();
+    fs::write(out.join("net").join("tcp.rs"), "pub fn connect() {}\n").unwrap();
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_crateify"))
+            .args(["--cargo-init", "--crate-name", "foo"])
+            .args(args)
+            .arg(&out)
+            .output()
+            .unwrap()
+    };
+
+    // the manifest is outside of the crate directory, so the diff gives where it really goes
+    let output = run(&["--dry-run"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let diff = String::from_utf8(output.stdout).unwrap();
+    let resolved = fs::canonicalize(dir.path()).unwrap().join("Cargo.toml");
+    assert!(
+        diff.contains(&format!("--- /dev/null\n+++ {}\n", resolved.display())),
+        "{diff}"
+    );
+    assert!(!diff.contains(".."), "{diff}");
+
+    let output = run(&[]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let manifest = dir.path().join("Cargo.toml");
+    assert_eq!(
+        fs::read_to_string(&manifest).unwrap(),
+        "[package]\nname = \"foo\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n\
+         [lib]\npath = \"out/mod.rs\"\n"
+    );
+    common::cargo_check(&manifest, &dir.path().join("target")).unwrap();
+}
+
+#[test]
+fn cargo_init_points_at_lib_rs_in_src() {
+    let dir = tempfile::tempdir().unwrap();
+    let package = dir.path().join("libfoo");
+    let src = package.join("src");
+    fs::create_dir_all(&src).unwrap();
+    fs::write(src.join("util.rs"), "").unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_crateify"))
+        .args(["--cargo-init", "--edition", "2024"])
+        .arg(&src)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        fs::read_to_string(package.join("Cargo.toml")).unwrap(),
+        "[package]\nname = \"libfoo\"\nversion = \"0.1.0\"\nedition = \"2024\"\n\n\
+         [lib]\npath = \"src/lib.rs\"\n"
+    );
+
+    let output = Command::new(env!("CARGO_BIN_EXE_crateify"))
+        .args(["--cargo-init", "--edition", "2015"])
+        .arg(&src)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2), "{}", stderr(&output));
+}
+
+#[test]
+fn cargo_init_inside_src_names_it_by_its_real_name() {
+    let dir = tempfile::tempdir().unwrap();
+    let package = dir.path().join("libfoo");
+    let src = package.join("src");
+    fs::create_dir_all(&src).unwrap();
+    fs::write(src.join("util.rs"), "").unwrap();
+
+    for typed in [".", "./"] {
+        let output = Command::new(env!("CARGO_BIN_EXE_crateify"))
+            .args(["--cargo-init", "--force", "--edition", "2024", typed])
+            .current_dir(&src)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{}", stderr(&output));
+        assert!(!src.join("mod.rs").exists(), "{typed}");
+        assert_eq!(generated_body(&src.join("lib.rs")), "pub mod util;\n");
+        assert_eq!(
+            fs::read_to_string(package.join("Cargo.toml")).unwrap(),
+            "[package]\nname = \"libfoo\"\nversion = \"0.1.0\"\nedition = \"2024\"\n\n\
+             [lib]\npath = \"src/lib.rs\"\n"
+        );
+    }
+    common::cargo_check(&package.join("Cargo.toml"), &dir.path().join("target")).unwrap();
+}
+
+#[test]
+fn cargo_init_leaves_an_existing_manifest_alone() {
+    let dir = tempfile::tempdir().unwrap();
+    let src = dir.path().join("src");
+    fs::create_dir(&src).unwrap();
+    fs::write(src.join("util.rs"), "").unwrap();
+    let manifest = dir.path().join("Cargo.toml");
+    fs::write(&manifest, "# hand-written\n").unwrap();
+
+    let cargo_init = |extra: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_crateify"))
+            .args(["--cargo-init", "--crate-name", "foo"])
+            .args(extra)
+            .arg(&src)
+            .output()
+            .unwrap()
+    };
+    let output = cargo_init(&[]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stderr(&output).contains("already exists, left untouched"));
+    assert_eq!(fs::read_to_string(&manifest).unwrap(), "# hand-written\n");
+    // the tree itself is still generated
+    assert!(src.join("lib.rs").exists());
+
+    let output = cargo_init(&["--force"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(
+        fs::read_to_string(&manifest)
+            .unwrap()
+            .contains("name = \"foo\"")
+    );
+}
+
+#[test]
+fn cargo_init_makes_binaries_of_directories_with_main_rs() {
+    let dir = tempfile::tempdir().unwrap();
+    let package = dir.path().join("text_tools");
+    let src = package.join("src");
+    copy_fixture("binaries", &src);
+    let manifest = dir.path().join("modules.json");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_crateify"))
+        .arg("--cargo-init")
+        .arg("--manifest")
+        .arg(&manifest)
+        .arg(&src)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(!stderr(&output).contains("warning:"), "{}", stderr(&output));
+    // a src/ directory is named after its parent, the way the crate was laid out in C
+    assert_eq!(
+        fs::read_to_string(package.join("Cargo.toml")).unwrap(),
+        "[package]\nname = \"text_tools\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n\
+         [lib]\npath = \"src/lib.rs\"\n\n\
+         [[bin]]\nname = \"text_processor\"\npath = \"src/tools/text_processor/src/main.rs\"\n\n\
+         [[bin]]\nname = \"wc\"\npath = \"src/tools/wc/main.rs\"\n"
+    );
+    assert_eq!(
+        generated_body(&src.join("tools").join("mod.rs")),
+        "pub mod text_processor;\n"
+    );
+    assert_eq!(
+        generated_body(&src.join("tools").join("text_processor").join("mod.rs")),
+        "#[cfg(test)]\nmod tests;\n"
+    );
+    // the files next to main.rs get a mod.rs of their own for the binary to declare
+    let bin = src.join("tools").join("text_processor").join("src");
+    assert_eq!(generated_body(&bin.join("mod.rs")), "pub mod modes;\n");
+    assert!(!src.join("tools").join("wc").join("mod.rs").exists());
+
+    let manifest: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&manifest).unwrap()).unwrap();
+    assert_eq!(
+        manifest["binaries"][src.to_str().unwrap()],
+        serde_json::json!([
+            {
+                "path": "tools/text_processor/src",
+                "name": "text_processor",
+                "main": "tools/text_processor/src/main.rs",
+                "module_file": "tools/text_processor/src/mod.rs"
+            },
+            {"path": "tools/wc", "name": "wc", "main": "tools/wc/main.rs"}
+        ])
+    );
+    // no module path of the library leads to the module file of a binary
+    let module_paths: Vec<&str> = manifest["roots"][src.to_str().unwrap()]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|d| d["module_path"].as_str().unwrap())
+        .collect();
+    assert_eq!(
+        module_paths,
+        [
+            "crate",
+            "crate::net",
+            "crate::tools",
+            "crate::tools::text_processor",
+            "crate::tools::text_processor::tests"
+        ]
+    );
+    common::cargo_check_with(
+        &package.join("Cargo.toml"),
+        &dir.path().join("target"),
+        &["--all-targets"],
+    )
+    .unwrap();
+}
+
+#[test]
+fn hybrid_builds_the_c_sources_left_next_to_the_translation() {
+    let dir = tempfile::tempdir().unwrap();
+    let package = dir.path().join("calc");
+    let src = package.join("src");
+    copy_fixture("hybrid", &package);
+    let manifest = dir.path().join("modules.json");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_crateify"))
+        .args(["--cargo-init", "--hybrid", "--manifest"])
+        .arg(&manifest)
+        .arg(&src)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    // C files are neither modules nor skipped entries
+    assert_eq!(generated_body(&src.join("lib.rs")), "pub mod calc;\n");
+    assert!(
+        stderr(&output).contains("0 entries skipped"),
+        "{}",
+        stderr(&output)
+    );
+    assert_eq!(
+        fs::read_to_string(package.join("Cargo.toml")).unwrap(),
+        "[package]\nname = \"calc\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n\
+         [lib]\npath = \"src/lib.rs\"\n\n\
+         [build-dependencies]\ncc = \"1\"\n"
+    );
+    let build_rs = fs::read_to_string(package.join("build.rs")).unwrap();
+    assert!(
+        build_rs.contains(
+            "    cc::Build::new()\n        .include(\"src/include\")\n        \
+             .file(\"src/ffi/add.c\")\n        .compile(\"c_ffi\");\n"
+        ),
+        "{build_rs}"
+    );
+    let manifest: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&manifest).unwrap()).unwrap();
+    assert_eq!(
+        manifest["c_sources"][src.to_str().unwrap()],
+        serde_json::json!(["ffi/add.c"])
+    );
+    assert_eq!(
+        manifest["roots"][src.to_str().unwrap()][0]["modules"]
+            .as_array()
+            .unwrap()
+            .len(),
+        1
+    );
+    common::cargo(
+        "test",
+        &package.join("Cargo.toml"),
+        &dir.path().join("target"),
+        &[],
+    )
+    .unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_crateify"))
+        .arg("--hybrid")
+        .arg(&src)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2), "{}", stderr(&output));
+}
+
+#[test]
+fn workspace_makes_a_crate_of_each_subdirectory() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path().join("translated");
+    copy_fixture("workspace", &root);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_crateify"))
+        .arg("--workspace")
+        .arg(&root)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        fs::read_to_string(root.join("Cargo.toml")).unwrap(),
+        "[workspace]\nresolver = \"2\"\nmembers = [\"http-parser\", \"libfoo\"]\n"
+    );
+    // a member with src/ gets src/lib.rs, one without gets lib.rs next to its files
+    assert_eq!(
+        fs::read_to_string(root.join("libfoo").join("Cargo.toml")).unwrap(),
+        "[package]\nname = \"libfoo\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n\
+         [lib]\npath = \"src/lib.rs\"\n"
+    );
+    assert_eq!(
+        generated_body(&root.join("libfoo").join("src").join("lib.rs")),
+        "pub mod net;\npub mod util;\n"
+    );
+    // package names are sanitized like module names
+    assert_eq!(
+        fs::read_to_string(root.join("http-parser").join("Cargo.toml")).unwrap(),
+        "[package]\nname = \"http_parser\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n\
+         [lib]\npath = \"lib.rs\"\n"
+    );
+    assert_eq!(
+        generated_body(&root.join("http-parser").join("lib.rs")),
+        "pub mod parse;\n"
+    );
+    assert!(!root.join("mod.rs").exists());
+    assert!(!root.join("lib.rs").exists());
+    common::cargo_check(&root.join("Cargo.toml"), &dir.path().join("target")).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_crateify"))
+        .args(["--workspace", "--crate-name", "foo"])
+        .arg(&root)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn dry_run_prints_diffs_and_writes_nothing() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    fs::create_dir(root.join("net")).unwrap();
+    fs::write(root.join("net").join("tcp.rs"), "").unwrap();
+    fs::write(root.join("util.rs"), "").unwrap();
+    let dry_run = || {
+        Command::new(env!("CARGO_BIN_EXE_crateify"))
+            .arg("--dry-run")
+            .arg(root)
+            .output()
+            .unwrap()
+    };
+
+    let output = dry_run();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        format!(
+            "--- /dev/null\n+++ b/net/mod.rs\n@@ -0,0 +1,2 @@\n\
+             +{}+pub mod tcp;\n\
+             --- /dev/null\n+++ b/mod.rs\n@@ -0,0 +1,3 @@\n\
+             +{}+pub mod net;\n+pub mod util;\n",
+            header("net"),
+            header(".")
+        )
+    );
+    assert!(!root.join("mod.rs").exists());
+    assert!(!root.join("net").join("mod.rs").exists());
+
+    assert!(crateify(&[root]).status.success());
+    fs::write(root.join("io.rs"), "").unwrap();
+    let output = dry_run();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        format!(
+            "--- a/mod.rs\n+++ b/mod.rs\n@@ -1,3 +1,4 @@\n \
+             {}+pub mod io;\n pub mod net;\n pub mod util;\n",
+            header(".")
+        )
+    );
+    assert_eq!(
+        generated_body(&root.join("mod.rs")),
+        "pub mod net;\npub mod util;\n"
+    );
+}
+
+#[test]
+fn check_reports_stale_and_missing_mod_rs() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    for sub in ["net", "io"] {
+        fs::create_dir(root.join(sub)).unwrap();
+    }
+    fs::write(root.join("net").join("tcp.rs"), "").unwrap();
+    fs::write(root.join("io").join("file.rs"), "").unwrap();
+    fs::write(root.join("util.rs"), "").unwrap();
+    assert!(crateify(&[root]).status.success());
+    let check = || {
+        Command::new(env!("CARGO_BIN_EXE_crateify"))
+            .arg("--check")
+            .arg(root)
+            .output()
+            .unwrap()
+    };
+    let output = check();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(output.stdout.is_empty());
+
+    fs::remove_file(root.join("util.rs")).unwrap();
+    fs::write(root.join("time.rs"), "").unwrap();
+    fs::remove_file(root.join("io").join("mod.rs")).unwrap();
+    let before = fs::read_to_string(root.join("mod.rs")).unwrap();
+    let output = check();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("2 generated files are out of date"));
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "io/mod.rs: missing\n\
+         mod.rs: out of date (+1 -1)\n  - pub mod util;\n  + pub mod time;\n"
+    );
+    // nothing was touched
+    assert!(!root.join("io").join("mod.rs").exists());
+    assert_eq!(fs::read_to_string(root.join("mod.rs")).unwrap(), before);
+}
+
+#[test]
+fn build_output_and_hidden_directories_are_skipped() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    let skipped = [
+        root.join("target").join("debug").join("build"),
+        root.join(".git").join("objects"),
+        root.join(".idea"),
+        root.join("node_modules").join("left-pad"),
+    ];
+    for path in &skipped {
+        fs::create_dir_all(path).unwrap();
+        fs::write(path.join("generated.rs"), "").unwrap();
+    }
+    fs::write(root.join("util.rs"), "").unwrap();
+
+    let output = crateify(&[root]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(generated_body(&root.join("mod.rs")), "pub mod util;\n");
+    for path in &skipped {
+        for dir in path.ancestors().take_while(|d| *d != root) {
+            assert!(!dir.join("mod.rs").exists(), "{}", dir.display());
+        }
+    }
+
+    let output = Command::new(env!("CARGO_BIN_EXE_crateify"))
+        .arg("--no-default-ignores")
+        .arg(root)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(
+        root.join("target")
+            .join("debug")
+            .join("build")
+            .join("mod.rs")
+            .exists()
+    );
+}
+
+#[test]
+fn exclude_leaves_out_files_and_directories() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    for sub in ["experimental", "net", "vendored/zlib", "vendored/png"] {
+        fs::create_dir_all(root.join(sub)).unwrap();
+    }
+    for file in [
+        "experimental/simd.rs",
+        "net/tcp.rs",
+        "net/tcp_test.rs",
+        "vendored/zlib/inflate.rs",
+        "vendored/png/decode.rs",
+        "vendored/version.rs",
+        "util.rs",
+    ] {
+        fs::write(root.join(file), "").unwrap();
+    }
+
+    let output = Command::new(env!("CARGO_BIN_EXE_crateify"))
+        .args([
+            "--exclude",
+            "experimental/",
+            "--exclude",
+            "vendored/*/",
+            "--exclude",
+            "**/*_test.rs",
+        ])
+        .arg(root)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        generated_body(&root.join("mod.rs")),
+        "pub mod net;\npub mod util;\npub mod vendored;\n"
+    );
+    assert_eq!(
+        generated_body(&root.join("net").join("mod.rs")),
+        "pub mod tcp;\n"
+    );
+    // a trailing `/` only matches directories
+    assert_eq!(
+        generated_body(&root.join("vendored").join("mod.rs")),
+        "pub mod version;\n"
+    );
+    assert!(!root.join("experimental").join("mod.rs").exists());
+    assert!(!root.join("vendored").join("zlib").join("mod.rs").exists());
+
+    let stderr = stderr(&output);
+    assert!(
+        stderr.contains("--exclude vendored/*/: 2 entries excluded"),
+        "{stderr}"
+    );
+    assert!(
+        stderr.contains("--exclude experimental/: 1 entry excluded"),
+        "{stderr}"
+    );
+    assert!(
+        stderr.contains("--exclude **/*_test.rs: 1 entry excluded"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn crateifyignore_files_leave_out_entries_nearest_file_first() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    for sub in ["net/vendor", "scratch", "vendor"] {
+        fs::create_dir_all(root.join(sub)).unwrap();
+    }
+    for file in [
+        "net/drop_old.rs",
+        "net/keep_old.rs",
+        "net/tcp.rs",
+        "net/vendor/zlib.rs",
+        "scratch/try.rs",
+        "vendor/png.rs",
+        "util.rs",
+        "util_old.rs",
+    ] {
+        fs::write(root.join(file), "").unwrap();
+    }
+    fs::write(
+        root.join(".crateifyignore"),
+        "# left over from the last translation\n*_old.rs\n\nscratch/\n/vendor\n",
+    )
+    .unwrap();
+    fs::write(root.join("net").join(".crateifyignore"), "!keep_old.rs\n").unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_crateify"))
+        .arg("-vv")
+        .arg(root)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        generated_body(&root.join("mod.rs")),
+        "pub mod net;\npub mod util;\n"
+    );
+    // re-included by the nearer file, and /vendor is anchored to the root's
+    assert_eq!(
+        generated_body(&root.join("net").join("mod.rs")),
+        "pub mod keep_old;\npub mod tcp;\npub mod vendor;\n"
+    );
+    assert!(!root.join("scratch").join("mod.rs").exists());
+    assert!(!root.join("vendor").join("mod.rs").exists());
+    let log = stderr(&output);
+    let ignore_file = root.join(".crateifyignore");
+    assert!(
+        log.contains(&format!(
+            "{}: ignored by *_old.rs in {}",
+            root.join("net").join("drop_old.rs").display(),
+            ignore_file.display()
+        )),
+        "{log}"
+    );
+    assert!(
+        log.contains(&format!(
+            "{}: ignored by scratch/ in {}",
+            root.join("scratch").display(),
+            ignore_file.display()
+        )),
+        "{log}"
+    );
+
+    fs::write(root.join("net").join(".crateifyignore"), "tcp[.rs\n").unwrap();
+    let output = crateify(&[root]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(
+        stderr(&output).contains(".crateifyignore:1: invalid pattern"),
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[test]
+fn entry_point_files_are_not_declared() {
+    let dir = tempfile::tempdir().unwrap();
+    let src = dir.path().join("src");
+    copy_fixture("entry_points", &src);
+
+    let output = crateify(&[&src]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        generated_body(&src.join("lib.rs")),
+        "pub mod net;\npub mod tools;\npub mod util;\n"
+    );
+    // the stale `pub mod udp;` is gone and mod.rs doesn't declare itself
+    assert_eq!(
+        generated_body(&src.join("net").join("mod.rs")),
+        "pub mod tcp;\n"
+    );
+    assert_eq!(
+        generated_body(&src.join("tools").join("mod.rs")),
+        "pub mod cli;\n"
+    );
+    let warnings = stderr(&output);
+    assert!(
+        warnings.contains("tools/lib.rs: named like a crate entry point, not declared"),
+        "{warnings}"
+    );
+    assert!(
+        warnings.contains("tools/main.rs: named like a crate entry point, not declared"),
+        "{warnings}"
+    );
+    // src/main.rs builds as the binary using the library
+    common::check_compiles(dir.path(), &src.join("lib.rs")).unwrap();
+
+    // with a mod.rs root, lib.rs and main.rs in the crate directory are entry points all the same
+    let tree = dir.path().join("tree");
+    copy_fixture("entry_points", &tree);
+    fs::write(tree.join("lib.rs"), "").unwrap();
+    let output = crateify(&[&tree]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        generated_body(&tree.join("mod.rs")),
+        "pub mod net;\npub mod tools;\npub mod util;\n"
+    );
+}
+
+#[test]
+fn directories_without_modules_are_not_declared() {
+    let dir = tempfile::tempdir().unwrap();
+    let tree = dir.path().join("tree");
+    fs::create_dir_all(tree.join("net").join("empty")).unwrap();
+    fs::create_dir_all(tree.join("docs")).unwrap();
+    fs::write(tree.join("docs").join("README.md"), "").unwrap();
+    fs::write(tree.join("net").join("tcp.rs"), "").unwrap();
+    fs::write(tree.join("util.rs"), "").unwrap();
+
+    let output = crateify(&[&tree]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        generated_body(&tree.join("mod.rs")),
+        "pub mod net;\npub mod util;\n"
+    );
+    assert_eq!(
+        generated_body(&tree.join("net").join("mod.rs")),
+        "pub mod tcp;\n"
+    );
+    assert!(!tree.join("docs").join("mod.rs").exists());
+    assert!(!tree.join("net").join("empty").join("mod.rs").exists());
+    common::check_compiles(dir.path(), &tree.join("mod.rs")).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_crateify"))
+        .arg("--keep-empty")
+        .arg(&tree)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        generated_body(&tree.join("net").join("mod.rs")),
+        "pub mod empty;\npub mod tcp;\n"
+    );
+    assert_eq!(
+        generated_body(&tree.join("net").join("empty").join("mod.rs")),
+        ""
+    );
+    assert_eq!(
+        generated_body(&tree.join("mod.rs")),
+        "pub mod docs;\npub mod net;\npub mod util;\n"
+    );
+    common::check_compiles(dir.path(), &tree.join("mod.rs")).unwrap();
+}
+
+#[test]
+fn clean_removes_what_went_away() {
+    let dir = tempfile::tempdir().unwrap();
+    let tree = dir.path().join("tree");
+    for sub in ["net", "old", "legacy", "gone"] {
+        fs::create_dir_all(tree.join(sub)).unwrap();
+    }
+    for file in [
+        "net/tcp.rs",
+        "net/udp.rs",
+        "old/compat.rs",
+        "legacy/shim.rs",
+        "gone/lost.rs",
+    ] {
+        fs::write(tree.join(file), "").unwrap();
+    }
+    assert!(crateify(&[&tree]).status.success());
+    fs::write(tree.join("legacy").join("mod.rs"), "pub mod shim;\n").unwrap();
+
+    // a module file and a whole directory disappear, and two directories lose their last module
+    fs::remove_file(tree.join("net").join("udp.rs")).unwrap();
+    fs::remove_dir_all(tree.join("gone")).unwrap();
+    fs::remove_file(tree.join("old").join("compat.rs")).unwrap();
+    fs::remove_file(tree.join("legacy").join("shim.rs")).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_crateify"))
+        .arg("--clean")
+        .arg(&tree)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    let messages = stderr(&output);
+    assert!(
+        messages.contains("net/mod.rs: removed stale declaration of udp"),
+        "{messages}"
+    );
+    assert!(
+        messages.contains("old/mod.rs: removed, no modules left"),
+        "{messages}"
+    );
+    assert!(
+        messages.contains("mod.rs: removed stale declaration of old"),
+        "{messages}"
+    );
+    assert!(
+        messages.contains("mod.rs: removed stale declaration of gone"),
+        "{messages}"
+    );
+    assert!(!tree.join("old").join("mod.rs").exists());
+    // hand-written, so kept and still declared even without modules of its own
+    assert_eq!(
+        fs::read_to_string(tree.join("legacy").join("mod.rs")).unwrap(),
+        "pub mod shim;\n"
+    );
+    assert_eq!(
+        generated_body(&tree.join("mod.rs")),
+        "pub mod legacy;\npub mod net;\n"
+    );
+    assert_eq!(
+        generated_body(&tree.join("net").join("mod.rs")),
+        "pub mod tcp;\n"
+    );
+}
+
+/// `shared/` reused by `app/` through a symlink, a symlinked `.rs` file, and optionally a link
+/// from `app/` back up to the root
+#[cfg(unix)]
+fn tree_with_symlinks(root: &Path, with_cycle: bool) {
+    use std::os::unix::fs::symlink;
+
+    fs::create_dir_all(root.join("shared")).unwrap();
+    fs::write(root.join("shared").join("alloc.rs"), "pub fn alloc() {}\n").unwrap();
+    fs::create_dir(root.join("app")).unwrap();
+    fs::write(root.join("app").join("cli.rs"), "").unwrap();
+    symlink("../shared", root.join("app").join("common")).unwrap();
+    fs::write(root.join("util.rs"), "").unwrap();
+    symlink("util.rs", root.join("helpers.rs")).unwrap();
+    if with_cycle {
+        symlink("..", root.join("app").join("up")).unwrap();
+    }
+}
+
+#[test]
+#[cfg(unix)]
+fn deep_trees_do_not_exhaust_the_stack() {
+    use std::io::Read;
+
+    use rustix::fs::{Mode, OFlags};
+
+    let dir = tempfile::tempdir().unwrap();
+    // 5,000 levels named as translated C files are, some 60 KB of path: far past PATH_MAX, so
+    // the tree is built, and its deepest mod.rs read, one directory handle at a time
+    let mut deepest = open_dir_at(rustix::fs::CWD, dir.path());
+    for i in 0..5000 {
+        let name = format!("module_{i:04}");
+        rustix::fs::mkdirat(&deepest, &name, Mode::from_raw_mode(0o755)).unwrap();
+        deepest = open_dir_at(&deepest, Path::new(&name));
+    }
+    let flags = OFlags::WRONLY | OFlags::CREATE | OFlags::CLOEXEC;
+    rustix::fs::openat(&deepest, "leaf.rs", flags, Mode::from_raw_mode(0o644)).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_crateify"))
+        .arg("--quiet")
+        .arg(dir.path())
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    let flags = OFlags::RDONLY | OFlags::CLOEXEC;
+    let mod_rs = rustix::fs::openat(&deepest, "mod.rs", flags, Mode::empty()).unwrap();
+    let mut content = String::new();
+    fs::File::from(mod_rs).read_to_string(&mut content).unwrap();
+    assert_eq!(strip_header(&content), Some("pub mod leaf;\n"), "{content}");
+    assert_eq!(
+        generated_body(&dir.path().join("mod.rs")),
+        "pub mod module_0000;\n"
+    );
+
+    drop(deepest);
+    remove_deep_tree(dir.path());
+}
+
+/// The directory `path` below `dir` opened
+#[cfg(unix)]
+fn open_dir_at(dir: impl std::os::fd::AsFd, path: &Path) -> std::os::fd::OwnedFd {
+    use rustix::fs::{Mode, OFlags};
+
+    let flags = OFlags::DIRECTORY | OFlags::RDONLY | OFlags::CLOEXEC;
+    rustix::fs::openat(dir, path, flags, Mode::empty()).unwrap()
+}
+
+/// Empties `top` of its directories however deep they go, by moving the entries of each up into
+/// `top` before removing it: `fs::remove_dir_all` keeps a handle open per level, more than the
+/// usual limit of open files
+#[cfg(unix)]
+fn remove_deep_tree(top: &Path) {
+    use rustix::fs::{AtFlags, Dir, FileType};
+
+    let top = open_dir_at(rustix::fs::CWD, top);
+    let mut moved = 0;
+    loop {
+        let subdirs: Vec<_> = Dir::read_from(&top)
+            .unwrap()
+            .map(Result::unwrap)
+            .filter(|e| e.file_type() == FileType::Directory)
+            .map(|e| e.file_name().to_owned())
+            .filter(|name| name.to_bytes() != b"." && name.to_bytes() != b"..")
+            .collect();
+        if subdirs.is_empty() {
+            break;
+        }
+        for subdir in subdirs {
+            let dir = open_dir_at(&top, Path::new(subdir.to_str().unwrap()));
+            for entry in Dir::read_from(&dir).unwrap().map(Result::unwrap) {
+                let name = entry.file_name();
+                if name.to_bytes() == b"." || name.to_bytes() == b".." {
+                    continue;
+                }
+                if entry.file_type() == FileType::Directory {
+                    moved += 1;
+                    rustix::fs::renameat(&dir, name, &top, format!("moved_{moved}")).unwrap();
+                } else {
+                    rustix::fs::unlinkat(&dir, name, AtFlags::empty()).unwrap();
+                }
+            }
+            rustix::fs::unlinkat(&top, &subdir, AtFlags::REMOVEDIR).unwrap();
+        }
+    }
+}
+
+#[test]
+fn max_depth_stops_descending_and_says_where() {
+    let dir = tempfile::tempdir().unwrap();
+    let deep = dir.path().join("a").join("b").join("c");
+    fs::create_dir_all(&deep).unwrap();
+    fs::write(dir.path().join("util.rs"), "").unwrap();
+    fs::write(dir.path().join("a").join("x.rs"), "").unwrap();
+    fs::write(deep.join("leaf.rs"), "").unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_crateify"))
+        .args(["--max-depth", "1"])
+        .arg(dir.path())
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(
+        stderr(&output).contains("warning: a/b: deeper than --max-depth 1, not descended into"),
+        "{}",
+        stderr(&output)
+    );
+    assert_eq!(
+        generated_body(&dir.path().join("mod.rs")),
+        "pub mod a;\npub mod util;\n"
+    );
+    assert_eq!(
+        generated_body(&dir.path().join("a").join("mod.rs")),
+        "pub mod x;\n"
+    );
+    assert!(!dir.path().join("a").join("b").join("mod.rs").exists());
+
+    let output = Command::new(env!("CARGO_BIN_EXE_crateify"))
+        .args(["--max-depth", "deep"])
+        .arg(dir.path())
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2), "{}", stderr(&output));
+}
+
+#[test]
+fn progress_stays_off_stderr_that_is_not_a_terminal() {
+    let dir = tempfile::tempdir().unwrap();
+    for i in 0..20 {
+        let sub = dir.path().join(format!("d{i}")).join("inner");
+        fs::create_dir_all(&sub).unwrap();
+        fs::write(sub.join("x.rs"), "").unwrap();
+    }
+
+    // stderr is a pipe here, which a bar redrawn in place would fill with control characters
+    let output = Command::new(env!("CARGO_BIN_EXE_crateify"))
+        .args(["-v", "--max-depth", "1"])
+        .arg(dir.path())
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    let text = stderr(&output);
+    assert!(text.contains("21 directories processed"), "{text}");
+    assert!(text.contains("deeper than --max-depth 1"), "{text}");
+    assert!(
+        !text.chars().any(|c| c.is_control() && c != '\n'),
+        "{text:?}"
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn symlinked_directories_are_not_followed_by_default() {
+    let dir = tempfile::tempdir().unwrap();
+    let tree = dir.path().join("tree");
+    tree_with_symlinks(&tree, true);
+
+    let output = crateify(&[&tree]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        generated_body(&tree.join("app").join("mod.rs")),
+        "pub mod cli;\n"
+    );
+    // file symlinks are modules like any other file
+    assert_eq!(
+        generated_body(&tree.join("mod.rs")),
+        "pub mod app;\npub mod helpers;\npub mod shared;\npub mod util;\n"
+    );
+    common::check_compiles(dir.path(), &tree.join("mod.rs")).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn follow_symlinks_declares_shared_directories_and_rejects_cycles() {
+    let dir = tempfile::tempdir().unwrap();
+    let tree = dir.path().join("tree");
+    tree_with_symlinks(&tree, false);
+    let follow = |tree: &Path| {
+        Command::new(env!("CARGO_BIN_EXE_crateify"))
+            .arg("--follow-symlinks")
+            .arg(tree)
+            .output()
+            .unwrap()
+    };
+
+    let output = follow(&tree);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        generated_body(&tree.join("app").join("mod.rs")),
+        "pub mod cli;\npub mod common;\n"
+    );
+    assert_eq!(
+        generated_body(&tree.join("shared").join("mod.rs")),
+        "pub mod alloc;\n"
+    );
+    common::check_compiles(dir.path(), &tree.join("mod.rs")).unwrap();
+
+    let cyclic = dir.path().join("cyclic");
+    tree_with_symlinks(&cyclic, true);
+    let output = follow(&cyclic);
+    assert_eq!(output.status.code(), Some(1));
+    let messages = stderr(&output);
+    assert!(
+        messages.contains(&format!(
+            "{}: symlink cycle, it links back to {}",
+            cyclic.join("app").join("up").display(),
+            fs::canonicalize(&cyclic).unwrap().display()
+        )),
+        "{messages}"
+    );
+    // the link is an error, but no directory that failed: every one of them was written
+    assert!(
+        messages.contains(
+            "crateify: 3 directories processed: 3 succeeded, 0 failed, 1 other error(s), 1 warning(s);"
+        ),
+        "{messages}"
+    );
+    assert!(
+        messages.contains("crateify: error: 1 error\n"),
+        "{messages}"
+    );
+    // the rest of the tree is still generated, and the link is not declared
+    assert_eq!(
+        generated_body(&cyclic.join("app").join("mod.rs")),
+        "pub mod cli;\npub mod common;\n"
+    );
+}
+
+#[test]
+fn more_symlink_cycles_than_directories_still_sum_up() {
+    use std::os::unix::fs::symlink;
+
+    let dir = tempfile::tempdir().unwrap();
+    let tree = dir.path().join("tree");
+    fs::create_dir(&tree).unwrap();
+    fs::write(tree.join("x.rs"), "pub fn f() {}\n").unwrap();
+    symlink(".", tree.join("l1")).unwrap();
+    symlink(".", tree.join("l2")).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_crateify"))
+        .arg("--follow-symlinks")
+        .arg(&tree)
+        .output()
+        .unwrap();
+    // each cycle is an error, but the one directory there is succeeded
+    assert_eq!(output.status.code(), Some(1), "{}", stderr(&output));
+    assert!(
+        stderr(&output).contains(
+            "crateify: 1 directories processed: 1 succeeded, 0 failed, 2 other error(s),"
+        ),
+        "{}",
+        stderr(&output)
+    );
+    assert_eq!(generated_body(&tree.join("mod.rs")), "pub mod x;\n");
+}
+
+#[test]
+fn verify_reports_dangling_modules_with_their_line() {
+    let dir = tempfile::tempdir().unwrap();
+    let tree = dir.path().join("translated");
+    copy_fixture("dangling", &tree);
+
+    // the hand-written net/mod.rs declares a module that has no file
+    let output = Command::new(env!("CARGO_BIN_EXE_crateify"))
+        .arg("--verify")
+        .arg(&tree)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    let messages = stderr(&output);
+    assert!(
+        messages.contains(
+            "crateify: error: net/mod.rs:3: file not found for module `missing` [E0583]\n\
+             crateify:     pub mod missing;\n"
+        ),
+        "{messages}"
+    );
+    assert!(
+        messages.contains("crateify: error: 1 module resolution error"),
+        "{messages}"
+    );
+
+    // with the declaration fixed, the crate --cargo-init made is checked and passes
+    fs::write(tree.join("net").join("mod.rs"), "pub mod tcp;\n").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_crateify"))
+        .args(["--verify", "--cargo-init", "--crate-name", "dangling"])
+        .arg(&tree)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(dir.path().join("target").is_dir());
+
+    let output = Command::new(env!("CARGO_BIN_EXE_crateify"))
+        .args(["--verify", "--check"])
+        .arg(&tree)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn validate_crate_catches_the_files_the_manifest_leaves_out() {
+    let dir = tempfile::tempdir().unwrap();
+    copy_fixture("undeclared", &dir.path().join("src"));
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_crateify"))
+            .args(args)
+            .current_dir(dir.path())
+            .output()
+            .unwrap()
+    };
+    let output = run(&[
+        "--cargo-init",
+        "--crate-name",
+        "undeclared",
+        "--manifest",
+        "modules.json",
+        "--exclude",
+        "draft.rs",
+        "src",
+    ]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let validate = ["--validate-crate", ".", "--manifest", "modules.json"];
+
+    let output = run(&[&validate[..], &["--cargo-check", "--exclude", "draft.rs"]].concat());
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(dir.path().join("target").is_dir());
+
+    // draft.rs is on disk, but nothing declares it
+    let undeclared = "crateify: error: src/draft.rs: no module file declares it, so it is never \
+                      compiled\n";
+    let output = run(&validate);
+    assert_eq!(output.status.code(), Some(1));
+    let messages = stderr(&output);
+    assert!(messages.contains(undeclared), "{messages}");
+    assert!(
+        messages.contains("crateify: error: 1 file doesn't line up with the package\n"),
+        "{messages}"
+    );
+    // without cargo, from Cargo.toml alone
+    let output = Command::new(env!("CARGO_BIN_EXE_crateify"))
+        .args(validate)
+        .arg("--cargo-check")
+        .current_dir(dir.path())
+        .env("CARGO", dir.path().join("no-cargo"))
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    let messages = stderr(&output);
+    assert!(messages.contains(undeclared), "{messages}");
+    assert!(
+        messages.contains(
+            "crateify: cargo not found, read the targets from ./Cargo.toml and skipped \
+             --cargo-check\n"
+        ),
+        "{messages}"
+    );
+
+    // declared by hand, it is compiled but still not in the manifest, while util.rs no longer is
+    let lib = dir.path().join("src/lib.rs");
+    let generated = fs::read_to_string(&lib).unwrap();
+    fs::write(&lib, generated.replace("pub mod util;", "mod draft;")).unwrap();
+    let output = run(&[&validate[..], &["--cargo-check"]].concat());
+    assert_eq!(output.status.code(), Some(1));
+    let messages = stderr(&output);
+    assert!(
+        messages.contains(
+            "crateify: error: src/draft.rs: cargo check compiled it, but no generated module \
+             file declares it\n"
+        ),
+        "{messages}"
+    );
+    assert!(
+        messages.contains(
+            "crateify: error: src/util.rs: crate::util is declared, but cargo check never \
+             compiled it\n"
+        ),
+        "{messages}"
+    );
+    fs::write(&lib, generated).unwrap();
+
+    // a [lib] somewhere else builds none of the tree
+    let manifest = dir.path().join("Cargo.toml");
+    let cargo_toml = fs::read_to_string(&manifest).unwrap();
+    fs::write(&manifest, cargo_toml.replace("src/lib.rs", "src/util.rs")).unwrap();
+    let output = run(&[&validate[..], &["--exclude", "draft.rs"]].concat());
+    assert_eq!(output.status.code(), Some(1));
+    let messages = stderr(&output);
+    assert!(
+        messages.contains(
+            "crateify: error: src/lib.rs: the crate root has no [lib] target building it; the \
+             package's [lib] is src/util.rs\n"
+        ),
+        "{messages}"
+    );
+}
+
+#[test]
+fn validate_crate_needs_the_manifest_and_no_crate_directory() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = crateify(&[
+        Path::new("--validate-crate"),
+        dir.path(),
+        &dir.path().join("src"),
+    ]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(
+        stderr(&output).contains("--validate-crate checks the crate directories of its --manifest"),
+        "{}",
+        stderr(&output)
+    );
+    let output = crateify(&[Path::new("--validate-crate"), dir.path()]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(
+        stderr(&output).contains("--validate-crate expects the --manifest"),
+        "{}",
+        stderr(&output)
+    );
+    let output = crateify(&[Path::new("--cargo-check"), dir.path()]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stderr(&output).contains(
+        "crateify: hint: usage of --cargo-check: crateify --validate-crate <package_dir> \
+         --manifest <path.json> [--cargo-check]"
+    ));
+}
+
+#[test]
+fn header_records_version_and_input_and_ignores_version_bumps() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    fs::create_dir(root.join("net")).unwrap();
+    fs::write(root.join("net").join("tcp.rs"), "").unwrap();
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_crateify"))
+            .args(args)
+            .arg(root)
+            .output()
+            .unwrap()
+    };
+
+    assert!(run(&[]).status.success());
+    let net = root.join("net").join("mod.rs");
+    assert_eq!(
+        fs::read_to_string(root.join("mod.rs")).unwrap(),
+        format!("{}pub mod net;\n", header("."))
+    );
+    assert_eq!(
+        fs::read_to_string(&net).unwrap(),
+        format!("{}pub mod tcp;\n", header("net"))
+    );
+
+    // as if written by another version: only --strict-version calls that stale, or rewrites it
+    let older = "// @generated by crateify 0.0.1 from net; do not edit\npub mod tcp;\n";
+    fs::write(&net, older).unwrap();
+    let output = run(&["--check"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(run(&["--check", "--strict-version"]).status.code(), Some(1));
+    assert!(run(&[]).status.success());
+    assert_eq!(fs::read_to_string(&net).unwrap(), older);
+    assert!(run(&["--strict-version"]).status.success());
+    assert_eq!(generated_body(&net), "pub mod tcp;\n");
+    assert!(
+        fs::read_to_string(&net)
+            .unwrap()
+            .starts_with(&header("net"))
+    );
+
+    // the bare marker of older files is still recognized, and is what --no-header writes
+    let output = run(&["--no-header"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        fs::read_to_string(&net).unwrap(),
+        format!("{GENERATED}pub mod tcp;\n")
+    );
+    let output = run(&[]);
+    assert!(!stderr(&output).contains("warning:"), "{}", stderr(&output));
+    assert!(
+        fs::read_to_string(&net)
+            .unwrap()
+            .starts_with(&header("net"))
+    );
+}
+
+#[test]
+fn fmt_formats_generated_files_under_the_crate_config() {
+    let dir = tempfile::tempdir().unwrap();
+    let src = dir.path().join("src");
+    fs::create_dir_all(src.join("net")).unwrap();
+    fs::write(dir.path().join("rustfmt.toml"), "max_width = 80\n").unwrap();
+    fs::write(
+        src.join("net").join("tcp.rs"),
+        "pub struct ConnectionHandle;\npub fn connect_to_server() {}\n\
+         pub fn disconnect_from_server() {}\n",
+    )
+    .unwrap();
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_crateify"))
+            .args(["--reexport", "items"])
+            .args(args)
+            .arg(&src)
+            .output()
+            .unwrap()
+    };
+
+    let output = run(&["--fmt"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(!stderr(&output).contains("warning:"), "{}", stderr(&output));
+    // 82 columns on one line, which rustfmt only keeps with its default max_width of 100
+    assert_eq!(
+        generated_body(&src.join("net").join("mod.rs")),
+        "pub mod tcp;\npub use self::tcp::{\n    \
+         connect_to_server, disconnect_from_server, ConnectionHandle,\n};\n"
+    );
+    let rustfmt = Command::new("rustfmt")
+        .args(["--check", "--edition", "2021"])
+        .arg(src.join("lib.rs"))
+        .current_dir(dir.path())
+        .output()
+        .unwrap();
+    assert!(
+        rustfmt.status.success(),
+        "{}",
+        String::from_utf8_lossy(&rustfmt.stdout)
+    );
+    // formatting alone never makes a file stale, with --fmt
+    assert!(run(&["--fmt", "--check"]).status.success());
+    assert_eq!(run(&["--check"]).status.code(), Some(1));
+
+    let output = Command::new(env!("CARGO_BIN_EXE_crateify"))
+        .args(["--fmt", "--force"])
+        .arg(&src)
+        .env("RUSTFMT", dir.path().join("no-rustfmt"))
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        stderr(&output)
+            .matches("not found, writing files unformatted")
+            .count(),
+        1,
+        "{}",
+        stderr(&output)
+    );
+    assert_eq!(
+        generated_body(&src.join("net").join("mod.rs")),
+        "pub mod tcp;\n"
+    );
+}
+
+#[test]
+fn several_directories_share_one_run() {
+    let dir = tempfile::tempdir().unwrap();
+    let out = dir.path();
+    for (path, content) in [
+        ("lib/util.rs", ""),
+        ("lib/net/tcp.rs", ""),
+        ("cli/args.rs", ""),
+        ("tests/smoke.rs", ""),
+    ] {
+        fs::create_dir_all(out.join(path).parent().unwrap()).unwrap();
+        fs::write(out.join(path), content).unwrap();
+    }
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_crateify"))
+            .args(args)
+            .current_dir(out)
+            .output()
+            .unwrap()
+    };
+
+    let output = run(&["--manifest", "modules.json", "--", "lib", "cli"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        generated_body(&out.join("lib").join("mod.rs")),
+        "pub mod net;\npub mod util;\n"
+    );
+    assert_eq!(
+        generated_body(&out.join("cli").join("mod.rs")),
+        "pub mod args;\n"
+    );
+    let manifest: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(out.join("modules.json")).unwrap()).unwrap();
+    let roots = manifest["roots"].as_object().unwrap();
+    assert_eq!(roots.keys().collect::<Vec<_>>(), ["cli", "lib"]);
+    let paths: Vec<_> = roots["lib"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|d| d["module_file"].as_str().unwrap())
+        .collect();
+    assert_eq!(paths, ["mod.rs", "net/mod.rs"]);
+
+    // one failing root fails the run, and the summary covers them all
+    fs::create_dir(out.join("tests").join("mod.rs")).unwrap();
+    let output = run(&["lib", "cli", "tests"]);
+    assert_eq!(output.status.code(), Some(1));
+    let messages = stderr(&output);
+    assert!(
+        messages.contains("crateify: 5 directories processed: 4 succeeded, 1 failed"),
+        "{messages}"
+    );
+    // with several roots, paths start with the root as given
+    assert!(
+        messages.contains("I/O error on tests/mod.rs: "),
+        "{messages}"
+    );
+
+    assert_eq!(run(&["lib", "lib/net"]).status.code(), Some(2));
+    assert_eq!(run(&["lib", "lib"]).status.code(), Some(2));
+    assert_eq!(run(&["--watch", "lib", "cli"]).status.code(), Some(2));
+}
+
+#[test]
+fn test_modules_are_only_compiled_in_test_builds() {
+    let dir = tempfile::tempdir().unwrap();
+    let src = dir.path().join("src");
+    copy_fixture("test_modules", &src);
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_crateify"))
+            .args(["--cargo-init", "--crate-name", "gated", "--force"])
+            .args(args)
+            .arg(&src)
+            .output()
+            .unwrap()
+    };
+    let manifest = dir.path().join("Cargo.toml");
+    let target = dir.path().join("target");
+
+    let output = run(&[]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        generated_body(&src.join("lib.rs")),
+        "pub mod parser;\n#[cfg(test)]\nmod parser_test;\n#[cfg(test)]\nmod tests;\n"
+    );
+    // tests/helpers.rs uses an item that only exists under #[cfg(test)]
+    common::cargo_check(&manifest, &target).unwrap();
+    common::cargo_check_with(&manifest, &target, &["--tests"]).unwrap();
+
+    let output = run(&["--no-test-detection"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        generated_body(&src.join("lib.rs")),
+        "pub mod parser;\npub mod parser_test;\npub mod tests;\n"
+    );
+    assert!(common::cargo_check(&manifest, &target).is_err());
+
+    fs::write(src.join("test_utils.rs"), "").unwrap();
+    let output = run(&["--test-pattern", "test_*.rs"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        generated_body(&src.join("lib.rs")),
+        "pub mod parser;\n#[cfg(test)]\nmod parser_test;\n#[cfg(test)]\nmod test_utils;\n\
+         #[cfg(test)]\nmod tests;\n"
+    );
+}
+
+#[test]
+fn inner_attrs_open_the_root_module() {
+    let dir = tempfile::tempdir().unwrap();
+    let tree = dir.path().join("tree");
+    fs::create_dir_all(tree.join("net")).unwrap();
+    fs::write(tree.join("net").join("tcp.rs"), "fn unused() {}\n").unwrap();
+    fs::write(tree.join("util.rs"), "").unwrap();
+    let attrs = dir.path().join("attrs.txt");
+    fs::write(
+        &attrs,
+        "# lints translated code trips\n#![allow(unused_variables)]\nallow(clippy::all) # noise\n\
+         doc = \"see #12\" # the issue\n#![cfg_attr(test, doc = \"#x\")]\n",
+    )
+    .unwrap();
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_crateify"))
+            .args(["--root", "lib"])
+            .args(args)
+            .arg(&tree)
+            .output()
+            .unwrap()
+    };
+    let attr_args = [
+        "--header",
+        "Translated.",
+        "--inner-attr",
+        "allow(dead_code)",
+        "--inner-attr-file",
+        attrs.to_str().unwrap(),
+    ];
+
+    let output = run(&attr_args);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        generated_body(&tree.join("lib.rs")),
+        "//! Translated.\n#![allow(dead_code)]\n#![allow(unused_variables)]\n\
+         #![allow(clippy::all)]\n#![doc = \"see #12\"]\n#![cfg_attr(test, doc = \"#x\")]\n\
+         pub mod net;\npub mod util;\n"
+    );
+    assert_eq!(
+        generated_body(&tree.join("net").join("mod.rs")),
+        "pub mod tcp;\n"
+    );
+    common::check_compiles(dir.path(), &tree.join("lib.rs")).unwrap();
+
+    // they are part of what --check compares
+    let check: Vec<&str> = ["--check"].iter().chain(&attr_args).copied().collect();
+    let output = run(&check);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(run(&["--check"]).status.code(), Some(1));
+
+    let output = run(&["--inner-attr", "allow(dead_code"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(
+        stderr(&output).contains("--inner-attr allow(dead_code: "),
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[test]
+fn prelude_reexports_what_its_selectors_name() {
+    let dir = tempfile::tempdir().unwrap();
+    let out = dir.path().join("out");
+    copy_fixture("prelude", &out);
+    let selectors = dir.path().join("prelude.txt");
+    fs::write(
+        &selectors,
+        "# what every translated file uses\ntypes::*\nerrors::ErrorCode\nffi::*::BUF_*\n",
+    )
+    .unwrap();
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_crateify"))
+            .args(["--cargo-init", "--crate-name", "translated", "--prelude"])
+            .arg(&selectors)
+            .args(args)
+            .arg(&out)
+            .output()
+            .unwrap()
+    };
+
+    let output = run(&[]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        generated_body(&out.join("mod.rs")),
+        "pub mod errors;\npub mod ffi;\npub mod types;\npub mod prelude;\n"
+    );
+    assert_eq!(
+        fs::read_to_string(out.join("prelude.rs")).unwrap(),
+        format!(
+            "{}pub use super::types::*;\npub use super::errors::ErrorCode;\n\
+             pub use super::ffi::consts::BUF_ALIGN;\npub use super::ffi::consts::BUF_LEN;\n",
+            header(".")
+        )
+    );
+    let examples = dir.path().join("examples");
+    fs::create_dir_all(&examples).unwrap();
+    fs::write(
+        examples.join("consumer.rs"),
+        "use translated::prelude::*;\n\n\
+         fn main() {\n    let _ = (Point { x: 0, y: 0 }, ErrorCode::NotFound as Handle);\n    \
+         let _ = [0u8; BUF_LEN + BUF_ALIGN];\n}\n",
+    )
+    .unwrap();
+    common::cargo_check_with(
+        &dir.path().join("Cargo.toml"),
+        &dir.path().join("target"),
+        &["--examples"],
+    )
+    .unwrap();
+
+    // it is one of the generated files --check compares
+    assert!(run(&["--check"]).status.success());
+    fs::write(out.join("prelude.rs"), header(".")).unwrap();
+    assert_eq!(run(&["--check"]).status.code(), Some(1));
+
+    fs::write(&selectors, "types::*\nerrors::ErrCode\n").unwrap();
+    let output = run(&[]);
+    assert_eq!(output.status.code(), Some(1), "{}", stderr(&output));
+    assert!(
+        stderr(&output)
+            .contains("--prelude errors::ErrCode: no public item or module in crate::errors"),
+        "{}",
+        stderr(&output)
+    );
+    // the prelude isn't written then, and the root doesn't declare it
+    assert_eq!(
+        generated_body(&out.join("mod.rs")),
+        "pub mod errors;\npub mod ffi;\npub mod types;\n"
+    );
+    common::cargo_check(&dir.path().join("Cargo.toml"), &dir.path().join("target")).unwrap();
+    fs::write(&selectors, "types::\n").unwrap();
+    let output = run(&[]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(
+        stderr(&output).contains("prelude.txt:1: types:: is not a path"),
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[test]
+fn file_attrs_go_after_existing_inner_attributes_once() {
+    let dir = tempfile::tempdir().unwrap();
+    let tree = dir.path().join("tree");
+    fs::create_dir_all(tree.join("ffi")).unwrap();
+    let translated = "//! Translated from ffi/sys.c\n#![allow(dead_code)]\n\n\
+                      // layout of the C struct\n#[repr(C)]\npub struct Sys {\n    pub FD: i32,\n}\n";
+    fs::write(tree.join("ffi").join("sys.rs"), translated).unwrap();
+    fs::write(tree.join("util.rs"), "pub fn helper() {}\n").unwrap();
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_crateify"))
+            .args(["--root", "lib"])
+            .args(args)
+            .arg(&tree)
+            .output()
+            .unwrap()
+    };
+    let attr_args = [
+        "--file-attr",
+        "ffi/*.rs=allow(non_snake_case)",
+        "--file-attr",
+        "ffi/*.rs=#![allow(dead_code)]",
+        "--file-attr",
+        "**/*.rs=allow(clippy::missing_safety_doc)",
+    ];
+
+    let output = run(&attr_args);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let edited = "//! Translated from ffi/sys.c\n#![allow(dead_code)]\n\
+                  #![allow(non_snake_case)] // crateify: file-attr\n\
+                  #![allow(clippy::missing_safety_doc)] // crateify: file-attr\n\n\
+                  // layout of the C struct\n#[repr(C)]\npub struct Sys {\n    pub FD: i32,\n}\n";
+    assert_eq!(
+        fs::read_to_string(tree.join("ffi").join("sys.rs")).unwrap(),
+        edited
+    );
+    assert_eq!(
+        fs::read_to_string(tree.join("util.rs")).unwrap(),
+        "#![allow(clippy::missing_safety_doc)] // crateify: file-attr\npub fn helper() {}\n"
+    );
+    common::check_compiles(dir.path(), &tree.join("lib.rs")).unwrap();
+
+    // a second run finds them all in place, and so does --check
+    let output = run(&attr_args);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        fs::read_to_string(tree.join("ffi").join("sys.rs")).unwrap(),
+        edited
+    );
+    let check: Vec<&str> = ["--check"].iter().chain(&attr_args).copied().collect();
+    assert!(run(&check).status.success());
+
+    let output = run(&["--remove-file-attrs"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        fs::read_to_string(tree.join("ffi").join("sys.rs")).unwrap(),
+        translated
+    );
+    assert_eq!(
+        fs::read_to_string(tree.join("util.rs")).unwrap(),
+        "pub fn helper() {}\n"
+    );
+
+    let output = run(&["--file-attr", "allow(dead_code)"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(
+        stderr(&output).contains("--file-attr expects <glob>=<attr>"),
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[test]
+fn crateify_toml_gives_options_the_command_line_replaces() {
+    let dir = tempfile::tempdir().unwrap();
+    let tree = dir.path().join("tree");
+    fs::create_dir_all(tree.join("ffi")).unwrap();
+    fs::create_dir_all(tree.join("vendor")).unwrap();
+    fs::write(tree.join("ffi").join("sys.rs"), "").unwrap();
+    fs::write(tree.join("vendor").join("zlib.rs"), "").unwrap();
+    fs::write(tree.join("util.rs"), "").unwrap();
+    fs::write(
+        dir.path().join("crateify.toml"),
+        "root = \"lib\"\nexclude = \"vendor/**\"\nkeep-empty = false\n\
+         file-attr = [\"ffi/*.rs=allow(non_snake_case)\"]\n",
+    )
+    .unwrap();
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_crateify"))
+            .args(args)
+            .arg("tree")
+            .current_dir(dir.path())
+            .output()
+            .unwrap()
+    };
+
+    let output = run(&[]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        generated_body(&tree.join("lib.rs")),
+        "pub mod ffi;\npub mod util;\n"
+    );
+    assert_eq!(
+        fs::read_to_string(tree.join("ffi").join("sys.rs")).unwrap(),
+        "#![allow(non_snake_case)] // crateify: file-attr\n"
+    );
+
+    // --file-attr replaces every rule of the file, and --root the file's root
+    fs::write(tree.join("ffi").join("io.rs"), "").unwrap();
+    let output = run(&["--root", "mod", "--file-attr", "util.rs=allow(dead_code)"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        generated_body(&tree.join("mod.rs")),
+        "pub mod ffi;\npub mod util;\n"
+    );
+    assert_eq!(
+        fs::read_to_string(tree.join("ffi").join("io.rs")).unwrap(),
+        ""
+    );
+    assert_eq!(
+        fs::read_to_string(tree.join("util.rs")).unwrap(),
+        "#![allow(dead_code)] // crateify: file-attr\n"
+    );
+
+    // --config names another file, which takes the place of crateify.toml
+    let other = dir.path().join("other.toml");
+    fs::write(&other, "exclude = [\"ffi/**\", \"vendor/**\"]\n").unwrap();
+    let output = run(&["--config", other.to_str().unwrap()]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(generated_body(&tree.join("mod.rs")), "pub mod util;\n");
+}
+
+#[test]
+fn crateify_toml_rejects_what_is_not_an_option() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::create_dir(dir.path().join("tree")).unwrap();
+    fs::write(dir.path().join("tree").join("util.rs"), "").unwrap();
+    let run = |config: &str, args: &[&str]| {
+        fs::write(dir.path().join("crateify.toml"), config).unwrap();
+        Command::new(env!("CARGO_BIN_EXE_crateify"))
+            .args(args)
+            .arg("tree")
+            .current_dir(dir.path())
+            .output()
+            .unwrap()
+    };
+    for (config, message) in [
+        ("jbos = 4\n", "crateify.toml: unknown key jbos"),
+        (
+            "config = \"other.toml\"\n",
+            "crateify.toml: unknown key config",
+        ),
+        (
+            "jobs = [4]\n",
+            "crateify.toml: jobs expects a string or an integer",
+        ),
+        (
+            "force = \"yes\"\n",
+            "crateify.toml: force expects true or false",
+        ),
+        (
+            "exclude = [1]\n",
+            "crateify.toml: exclude expects an array of strings",
+        ),
+        ("root = lib\n", "crateify.toml: line 1: "),
+    ] {
+        let output = run(config, &[]);
+        assert_eq!(output.status.code(), Some(2), "{config}");
+        assert!(stderr(&output).contains(message), "{}", stderr(&output));
+        assert!(!dir.path().join("tree").join("mod.rs").exists());
+    }
+
+    // the file is read even when the command line gives a key of it, but --help never needs it
+    assert_eq!(run("jbos = 4\n", &["--jobs", "2"]).status.code(), Some(2));
+    assert!(run("jbos = 4\n", &["--help"]).status.success());
+
+    let output = run("", &["--config", "missing.toml"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(
+        stderr(&output).contains("missing.toml"),
+        "{}",
+        stderr(&output)
+    );
+}
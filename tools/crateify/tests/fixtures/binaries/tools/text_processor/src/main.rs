@@ -0,0 +1,8 @@
+#[path = "mod.rs"]
+mod text;
+
+fn main() {
+    let mut input = String::new();
+    std::io::stdin().read_line(&mut input).unwrap();
+    print!("{}", text::modes::upper(&input));
+}
@@ -0,0 +1,3 @@
+pub fn upper(input: &str) -> String {
+    input.to_uppercase()
+}
@@ -0,0 +1,2 @@
+#[test]
+fn upper_is_idempotent() {}
@@ -0,0 +1,4 @@
+fn main() {
+    generated_tree::tools::cli::run();
+    generated_tree::util::helper();
+}
@@ -0,0 +1,3 @@
+// @generated by crateify
+pub mod tcp;
+pub mod udp;
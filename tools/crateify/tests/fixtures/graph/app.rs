@@ -0,0 +1,6 @@
+use std::fmt;
+
+use crate::missing::thing;
+use crate::net::{tcp, udp::Socket};
+
+pub fn run() {}
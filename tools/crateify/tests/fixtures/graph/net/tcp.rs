@@ -0,0 +1,4 @@
+use super::udp::Socket;
+use crate::util::helper;
+
+pub fn connect() {}
@@ -0,0 +1,3 @@
+use crate::net::tcp;
+
+pub struct Socket;
@@ -0,0 +1,3 @@
+use super::super::nothing;
+
+pub fn helper() {}
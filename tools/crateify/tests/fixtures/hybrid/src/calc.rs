@@ -0,0 +1,14 @@
+use std::os::raw::c_int;
+
+extern "C" {
+    fn add(a: c_int, b: c_int) -> c_int;
+}
+
+pub fn sum(a: i32, b: i32) -> i32 {
+    unsafe { add(a, b) }
+}
+
+#[test]
+fn sum_calls_into_c() {
+    assert_eq!(sum(2, 3), 5);
+}
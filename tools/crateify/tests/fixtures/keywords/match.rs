@@ -0,0 +1 @@
+pub fn in_match() {}
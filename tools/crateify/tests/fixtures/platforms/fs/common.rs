@@ -0,0 +1 @@
+pub const SEPARATOR: char = '/';
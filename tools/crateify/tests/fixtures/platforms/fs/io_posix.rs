@@ -0,0 +1,3 @@
+use std::os::unix::io::RawFd;
+
+pub type Handle = RawFd;
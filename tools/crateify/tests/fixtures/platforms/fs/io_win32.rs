@@ -0,0 +1,3 @@
+use std::os::windows::io::RawHandle;
+
+pub type Handle = RawHandle;
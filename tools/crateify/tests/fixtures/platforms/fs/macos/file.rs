@@ -0,0 +1,3 @@
+pub fn open(path: &str) -> std::io::Result<std::fs::File> {
+    std::fs::File::open(path)
+}
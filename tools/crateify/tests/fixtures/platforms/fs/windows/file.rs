@@ -0,0 +1,5 @@
+use std::os::windows::fs::OpenOptionsExt;
+
+pub fn open(path: &str) -> std::io::Result<std::fs::File> {
+    std::fs::OpenOptions::new().read(true).share_mode(0).open(path)
+}
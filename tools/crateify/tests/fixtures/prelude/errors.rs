@@ -0,0 +1,11 @@
+pub enum ErrorCode {
+    Ok,
+    NotFound,
+}
+
+pub fn describe(code: ErrorCode) -> &'static str {
+    match code {
+        ErrorCode::Ok => "ok",
+        ErrorCode::NotFound => "not found",
+    }
+}
@@ -0,0 +1,3 @@
+pub const BUF_LEN: usize = 64;
+pub const BUF_ALIGN: usize = 8;
+pub const MAX_RETRIES: u32 = 3;
@@ -0,0 +1,10 @@
+pub type Handle = u32;
+
+pub struct Point {
+    pub x: i32,
+    pub y: i32,
+}
+
+fn origin() -> Point {
+    Point { x: 0, y: 0 }
+}
@@ -0,0 +1,14 @@
+pub const MD5_DIGEST_LEN: usize = 16;
+
+extern "C" {
+    pub fn md5_block_asm(state: *mut u32, block: *const u8);
+}
+
+pub fn init() -> [u32; 4] {
+    [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476]
+}
+
+pub fn md5(data: &[u8]) -> [u8; MD5_DIGEST_LEN] {
+    let _ = (init(), data);
+    [0; MD5_DIGEST_LEN]
+}
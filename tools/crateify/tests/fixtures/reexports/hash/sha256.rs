@@ -0,0 +1,16 @@
+pub struct Sha256Ctx {
+    pub state: [u32; 8],
+}
+
+pub fn init() -> Sha256Ctx {
+    Sha256Ctx { state: [0; 8] }
+}
+
+pub fn sha256(data: &[u8]) -> [u8; 32] {
+    let _ = (init(), data);
+    [0; 32]
+}
+
+fn rotate(x: u32, n: u32) -> u32 {
+    x.rotate_right(n)
+}
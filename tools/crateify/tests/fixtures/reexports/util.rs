@@ -0,0 +1,6 @@
+pub use std::mem::size_of as sizeof;
+
+pub type Digest = [u8; 32];
+
+#[cfg(test)]
+pub fn only_in_tests() {}
@@ -0,0 +1,6 @@
+pub fn parse(input: &str) -> usize {
+    input.len()
+}
+
+#[cfg(test)]
+pub const TEST_INPUT: &str = "x";
@@ -0,0 +1,7 @@
+use crate::parser::parse;
+use crate::tests::helpers::input;
+
+#[test]
+fn parses_the_test_input() {
+    assert_eq!(parse(input()), 1);
+}
@@ -0,0 +1,4 @@
+// only compiles in test builds, where TEST_INPUT exists
+pub fn input() -> &'static str {
+    crate::parser::TEST_INPUT
+}
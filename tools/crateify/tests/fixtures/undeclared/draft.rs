@@ -0,0 +1,2 @@
+// a draft translation, excluded when the crate was generated and declared by nobody since
+pub fn draft() {}
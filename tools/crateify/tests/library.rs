@@ -0,0 +1,196 @@
+//! crateify used as a library through [`Crateify`], without spawning the binary: the module file
+//! of each directory and the traversal that collects them, seen through the [`Report`] of a run.
+
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use ideas_crateify::{Change, Crateify, Event, Progress, Reexport, Report, Visibility};
+
+/// The generated content of `path` in `report`, without its header line
+fn body(report: &Report, path: &Path) -> String {
+    let content = report
+        .generated
+        .get(path)
+        .unwrap_or_else(|| panic!("nothing generated for {}", path.display()));
+    let (header, body) = content.split_once('\n').unwrap();
+    assert!(header.starts_with("// @generated by crateify"), "{content}");
+    body.to_string()
+}
+
+#[test]
+fn dry_run_reports_every_module_file_without_writing_any() {
+    let dir = tempfile::tempdir().unwrap();
+    let tree = dir.path();
+    fs::create_dir_all(tree.join("net").join("proto")).unwrap();
+    fs::write(tree.join("util.rs"), "").unwrap();
+    fs::write(tree.join("net").join("tcp.rs"), "").unwrap();
+    fs::write(tree.join("net").join("proto").join("http.rs"), "").unwrap();
+    fs::write(tree.join("README.md"), "").unwrap();
+
+    let report = Crateify::new(tree).dry_run(true).run().unwrap();
+    assert!(!tree.join("mod.rs").exists());
+    assert!(!tree.join("net").join("mod.rs").exists());
+    assert_eq!(report.generated.len(), 3);
+    assert_eq!(
+        body(&report, &tree.join("mod.rs")),
+        "pub mod net;\npub mod util;\n"
+    );
+    assert_eq!(
+        body(&report, &tree.join("net").join("mod.rs")),
+        "pub mod proto;\npub mod tcp;\n"
+    );
+    assert_eq!(
+        body(&report, &tree.join("net").join("proto").join("mod.rs")),
+        "pub mod http;\n"
+    );
+
+    // the traversal visits every directory and records children before their parents
+    let paths: Vec<&str> = report.directories.iter().map(|d| d.path.as_str()).collect();
+    assert_eq!(paths, ["", "net", "net/proto"]);
+    assert!(
+        report
+            .directories
+            .iter()
+            .all(|d| d.status == Change::Created)
+    );
+    assert_eq!(report.directories[1].module_path, "crate::net");
+    let Some(Event::Summary {
+        directories,
+        files,
+        mod_rs,
+        skipped,
+        failed,
+        ..
+    }) = report.events.last()
+    else {
+        panic!("no summary last: {:?}", report.events);
+    };
+    assert_eq!(
+        (*directories, *files, *mod_rs, *skipped, *failed),
+        (3, 3, 3, 1, 0)
+    );
+}
+
+#[test]
+fn options_shape_the_declarations() {
+    let dir = tempfile::tempdir().unwrap();
+    let tree = dir.path();
+    fs::create_dir_all(tree.join("net")).unwrap();
+    fs::create_dir_all(tree.join("vendor")).unwrap();
+    fs::write(tree.join("net").join("tcp.rs"), "pub fn connect() {}\n").unwrap();
+    fs::write(tree.join("vendor").join("zlib.rs"), "").unwrap();
+
+    let report = Crateify::new(tree)
+        .exclude("vendor")
+        .visibility(Visibility::PubCrate)
+        .reexport(Reexport::Glob)
+        .header("Translated from C.")
+        .run()
+        .unwrap();
+    assert_eq!(
+        body(&report, &tree.join("mod.rs")),
+        "//! Translated from C.\npub(crate) mod net;\npub(crate) use self::net::*;\n"
+    );
+    assert_eq!(
+        body(&report, &tree.join("net").join("mod.rs")),
+        "pub(crate) mod tcp;\npub(crate) use self::tcp::*;\n"
+    );
+    assert!(!tree.join("vendor").join("mod.rs").exists());
+    // what the report holds is what was written
+    assert_eq!(
+        fs::read_to_string(tree.join("mod.rs")).unwrap(),
+        report.generated[&tree.join("mod.rs")]
+    );
+
+    let rerun = Crateify::new(tree)
+        .exclude("vendor")
+        .visibility(Visibility::PubCrate)
+        .reexport(Reexport::Glob)
+        .header("Translated from C.")
+        .run()
+        .unwrap();
+    assert!(
+        rerun
+            .directories
+            .iter()
+            .all(|d| d.status == Change::Unchanged)
+    );
+}
+
+#[test]
+fn failed_directories_are_in_the_report_not_on_stderr() {
+    let dir = tempfile::tempdir().unwrap();
+    let tree = dir.path();
+    fs::create_dir_all(tree.join("codec")).unwrap();
+    fs::write(tree.join("codec").join("foo-bar.rs"), "").unwrap();
+    fs::write(tree.join("codec").join("foo_bar.rs"), "").unwrap();
+    fs::write(tree.join("util.rs"), "").unwrap();
+
+    let report = Crateify::new(tree).run().unwrap();
+    assert_eq!(report.failed(), 1);
+    let Some(Event::Error { path, message }) = report
+        .events
+        .iter()
+        .find(|e| matches!(e, Event::Error { .. }))
+    else {
+        unreachable!();
+    };
+    assert_eq!(path, "codec");
+    assert!(message.contains("foo-bar.rs"), "{message}");
+    assert!(!tree.join("codec").join("mod.rs").exists());
+    // the rest of the tree is generated all the same
+    assert_eq!(
+        body(&report, &tree.join("mod.rs")),
+        "pub mod codec;\npub mod util;\n"
+    );
+}
+
+#[test]
+fn flatten_generates_the_root_module_alone() {
+    let dir = tempfile::tempdir().unwrap();
+    let tree = dir.path().join("src");
+    fs::create_dir_all(tree.join("net")).unwrap();
+    fs::write(tree.join("net").join("tcp.rs"), "").unwrap();
+
+    let report = Crateify::new(&tree)
+        .flatten(true)
+        .dry_run(true)
+        .run()
+        .unwrap();
+    assert_eq!(report.generated.len(), 1);
+    assert_eq!(
+        body(&report, &tree.join("lib.rs")),
+        "pub mod net {\n    pub mod tcp;\n}\n"
+    );
+    assert!(report.directories.iter().all(|d| d.module_file == "lib.rs"));
+}
+
+#[test]
+fn progress_is_handed_to_the_callback_for_every_directory() {
+    let dir = tempfile::tempdir().unwrap();
+    let tree = dir.path();
+    fs::create_dir_all(tree.join("net").join("proto")).unwrap();
+    fs::create_dir_all(tree.join("util")).unwrap();
+    fs::write(tree.join("net").join("proto").join("http.rs"), "").unwrap();
+    fs::write(tree.join("util").join("log.rs"), "").unwrap();
+
+    let seen = Arc::new(Mutex::new(Vec::<Progress>::new()));
+    let updates = Arc::clone(&seen);
+    Crateify::new(tree)
+        .dry_run(true)
+        .on_progress(move |progress| updates.lock().unwrap().push(progress.clone()))
+        .run()
+        .unwrap();
+
+    let seen = seen.lock().unwrap();
+    let done: Vec<usize> = seen.iter().map(|p| p.done).collect();
+    assert_eq!(done, [1, 2, 3, 4]);
+    assert!(seen.iter().all(|p| p.done <= p.total), "{seen:?}");
+    // the total is only complete once each directory has been read
+    assert_eq!(seen.first().unwrap().total, 1);
+    assert_eq!(seen.last().unwrap().total, 4);
+    let mut paths: Vec<&str> = seen.iter().map(|p| p.path.as_str()).collect();
+    paths.sort();
+    assert_eq!(paths, ["", "net", "net/proto", "util"]);
+}
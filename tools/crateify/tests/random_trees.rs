@@ -0,0 +1,337 @@
+//! Randomized directory trees run through crateify, checking invariants that must hold for any
+//! input rather than the exact output for a hand-picked one: crateify either reports a module
+//! collision or generates a tree that compiles.
+//!
+//! Every failure reports its seed; rerun a single tree with `CRATEIFY_SEED=<seed> cargo test
+//! --test random_trees -- --ignored`. The ignored `many_random_trees` test runs
+//! `CRATEIFY_ITERATIONS` (default 200) trees for longer soak runs.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+mod common;
+
+use common::check_compiles;
+
+/// Seeds run on every `cargo test`; 4, 14 and 24 plant name collisions, a file next to a
+/// directory, and a name collision in a directory with a hand-written mod.rs
+const CI_SEEDS: [u64; 11] = [1, 2, 3, 4, 5, 8, 13, 14, 21, 24, 34];
+
+/// Module names the generator draws from, including keywords and names that aren't identifiers;
+/// no two of them sanitize to the same module name
+const MODULE_NAMES: &[&str] = &[
+    "alpha",
+    "beta",
+    "gamma",
+    "delta",
+    "parser",
+    "lexer",
+    "util",
+    "net",
+    "tcp",
+    "udp",
+    "hash",
+    "sha256",
+    "table",
+    "io_uring",
+    "match",
+    "type",
+    "async",
+    "crate",
+    "self",
+    "super",
+    "9p",
+    "http-parser",
+    "v1.2",
+];
+
+/// Pairs of file names that sanitize to the same module name
+const COLLIDING_NAMES: &[(&str, &str)] = &[("foo-bar", "foo_bar"), ("io-ctl", "io_ctl")];
+
+/// A mod.rs that someone wrote by hand, which crateify must leave alone
+const HAND_WRITTEN_MOD_RS: &str = "//! written by hand\npub fn hand_written() {}\n";
+
+/// Non-Rust files that crateify must leave out of every mod.rs
+const OTHER_FILES: &[&str] = &[
+    "notes.txt",
+    "build.c",
+    "types.h",
+    "README.md",
+    "LICENSE",
+    "Makefile",
+];
+
+const MAX_DEPTH: u32 = 3;
+
+/// splitmix64, good enough to shuffle directory layouts and fully reproducible from the seed
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    fn pick<'a>(&mut self, pool: &[&'a str]) -> &'a str {
+        pool[self.below(pool.len())]
+    }
+}
+
+/// The collisions [`generate`] planted in a tree
+#[derive(Debug, Default)]
+struct Planted {
+    /// Two files whose names sanitize to the same module name, which nothing resolves
+    names: bool,
+    /// A `foo.rs` next to a `foo/`, which `--merge-collisions` resolves
+    file_and_dir: bool,
+}
+
+/// Fills `dir` with a random mix of modules, non-Rust files, subdirectories, hand-written mod.rs
+/// files, and now and then a collision
+fn generate(rng: &mut Rng, dir: &Path, depth: u32, planted: &mut Planted) {
+    generate_with(rng, dir, depth, depth == 0, planted);
+}
+
+/// [`generate`], with at least one module in `dir` if `needs_module`
+fn generate_with(rng: &mut Rng, dir: &Path, depth: u32, needs_module: bool, planted: &mut Planted) {
+    fs::create_dir_all(dir).unwrap();
+    let mut used = BTreeSet::new();
+
+    // directories below the root may end up without modules; their parents must not declare them
+    let min = if needs_module { 1 } else { 0 };
+    for _ in 0..min + rng.below(4) {
+        let name = rng.pick(MODULE_NAMES);
+        if used.insert(name) {
+            fs::write(dir.join(format!("{name}.rs")), "pub fn f() {}\n").unwrap();
+        }
+    }
+    for _ in 0..rng.below(3) {
+        fs::write(dir.join(rng.pick(OTHER_FILES)), "").unwrap();
+    }
+    // never declared: entry points in the root, skipped with a warning below it
+    if rng.below(4) == 0 {
+        fs::write(dir.join(rng.pick(&["main.rs", "lib.rs"])), "").unwrap();
+    }
+    if depth > 0 && rng.below(6) == 0 {
+        fs::write(dir.join("mod.rs"), HAND_WRITTEN_MOD_RS).unwrap();
+    }
+    if rng.below(8) == 0 {
+        let (a, b) = COLLIDING_NAMES[rng.below(COLLIDING_NAMES.len())];
+        fs::write(dir.join(format!("{a}.rs")), "pub fn f() {}\n").unwrap();
+        fs::write(dir.join(format!("{b}.rs")), "pub fn f() {}\n").unwrap();
+        planted.names = true;
+    }
+    if depth < MAX_DEPTH {
+        for _ in 0..rng.below(3) {
+            let name = rng.pick(MODULE_NAMES);
+            if !used.insert(name) {
+                continue;
+            }
+            // `parser.rs` next to `parser/`, which only collides if the directory is declared
+            let next_to_file = rng.below(6) == 0;
+            generate_with(rng, &dir.join(name), depth + 1, next_to_file, planted);
+            if next_to_file {
+                fs::write(dir.join(format!("{name}.rs")), "pub fn f() {}\n").unwrap();
+                planted.file_and_dir = true;
+            }
+        }
+    }
+}
+
+/// How a run of crateify ended
+enum Run {
+    Succeeded,
+    /// Failed, and the errors include a module collision
+    Collided,
+}
+
+fn run_crateify(root: &Path, args: &[&str]) -> Result<Run, String> {
+    let output = Command::new(env!("CARGO_BIN_EXE_crateify"))
+        .args(["--error-format", "json"])
+        .args(args)
+        .arg(root)
+        .output()
+        .map_err(|e| e.to_string())?;
+    if output.status.success() {
+        return Ok(Run::Succeeded);
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let collided = stderr.lines().any(|line| {
+        serde_json::from_str::<serde_json::Value>(line)
+            .is_ok_and(|d| d["code"] == "module-collision")
+    });
+    if collided {
+        Ok(Run::Collided)
+    } else {
+        Err(format!("crateify failed: {stderr}"))
+    }
+}
+
+/// [`run_crateify`] on a tree it must generate without errors
+fn run_cleanly(root: &Path, args: &[&str]) -> Result<(), String> {
+    match run_crateify(root, args)? {
+        Run::Succeeded => Ok(()),
+        Run::Collided => Err("crateify reported a collision on its own output".to_string()),
+    }
+}
+
+/// Contents of every file below `dir`, keyed by path
+fn snapshot(dir: &Path, files: &mut BTreeMap<PathBuf, Vec<u8>>) {
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            snapshot(&path, files);
+        } else {
+            files.insert(path.clone(), fs::read(&path).unwrap());
+        }
+    }
+}
+
+/// Every `pub mod` in every module file crateify generated below `root`, the root's `lib.rs`
+/// included, is declared once and resolves to a file or directory
+fn check_declarations(root: &Path, files: &BTreeMap<PathBuf, Vec<u8>>) -> Result<(), String> {
+    for (path, content) in files {
+        let module_file = path.file_name().unwrap() == "mod.rs" || *path == root.join("lib.rs");
+        if !module_file || !content.starts_with(b"// @generated by crateify") {
+            continue;
+        }
+        let dir = path.parent().unwrap();
+        let mut seen = BTreeSet::new();
+        let mut path_attr = None;
+        for line in String::from_utf8_lossy(content).lines() {
+            if let Some(p) = line
+                .strip_prefix("#[path = \"")
+                .and_then(|l| l.strip_suffix("\"]"))
+            {
+                path_attr = Some(p.to_string());
+                continue;
+            }
+            let Some(name) = line
+                .strip_prefix("pub mod ")
+                .and_then(|l| l.strip_suffix(';'))
+            else {
+                continue;
+            };
+            let name = name.strip_prefix("r#").unwrap_or(name);
+            if !seen.insert(name.to_string()) {
+                return Err(format!("{}: {name} declared twice", path.display()));
+            }
+            let resolves = match path_attr.take() {
+                Some(p) => files.contains_key(&dir.join(p)),
+                None => {
+                    files.contains_key(&dir.join(format!("{name}.rs")))
+                        || files.contains_key(&dir.join(name).join("mod.rs"))
+                }
+            };
+            if !resolves {
+                return Err(format!("{}: {name} does not resolve", path.display()));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn check_seed(seed: u64) -> Result<(), String> {
+    let workdir = tempfile::tempdir().unwrap();
+    let root = workdir.path().join("tree");
+    let mut planted = Planted::default();
+    generate(&mut Rng(seed), &root, 0, &mut planted);
+
+    // collisions that --merge-collisions resolves must be, with a lib.rs root for a change
+    let merged = workdir.path().join("merged");
+    generate(&mut Rng(seed), &merged, 0, &mut Planted::default());
+    match run_crateify(&merged, &["--merge-collisions", "--root", "lib"])? {
+        Run::Collided if !planted.names => {
+            return Err(format!(
+                "--merge-collisions reported a collision in a tree without name collisions \
+                 ({planted:?})"
+            ));
+        }
+        Run::Collided => {}
+        Run::Succeeded => {
+            let mut files = BTreeMap::new();
+            snapshot(&merged, &mut files);
+            check_declarations(&merged, &files)?;
+            let build = workdir.path().join("merged_build");
+            fs::create_dir(&build).unwrap();
+            check_compiles(&build, &merged.join("lib.rs"))?;
+        }
+    }
+
+    match run_crateify(&root, &[])? {
+        Run::Collided if !planted.names && !planted.file_and_dir => {
+            return Err("reported a collision in a tree without one".to_string());
+        }
+        Run::Collided => return Ok(()),
+        Run::Succeeded => {}
+    }
+    let mut files = BTreeMap::new();
+    snapshot(&root, &mut files);
+    check_declarations(&root, &files)?;
+
+    // watch mode and --since rerun crateify over its own output, which must change nothing
+    run_cleanly(&root, &[])?;
+    let mut rerun = BTreeMap::new();
+    snapshot(&root, &mut rerun);
+    if let Some(path) = files
+        .keys()
+        .chain(rerun.keys())
+        .find(|p| files.get(*p) != rerun.get(*p))
+    {
+        return Err(format!("{}: changed when rerun", path.display()));
+    }
+
+    // the parallel traversal must produce exactly what a single thread does
+    let sequential = workdir.path().join("sequential");
+    generate(&mut Rng(seed), &sequential, 0, &mut Planted::default());
+    run_cleanly(&sequential, &["--jobs", "1"])?;
+    let mut single = BTreeMap::new();
+    snapshot(&sequential, &mut single);
+    let relative =
+        |files: &BTreeMap<PathBuf, Vec<u8>>, base: &Path| -> BTreeMap<PathBuf, Vec<u8>> {
+            files
+                .iter()
+                .map(|(p, c)| (p.strip_prefix(base).unwrap().to_path_buf(), c.clone()))
+                .collect()
+        };
+    if relative(&files, &root) != relative(&single, &sequential) {
+        return Err("--jobs 1 generated a different tree".to_string());
+    }
+    check_compiles(workdir.path(), &root.join("mod.rs"))
+}
+
+fn check_seeds(seeds: impl IntoIterator<Item = u64>) {
+    for seed in seeds {
+        if let Err(e) = check_seed(seed) {
+            panic!("seed {seed}: {e}");
+        }
+    }
+}
+
+#[test]
+fn random_trees() {
+    check_seeds(CI_SEEDS);
+}
+
+#[test]
+#[ignore = "long-running; set CRATEIFY_SEED or CRATEIFY_ITERATIONS"]
+fn many_random_trees() {
+    if let Some(seed) = env::var("CRATEIFY_SEED").ok().and_then(|s| s.parse().ok()) {
+        return check_seeds([seed]);
+    }
+    let iterations = env::var("CRATEIFY_ITERATIONS")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(200);
+    check_seeds(1000..1000 + iterations);
+}